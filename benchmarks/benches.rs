@@ -149,6 +149,7 @@ pub fn criterion_benchmark(c: &mut Criterion<Instructions>) {
     bench_function(c, "insert_block_headers_multiple_times");
     bench_function(c, "insert_300_blocks");
     bench_function(c, "get_metrics");
+    bench_function(c, "ingest_blocks_with_large_utxos");
 }
 
 fn benches() {
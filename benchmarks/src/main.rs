@@ -1,6 +1,13 @@
 use bitcoin::consensus::Decodable;
-use bitcoin::{consensus::Encodable, Block as BitcoinBlock, BlockHeader};
-use ic_btc_canister::{types::BlockHeaderBlob, with_state_mut};
+use bitcoin::{
+    blockdata::constants::genesis_block, consensus::Encodable, hashes::Hash, Block as BitcoinBlock,
+    BlockHash, BlockHeader, OutPoint, Script, Transaction, TxIn, TxMerkleNode, TxOut, Witness,
+};
+use ic_btc_canister::{
+    state::State,
+    types::{BlockHeaderBlob, Slicing},
+    with_state_mut,
+};
 use ic_btc_interface::{Config, Network};
 use ic_btc_types::Block;
 use ic_cdk_macros::{init, query};
@@ -112,7 +119,11 @@ fn insert_block_headers() -> u64 {
     // Benchmark inserting the block headers.
     count_instructions(|| {
         with_state_mut(|s| {
-            ic_btc_canister::state::insert_next_block_headers(s, next_block_headers.as_slice());
+            let _ = ic_btc_canister::state::insert_next_block_headers(
+                s,
+                next_block_headers.as_slice(),
+                &mut || false,
+            );
         });
     })
 }
@@ -143,12 +154,113 @@ fn insert_block_headers_multiple_times() -> u64 {
     count_instructions(|| {
         with_state_mut(|s| {
             for _ in 0..10 {
-                ic_btc_canister::state::insert_next_block_headers(s, next_block_headers.as_slice());
+                let _ = ic_btc_canister::state::insert_next_block_headers(
+                    s,
+                    next_block_headers.as_slice(),
+                    &mut || false,
+                );
             }
         });
     })
 }
 
+// Benchmarks ingesting a block with many large UTXOs into the UTXO set, followed by a block
+// that spends them all.
+#[query]
+fn ingest_blocks_with_large_utxos() -> u64 {
+    const NUM_UTXOS: u32 = 1_000;
+
+    ic_btc_canister::init(Config {
+        network: Network::Regtest,
+        ..Config::default()
+    });
+
+    // A script that's too large for the small and medium UTXOs.
+    let script_pubkey = Script::from(vec![0x51; 500]);
+
+    let coinbase_1 = coinbase(1, (0..NUM_UTXOS).map(|_| script_pubkey.clone()).collect());
+    let spending_tx = Transaction {
+        version: 1,
+        lock_time: 0,
+        input: (0..NUM_UTXOS)
+            .map(|vout| tx_in(OutPoint::new(coinbase_1.txid(), vout), Script::new()))
+            .collect(),
+        output: vec![TxOut {
+            value: NUM_UTXOS as u64,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    };
+
+    let block_1 = block(
+        genesis_block(bitcoin::Network::Regtest).block_hash(),
+        vec![coinbase_1],
+    );
+    let block_2 = block(
+        block_1.header().block_hash(),
+        vec![coinbase(2, vec![script_pubkey]), spending_tx],
+    );
+
+    count_instructions(|| {
+        with_state_mut(|s| {
+            ingest_block(s, block_1);
+            ingest_block(s, block_2);
+        });
+    })
+}
+
+// Ingests the given block into the UTXO set, including all its time slices.
+fn ingest_block(state: &mut State, block: Block) {
+    let mut slicing = state.utxos.ingest_block(block);
+    while let Slicing::Paused(()) = slicing {
+        slicing = state
+            .utxos
+            .ingest_block_continue()
+            .expect("a block to ingest must exist.");
+    }
+}
+
+// Returns a coinbase transaction with an output for each of the given scripts, which is
+// made unique by the given tag.
+fn coinbase(tag: u8, scripts: Vec<Script>) -> Transaction {
+    Transaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![tx_in(OutPoint::null(), Script::from(vec![tag]))],
+        output: scripts
+            .into_iter()
+            .map(|script_pubkey| TxOut {
+                value: 1,
+                script_pubkey,
+            })
+            .collect(),
+    }
+}
+
+fn tx_in(previous_output: OutPoint, script_sig: Script) -> TxIn {
+    TxIn {
+        previous_output,
+        script_sig,
+        sequence: 0xffffffff,
+        witness: Witness::default(),
+    }
+}
+
+fn block(prev_blockhash: BlockHash, txdata: Vec<Transaction>) -> Block {
+    let mut block = BitcoinBlock {
+        header: BlockHeader {
+            version: 1,
+            prev_blockhash,
+            merkle_root: TxMerkleNode::from_inner([0; 32]),
+            time: 0,
+            bits: 0x207fffff,
+            nonce: 0,
+        },
+        txdata,
+    };
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    Block::new(block)
+}
+
 // Returns the number of instructions consumed by the given function.
 fn count_instructions<R>(f: impl FnOnce() -> R) -> u64 {
     let start = ic_cdk::api::performance_counter(0);
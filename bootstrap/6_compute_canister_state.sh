@@ -27,7 +27,10 @@ cargo run --release --bin build-balances -- \
 
 echo "Computing address UTXOs..."
 cargo run --release --bin build-address-utxos -- \
-   --output $CANISTER_STATE_DIR/address_utxos --network "$NETWORK" --utxos-dump-path $UTXO_FILE
+   --output $CANISTER_STATE_DIR/address_utxos \
+   --output-by-value $CANISTER_STATE_DIR/address_utxos_by_value \
+   --output-by-height-ascending $CANISTER_STATE_DIR/address_utxos_by_height_ascending \
+   --network "$NETWORK" --utxos-dump-path $UTXO_FILE
 
 echo "Computing UTXOs..."
 cargo run --release --bin build-utxos -- \
@@ -109,7 +109,9 @@ fn main() {
 
     println!("Ingesting unstable blocks..");
     with_state_mut(|s| {
-        s.utxos.utxos.large_utxos = large_utxos;
+        for (outpoint, utxo) in large_utxos {
+            s.utxos.utxos.insert(outpoint, utxo);
+        }
 
         s.utxos.next_height = args.anchor_height;
 
@@ -154,6 +156,10 @@ fn main() {
         "# Medium UTXOs: {}",
         with_state(|s| s.utxos.utxos.medium_utxos.len())
     );
+    println!(
+        "# Large UTXOs: {}",
+        with_state(|s| s.utxos.utxos.large_utxos.len())
+    );
     println!("# Balances: {}", with_state(|s| s.utxos.balances_len()));
     println!(
         "# Address UTXOs: {}",
@@ -4,11 +4,15 @@
 //!
 //! cargo run --release --bin build-address-utxos -- \
 //!   --network testnet \
-//!   --output balances.bin \
+//!   --output address_utxos.bin \
+//!   --output-by-value address_utxos_by_value.bin \
+//!   --output-by-height-ascending address_utxos_by_height_ascending.bin \
 //!   --utxos-dump-path utxos-dump.csv
 use bitcoin::{Address as BitcoinAddress, Script, Txid as BitcoinTxid};
 use clap::Parser;
-use ic_btc_canister::types::{into_bitcoin_network, Address, AddressUtxo};
+use ic_btc_canister::types::{
+    into_bitcoin_network, Address, AddressHeightAscendingUtxo, AddressUtxo, AddressValueUtxo,
+};
 use ic_btc_interface::Network;
 use ic_btc_types::{OutPoint, Txid};
 use ic_stable_structures::{
@@ -31,6 +35,14 @@ struct Args {
     #[clap(long, value_hint = clap::ValueHint::DirPath)]
     output: PathBuf,
 
+    /// The path to store the index of address UTXOs sorted by value in.
+    #[clap(long, value_hint = clap::ValueHint::DirPath)]
+    output_by_value: PathBuf,
+
+    /// The path to store the index of address UTXOs sorted by ascending height in.
+    #[clap(long, value_hint = clap::ValueHint::DirPath)]
+    output_by_height_ascending: PathBuf,
+
     /// The bitcoin network.
     #[clap(long)]
     network: Network,
@@ -47,6 +59,20 @@ fn main() {
     let mut address_utxos: StableBTreeMap<Blob<{ AddressUtxo::MAX_SIZE as usize }>, (), _> =
         StableBTreeMap::init(memory.clone());
 
+    let memory_by_value = DefaultMemoryImpl::default();
+    let mut address_utxos_by_value: StableBTreeMap<
+        Blob<{ AddressValueUtxo::MAX_SIZE as usize }>,
+        (),
+        _,
+    > = StableBTreeMap::init(memory_by_value.clone());
+
+    let memory_by_height_ascending = DefaultMemoryImpl::default();
+    let mut address_utxos_by_height_ascending: StableBTreeMap<
+        Blob<{ AddressHeightAscendingUtxo::MAX_SIZE as usize }>,
+        (),
+        _,
+    > = StableBTreeMap::init(memory_by_height_ascending.clone());
+
     for (i, line) in reader.lines().enumerate() {
         let line = line.unwrap();
         let parts: Vec<_> = line.split(',').collect();
@@ -55,6 +81,7 @@ fn main() {
         let vout: u32 = parts[2].parse().unwrap();
         let address_str = parts[5];
         let height: u32 = parts[0].parse().unwrap();
+        let value: u64 = parts[3].parse().unwrap();
         let script = parts[6];
 
         if i % 100_000 == 0 {
@@ -75,6 +102,44 @@ fn main() {
         if let Some(address) = address {
             let address: Address = address.into();
 
+            address_utxos_by_value
+                .insert(
+                    Blob::try_from(
+                        AddressValueUtxo {
+                            address: address.clone(),
+                            value,
+                            outpoint: OutPoint {
+                                txid: txid.clone(),
+                                vout,
+                            },
+                        }
+                        .to_bytes()
+                        .as_ref(),
+                    )
+                    .unwrap(),
+                    (),
+                )
+                .unwrap();
+
+            address_utxos_by_height_ascending
+                .insert(
+                    Blob::try_from(
+                        AddressHeightAscendingUtxo {
+                            address: address.clone(),
+                            height,
+                            outpoint: OutPoint {
+                                txid: txid.clone(),
+                                vout,
+                            },
+                        }
+                        .to_bytes()
+                        .as_ref(),
+                    )
+                    .unwrap(),
+                    (),
+                )
+                .unwrap();
+
             address_utxos
                 .insert(
                     Blob::try_from(
@@ -96,14 +161,23 @@ fn main() {
         }
     }
 
-    println!("Writing stable structure to file...");
-    let mut file = match File::create(&args.output) {
-        Err(err) => panic!("couldn't create {}: {}", args.output.display(), err),
+    println!("Writing stable structures to file...");
+    write_memory(&memory, &args.output);
+    write_memory(&memory_by_value, &args.output_by_value);
+    write_memory(
+        &memory_by_height_ascending,
+        &args.output_by_height_ascending,
+    );
+}
+
+fn write_memory(memory: &DefaultMemoryImpl, output: &PathBuf) {
+    let mut file = match File::create(output) {
+        Err(err) => panic!("couldn't create {}: {}", output.display(), err),
         Ok(file) => file,
     };
 
     match file.write_all(&memory.borrow()) {
-        Err(err) => panic!("couldn't write to {}: {}", args.output.display(), err),
-        Ok(_) => println!("successfully wrote balances to {}", args.output.display()),
+        Err(err) => panic!("couldn't write to {}: {}", output.display(), err),
+        Ok(_) => println!("successfully wrote address UTXOs to {}", output.display()),
     };
 }
@@ -16,6 +16,7 @@ use ic_stable_structures::{
     Memory,
 };
 use std::{
+    collections::BTreeMap,
     fs::{create_dir_all, File},
     io::{BufRead, BufReader, Write},
     path::PathBuf,
@@ -126,8 +127,8 @@ fn main() {
     p.push("medium_utxos");
     write_memory_to_file(&p, MemoryId::new(3));
 
-    // Write the large UTXOs, which is a standard BTreeMap so it needs to
-    // be serialized.
+    // Write the large UTXOs. These are stored in chunks that are tied to the layout of
+    // the canister's stable structures, so they're serialized as a map instead.
     println!("Writing large UTXOs...");
     let mut p = args.output;
     p.push("large_utxos");
@@ -139,8 +140,8 @@ fn main() {
 
     with_state(|s| {
         let mut bytes = vec![];
-        ciborium::ser::into_writer(&s.utxos.utxos.large_utxos, &mut bytes)
-            .expect("failed to encode large utxos");
+        let large_utxos: BTreeMap<_, _> = s.utxos.utxos.large_utxos.iter().collect();
+        ciborium::ser::into_writer(&large_utxos, &mut bytes).expect("failed to encode large utxos");
         match file.write_all(&bytes) {
             Err(err) => panic!("couldn't write to {}: {}", p.display(), err),
             Ok(_) => println!("successfully wrote to {}", p.display()),
@@ -5,6 +5,14 @@
 //! cargo run --release --bin combine-state -- \
 //!   --output canister.bin \
 //!   --canister-state-dir ./canister_state
+//!
+//! The script can also combine a snapshot of a canister's stable memory that was downloaded
+//! with the `download` script of the `uploader`:
+//!
+//! cargo run --release --bin combine-state -- \
+//!   --output canister.bin \
+//!   --canister-state-dir ./snapshot \
+//!   --snapshot
 use clap::Parser;
 use ic_stable_structures::{
     memory_manager::{MemoryId, MemoryManager},
@@ -24,6 +32,11 @@ struct Args {
 
     #[clap(long, value_hint = clap::ValueHint::DirPath)]
     output: PathBuf,
+
+    /// Whether the state directory is a snapshot of a canister's stable memory, where each
+    /// memory is stored in a file named `memory_<id>`.
+    #[clap(long, default_value_t = false)]
+    snapshot: bool,
 }
 
 fn write_memory(memory_manager: &MemoryManager<FileMemory>, memory_id: u8, memory: &PathBuf) {
@@ -65,6 +78,18 @@ fn main() {
     let memory = FileMemory::new(File::create(&args.output).expect("Cannot create output file."));
     let memory_manager = MemoryManager::init(memory);
 
+    if args.snapshot {
+        for memory_id in 0..u8::MAX {
+            let p = args
+                .canister_state_dir
+                .join(format!("memory_{}", memory_id));
+            if p.exists() {
+                write_memory(&memory_manager, memory_id, &p);
+            }
+        }
+        return;
+    }
+
     // Add the various memories.
     let mut p = args.canister_state_dir.clone();
     p.push("./address_utxos");
@@ -78,7 +103,15 @@ fn main() {
     p.push("./medium_utxos");
     write_memory(&memory_manager, 3, &p);
 
-    let mut p = args.canister_state_dir;
+    let mut p = args.canister_state_dir.clone();
     p.push("./balances");
     write_memory(&memory_manager, 4, &p);
+
+    let mut p = args.canister_state_dir.clone();
+    p.push("./address_utxos_by_value");
+    write_memory(&memory_manager, 7, &p);
+
+    let mut p = args.canister_state_dir;
+    p.push("./address_utxos_by_height_ascending");
+    write_memory(&memory_manager, 14, &p);
 }
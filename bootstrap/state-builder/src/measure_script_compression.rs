@@ -0,0 +1,158 @@
+//! A script for measuring the memory saved by compressing the scripts of the UTXOs in a
+//! UTXO dump text file.
+//!
+//! The UTXOs are inserted into the canister's UTXO set, which compresses their scripts, and
+//! the size of the stable memories that store them is compared with the size the UTXOs take
+//! in their uncompressed encoding.
+//!
+//! Example run:
+//!
+//! cargo run --release --bin measure-script-compression -- \
+//!   --network testnet \
+//!   --utxos-dump-path utxos-dump.csv
+use bitcoin::{Address, Txid as BitcoinTxid};
+use clap::Parser;
+use ic_btc_canister::{
+    memory::get_memory_by_id,
+    state::{UTXO_KEY_SIZE, UTXO_VALUE_MAX_SIZE_MEDIUM, UTXO_VALUE_MAX_SIZE_SMALL},
+    types::{Storable, TxOut},
+    with_state, with_state_mut,
+};
+use ic_btc_interface::{Config, Flag, Network};
+use ic_btc_types::{OutPoint, Txid};
+use ic_stable_structures::Memory;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    str::FromStr,
+};
+
+const WASM_PAGE_SIZE: u64 = 65536;
+
+// The ids of the memories that store the small, medium and large UTXOs.
+const UTXOS_MEMORY_IDS: [(&str, u8); 3] = [("small", 2), ("medium", 3), ("large", 13)];
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// The path of the UTXOs dump.
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    utxos_dump_path: PathBuf,
+
+    /// The bitcoin network.
+    #[clap(long)]
+    network: Network,
+}
+
+// The number of UTXOs in each bucket and the total size of their encodings.
+#[derive(Default)]
+struct Stats {
+    num_small: u64,
+    num_medium: u64,
+    num_large: u64,
+    encoded_bytes: u64,
+}
+
+impl Stats {
+    fn add(&mut self, encoded_size: usize) {
+        if encoded_size <= UTXO_VALUE_MAX_SIZE_SMALL {
+            self.num_small += 1;
+        } else if encoded_size <= UTXO_VALUE_MAX_SIZE_MEDIUM {
+            self.num_medium += 1;
+        } else {
+            self.num_large += 1;
+        }
+        self.encoded_bytes += encoded_size as u64;
+    }
+
+    // The size of the small and medium UTXOs in their stable structures, where every
+    // entry takes the maximum size of a key and a value.
+    fn allocated_bytes(&self) -> u64 {
+        self.num_small * (UTXO_KEY_SIZE + UTXO_VALUE_MAX_SIZE_SMALL) as u64
+            + self.num_medium * (UTXO_KEY_SIZE + UTXO_VALUE_MAX_SIZE_MEDIUM) as u64
+    }
+
+    fn print(&self, name: &str) {
+        println!("{}:", name);
+        println!("  # Small UTXOs: {}", self.num_small);
+        println!("  # Medium UTXOs: {}", self.num_medium);
+        println!("  # Large UTXOs: {}", self.num_large);
+        println!("  Encoded size: {} bytes", self.encoded_bytes);
+        println!(
+            "  Allocated size of the small and medium UTXOs: {} bytes",
+            self.allocated_bytes()
+        );
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let utxos_file = File::open(args.utxos_dump_path).unwrap();
+    let reader = BufReader::new(utxos_file);
+
+    ic_btc_canister::init(Config {
+        network: args.network,
+        api_access: Flag::Disabled,
+        ..Config::default()
+    });
+
+    let mut uncompressed = Stats::default();
+    let mut compressed = Stats::default();
+
+    with_state_mut(|s| {
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.unwrap();
+            let parts: Vec<_> = line.split(',').collect();
+
+            let txid = Txid::from(BitcoinTxid::from_str(parts[1]).unwrap().to_vec());
+            let vout: u32 = parts[2].parse().unwrap();
+            let amount: u64 = parts[3].parse().unwrap();
+            let script = parts[6];
+            let height: u32 = parts[0].parse().unwrap();
+            let address_str = parts[5];
+
+            if i % 100_000 == 0 {
+                println!("Processed {} UTXOs", i);
+            }
+
+            // Scripts in the chainstate database are compressed in the case of standard types.
+            // Instead of using the scripts from the database, we can infer the script from the
+            // address. Otherwise, we use the script in the chainstate database as-is.
+            let script = match Address::from_str(address_str) {
+                Ok(address) => address.script_pubkey().as_bytes().to_vec(),
+                Err(_) => hex::decode(script).unwrap(),
+            };
+
+            if bitcoin::Script::from(script.clone()).is_provably_unspendable() {
+                continue;
+            }
+
+            // The uncompressed encoding is the value, followed by the script, followed by
+            // the height.
+            uncompressed.add(8 + script.len() + 4);
+
+            let utxo = (
+                TxOut {
+                    value: amount,
+                    script_pubkey: script,
+                },
+                height,
+            );
+            compressed.add(utxo.to_bytes().len());
+
+            let found = s.utxos.utxos.insert(OutPoint { txid, vout }, utxo);
+            assert!(!found); // A UTXO cannot be seen more than once.
+        }
+    });
+
+    uncompressed.print("Uncompressed scripts");
+    compressed.print("Compressed scripts");
+
+    println!("Stable memories of the UTXOs with compressed scripts:");
+    for (name, memory_id) in UTXOS_MEMORY_IDS {
+        let memory = get_memory_by_id(memory_id).expect("memory must exist");
+        println!("  {} UTXOs: {} bytes", name, memory.size() * WASM_PAGE_SIZE);
+    }
+    println!("# UTXOs: {}", with_state(|s| s.utxos.utxos_len()));
+}
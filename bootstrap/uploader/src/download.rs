@@ -0,0 +1,134 @@
+//! A script for downloading a snapshot of the stable memory of a bitcoin canister.
+//!
+//! Each memory is downloaded into its own file in the output directory, named
+//! `memory_<id>`, and the hash of every chunk is verified as it's downloaded. The files can
+//! then be combined into a single state with the `combine-state` script of the
+//! `state-builder`, which can be uploaded into another canister with the `upload` script.
+//!
+//! A snapshot must first be taken by disabling syncing and calling
+//! `take_stable_memory_snapshot` on the canister.
+//!
+//! Example run:
+//!
+//! cargo run --example download -- \
+//!     --canister-id g4xu7-jiaaa-aaaan-aaaaq-cai \
+//!     --identity ~/.config/dfx/identity/default/identity.pem \
+//!     --output ./snapshot
+use candid::{Decode, Encode};
+use clap::Parser;
+use ic_agent::{export::Principal, identity::Secp256k1Identity, Agent};
+use ic_btc_interface::{GetStableMemoryChunkRequest, StableMemoryChunk, StableMemoryManifest};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+use url::Url;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// The directory to write the snapshot to.
+    #[clap(long, value_hint = clap::ValueHint::DirPath)]
+    output: PathBuf,
+
+    /// Url of the IC network to connect to.
+    #[clap(long, default_value_t = Url::parse("https://ic0.app").unwrap())]
+    ic_network: Url,
+
+    /// Whether or not to fetch the root key. Should be true for testnets, false otherwise.
+    #[clap(long, default_value_t = false)]
+    fetch_root_key: bool,
+
+    /// The canister to download the snapshot from.
+    #[clap(long)]
+    canister_id: Principal,
+
+    /// The PEM file of the identity to call the canister with, which must be a controller.
+    #[clap(long, value_hint = clap::ValueHint::FilePath)]
+    identity: PathBuf,
+}
+
+#[async_std::main]
+async fn main() {
+    let args = Args::parse();
+
+    // Connect to the given network.
+    #[allow(deprecated)]
+    let agent = Agent::builder()
+        .with_url(args.ic_network.to_string())
+        .with_identity(
+            Secp256k1Identity::from_pem_file(&args.identity)
+                .expect("reading identity must succeed"),
+        )
+        .build()
+        .expect("agent creation must succeed");
+
+    // Fetch root key if needed.
+    if args.fetch_root_key {
+        agent
+            .fetch_root_key()
+            .await
+            .expect("fetch root key must succeed");
+    }
+
+    let response = agent
+        .query(&args.canister_id, "get_stable_memory_manifest")
+        .with_arg(Encode!().unwrap())
+        .call()
+        .await
+        .expect("fetching the manifest must succeed");
+    let manifest = Decode!(&response, StableMemoryManifest).unwrap();
+
+    fs::create_dir_all(&args.output).expect("creating output directory must succeed");
+    let mut hashes = vec![];
+    for memory in manifest.memories {
+        println!(
+            "Downloading memory {} ({} bytes)..",
+            memory.memory_id, memory.size
+        );
+        let mut file = BufWriter::new(
+            File::create(args.output.join(format!("memory_{}", memory.memory_id)))
+                .expect("creating memory file must succeed"),
+        );
+
+        for chunk_index in 0..memory.num_chunks {
+            let response = agent
+                .query(&args.canister_id, "get_stable_memory_chunk")
+                .with_arg(
+                    Encode!(&GetStableMemoryChunkRequest {
+                        memory_id: memory.memory_id,
+                        chunk_index,
+                    })
+                    .unwrap(),
+                )
+                .call()
+                .await
+                .expect("fetching chunk must succeed");
+            let chunk = Decode!(&response, StableMemoryChunk).unwrap();
+
+            // Verify the chunk against its hash.
+            let hash = sha256::digest(&*chunk.bytes);
+            if hash != hex(&chunk.sha256) {
+                panic!(
+                    "Chunk {} of memory {} has digest {} but {} was expected.",
+                    chunk_index,
+                    memory.memory_id,
+                    hash,
+                    hex(&chunk.sha256)
+                );
+            }
+
+            file.write_all(&chunk.bytes)
+                .expect("writing chunk must succeed");
+            hashes.push(format!("{} {} {}", memory.memory_id, chunk_index, hash));
+        }
+    }
+
+    // Write the hashes of all the chunks, so that the snapshot can be verified later on.
+    fs::write(args.output.join("manifest.txt"), hashes.join("\n") + "\n")
+        .expect("writing manifest must succeed");
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
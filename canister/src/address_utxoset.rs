@@ -1,6 +1,6 @@
 use crate::{
     multi_iter::MultiIter,
-    types::{Address, Utxo},
+    types::{Address, Utxo, UtxoByHeightAscending, UtxoByValue},
     unstable_blocks::UnstableBlocks,
     UtxoSet,
 };
@@ -49,7 +49,7 @@ impl<'a> AddressUtxoSet<'a> {
             .unstable_blocks
             .get_removed_outpoints(&block.block_hash(), &self.address)
         {
-            self.removed_outpoints.insert(outpoint.clone());
+            self.removed_outpoints.insert(outpoint);
         }
 
         for outpoint in self
@@ -58,7 +58,7 @@ impl<'a> AddressUtxoSet<'a> {
         {
             let (txout, height) = self
                 .unstable_blocks
-                .get_tx_out(outpoint)
+                .get_tx_out(&outpoint)
                 .unwrap_or_else(|| {
                     panic!(
                         "tx out for outpoint {:?} must exist in added outpoints",
@@ -66,7 +66,7 @@ impl<'a> AddressUtxoSet<'a> {
                     );
                 });
             self.added_utxos.insert(Utxo {
-                outpoint: outpoint.clone(),
+                outpoint,
                 value: txout.value,
                 height,
             });
@@ -111,6 +111,87 @@ impl<'a> AddressUtxoSet<'a> {
 
         MultiIter::new(stable_utxos, unstable_utxos)
     }
+
+    /// Returns an iterator with the address's UTXOs starting from the given (optional) offset.
+    /// UTXOs are returned in descending order by value, then by outpoint.
+    pub fn into_iter_by_value(self, offset: Option<Utxo>) -> impl Iterator<Item = Utxo> + 'a {
+        let removed_outpoints = Arc::new(self.removed_outpoints);
+        let removed_outpoints_2 = Arc::clone(&removed_outpoints);
+        let full_utxo_set = self.full_utxo_set;
+
+        let stable_utxos = self
+            .full_utxo_set
+            .get_address_outpoints_by_value(&self.address, &offset)
+            .filter(move |outpoint| !removed_outpoints.contains(outpoint))
+            .map(move |outpoint| {
+                // Look up the UTXO corresponding to the given outpoint.
+                let (tx_out, height) = full_utxo_set.get_utxo(&outpoint).unwrap_or_else(|| {
+                    panic!("Could not find UTXO with outpoint: {:?}", outpoint);
+                });
+                UtxoByValue(Utxo {
+                    outpoint,
+                    height,
+                    value: tx_out.value,
+                })
+            });
+
+        let offset = offset.map(UtxoByValue);
+        let mut unstable_utxos: Vec<_> = self
+            .added_utxos
+            .into_iter()
+            .filter(move |utxo| !removed_outpoints_2.contains(&utxo.outpoint))
+            .map(UtxoByValue)
+            .filter(move |utxo| match &offset {
+                Some(offset) => utxo >= offset,
+                None => true,
+            })
+            .collect();
+        unstable_utxos.sort();
+
+        MultiIter::new(stable_utxos, unstable_utxos.into_iter()).map(|utxo| utxo.0)
+    }
+
+    /// Returns an iterator with the address's UTXOs starting from the given (optional) offset.
+    /// UTXOs are returned in ascending order by height, then by outpoint.
+    pub fn into_iter_height_ascending(
+        self,
+        offset: Option<Utxo>,
+    ) -> impl Iterator<Item = Utxo> + 'a {
+        let removed_outpoints = Arc::new(self.removed_outpoints);
+        let removed_outpoints_2 = Arc::clone(&removed_outpoints);
+        let full_utxo_set = self.full_utxo_set;
+
+        let stable_utxos = self
+            .full_utxo_set
+            .get_address_outpoints_by_height_ascending(&self.address, &offset)
+            .filter(move |outpoint| !removed_outpoints.contains(outpoint))
+            .map(move |outpoint| {
+                // Look up the UTXO corresponding to the given outpoint.
+                let (tx_out, height) = full_utxo_set.get_utxo(&outpoint).unwrap_or_else(|| {
+                    panic!("Could not find UTXO with outpoint: {:?}", outpoint);
+                });
+                UtxoByHeightAscending(Utxo {
+                    outpoint,
+                    height,
+                    value: tx_out.value,
+                })
+            });
+
+        let offset = offset.map(UtxoByHeightAscending);
+        let mut unstable_utxos: Vec<_> = self
+            .added_utxos
+            .into_iter()
+            .filter(move |utxo| !removed_outpoints_2.contains(&utxo.outpoint))
+            .map(UtxoByHeightAscending)
+            .filter(move |utxo| match &offset {
+                Some(offset) => utxo >= offset,
+                None => true,
+            })
+            .collect();
+        unstable_utxos.sort();
+
+        MultiIter::new(stable_utxos, unstable_utxos.into_iter()).map(|utxo| utxo.0)
+    }
 }
 
 #[cfg(test)]
@@ -1,14 +1,31 @@
+mod block_height;
+mod block_invalidation;
+mod deployments;
 mod fee_percentiles;
 mod get_balance;
 mod get_utxos;
+mod invalid_blocks;
+mod median_time_past;
 mod metrics;
 mod send_transaction;
 mod set_config;
+mod stable_memory_snapshot;
+mod utxo_audit;
+pub use block_height::get_block_height;
+pub use block_invalidation::{get_audit_log, invalidate_block, reconsider_block};
+pub use deployments::get_deployments;
 pub use fee_percentiles::get_current_fee_percentiles;
 pub use get_balance::get_balance;
 pub use get_balance::get_balance_query;
 pub use get_utxos::get_utxos;
 pub use get_utxos::get_utxos_query;
+pub use invalid_blocks::{clear_invalid_blocks, get_invalid_blocks};
+pub use median_time_past::{get_height_by_timestamp, get_median_time_past};
 pub use metrics::get_metrics;
 pub use send_transaction::send_transaction;
 pub use set_config::set_config;
+pub use stable_memory_snapshot::{
+    end_snapshot, get_stable_memory_chunk, get_stable_memory_manifest, is_snapshot_pending,
+    take_stable_memory_snapshot,
+};
+pub use utxo_audit::get_utxo_audit_report;
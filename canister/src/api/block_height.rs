@@ -0,0 +1,88 @@
+use super::block_invalidation::parse_block_hash;
+use crate::{unstable_blocks, with_state};
+use ic_btc_interface::{BlockHash, GetBlockHeightError, GetBlockHeightResponse};
+
+/// Returns the height and the number of confirmations of the block with the given hash,
+/// or `None` if the block isn't part of the main chain (e.g. it was reorged out).
+pub fn get_block_height(
+    block_hash: BlockHash,
+) -> Result<Option<GetBlockHeightResponse>, GetBlockHeightError> {
+    let block_hash = parse_block_hash(block_hash).ok_or(GetBlockHeightError::MalformedBlockHash)?;
+
+    Ok(with_state(|s| {
+        let main_chain = unstable_blocks::get_main_chain(&s.unstable_blocks).into_chain();
+        let stable_height = s.utxos.next_height();
+        let tip_height = stable_height + main_chain.len() as u32 - 1;
+
+        // Look the block up in the unstable main chain, then in the stable headers.
+        let height = main_chain
+            .iter()
+            .position(|block| block.block_hash() == block_hash)
+            .map(|i| stable_height + i as u32)
+            .or_else(|| s.stable_block_headers.get_height(&block_hash))?;
+
+        Some(GetBlockHeightResponse {
+            height,
+            confirmations: tip_height - height + 1,
+        })
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        init,
+        state::{ingest_stable_blocks_into_utxoset, insert_block},
+        test_utils::BlockChainBuilder,
+        with_state_mut,
+    };
+    use ic_btc_interface::{Config, Network};
+
+    #[test]
+    fn height_and_confirmations_of_blocks() {
+        let network = Network::Regtest;
+        init(Config {
+            stability_threshold: 3,
+            network,
+            ..Default::default()
+        });
+
+        let genesis = crate::genesis_block(network);
+        let chain = BlockChainBuilder::fork(&genesis, 10).build();
+        // A fork that is shorter than the main chain.
+        let fork = BlockChainBuilder::fork(&chain[6], 2).build();
+        with_state_mut(|s| {
+            for block in chain.iter().chain(fork.iter()) {
+                insert_block(s, block.clone()).unwrap();
+            }
+            ingest_stable_blocks_into_utxoset(s);
+            assert!(s.stable_height() > 1);
+        });
+
+        let get = |hash: ic_btc_types::BlockHash| get_block_height(hash.to_vec()).unwrap();
+        let response = |height, confirmations| {
+            Some(GetBlockHeightResponse {
+                height,
+                confirmations,
+            })
+        };
+
+        // Stable blocks.
+        assert_eq!(get(genesis.block_hash()), response(0, 11));
+        assert_eq!(get(chain[0].block_hash()), response(1, 10));
+
+        // Unstable blocks in the main chain.
+        assert_eq!(get(chain[9].block_hash()), response(10, 1));
+        assert_eq!(get(chain[7].block_hash()), response(8, 3));
+
+        // Blocks that aren't in the main chain.
+        assert_eq!(get(fork[0].block_hash()), None);
+        assert_eq!(get(ic_btc_types::BlockHash::from(vec![0; 32])), None);
+
+        assert_eq!(
+            get_block_height(vec![0; 31]),
+            Err(GetBlockHeightError::MalformedBlockHash)
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{runtime, state, with_state, with_state_mut};
+use ic_btc_interface::{
+    AdminAction, AuditLogEntry, BlockHash, InvalidateBlockError, ReconsiderBlockError,
+};
+use ic_btc_types::BlockHash as InternalBlockHash;
+
+/// The maximum number of entries kept in the audit log. Once full, the oldest entries are
+/// evicted first.
+const MAX_AUDIT_LOG_ENTRIES: usize = 1_000;
+
+/// Invalidates an unstable block, removing it along with all its descendants.
+pub async fn invalidate_block(block_hash: BlockHash) -> Result<(), InvalidateBlockError> {
+    crate::verify_controller("invalidate_block").await;
+    invalidate_block_no_verification(block_hash)
+}
+
+/// Reconsiders a block that was previously invalidated.
+pub async fn reconsider_block(block_hash: BlockHash) -> Result<(), ReconsiderBlockError> {
+    crate::verify_controller("reconsider_block").await;
+    reconsider_block_no_verification(block_hash)
+}
+
+/// Returns the actions that controllers took on the canister, oldest first.
+pub fn get_audit_log() -> Vec<AuditLogEntry> {
+    crate::verify_controller_query("get_audit_log");
+    with_state(|s| s.audit_log.iter().cloned().collect())
+}
+
+fn invalidate_block_no_verification(block_hash: BlockHash) -> Result<(), InvalidateBlockError> {
+    let action = AdminAction::InvalidateBlock {
+        block_hash: block_hash.clone(),
+    };
+    let result = match parse_block_hash(block_hash) {
+        Some(block_hash) => with_state_mut(|s| state::invalidate_block(s, block_hash)),
+        None => Err(InvalidateBlockError::MalformedBlockHash),
+    };
+    record_action(action, &result);
+    result
+}
+
+fn reconsider_block_no_verification(block_hash: BlockHash) -> Result<(), ReconsiderBlockError> {
+    let action = AdminAction::ReconsiderBlock {
+        block_hash: block_hash.clone(),
+    };
+    let result = match parse_block_hash(block_hash) {
+        Some(block_hash) => with_state_mut(|s| state::reconsider_block(s, &block_hash)),
+        None => Err(ReconsiderBlockError::MalformedBlockHash),
+    };
+    record_action(action, &result);
+    result
+}
+
+// Parses a block hash given by the user, returning `None` if it's malformed.
+pub(super) fn parse_block_hash(block_hash: BlockHash) -> Option<InternalBlockHash> {
+    if block_hash.len() == 32 {
+        Some(InternalBlockHash::from(block_hash))
+    } else {
+        None
+    }
+}
+
+// Records the action taken by the caller, along with its result, in the audit log.
+fn record_action<E: std::fmt::Display>(action: AdminAction, result: &Result<(), E>) {
+    let entry = AuditLogEntry {
+        timestamp: runtime::time(),
+        caller: runtime::caller(),
+        action,
+        error: result.as_ref().err().map(|err| err.to_string()),
+    };
+    with_state_mut(|s| {
+        s.audit_log.push_back(entry);
+        if s.audit_log.len() > MAX_AUDIT_LOG_ENTRIES {
+            s.audit_log.pop_front();
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        init,
+        state::{ingest_stable_blocks_into_utxoset, insert_block, InsertBlockError},
+        test_utils::BlockChainBuilder,
+    };
+    use ic_btc_interface::{Config, Network};
+
+    #[test]
+    fn invalidate_and_reconsider_block() {
+        let network = Network::Regtest;
+        init(Config {
+            stability_threshold: 10,
+            network,
+            ..Default::default()
+        });
+
+        let genesis = crate::genesis_block(network);
+        let chain = BlockChainBuilder::fork(&genesis, 3).build();
+        let fork = BlockChainBuilder::fork(&genesis, 2).build();
+        with_state_mut(|s| {
+            for block in chain.iter().chain(fork.iter()) {
+                insert_block(s, block.clone()).unwrap();
+            }
+        });
+
+        let main_chain_tip = || {
+            with_state(|s| {
+                crate::unstable_blocks::get_main_chain(&s.unstable_blocks)
+                    .tip()
+                    .block_hash()
+            })
+        };
+        assert_eq!(main_chain_tip(), chain[2].block_hash());
+
+        // Invalidating a block prunes its subtree and switches to the other branch.
+        assert_eq!(
+            invalidate_block_no_verification(chain[1].block_hash().to_vec()),
+            Ok(())
+        );
+        assert_eq!(main_chain_tip(), fork[1].block_hash());
+
+        // The invalidated block and its descendants are rejected.
+        with_state_mut(|s| {
+            assert_eq!(
+                insert_block(s, chain[1].clone()),
+                Err(InsertBlockError::Invalidated)
+            );
+            assert_eq!(
+                insert_block(s, chain[2].clone()),
+                Err(InsertBlockError::Invalidated)
+            );
+        });
+
+        // Blocks that aren't unstable, or the anchor, cannot be invalidated.
+        assert_eq!(
+            invalidate_block_no_verification(chain[2].block_hash().to_vec()),
+            Err(InvalidateBlockError::UnknownBlock)
+        );
+        assert_eq!(
+            invalidate_block_no_verification(genesis.block_hash().to_vec()),
+            Err(InvalidateBlockError::AnchorBlock)
+        );
+        assert_eq!(
+            invalidate_block_no_verification(vec![0; 31]),
+            Err(InvalidateBlockError::MalformedBlockHash)
+        );
+
+        // Once reconsidered, the blocks can be inserted again.
+        assert_eq!(
+            reconsider_block_no_verification(chain[1].block_hash().to_vec()),
+            Ok(())
+        );
+        assert_eq!(
+            reconsider_block_no_verification(chain[1].block_hash().to_vec()),
+            Err(ReconsiderBlockError::NotInvalidated)
+        );
+        with_state_mut(|s| {
+            insert_block(s, chain[1].clone()).unwrap();
+            insert_block(s, chain[2].clone()).unwrap();
+        });
+        assert_eq!(main_chain_tip(), chain[2].block_hash());
+
+        // All the actions are recorded in the audit log.
+        let audit_log: Vec<_> = get_audit_log()
+            .into_iter()
+            .map(|entry| (entry.action, entry.error))
+            .collect();
+        let invalidate = |block_hash: BlockHash| AdminAction::InvalidateBlock { block_hash };
+        let reconsider = |block_hash: BlockHash| AdminAction::ReconsiderBlock { block_hash };
+        assert_eq!(
+            audit_log,
+            vec![
+                (invalidate(chain[1].block_hash().to_vec()), None),
+                (
+                    invalidate(chain[2].block_hash().to_vec()),
+                    Some(InvalidateBlockError::UnknownBlock.to_string())
+                ),
+                (
+                    invalidate(genesis.block_hash().to_vec()),
+                    Some(InvalidateBlockError::AnchorBlock.to_string())
+                ),
+                (
+                    invalidate(vec![0; 31]),
+                    Some(InvalidateBlockError::MalformedBlockHash.to_string())
+                ),
+                (reconsider(chain[1].block_hash().to_vec()), None),
+                (
+                    reconsider(chain[1].block_hash().to_vec()),
+                    Some(ReconsiderBlockError::NotInvalidated.to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalidated_blocks_are_dropped_once_the_anchor_moves_past_them() {
+        let network = Network::Regtest;
+        init(Config {
+            stability_threshold: 2,
+            network,
+            ..Default::default()
+        });
+
+        let genesis = crate::genesis_block(network);
+        let chain = BlockChainBuilder::fork(&genesis, 5).build();
+        let fork = BlockChainBuilder::fork(&genesis, 1).build();
+        with_state_mut(|s| {
+            insert_block(s, chain[0].clone()).unwrap();
+            insert_block(s, fork[0].clone()).unwrap();
+        });
+
+        assert_eq!(
+            invalidate_block_no_verification(fork[0].block_hash().to_vec()),
+            Ok(())
+        );
+        with_state(|s| {
+            assert_eq!(
+                s.invalidated_blocks.iter().collect::<Vec<_>>(),
+                vec![(&fork[0].block_hash(), &1)]
+            )
+        });
+
+        // Once the main chain is stable past the height of the invalidated block, the
+        // block can no longer extend the tree and is dropped.
+        with_state_mut(|s| {
+            for block in chain[1..].iter() {
+                insert_block(s, block.clone()).unwrap();
+            }
+            ingest_stable_blocks_into_utxoset(s);
+            assert!(s.stable_height() >= 1);
+            assert!(s.invalidated_blocks.is_empty());
+        });
+    }
+
+    #[test]
+    fn oldest_audit_log_entries_are_evicted() {
+        init(Config {
+            network: Network::Regtest,
+            ..Default::default()
+        });
+
+        for i in 0..MAX_AUDIT_LOG_ENTRIES + 10 {
+            let block_hash = vec![i as u8; 31];
+            assert_eq!(
+                invalidate_block_no_verification(block_hash),
+                Err(InvalidateBlockError::MalformedBlockHash)
+            );
+        }
+
+        let audit_log = get_audit_log();
+        assert_eq!(audit_log.len(), MAX_AUDIT_LOG_ENTRIES);
+        assert_eq!(
+            audit_log[0].action,
+            AdminAction::InvalidateBlock {
+                block_hash: vec![10; 31]
+            }
+        );
+    }
+}
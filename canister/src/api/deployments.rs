@@ -0,0 +1,76 @@
+use crate::{types::into_validation_network, validation::ValidationContext, with_state};
+use ic_btc_interface::{Deployment, DeploymentState};
+use ic_btc_validation::{
+    deployment_state, deployments, DeploymentState as ValidationDeploymentState,
+};
+
+/// Returns the BIP9 deployments of the network, along with their state for the block
+/// that extends the tip of the main chain.
+pub fn get_deployments() -> Vec<Deployment> {
+    with_state(|s| {
+        let network = into_validation_network(s.network());
+        let store = ValidationContext::new_for_main_chain(s);
+
+        deployments(&network)
+            .iter()
+            .map(|deployment| {
+                let (state, since) = deployment_state(&network, &store, deployment);
+                Deployment {
+                    name: deployment.name.to_string(),
+                    bit: deployment.bit,
+                    state: into_deployment_state(state),
+                    since,
+                }
+            })
+            .collect()
+    })
+}
+
+fn into_deployment_state(state: ValidationDeploymentState) -> DeploymentState {
+    match state {
+        ValidationDeploymentState::Defined => DeploymentState::Defined,
+        ValidationDeploymentState::Started => DeploymentState::Started,
+        ValidationDeploymentState::LockedIn => DeploymentState::LockedIn,
+        ValidationDeploymentState::Active => DeploymentState::Active,
+        ValidationDeploymentState::Failed => DeploymentState::Failed,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{init, state::insert_block, test_utils::BlockChainBuilder, with_state_mut};
+    use ic_btc_interface::{Config, Network};
+
+    #[test]
+    fn deployments_on_regtest() {
+        let network = Network::Regtest;
+        init(Config {
+            stability_threshold: 10,
+            network,
+            ..Default::default()
+        });
+
+        let deployments = get_deployments();
+        let names: Vec<_> = deployments.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["csv", "segwit", "taproot", "testdummy"]);
+        for deployment in &deployments[..3] {
+            assert_eq!(deployment.state, DeploymentState::Active);
+            assert_eq!(deployment.since, 0);
+        }
+
+        // The test dummy is started once the chain reaches the second period.
+        assert_eq!(deployments[3].state, DeploymentState::Defined);
+        let genesis = crate::genesis_block(network);
+        let chain = BlockChainBuilder::fork(&genesis, 143).build();
+        with_state_mut(|s| {
+            for block in chain {
+                insert_block(s, block).unwrap();
+            }
+        });
+
+        let testdummy = get_deployments().pop().unwrap();
+        assert_eq!(testdummy.state, DeploymentState::Started);
+        assert_eq!(testdummy.since, 144);
+    }
+}
@@ -75,6 +75,8 @@ fn get_fees_per_byte(
         if tx_i >= number_of_transactions {
             break;
         }
+        // The blocks of the main chain only have their headers, so the full block is loaded.
+        let block = unstable_blocks::load_block(unstable_blocks, block);
         for tx in block.txdata() {
             if tx_i >= number_of_transactions {
                 break;
@@ -235,6 +237,7 @@ mod test {
         let address_2 = random_p2pkh_address(network);
 
         let coinbase_tx = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&address_1, initial_balance)
             .build();
         let block_0 = BlockBuilder::with_prev_header(genesis_block(network).header())
@@ -337,6 +340,7 @@ mod test {
         let fee_in_millisatoshi = fee * 1000;
 
         let tx_1 = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&random_p2pkh_address(Network::Regtest), balance)
             .build();
         let tx_2 = TransactionBuilder::new()
@@ -344,12 +348,13 @@ mod test {
             .with_output(&random_p2pkh_address(Network::Regtest), balance - fee)
             .build();
 
-        let blocks = vec![
-            BlockBuilder::with_prev_header(genesis_block(Network::Regtest).header())
-                .with_transaction(tx_1)
-                .with_transaction(tx_2.clone())
-                .build(),
-        ];
+        let block_1 = BlockBuilder::with_prev_header(genesis_block(Network::Regtest).header())
+            .with_transaction(tx_1)
+            .build();
+        let block_2 = BlockBuilder::with_prev_header(block_1.header())
+            .with_transaction(tx_2.clone())
+            .build();
+        let blocks = vec![block_1, block_2];
 
         let stability_threshold = blocks.len() as u128;
         init_state(blocks, stability_threshold);
@@ -527,6 +532,7 @@ mod test {
         let fee_in_millisatoshi = 1000;
 
         let coinbase_tx = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&random_p2pkh_address(Network::Regtest), balance)
             .build();
 
@@ -550,12 +556,13 @@ mod test {
         assert_ne!(tx.vsize(), tx.size());
         assert_eq!(tx_without_witness.vsize(), tx_without_witness.size());
 
-        let blocks = vec![
-            BlockBuilder::with_prev_header(genesis_block(Network::Regtest).header())
-                .with_transaction(coinbase_tx)
-                .with_transaction(tx.clone())
-                .build(),
-        ];
+        let block_1 = BlockBuilder::with_prev_header(genesis_block(Network::Regtest).header())
+            .with_transaction(coinbase_tx)
+            .build();
+        let block_2 = BlockBuilder::with_prev_header(block_1.header())
+            .with_transaction(tx.clone())
+            .build();
+        let blocks = vec![block_1, block_2];
 
         let stability_threshold = blocks.len() as u128;
         init_state(blocks, stability_threshold);
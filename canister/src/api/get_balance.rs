@@ -68,7 +68,7 @@ fn get_balance_private(request: GetBalanceRequest) -> Result<Satoshi, GetBalance
                 .unstable_blocks
                 .get_added_outpoints(&block.block_hash(), &address)
             {
-                let (txout, _) = state.unstable_blocks.get_tx_out(outpoint).unwrap();
+                let (txout, _) = state.unstable_blocks.get_tx_out(&outpoint).unwrap();
                 balance += txout.value;
             }
 
@@ -76,7 +76,7 @@ fn get_balance_private(request: GetBalanceRequest) -> Result<Satoshi, GetBalance
                 .unstable_blocks
                 .get_removed_outpoints(&block.block_hash(), &address)
             {
-                let (txout, _) = state.unstable_blocks.get_tx_out(outpoint).unwrap();
+                let (txout, _) = state.unstable_blocks.get_tx_out(&outpoint).unwrap();
                 balance -= txout.value;
             }
         }
@@ -160,6 +160,7 @@ mod test {
         // Create a block where 1000 satoshis are given to an address.
         let address = random_p2pkh_address(network);
         let coinbase_tx = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&address, 1000)
             .build();
         let block = BlockBuilder::with_prev_header(genesis_block(network).header())
@@ -243,6 +244,7 @@ mod test {
         // Create a chain where 1000 satoshis are given to the address_1, then
         // address_1 gives 1000 satoshis to address_2.
         let coinbase_tx = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&address_1, 1000)
             .build();
         let block_0 = genesis_block(network);
@@ -5,7 +5,9 @@ use crate::{
     types::{Address, GetUtxosRequest, Page, Utxo},
     unstable_blocks, verify_has_enough_cycles, with_state, with_state_mut, State,
 };
-use ic_btc_interface::{GetUtxosError, GetUtxosResponse, Utxo as PublicUtxo, UtxosFilter};
+use ic_btc_interface::{
+    GetUtxosError, GetUtxosResponse, Utxo as PublicUtxo, UtxosFilter, UtxosSortOrder,
+};
 use ic_btc_types::{Block, BlockHash, OutPoint, Txid};
 use serde_bytes::ByteBuf;
 use std::str::FromStr;
@@ -48,7 +50,14 @@ fn get_utxos_private(
         match &request.filter {
             None => {
                 // No filter is specified. Return all UTXOs for the address.
-                get_utxos_internal(state, &request.address, 0, None, MAX_UTXOS_PER_RESPONSE)
+                get_utxos_internal(
+                    state,
+                    &request.address,
+                    0,
+                    None,
+                    request.sort_order,
+                    MAX_UTXOS_PER_RESPONSE,
+                )
             }
             Some(UtxosFilter::MinConfirmations(min_confirmations)) => {
                 // Return UTXOs with the requested number of confirmations.
@@ -57,6 +66,7 @@ fn get_utxos_private(
                     &request.address,
                     *min_confirmations,
                     None,
+                    request.sort_order,
                     MAX_UTXOS_PER_RESPONSE,
                 )
             }
@@ -65,6 +75,7 @@ fn get_utxos_private(
                 &request.address,
                 0,
                 Some(page.to_vec()),
+                request.sort_order,
                 MAX_UTXOS_PER_RESPONSE,
             ),
         }
@@ -115,6 +126,10 @@ pub fn get_utxos_query(request: GetUtxosRequest) -> Result<GetUtxosResponse, Get
 // If the optional `page` is set, then it will be used to return the next chunk
 // of UTXOs starting from that page reference.
 //
+// The optional `sort_order` determines the order in which the UTXOs are returned,
+// defaulting to descending height. A page can only be used with the sort order
+// it was created with.
+//
 // The optional `utxo_limit` restricts the number of UTXOs that can be included
 // in the response in case there are too many UTXOs for this address and they
 // cannot fit in a single response. A `page` reference will be returned along
@@ -125,6 +140,7 @@ fn get_utxos_internal(
     address: &str,
     min_confirmations: u32,
     page: Option<Vec<u8>>,
+    sort_order: Option<UtxosSortOrder>,
     utxo_limit: usize,
 ) -> Result<(GetUtxosResponse, Stats), GetUtxosError> {
     match page {
@@ -135,7 +151,19 @@ fn get_utxos_internal(
                 tip_block_hash,
                 height,
                 outpoint,
+                value,
+                sort_order: page_sort_order,
             } = Page::from_bytes(page).map_err(|err| GetUtxosError::MalformedPage { err })?;
+            if let Some(sort_order) = sort_order {
+                if sort_order != page_sort_order {
+                    return Err(GetUtxosError::MalformedPage {
+                        err: format!(
+                            "The page was created with sort order {:?}, but {:?} was requested.",
+                            page_sort_order, sort_order
+                        ),
+                    });
+                }
+            }
             let chain =
                 unstable_blocks::get_chain_with_tip(&state.unstable_blocks, &tip_block_hash)
                     .ok_or(GetUtxosError::UnknownTipBlockHash {
@@ -149,15 +177,24 @@ fn get_utxos_internal(
                 Some(Utxo {
                     height,
                     outpoint,
-                    value: 0,
+                    value,
                 }),
+                page_sort_order,
                 utxo_limit,
             )
         }
         // No specific page was provided, so we use the main chain for computing UTXOs.
         None => {
             let chain = unstable_blocks::get_main_chain(&state.unstable_blocks);
-            get_utxos_from_chain(state, address, min_confirmations, chain, None, utxo_limit)
+            get_utxos_from_chain(
+                state,
+                address,
+                min_confirmations,
+                chain,
+                None,
+                sort_order.unwrap_or_default(),
+                utxo_limit,
+            )
         }
     }
 }
@@ -196,6 +233,7 @@ fn get_utxos_from_chain(
     min_confirmations: u32,
     chain: BlockChain,
     offset: Option<Utxo>,
+    sort_order: UtxosSortOrder,
     utxo_limit: usize,
 ) -> Result<(GetUtxosResponse, Stats), GetUtxosError> {
     let mut stats = Stats::default();
@@ -209,6 +247,12 @@ fn get_utxos_from_chain(
         });
     }
 
+    // The indices that sort the UTXOs in the other orders are incomplete until they're
+    // backfilled after an upgrade.
+    if sort_order != UtxosSortOrder::HeightDescending && state.utxos.is_backfilling_indices() {
+        return Err(GetUtxosError::SortOrderUnavailable { sort_order });
+    }
+
     let mut address_utxos = state.get_utxos(address);
 
     let mut tip_block_hash = chain.first().block_hash();
@@ -239,8 +283,15 @@ fn get_utxos_from_chain(
     let (utxos_to_take, overflow) = utxo_limit.overflowing_add(1);
     assert!(!overflow, "overflow when computing utxos to take");
 
-    let mut utxos: Vec<_> = address_utxos
-        .into_iter(offset)
+    let sorted_utxos: Box<dyn Iterator<Item = Utxo>> = match sort_order {
+        UtxosSortOrder::HeightDescending => Box::new(address_utxos.into_iter(offset)),
+        UtxosSortOrder::HeightAscending => {
+            Box::new(address_utxos.into_iter_height_ascending(offset))
+        }
+        UtxosSortOrder::ValueDescending => Box::new(address_utxos.into_iter_by_value(offset)),
+    };
+
+    let mut utxos: Vec<_> = sorted_utxos
         .take(utxos_to_take)
         .map(|utxo| {
             // Convert UTXOs to their public representation.
@@ -264,6 +315,8 @@ fn get_utxos_from_chain(
             tip_block_hash: tip_block_hash.clone(),
             height: next.height,
             outpoint: OutPoint::new(Txid::from(next.outpoint.txid), next.outpoint.vout),
+            value: next.value,
+            sort_order,
         }
         .to_bytes()
     });
@@ -309,6 +362,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: String::from("not an address"),
                 filter: None,
+                sort_order: None,
             }),
             Err(GetUtxosError::MalformedAddress)
         );
@@ -325,6 +379,7 @@ mod test {
             get_utxos_query(GetUtxosRequest {
                 address: String::from("not an address"),
                 filter: None,
+                sort_order: None,
             }),
             Err(GetUtxosError::MalformedAddress)
         );
@@ -342,7 +397,8 @@ mod test {
         assert_eq!(
             get_utxos(GetUtxosRequest {
                 address: random_p2pkh_address(network).to_string(),
-                filter: None
+                filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -368,6 +424,7 @@ mod test {
 
         // Create a block where 1000 satoshis are given to the address.
         let coinbase_tx = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&address, 1000)
             .build();
         let block = BlockBuilder::with_prev_header(genesis_block(network).header())
@@ -382,7 +439,8 @@ mod test {
         assert_eq!(
             get_utxos(GetUtxosRequest {
                 address: address.to_string(),
-                filter: None
+                filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -425,10 +483,12 @@ mod test {
         for i in 0..num_blocks {
             let tx = if i % 2 == 0 {
                 TransactionBuilder::coinbase()
+                    .with_height(i as u32 + 1)
                     .with_output(&address_1, i + 1)
                     .build()
             } else {
                 TransactionBuilder::coinbase()
+                    .with_height(i as u32 + 1)
                     .with_output(&address_2, i + 1)
                     .build()
             };
@@ -471,7 +531,8 @@ mod test {
         assert_eq!(
             get_utxos(GetUtxosRequest {
                 address: address_1.to_string(),
-                filter: None
+                filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -485,7 +546,8 @@ mod test {
         assert_eq!(
             get_utxos(GetUtxosRequest {
                 address: address_2.to_string(),
-                filter: None
+                filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -524,6 +586,7 @@ mod test {
 
         // Create a genesis block where 1000 satoshis are given to the address.
         let coinbase_tx = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&address, 1000)
             .build();
 
@@ -540,7 +603,8 @@ mod test {
         assert_eq!(
             get_utxos(GetUtxosRequest {
                 address: address.to_string(),
-                filter: None
+                filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -577,6 +641,7 @@ mod test {
         // Create a block where 1000 satoshis are given to the address_1, followed
         // by a block where address_1 gives 1000 satoshis to address_2.
         let coinbase_tx = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&address_1, 1000)
             .build();
         let block_0 = BlockBuilder::with_prev_header(genesis_block(network).header())
@@ -603,6 +668,7 @@ mod test {
                 get_utxos(GetUtxosRequest {
                     address: address_2.to_string(),
                     filter: min_confirmations.map(UtxosFilter::MinConfirmations),
+                    sort_order: None,
                 })
                 .unwrap(),
                 GetUtxosResponse {
@@ -624,6 +690,7 @@ mod test {
                 get_utxos(GetUtxosRequest {
                     address: address_1.to_string(),
                     filter: min_confirmations.map(UtxosFilter::MinConfirmations),
+                    sort_order: None,
                 })
                 .unwrap(),
                 GetUtxosResponse {
@@ -640,7 +707,8 @@ mod test {
         assert_eq!(
             get_utxos(GetUtxosRequest {
                 address: address_2.to_string(),
-                filter: Some(UtxosFilter::MinConfirmations(2))
+                filter: Some(UtxosFilter::MinConfirmations(2)),
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -653,7 +721,8 @@ mod test {
         assert_eq!(
             get_utxos(GetUtxosRequest {
                 address: address_1.to_string(),
-                filter: Some(UtxosFilter::MinConfirmations(2))
+                filter: Some(UtxosFilter::MinConfirmations(2)),
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -687,7 +756,8 @@ mod test {
             assert_eq!(
                 get_utxos(GetUtxosRequest {
                     address: address.to_string(),
-                    filter
+                    filter,
+                    sort_order: None,
                 })
                 .unwrap(),
                 GetUtxosResponse {
@@ -705,6 +775,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address.to_string(),
                 filter: Some(UtxosFilter::MinConfirmations(2)),
+                sort_order: None,
             }),
             Err(GetUtxosError::MinConfirmationsTooLarge { given: 2, max: 1 })
         );
@@ -722,6 +793,7 @@ mod test {
 
         // Create a genesis block where 1000 satoshis are given to address 1.
         let coinbase_tx = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&address_1, 1000)
             .build();
 
@@ -758,6 +830,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_1.to_string(),
                 filter: None,
+                sort_order: None,
             })
             .unwrap(),
             block_0_utxos
@@ -781,6 +854,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_2.to_string(),
                 filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -802,6 +876,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_1.to_string(),
                 filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -826,42 +901,45 @@ mod test {
             state::insert_block(state, block_1_prime.clone()).unwrap();
         });
 
-        // Because block 1 and block 1' contest with each other, neither of them are included
-        // in the UTXOs. Only the UTXOs of block 0 are returned.
+        // Block 1 and block 1' have the same work. Block 1 was seen first, so it remains
+        // part of the main chain, and the UTXOs of block 1' are not included.
         assert_eq!(
             get_utxos(GetUtxosRequest {
                 address: address_2.to_string(),
                 filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
-                utxos: vec![],
-                tip_block_hash: block_0.block_hash().to_vec(),
-                tip_height: 1,
-                next_page: None,
-            }
-        );
-        assert_eq!(
-            get_utxos(GetUtxosRequest {
-                address: address_3.to_string(),
-                filter: None,
-            })
-            .unwrap(),
-            GetUtxosResponse {
-                utxos: vec![],
-                tip_block_hash: block_0.block_hash().to_vec(),
-                tip_height: 1,
+                utxos: vec![Utxo {
+                    outpoint: OutPoint {
+                        txid: block_1.txdata()[1].txid().into(),
+                        vout: 0,
+                    },
+                    value: 1000,
+                    height: 2,
+                }],
+                tip_block_hash: block_1.block_hash().to_vec(),
+                tip_height: 2,
                 next_page: None,
             }
         );
-        assert_eq!(
-            get_utxos(GetUtxosRequest {
-                address: address_1.to_string(),
-                filter: None,
-            })
-            .unwrap(),
-            block_0_utxos
-        );
+        for address in [&address_1, &address_3] {
+            assert_eq!(
+                get_utxos(GetUtxosRequest {
+                    address: address.to_string(),
+                    filter: None,
+                    sort_order: None,
+                })
+                .unwrap(),
+                GetUtxosResponse {
+                    utxos: vec![],
+                    tip_block_hash: block_1.block_hash().to_vec(),
+                    tip_height: 2,
+                    next_page: None,
+                }
+            );
+        }
 
         // Now extend block 1' with another block that transfers the funds to address 4.
         // In this case, the fork of [block 1', block 2'] will be considered the "main"
@@ -882,6 +960,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_1.to_string(),
                 filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -895,6 +974,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_2.to_string(),
                 filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -908,6 +988,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_3.to_string(),
                 filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -922,6 +1003,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_4.to_string(),
                 filter: None,
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -947,6 +1029,7 @@ mod test {
 
         // Create a block where 1000 satoshis are given to the address_1.
         let tx = TransactionBuilder::coinbase()
+            .with_height(1)
             .with_output(&address_1, 1000)
             .build();
         let block_0 = BlockBuilder::with_prev_header(genesis_block(network).header())
@@ -968,6 +1051,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_1.to_string(),
                 filter: Some(UtxosFilter::MinConfirmations(1)),
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -990,6 +1074,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_1.to_string(),
                 filter: Some(UtxosFilter::MinConfirmations(2)),
+                sort_order: None,
             })
             .unwrap(),
             GetUtxosResponse {
@@ -1005,6 +1090,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address: address_1.to_string(),
                 filter: Some(UtxosFilter::MinConfirmations(3)),
+                sort_order: None,
             }),
             Err(GetUtxosError::MinConfirmationsTooLarge { given: 3, max: 2 })
         );
@@ -1044,6 +1130,7 @@ mod test {
                 &address_1.to_string(),
                 0,
                 None,
+                None,
                 MAX_UTXOS_PER_RESPONSE
             )
             .unwrap()
@@ -1086,6 +1173,7 @@ mod test {
                 &address.to_string(),
                 0,
                 None,
+                None,
                 MAX_UTXOS_PER_RESPONSE,
             )
             .unwrap()
@@ -1098,6 +1186,7 @@ mod test {
                 &address.to_string(),
                 0,
                 None,
+                None,
                 // Allow 3 UTXOs to be returned.
                 3,
             )
@@ -1116,6 +1205,7 @@ mod test {
                 &address.to_string(),
                 0,
                 None,
+                None,
                 // Allow 4 UTXOs to be returned.
                 4,
             )
@@ -1129,7 +1219,7 @@ mod test {
             assert!(response.next_page.is_some());
 
             // A very big limit will result in the same as requesting UTXOs without any limit.
-            let response = get_utxos_internal(&state, &address.to_string(), 0, None, 1000)
+            let response = get_utxos_internal(&state, &address.to_string(), 0, None, None, 1000)
                 .unwrap()
                 .0;
 
@@ -1144,7 +1234,7 @@ mod test {
     proptest! {
         #[test]
         fn get_utxos_with_pagination_is_consistent_with_no_pagination(
-            num_transactions in 1..20u64,
+            num_outputs in 1..20u64,
             num_blocks in 1..10u64,
             utxo_limit in prop_oneof![
                 Just(10),
@@ -1162,28 +1252,20 @@ mod test {
             let mut value = 1;
             let mut blocks = vec![];
             for block_idx in 0..num_blocks {
-                let mut block_builder = match prev_block {
+                let block_builder = match prev_block {
                     Some(b) => BlockBuilder::with_prev_header(b.header()),
                     None => BlockBuilder::genesis(),
                 };
 
-                let mut transactions = vec![];
-                for _ in 0..(num_transactions + block_idx) {
-                    transactions.push(
-                        TransactionBuilder::coinbase()
-                            .with_output(&address, value)
-                            .build()
-                    );
-                    // Vary the value of the transaction to ensure that
+                let mut coinbase = TransactionBuilder::coinbase().with_height(block_idx as u32);
+                for _ in 0..(num_outputs + block_idx) {
+                    coinbase = coinbase.with_output(&address, value);
+                    // Vary the value of the outputs to ensure that
                     // we get unique outpoints in the blockchain.
                     value += 1;
                 }
 
-                for transaction in transactions.iter() {
-                    block_builder = block_builder.with_transaction(transaction.clone());
-                }
-
-                let block = block_builder.build();
+                let block = block_builder.with_transaction(coinbase.build()).build();
                 blocks.push(block.clone());
                 prev_block = Some(block);
             }
@@ -1194,7 +1276,7 @@ mod test {
             }
 
             // Get UTXO set without any pagination...
-            let utxo_set = get_utxos_internal(&state, &address.to_string(), 0, None, MAX_UTXOS_PER_RESPONSE)
+            let utxo_set = get_utxos_internal(&state, &address.to_string(), 0, None, None, MAX_UTXOS_PER_RESPONSE)
                 .unwrap().0
                 .utxos;
 
@@ -1208,6 +1290,7 @@ mod test {
                     &address.to_string(),
                     0,
                     page,
+                    None,
                     utxo_limit,
                 )
                 .unwrap().0;
@@ -1224,6 +1307,168 @@ mod test {
         }
     }
 
+    proptest! {
+        #[test]
+        fn get_utxos_with_pagination_is_consistent_for_all_sort_orders(
+            num_outputs in 1..20u64,
+            num_blocks in 1..10u64,
+            utxo_limit in prop_oneof![
+                Just(1),
+                Just(7),
+                Just(20),
+                Just(100),
+            ],
+            sort_order in prop_oneof![
+                Just(UtxosSortOrder::HeightDescending),
+                Just(UtxosSortOrder::HeightAscending),
+                Just(UtxosSortOrder::ValueDescending),
+            ],
+        ) {
+            let network = Network::Regtest;
+
+            let address = random_p2pkh_address(network);
+
+            let mut prev_block: Option<Block> = None;
+            let mut counter = 1;
+            let mut blocks = vec![];
+            for block_idx in 0..num_blocks {
+                let block_builder = match prev_block {
+                    Some(b) => BlockBuilder::with_prev_header(b.header()),
+                    None => BlockBuilder::genesis(),
+                };
+
+                let mut coinbase = TransactionBuilder::coinbase().with_height(block_idx as u32);
+                for _ in 0..(num_outputs + block_idx) {
+                    // Values are intentionally repeated to exercise the ordering of UTXOs
+                    // with the same value.
+                    coinbase = coinbase.with_output(&address, counter % 5 + 1);
+                    counter += 1;
+                }
+
+                let block = block_builder.with_transaction(coinbase.build()).build();
+                blocks.push(block.clone());
+                prev_block = Some(block);
+            }
+
+            let mut state = State::new(2, network, blocks[0].clone());
+            for block in blocks[1..].iter() {
+                state::insert_block(&mut state, block.clone()).unwrap();
+            }
+
+            // Compute the expected UTXOs by sorting the UTXOs returned in the default order.
+            let mut expected_utxos = get_utxos_internal(&state, &address.to_string(), 0, None, None, MAX_UTXOS_PER_RESPONSE)
+                .unwrap().0
+                .utxos;
+            match sort_order {
+                UtxosSortOrder::HeightDescending => {}
+                UtxosSortOrder::HeightAscending => {
+                    expected_utxos.sort_by(|a, b| (a.height, &a.outpoint).cmp(&(b.height, &b.outpoint)))
+                }
+                UtxosSortOrder::ValueDescending => {
+                    expected_utxos.sort_by(|a, b| (b.value, &a.outpoint).cmp(&(a.value, &b.outpoint)))
+                }
+            }
+
+            // Get the UTXOs with pagination until there are no more pages returned.
+            let mut utxos_chunked = vec![];
+            let mut page = None;
+            loop {
+                let response = get_utxos_internal(
+                    &state,
+                    &address.to_string(),
+                    0,
+                    page,
+                    Some(sort_order),
+                    utxo_limit,
+                )
+                .unwrap().0;
+                assert!(response.utxos.len() <= utxo_limit);
+                utxos_chunked.extend(response.utxos);
+                if response.next_page.is_none() {
+                    break;
+                } else {
+                    page = response.next_page.map(|x| x.to_vec());
+                }
+            }
+
+            assert_eq!(expected_utxos, utxos_chunked);
+        }
+    }
+
+    #[test]
+    fn page_cannot_be_used_with_a_different_sort_order() {
+        let network = Network::Regtest;
+        let address = random_p2pkh_address(network);
+
+        let block = BlockBuilder::genesis()
+            .with_transaction(
+                TransactionBuilder::coinbase()
+                    .with_output(&address, 1000)
+                    .build(),
+            )
+            .with_transaction(
+                TransactionBuilder::coinbase()
+                    .with_output(&address, 2000)
+                    .build(),
+            )
+            .build();
+        let state = State::new(2, network, block);
+
+        let response = get_utxos_internal(
+            &state,
+            &address.to_string(),
+            0,
+            None,
+            Some(UtxosSortOrder::ValueDescending),
+            1,
+        )
+        .unwrap()
+        .0;
+        assert_eq!(response.utxos[0].value, 2000);
+
+        // The page is tied to the sort order it was created with.
+        let page = response.next_page.unwrap().to_vec();
+        assert!(matches!(
+            get_utxos_internal(
+                &state,
+                &address.to_string(),
+                0,
+                Some(page.clone()),
+                Some(UtxosSortOrder::HeightAscending),
+                1,
+            ),
+            Err(GetUtxosError::MalformedPage { .. })
+        ));
+
+        // Omitting the sort order uses the order of the page.
+        let response = get_utxos_internal(&state, &address.to_string(), 0, Some(page), None, 1)
+            .unwrap()
+            .0;
+        assert_eq!(response.utxos[0].value, 1000);
+        assert_eq!(response.next_page, None);
+    }
+
+    #[test]
+    fn cannot_sort_by_value_or_ascending_height_while_backfilling_indices() {
+        let network = Network::Regtest;
+        let mut state = State::new(2, network, genesis_block(network));
+        state.utxos.start_indices_backfill();
+        let address = random_p2pkh_address(network).to_string();
+
+        for sort_order in [
+            UtxosSortOrder::ValueDescending,
+            UtxosSortOrder::HeightAscending,
+        ] {
+            assert_eq!(
+                get_utxos_internal(&state, &address, 0, None, Some(sort_order), 1).map(|_| ()),
+                Err(GetUtxosError::SortOrderUnavailable { sort_order })
+            );
+        }
+
+        // The default order doesn't need the backfilled indices.
+        assert!(get_utxos_internal(&state, &address, 0, None, None, 1).is_ok());
+    }
+
     #[test]
     fn charges_cycles() {
         crate::init(Config {
@@ -1238,6 +1483,7 @@ mod test {
         get_utxos(GetUtxosRequest {
             address: random_p2pkh_address(Network::Regtest).to_string(),
             filter: None,
+            sort_order: None,
         })
         .unwrap();
 
@@ -1262,6 +1508,7 @@ mod test {
         get_utxos(GetUtxosRequest {
             address: random_p2pkh_address(Network::Regtest).to_string(),
             filter: None,
+            sort_order: None,
         })
         .unwrap();
 
@@ -1288,6 +1535,7 @@ mod test {
         get_utxos(GetUtxosRequest {
             address: random_p2pkh_address(Network::Regtest).to_string(),
             filter: None,
+            sort_order: None,
         })
         .unwrap();
 
@@ -1355,9 +1603,9 @@ mod test {
             }
         });
 
-        // Because the forks are of equal length, `A`, the root of the fork,
-        // is considered the tip at zero confirmations.
-        assert_tip_at_confirmations(0, chain[0].block_hash());
+        // Because the forks have the same work, the fork that was seen first
+        // is the main chain, and its tip is the tip at zero confirmations.
+        assert_tip_at_confirmations(0, chain[5].block_hash());
 
         // Extend the first fork by one block.
         let chain_6 = BlockBuilder::with_prev_header(chain[5].header()).build();
@@ -1388,6 +1636,7 @@ mod test {
             get_utxos(GetUtxosRequest {
                 address,
                 filter: Some(UtxosFilter::MinConfirmations(confirmations)),
+                sort_order: None,
             })
             .unwrap()
             .tip_block_hash,
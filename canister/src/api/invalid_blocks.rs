@@ -0,0 +1,92 @@
+use crate::{with_state, with_state_mut};
+use ic_btc_interface::BlockHash;
+
+/// Returns the hashes of the blocks that are known to be invalid, oldest first.
+pub fn get_invalid_blocks() -> Vec<BlockHash> {
+    crate::verify_controller_query("get_invalid_blocks");
+    with_state(|s| {
+        s.invalid_blocks
+            .iter()
+            .map(|block_hash| block_hash.clone().to_vec())
+            .collect()
+    })
+}
+
+/// Clears the cache of blocks that are known to be invalid.
+pub async fn clear_invalid_blocks() {
+    crate::verify_controller("clear_invalid_blocks").await;
+    clear_invalid_blocks_no_verification();
+}
+
+fn clear_invalid_blocks_no_verification() {
+    with_state_mut(|s| s.invalid_blocks.clear());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        init,
+        state::{insert_block, InsertBlockError},
+        test_utils::BlockBuilder,
+    };
+    use ic_btc_interface::{Config, Network};
+    use ic_btc_types::Block;
+    use ic_btc_validation::ValidateHeaderError;
+
+    #[test]
+    fn invalid_blocks_and_their_descendants_are_cached() {
+        let network = Network::Regtest;
+        init(Config {
+            network,
+            ..Default::default()
+        });
+
+        let genesis = crate::genesis_block(network);
+
+        // A block that doesn't have enough proof of work for the target in its header.
+        let mut block = BlockBuilder::with_prev_header(genesis.header())
+            .build()
+            .internal_bitcoin_block()
+            .clone();
+        block.header.bits = 0x1d00ffff;
+        let invalid_block = Block::new(block);
+
+        // A child of the invalid block. Its proof of work is never checked, as it is rejected
+        // for descending from a known invalid block.
+        let mut child = BlockBuilder::with_prev_header(genesis.header())
+            .build()
+            .internal_bitcoin_block()
+            .clone();
+        child.header.prev_blockhash = invalid_block.header().block_hash();
+        let child = Block::new(child);
+
+        with_state_mut(|s| {
+            assert_eq!(
+                insert_block(s, invalid_block.clone()),
+                Err(InsertBlockError::InvalidHeader(
+                    ValidateHeaderError::InvalidPoWForHeaderTarget
+                ))
+            );
+            assert_eq!(
+                insert_block(s, invalid_block.clone()),
+                Err(InsertBlockError::KnownInvalid)
+            );
+            assert_eq!(
+                insert_block(s, child.clone()),
+                Err(InsertBlockError::KnownInvalid)
+            );
+        });
+
+        assert_eq!(
+            get_invalid_blocks(),
+            vec![
+                invalid_block.block_hash().to_vec(),
+                child.block_hash().to_vec()
+            ]
+        );
+
+        clear_invalid_blocks_no_verification();
+        assert_eq!(get_invalid_blocks(), Vec::<BlockHash>::new());
+    }
+}
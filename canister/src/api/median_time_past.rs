@@ -0,0 +1,113 @@
+use crate::{validation::ValidationContext, with_state};
+use ic_btc_interface::{GetMedianTimePastError, Height};
+use ic_btc_validation::{median_time_past, HeaderStore};
+
+/// Returns the median time past of the block at the given height of the main chain,
+/// or of the tip of the main chain if no height is given.
+pub fn get_median_time_past(height: Option<Height>) -> Result<u32, GetMedianTimePastError> {
+    with_state(|s| {
+        let store = ValidationContext::new_for_main_chain(s);
+        let tip_height = store.height();
+        let height = height.unwrap_or(tip_height);
+        let header =
+            store
+                .get_with_height(height)
+                .ok_or(GetMedianTimePastError::HeightTooLarge {
+                    given: height,
+                    max: tip_height,
+                })?;
+        Ok(median_time_past(&store, &header))
+    })
+}
+
+/// Returns the height of the first block of the main chain whose median time past is at
+/// or after the given timestamp, or `None` if there is no such block.
+///
+/// Block timestamps aren't monotonic, whereas the median time past is, which is also
+/// the time that time-based locks are compared against (BIP113).
+pub fn get_height_by_timestamp(timestamp: u32) -> Option<Height> {
+    with_state(|s| {
+        let store = ValidationContext::new_for_main_chain(s);
+        let median_time_past_at = |height| {
+            let header = store
+                .get_with_height(height)
+                .expect("header of the main chain must exist");
+            median_time_past(&store, &header)
+        };
+
+        let tip_height = store.height();
+        if median_time_past_at(tip_height) < timestamp {
+            return None;
+        }
+
+        // Binary search for the first height whose median time past is at or after the
+        // timestamp. The invariant is that the answer is within `low..=high`.
+        let (mut low, mut high) = (0, tip_height);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if median_time_past_at(mid) >= timestamp {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        Some(low)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        init,
+        state::{ingest_stable_blocks_into_utxoset, insert_block},
+        test_utils::BlockChainBuilder,
+        with_state_mut,
+    };
+    use ic_btc_interface::{Config, Network};
+
+    #[test]
+    fn median_time_past_and_height_by_timestamp() {
+        let network = Network::Regtest;
+        init(Config {
+            stability_threshold: 5,
+            network,
+            ..Default::default()
+        });
+
+        // A chain where each block is 10 minutes after its parent.
+        let genesis = crate::genesis_block(network);
+        let chain = BlockChainBuilder::fork(&genesis, 30).build();
+        with_state_mut(|s| {
+            for block in chain.iter() {
+                insert_block(s, block.clone()).unwrap();
+                ingest_stable_blocks_into_utxoset(s);
+            }
+            // Some of the blocks are stable and some are not.
+            assert!(s.stable_height() > 10);
+        });
+
+        let time_at = |height: u32| genesis.header().time + height * 600;
+
+        // The median time past is the time of the block 5 blocks before, or the median
+        // of the timestamps down to genesis.
+        assert_eq!(get_median_time_past(None), Ok(time_at(25)));
+        assert_eq!(get_median_time_past(Some(30)), Ok(time_at(25)));
+        assert_eq!(get_median_time_past(Some(12)), Ok(time_at(7)));
+        assert_eq!(get_median_time_past(Some(4)), Ok(time_at(2)));
+        assert_eq!(get_median_time_past(Some(0)), Ok(time_at(0)));
+        assert_eq!(
+            get_median_time_past(Some(31)),
+            Err(GetMedianTimePastError::HeightTooLarge { given: 31, max: 30 })
+        );
+
+        assert_eq!(get_height_by_timestamp(0), Some(0));
+        assert_eq!(get_height_by_timestamp(time_at(0)), Some(0));
+        assert_eq!(get_height_by_timestamp(time_at(0) + 1), Some(1));
+        assert_eq!(get_height_by_timestamp(time_at(7)), Some(12));
+        assert_eq!(get_height_by_timestamp(time_at(7) - 1), Some(12));
+        assert_eq!(get_height_by_timestamp(time_at(25)), Some(30));
+        assert_eq!(get_height_by_timestamp(time_at(25) + 1), None);
+    }
+}
@@ -1,4 +1,12 @@
-use crate::{metrics::InstructionHistogram, state, types::HttpResponse, with_state};
+use crate::{
+    block_sources::BlockSource,
+    memory_limits::{memory_pressure, memory_usages, MemoryPressure},
+    metrics::InstructionHistogram,
+    runtime,
+    state::{self, State},
+    types::HttpResponse,
+    with_state,
+};
 use ic_btc_interface::Flag;
 use ic_cdk::api::time;
 use ic_metrics_encoder::MetricsEncoder;
@@ -88,6 +96,16 @@ fn encode_metrics(w: &mut MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
             state.unstable_blocks.blocks_difficulty_based_depth() as f64,
             "The difficulty-based depth of the unstable blocks.",
         )?;
+        w.encode_counter(
+            "num_pruned_blocks",
+            state.metrics.num_pruned_blocks as f64,
+            "The number of unstable blocks that were pruned from stale forks.",
+        )?;
+        w.encode_gauge(
+            "invalid_blocks_total",
+            state.invalid_blocks.len() as f64,
+            "The number of blocks that are known to be invalid.",
+        )?;
 
         // Memory
         w.encode_gauge(
@@ -97,10 +115,42 @@ fn encode_metrics(w: &mut MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
         )?;
         w.encode_gauge(
             "heap_size_in_bytes",
-            get_heap_size() as f64,
+            runtime::heap_size() as f64,
             "The size of the heap memory in pages.",
         )?;
 
+        // Memory limits
+        let usages = memory_usages(&state.memory_limits);
+        w.encode_gauge(
+            "memory_pressure",
+            match memory_pressure(&state.memory_limits) {
+                MemoryPressure::Normal => 0.0,
+                MemoryPressure::AboveSoftLimit => 1.0,
+                MemoryPressure::AboveHardLimit => 2.0,
+            },
+            "Whether the memory is within its limits (0), past a soft limit (1), or past a hard limit (2).",
+        )?;
+        let mut gauge = w.gauge_vec(
+            "memory_limits_in_bytes",
+            "The sizes and limits of the memories that have limits.",
+        )?;
+        for usage in usages.iter() {
+            let memory = usage.name.as_str();
+            gauge = gauge
+                .value(
+                    &[("memory", memory), ("kind", "size")],
+                    usage.size_bytes as f64,
+                )?
+                .value(
+                    &[("memory", memory), ("kind", "soft_limit")],
+                    usage.limit.soft_limit_bytes as f64,
+                )?
+                .value(
+                    &[("memory", memory), ("kind", "hard_limit")],
+                    usage.limit.hard_limit_bytes as f64,
+                )?;
+        }
+
         // Errors
         w.encode_counter(
             "num_get_successors_rejects",
@@ -118,6 +168,43 @@ fn encode_metrics(w: &mut MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
             "The number of errors occurred when inserting a block.",
         )?;
 
+        // Block sources
+        encode_block_sources_gauge(
+            w,
+            "blocks_source_calls",
+            "The number of calls made to each source of blocks.",
+            state,
+            |source| source.num_calls,
+        )?;
+        encode_block_sources_gauge(
+            w,
+            "blocks_source_rejects",
+            "The number of rejects received from each source of blocks.",
+            state,
+            |source| source.num_rejects,
+        )?;
+        encode_block_sources_gauge(
+            w,
+            "blocks_source_consecutive_rejects",
+            "The number of rejects received from each source of blocks since its last successful call.",
+            state,
+            |source| source.consecutive_rejects as u64,
+        )?;
+        encode_block_sources_gauge(
+            w,
+            "blocks_source_latency_ms_sum",
+            "The sum of the latencies of the calls made to each source of blocks, in milliseconds.",
+            state,
+            |source| source.latency_ms_sum,
+        )?;
+        encode_block_sources_gauge(
+            w,
+            "blocks_source_last_latency_ms",
+            "The latency of the last call made to each source of blocks, in milliseconds.",
+            state,
+            |source| source.last_latency_ms,
+        )?;
+
         // Profiling
         encode_instruction_histogram(w, &state.metrics.get_utxos_total)?;
         encode_instruction_histogram(w, &state.metrics.get_utxos_apply_unstable_blocks)?;
@@ -126,6 +213,7 @@ fn encode_metrics(w: &mut MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
         encode_instruction_histogram(w, &state.metrics.get_balance_apply_unstable_blocks)?;
         encode_instruction_histogram(w, &state.metrics.get_current_fee_percentiles_total)?;
         encode_instruction_histogram(w, &state.metrics.block_insertion)?;
+        encode_instruction_histogram(w, &state.metrics.response_processing)?;
 
         w.encode_gauge(
             "send_transaction_count",
@@ -163,6 +251,47 @@ fn encode_metrics(w: &mut MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
         .value(&[("flag", "enabled")], enabled)?
         .value(&[("flag", "disabled")], disabled)?;
 
+        // UTXO set audit
+        w.encode_counter(
+            "utxo_audit_rounds",
+            state.utxo_auditor.num_rounds as f64,
+            "The number of complete rounds of the UTXO set audit.",
+        )?;
+        if let Some(report) = &state.utxo_auditor.last_report {
+            w.gauge_vec(
+                "utxo_audit_mismatches",
+                "The mismatches found in the last complete round of the UTXO set audit.",
+            )?
+            .value(
+                &[("kind", "utxos_missing_from_address_index")],
+                report.utxos_missing_from_address_index as f64,
+            )?
+            .value(
+                &[("kind", "utxos_missing_from_value_index")],
+                report.utxos_missing_from_value_index as f64,
+            )?
+            .value(
+                &[("kind", "utxos_missing_from_height_ascending_index")],
+                report.utxos_missing_from_height_ascending_index as f64,
+            )?
+            .value(
+                &[("kind", "stale_address_index_entries")],
+                report.stale_address_index_entries as f64,
+            )?
+            .value(
+                &[("kind", "stale_value_index_entries")],
+                report.stale_value_index_entries as f64,
+            )?
+            .value(
+                &[("kind", "stale_height_ascending_index_entries")],
+                report.stale_height_ascending_index_entries as f64,
+            )?
+            .value(
+                &[("kind", "balance_mismatches")],
+                report.balance_mismatches as f64,
+            )?;
+        }
+
         Ok(())
     })
 }
@@ -174,30 +303,34 @@ fn encode_instruction_histogram(
     metrics_encoder.encode_histogram(&h.name, h.buckets(), h.sum, &h.help)
 }
 
-fn encode_labeled_gauge(
+fn encode_block_sources_gauge(
     metrics_encoder: &mut MetricsEncoder<Vec<u8>>,
     name: &str,
     help: &str,
-    labels_and_values: &[((&str, &str), u64)],
+    state: &State,
+    value: impl Fn(&BlockSource) -> u64,
 ) -> io::Result<()> {
     let mut gauge = metrics_encoder.gauge_vec(name, help)?;
 
-    for (label, value) in labels_and_values {
-        gauge = gauge.value(&[*label], *value as f64)?;
+    for source in state.blocks_sources.iter() {
+        let principal = source.principal.to_text();
+        gauge = gauge.value(&[("source", principal.as_str())], value(source) as f64)?;
     }
 
     Ok(())
 }
 
-// Returns the size of the heap in pages.
-fn get_heap_size() -> u64 {
-    #[cfg(target_arch = "wasm32")]
-    {
-        core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
-    }
+fn encode_labeled_gauge(
+    metrics_encoder: &mut MetricsEncoder<Vec<u8>>,
+    name: &str,
+    help: &str,
+    labels_and_values: &[((&str, &str), u64)],
+) -> io::Result<()> {
+    let mut gauge = metrics_encoder.gauge_vec(name, help)?;
 
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        0
+    for (label, value) in labels_and_values {
+        gauge = gauge.value(&[*label], *value as f64)?;
     }
+
+    Ok(())
 }
@@ -27,7 +27,7 @@ pub async fn send_transaction(request: SendTransactionRequest) -> Result<(), Sen
 
     // Use the internal endpoint to send the transaction to the bitcoin network.
     runtime::call_send_transaction_internal(
-        with_state(|s| s.blocks_source),
+        with_state(|s| s.blocks_sources.primary()),
         SendTransactionInternalRequest {
             network: request.network.into(),
             transaction: request.transaction,
@@ -1,4 +1,4 @@
-use ic_btc_interface::SetConfigRequest;
+use ic_btc_interface::{Flag, SetConfigRequest};
 use std::convert::TryInto;
 
 pub async fn set_config(request: SetConfigRequest) {
@@ -6,7 +6,7 @@ pub async fn set_config(request: SetConfigRequest) {
         // The watchdog canister can only set the API access flag.
         set_api_access(request);
     } else {
-        verify_caller().await;
+        crate::verify_controller("set_config").await;
         set_config_no_verification(request);
     }
 }
@@ -35,6 +35,10 @@ fn set_config_no_verification(request: SetConfigRequest) {
     crate::with_state_mut(|s| {
         if let Some(syncing) = request.syncing {
             s.syncing_state.syncing = syncing;
+            if syncing == Flag::Enabled {
+                // A snapshot of stable memory that was taken is no longer being downloaded.
+                super::end_snapshot();
+            }
         }
 
         if let Some(fees) = request.fees {
@@ -58,29 +62,24 @@ fn set_config_no_verification(request: SetConfigRequest) {
         if let Some(watchdog_canister) = request.watchdog_canister {
             s.watchdog_canister = watchdog_canister;
         }
-    });
-}
-
-async fn verify_caller() {
-    #[cfg(target_arch = "wasm32")]
-    {
-        use ic_cdk::api::management_canister::main::CanisterIdRecord;
-
-        let caller = ic_cdk::caller();
-        let controllers =
-            ic_cdk::api::management_canister::main::canister_status(CanisterIdRecord {
-                canister_id: ic_cdk::api::id(),
-            })
-            .await
-            .unwrap()
-            .0
-            .settings
-            .controllers;
-
-        if !controllers.contains(&caller) {
-            panic!("Only controllers can call set_config");
+        if let Some(checkpoints) = request.checkpoints {
+            s.checkpoints = checkpoints.into_iter().map(Into::into).collect();
         }
-    }
+        if let Some(utxo_audit) = request.utxo_audit {
+            s.utxo_auditor.flag = utxo_audit;
+        }
+        if let Some(sync_intervals) = request.sync_intervals {
+            crate::verify_sync_intervals(&sync_intervals);
+            s.syncing_state.sync_intervals = sync_intervals;
+        }
+        if let Some(fork_pruning) = request.fork_pruning {
+            s.unstable_blocks.set_fork_pruning(fork_pruning);
+        }
+        if let Some(memory_limits) = request.memory_limits {
+            crate::verify_memory_limits(&memory_limits);
+            s.memory_limits = memory_limits;
+        }
+    });
 }
 
 #[cfg(test)]
@@ -88,7 +87,9 @@ mod test {
     use super::*;
     use crate::{init, with_state};
     use candid::Principal;
-    use ic_btc_interface::{Config, Fees, Flag};
+    use ic_btc_interface::{
+        Checkpoint, Config, Fees, Flag, ForkPruning, MemoryLimit, MemoryLimits, SyncIntervals,
+    };
     use proptest::prelude::*;
 
     #[test]
@@ -226,6 +227,146 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_set_utxo_audit() {
+        init(Config::default());
+        assert_eq!(with_state(|s| s.utxo_auditor.flag), Flag::Disabled);
+
+        for flag in &[Flag::Enabled, Flag::Disabled] {
+            set_config_no_verification(SetConfigRequest {
+                utxo_audit: Some(*flag),
+                ..Default::default()
+            });
+
+            assert_eq!(with_state(|s| s.utxo_auditor.flag), *flag);
+        }
+    }
+
+    #[test]
+    fn test_set_sync_intervals() {
+        init(Config::default());
+        assert_eq!(
+            with_state(|s| s.syncing_state.sync_intervals),
+            SyncIntervals::default()
+        );
+
+        let sync_intervals = SyncIntervals {
+            catching_up_ms: 10,
+            idle_min_ms: 1_000,
+            idle_max_ms: 60_000,
+        };
+        set_config_no_verification(SetConfigRequest {
+            sync_intervals: Some(sync_intervals),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            with_state(|s| s.syncing_state.sync_intervals),
+            sync_intervals
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "idle_min_ms must not be greater than idle_max_ms.")]
+    fn test_set_sync_intervals_with_invalid_backoff() {
+        init(Config::default());
+        set_config_no_verification(SetConfigRequest {
+            sync_intervals: Some(SyncIntervals {
+                catching_up_ms: 0,
+                idle_min_ms: 2_000,
+                idle_max_ms: 1_000,
+            }),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_set_memory_limits() {
+        init(Config::default());
+        assert_eq!(
+            with_state(|s| s.memory_limits.clone()),
+            MemoryLimits::default()
+        );
+
+        let memory_limits = MemoryLimits {
+            heap: Some(MemoryLimit {
+                soft_limit_bytes: 1_000,
+                hard_limit_bytes: 2_000,
+            }),
+            stable_memories: vec![(
+                1,
+                MemoryLimit {
+                    soft_limit_bytes: 3_000,
+                    hard_limit_bytes: 3_000,
+                },
+            )],
+        };
+        set_config_no_verification(SetConfigRequest {
+            memory_limits: Some(memory_limits.clone()),
+            ..Default::default()
+        });
+
+        assert_eq!(with_state(|s| s.memory_limits.clone()), memory_limits);
+        assert_eq!(crate::get_config().memory_limits, Some(memory_limits));
+    }
+
+    #[test]
+    #[should_panic(expected = "soft_limit_bytes must not be greater than hard_limit_bytes.")]
+    fn test_set_memory_limits_with_soft_limit_above_hard_limit() {
+        init(Config::default());
+        set_config_no_verification(SetConfigRequest {
+            memory_limits: Some(MemoryLimits {
+                heap: Some(MemoryLimit {
+                    soft_limit_bytes: 2_000,
+                    hard_limit_bytes: 1_000,
+                }),
+                stable_memories: vec![],
+            }),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown memory id 200.")]
+    fn test_set_memory_limits_with_unknown_memory_id() {
+        init(Config::default());
+        set_config_no_verification(SetConfigRequest {
+            memory_limits: Some(MemoryLimits {
+                heap: None,
+                stable_memories: vec![(
+                    200,
+                    MemoryLimit {
+                        soft_limit_bytes: 1_000,
+                        hard_limit_bytes: 2_000,
+                    },
+                )],
+            }),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_set_fork_pruning() {
+        init(Config::default());
+        assert_eq!(crate::get_config().fork_pruning, None);
+
+        let fork_pruning = ForkPruning {
+            max_work_deficit: 6,
+            max_blocks_without_extension: 100,
+        };
+        for fork_pruning in [Some(fork_pruning), None] {
+            set_config_no_verification(SetConfigRequest {
+                fork_pruning: Some(fork_pruning),
+                ..Default::default()
+            });
+
+            assert_eq!(
+                with_state(|s| s.unstable_blocks.fork_pruning()),
+                fork_pruning
+            );
+        }
+    }
+
     #[test]
     fn test_set_watchdog_canister() {
         init(Config::default());
@@ -244,4 +385,41 @@ mod test {
             assert_eq!(with_state(|s| s.watchdog_canister), watchdog_canister);
         }
     }
+
+    #[test]
+    fn test_set_checkpoints() {
+        init(Config::default());
+        assert_eq!(crate::get_config().checkpoints, Some(vec![]));
+
+        let checkpoints = vec![
+            Checkpoint {
+                height: 10,
+                block_hash: vec![1; 32],
+            },
+            Checkpoint {
+                height: 20,
+                block_hash: vec![2; 32],
+            },
+        ];
+        set_config_no_verification(SetConfigRequest {
+            checkpoints: Some(checkpoints.clone()),
+            ..Default::default()
+        });
+
+        assert_eq!(crate::get_config().checkpoints, Some(checkpoints));
+    }
+
+    #[test]
+    #[should_panic(expected = "BlockHash must 32 bytes")]
+    fn test_set_checkpoints_with_invalid_block_hash() {
+        init(Config::default());
+
+        set_config_no_verification(SetConfigRequest {
+            checkpoints: Some(vec![Checkpoint {
+                height: 10,
+                block_hash: vec![1; 31],
+            }]),
+            ..Default::default()
+        });
+    }
 }
@@ -0,0 +1,288 @@
+//! Endpoints for exporting a snapshot of the canister's stable memory.
+//!
+//! A snapshot is taken by disabling syncing and calling `take_stable_memory_snapshot`, which
+//! writes the heap state into the upgrades memory. The memories can then be downloaded in
+//! chunks and restored into another canister, which loads them in `post_upgrade`.
+//!
+//! The stable memory must not change while it's downloaded, so the heartbeat is paused from
+//! when the snapshot is taken until syncing is enabled again.
+use crate::{
+    heartbeat::has_pending_work,
+    memory::{get_memory_by_id, NUM_MEMORIES},
+    upgrade, with_state, State,
+};
+use bitcoin::hashes::{sha256, Hash};
+use ic_btc_interface::{
+    Flag, GetStableMemoryChunkError, GetStableMemoryChunkRequest, StableMemoryChunk,
+    StableMemoryInfo, StableMemoryManifest,
+};
+use ic_stable_structures::Memory;
+use std::cell::Cell;
+
+const WASM_PAGE_SIZE: u64 = 65536;
+
+/// The size of the chunks that memories are exported in.
+///
+/// Chunks are hashed in a query, so they must be small enough to be hashed well within the
+/// instruction limit of a query, and to fit in its response.
+const CHUNK_SIZE: u64 = 16 * WASM_PAGE_SIZE;
+
+thread_local! {
+    // Whether a snapshot was taken since syncing was last enabled.
+    static IS_SNAPSHOT_PENDING: Cell<bool> = Cell::new(false);
+}
+
+/// Writes the heap state into the upgrades memory, so that stable memory contains the
+/// complete state of the canister.
+pub async fn take_stable_memory_snapshot() {
+    crate::verify_controller("take_stable_memory_snapshot").await;
+    take_stable_memory_snapshot_no_verification()
+}
+
+/// Returns true if a snapshot was taken and may still be downloaded, in which case the
+/// heartbeat must not change the stable memory.
+pub fn is_snapshot_pending() -> bool {
+    IS_SNAPSHOT_PENDING.with(Cell::get)
+}
+
+/// Marks the snapshot, if any, as done, which resumes the heartbeat.
+pub fn end_snapshot() {
+    IS_SNAPSHOT_PENDING.with(|pending| pending.set(false));
+}
+
+/// Returns the memories that a snapshot of stable memory consists of.
+pub fn get_stable_memory_manifest() -> StableMemoryManifest {
+    crate::verify_controller_query("get_stable_memory_manifest");
+    StableMemoryManifest {
+        chunk_size: CHUNK_SIZE,
+        memories: (0..NUM_MEMORIES)
+            .map(|memory_id| {
+                let size = memory_size(memory_id);
+                StableMemoryInfo {
+                    memory_id,
+                    size,
+                    num_chunks: num_chunks(size),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Returns a chunk of the given memory along with its hash.
+pub fn get_stable_memory_chunk(
+    request: GetStableMemoryChunkRequest,
+) -> Result<StableMemoryChunk, GetStableMemoryChunkError> {
+    crate::verify_controller_query("get_stable_memory_chunk");
+    let memory =
+        get_memory_by_id(request.memory_id).ok_or(GetStableMemoryChunkError::UnknownMemory)?;
+
+    let size = memory_size(request.memory_id);
+    if request.chunk_index >= num_chunks(size) {
+        return Err(GetStableMemoryChunkError::ChunkIndexOutOfBounds {
+            given: request.chunk_index,
+            num_chunks: num_chunks(size),
+        });
+    }
+
+    let offset = request.chunk_index * CHUNK_SIZE;
+    let mut bytes = vec![0; std::cmp::min(CHUNK_SIZE, size - offset) as usize];
+    memory.read(offset, &mut bytes);
+
+    Ok(StableMemoryChunk {
+        sha256: sha256::Hash::hash(&bytes).into_inner().to_vec(),
+        bytes,
+    })
+}
+
+fn take_stable_memory_snapshot_no_verification() {
+    // New blocks would otherwise keep being fetched while the snapshot is downloaded.
+    assert!(
+        with_state(|s| s.syncing_state.syncing == Flag::Disabled),
+        "Syncing must be disabled to take a snapshot of stable memory."
+    );
+
+    // The work that's already underway writes to the stable memories, and would leave them
+    // inconsistent with the heap state of the snapshot if it's done after the snapshot.
+    assert!(
+        with_state(is_idle),
+        "The canister must be idle to take a snapshot of stable memory. \
+        Wait for the pending blocks, responses and backfills to be processed."
+    );
+
+    with_state(upgrade::write_state);
+    IS_SNAPSHOT_PENDING.with(|pending| pending.set(true));
+}
+
+// Returns true if there's no work underway that writes to stable memory.
+fn is_idle(state: &State) -> bool {
+    !has_pending_work(state)
+        && !state.syncing_state.is_fetching_blocks
+        && state.syncing_state.response_to_process.is_none()
+        && state.syncing_state.pipelined_response.is_none()
+        && !state.utxos.is_backfilling_indices()
+        && !state.stable_block_headers.is_backfilling_heights()
+}
+
+// Returns the size of the memory with the given id, in bytes.
+fn memory_size(memory_id: u8) -> u64 {
+    get_memory_by_id(memory_id)
+        .expect("memory must exist")
+        .size()
+        * WASM_PAGE_SIZE
+}
+
+fn num_chunks(size: u64) -> u64 {
+    (size + CHUNK_SIZE - 1) / CHUNK_SIZE
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::set_config,
+        heartbeat::{heartbeat, SyncOutcome},
+        init,
+        memory::set_memory,
+        post_upgrade,
+        state::{ingest_stable_blocks_into_utxoset, insert_block},
+        test_utils::{BlockBuilder, BlockChainBuilder},
+        unstable_blocks, with_state_mut, STATE,
+    };
+    use ic_btc_interface::{Config, Network, SetConfigRequest};
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    fn init_state() {
+        let network = Network::Regtest;
+        init(Config {
+            stability_threshold: 2,
+            network,
+            ..Default::default()
+        });
+
+        let chain = BlockChainBuilder::fork(&crate::genesis_block(network), 5).build();
+        with_state_mut(|s| {
+            for block in chain.iter() {
+                insert_block(s, block.clone()).unwrap();
+                ingest_stable_blocks_into_utxoset(s);
+            }
+            s.syncing_state.syncing = Flag::Disabled;
+        });
+    }
+
+    // Downloads all the memories, verifying the hash of each chunk.
+    fn download_memories() -> Vec<Vec<u8>> {
+        let manifest = get_stable_memory_manifest();
+        manifest
+            .memories
+            .iter()
+            .map(|info| {
+                let mut bytes = vec![];
+                for chunk_index in 0..info.num_chunks {
+                    let chunk = get_stable_memory_chunk(GetStableMemoryChunkRequest {
+                        memory_id: info.memory_id,
+                        chunk_index,
+                    })
+                    .unwrap();
+                    assert_eq!(
+                        sha256::Hash::hash(&chunk.bytes).into_inner().to_vec(),
+                        chunk.sha256
+                    );
+                    bytes.extend(chunk.bytes);
+                }
+                assert_eq!(bytes.len() as u64, info.size);
+                bytes
+            })
+            .collect()
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        init_state();
+        take_stable_memory_snapshot_no_verification();
+        let memories = download_memories();
+        assert_eq!(memories.len(), NUM_MEMORIES as usize);
+
+        // Restore the memories into an empty stable memory and load the state from it.
+        let old_state = STATE.with(|cell| cell.take().unwrap());
+        set_memory(DefaultMemoryImpl::default());
+        for (memory_id, bytes) in memories.iter().enumerate() {
+            crate::memory::write(&get_memory_by_id(memory_id as u8).unwrap(), 0, bytes);
+        }
+        post_upgrade();
+
+        with_state(|new_state| assert!(new_state == &old_state));
+    }
+
+    #[test]
+    #[should_panic(expected = "Syncing must be disabled to take a snapshot of stable memory.")]
+    fn snapshot_requires_syncing_to_be_disabled() {
+        init_state();
+        with_state_mut(|s| s.syncing_state.syncing = Flag::Enabled);
+        take_stable_memory_snapshot_no_verification();
+    }
+
+    #[test]
+    #[should_panic(expected = "The canister must be idle to take a snapshot of stable memory.")]
+    fn snapshot_requires_the_canister_to_be_idle() {
+        init_state();
+
+        // A block that makes another block stable, which is yet to be ingested.
+        let tip = with_state(|s| {
+            unstable_blocks::get_main_chain(&s.unstable_blocks)
+                .tip()
+                .clone()
+        });
+        let block = BlockBuilder::with_prev_header(tip.header()).build();
+        with_state_mut(|s| insert_block(s, block).unwrap());
+
+        take_stable_memory_snapshot_no_verification();
+    }
+
+    #[async_std::test]
+    async fn heartbeat_is_paused_while_the_snapshot_is_pending() {
+        init_state();
+        take_stable_memory_snapshot_no_verification();
+        let memories = download_memories();
+
+        // Lowering the stability threshold makes the unstable blocks stable, but they're not
+        // ingested while the snapshot is pending.
+        with_state_mut(|s| s.unstable_blocks.set_stability_threshold(0));
+        assert_eq!(heartbeat().await, SyncOutcome::Idle);
+        assert_eq!(download_memories(), memories);
+
+        // Enabling syncing ends the snapshot, after which the blocks are ingested.
+        let next_height = with_state(|s| s.utxos.next_height());
+        set_config(SetConfigRequest {
+            syncing: Some(Flag::Enabled),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(heartbeat().await, SyncOutcome::IngestedStableBlocks);
+        assert!(with_state(|s| s.utxos.next_height()) > next_height);
+    }
+
+    #[test]
+    fn get_chunk_errors() {
+        init_state();
+
+        assert_eq!(
+            get_stable_memory_chunk(GetStableMemoryChunkRequest {
+                memory_id: NUM_MEMORIES,
+                chunk_index: 0,
+            }),
+            Err(GetStableMemoryChunkError::UnknownMemory)
+        );
+
+        let num_chunks = num_chunks(memory_size(0));
+        assert_eq!(
+            get_stable_memory_chunk(GetStableMemoryChunkRequest {
+                memory_id: 0,
+                chunk_index: num_chunks,
+            }),
+            Err(GetStableMemoryChunkError::ChunkIndexOutOfBounds {
+                given: num_chunks,
+                num_chunks
+            })
+        );
+    }
+}
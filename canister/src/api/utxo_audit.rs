@@ -0,0 +1,7 @@
+use crate::with_state;
+use ic_btc_interface::UtxoAuditReport;
+
+/// Returns the report of the last complete round of the UTXO set audit, if any.
+pub fn get_utxo_audit_report() -> Option<UtxoAuditReport> {
+    with_state(|s| s.utxo_auditor.last_report.clone())
+}
@@ -1,4 +1,7 @@
-use crate::{memory::Memory, types::BlockHeaderBlob};
+use crate::{
+    memory::Memory,
+    types::{BlockHeaderBlob, Slicing},
+};
 use bitcoin::consensus::{Decodable, Encodable};
 use bitcoin::BlockHeader;
 use ic_btc_interface::Height;
@@ -18,6 +21,19 @@ pub struct BlockHeaderStore {
     // NOTE: Stable structures don't need to be serialized.
     #[serde(skip, default = "init_block_heights")]
     pub block_heights: StableBTreeMap<Height, BlockHash, Memory>,
+
+    /// A map of a block hash to its corresponding block height.
+    // NOTE: Stable structures don't need to be serialized.
+    #[serde(skip, default = "init_block_hash_to_height")]
+    pub block_hash_to_height: StableBTreeMap<BlockHash, Height, Memory>,
+
+    /// The height of the next block to backfill into `block_hash_to_height`, if a backfill
+    /// is in progress.
+    ///
+    /// `block_hash_to_height` only has the blocks that were inserted since it was introduced,
+    /// so it's backfilled from `block_heights` after an upgrade.
+    #[serde(default)]
+    backfill_height: Option<Height>,
 }
 
 // NOTE: `PartialEq` is only available in tests as it would be impractically
@@ -28,6 +44,8 @@ impl PartialEq for BlockHeaderStore {
         use crate::test_utils::is_stable_btreemap_equal;
         is_stable_btreemap_equal(&self.block_headers, &other.block_headers)
             && is_stable_btreemap_equal(&self.block_heights, &other.block_heights)
+            && is_stable_btreemap_equal(&self.block_hash_to_height, &other.block_hash_to_height)
+            && self.backfill_height == other.backfill_height
     }
 }
 
@@ -36,6 +54,8 @@ impl BlockHeaderStore {
         Self {
             block_headers: init_block_headers(),
             block_heights: init_block_heights(),
+            block_hash_to_height: init_block_hash_to_height(),
+            backfill_height: None,
         }
     }
 
@@ -54,7 +74,8 @@ impl BlockHeaderStore {
     /// Inserts a block's header and hash into the store.
     pub fn insert(&mut self, block_hash: BlockHash, header_blob: BlockHeaderBlob, height: Height) {
         self.block_headers.insert(block_hash.clone(), header_blob);
-        self.block_heights.insert(height, block_hash);
+        self.block_heights.insert(height, block_hash.clone());
+        self.block_hash_to_height.insert(block_hash, height);
     }
 
     pub fn get_with_block_hash(&self, block_hash: &BlockHash) -> Option<BlockHeader> {
@@ -71,6 +92,51 @@ impl BlockHeaderStore {
                 .expect("block header must exist")
         })
     }
+
+    /// Returns the height of the block with the given hash, if it's in the store.
+    pub fn get_height(&self, block_hash: &BlockHash) -> Option<Height> {
+        let height = self.block_hash_to_height.get(block_hash);
+        assert!(
+            height.is_some() || !self.is_backfilling_heights(),
+            "The height of block {:?} cannot be looked up until the heights are backfilled.",
+            block_hash
+        );
+        height
+    }
+
+    /// Starts backfilling `block_hash_to_height` from the lowest height.
+    pub fn start_heights_backfill(&mut self) {
+        self.backfill_height = Some(0);
+    }
+
+    /// Returns true if `block_hash_to_height` is being backfilled.
+    pub fn is_backfilling_heights(&self) -> bool {
+        self.backfill_height.is_some()
+    }
+
+    /// Backfills `block_hash_to_height` until `should_pause` returns true, resuming from where
+    /// the previous call left off.
+    ///
+    /// Returns `Slicing::Done` once all the blocks are backfilled, or if there's no backfill
+    /// in progress.
+    pub fn backfill_heights(&mut self, should_pause: &mut dyn FnMut() -> bool) -> Slicing<(), ()> {
+        let start_height = match self.backfill_height {
+            Some(height) => height,
+            None => return Slicing::Done(()),
+        };
+
+        for (height, block_hash) in self.block_heights.range(start_height..) {
+            if should_pause() {
+                self.backfill_height = Some(height);
+                return Slicing::Paused(());
+            }
+
+            self.block_hash_to_height.insert(block_hash, height);
+        }
+
+        self.backfill_height = None;
+        Slicing::Done(())
+    }
 }
 
 fn deserialize_block_header(block_header_blob: BlockHeaderBlob) -> BlockHeader {
@@ -85,3 +151,65 @@ fn init_block_headers() -> StableBTreeMap<BlockHash, BlockHeaderBlob, Memory> {
 fn init_block_heights() -> StableBTreeMap<u32, BlockHash, Memory> {
     StableBTreeMap::init(crate::memory::get_block_heights_memory())
 }
+
+fn init_block_hash_to_height() -> StableBTreeMap<BlockHash, Height, Memory> {
+    StableBTreeMap::init(crate::memory::get_block_hash_to_height_memory())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{genesis_block, test_utils::BlockChainBuilder};
+    use ic_btc_interface::Network;
+
+    // Builds a store with a chain of blocks, whose heights aren't indexed by hash, as in a
+    // store that was upgraded from a version without `block_hash_to_height`.
+    fn build_store_without_heights() -> (BlockHeaderStore, Vec<Block>) {
+        let mut store = BlockHeaderStore::init();
+        let genesis = genesis_block(Network::Regtest);
+        let mut chain = vec![genesis.clone()];
+        chain.extend(BlockChainBuilder::fork(&genesis, 4).build());
+        for (height, block) in chain.iter().enumerate() {
+            store.insert_block(block, height as Height);
+            store.block_hash_to_height.remove(&block.block_hash());
+        }
+        (store, chain)
+    }
+
+    #[test]
+    fn backfills_heights() {
+        let (mut store, chain) = build_store_without_heights();
+
+        // Pause the backfill after every block.
+        store.start_heights_backfill();
+        let mut num_runs = 0;
+        loop {
+            let mut calls = 0;
+            num_runs += 1;
+            let result = store.backfill_heights(&mut || {
+                calls += 1;
+                calls > 1
+            });
+            if result == Slicing::Done(()) {
+                break;
+            }
+        }
+
+        assert_eq!(num_runs, chain.len());
+        assert!(!store.is_backfilling_heights());
+        for (height, block) in chain.iter().enumerate() {
+            assert_eq!(
+                store.get_height(&block.block_hash()),
+                Some(height as Height)
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be looked up until the heights are backfilled")]
+    fn get_height_panics_while_backfilling_heights() {
+        let (mut store, chain) = build_store_without_heights();
+        store.start_heights_backfill();
+        store.get_height(&chain[1].block_hash());
+    }
+}
@@ -0,0 +1,268 @@
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+
+/// The time, in seconds, that a source backs off for after its first reject.
+const INITIAL_BACKOFF_SECS: u64 = 1;
+
+/// The maximum time, in seconds, that a source backs off for.
+const MAX_BACKOFF_SECS: u64 = 60;
+
+/// The ordered list of the canisters from which blocks are retrieved, along with their health.
+///
+/// Blocks are retrieved from the active source until it rejects a request. The source then
+/// backs off for an interval that doubles with every consecutive reject, and the requests
+/// rotate to the next source in the list that isn't backing off.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlockSources {
+    sources: Vec<BlockSource>,
+
+    // The index of the source that requests are sent to.
+    #[serde(default)]
+    active: usize,
+}
+
+/// A canister from which blocks are retrieved.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlockSource {
+    pub principal: Principal,
+
+    /// The number of rejects received since the last successful call.
+    #[serde(default)]
+    pub consecutive_rejects: u32,
+
+    /// The time, in seconds, until which no requests are sent to the source.
+    #[serde(default)]
+    pub backoff_until: u64,
+
+    /// The number of calls made to the source, including the rejected ones.
+    #[serde(default)]
+    pub num_calls: u64,
+
+    /// The number of rejects received from the source.
+    #[serde(default)]
+    pub num_rejects: u64,
+
+    /// The sum of the latencies of the calls made to the source, in milliseconds.
+    #[serde(default)]
+    pub latency_ms_sum: u64,
+
+    /// The latency of the last call made to the source, in milliseconds.
+    #[serde(default)]
+    pub last_latency_ms: u64,
+}
+
+impl BlockSource {
+    fn new(principal: Principal) -> Self {
+        Self {
+            principal,
+            consecutive_rejects: 0,
+            backoff_until: 0,
+            num_calls: 0,
+            num_rejects: 0,
+            latency_ms_sum: 0,
+            last_latency_ms: 0,
+        }
+    }
+
+    fn is_backing_off(&self, now: u64) -> bool {
+        now < self.backoff_until
+    }
+}
+
+impl BlockSources {
+    /// Creates the list of sources from a primary source and the sources to fall back to,
+    /// in order.
+    pub fn new(primary: Principal, fallbacks: Vec<Principal>) -> Self {
+        let mut sources: Vec<BlockSource> = vec![BlockSource::new(primary)];
+        for principal in fallbacks {
+            assert!(
+                sources.iter().all(|source| source.principal != principal),
+                "Block source {} is specified more than once.",
+                principal
+            );
+            sources.push(BlockSource::new(principal));
+        }
+
+        Self { sources, active: 0 }
+    }
+
+    /// Returns the first source in the list.
+    pub fn primary(&self) -> Principal {
+        self.sources[0].principal
+    }
+
+    /// Returns the sources that follow the primary source, in order.
+    pub fn fallbacks(&self) -> Vec<Principal> {
+        self.sources[1..]
+            .iter()
+            .map(|source| source.principal)
+            .collect()
+    }
+
+    /// Returns an iterator over the sources, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &BlockSource> {
+        self.sources.iter()
+    }
+
+    /// Returns the source to send a request to at the given time, in seconds, or None if all
+    /// the sources are backing off.
+    ///
+    /// If the active source is backing off, the next source that isn't becomes active.
+    pub fn select(&mut self, now: u64) -> Option<Principal> {
+        let num_sources = self.sources.len();
+        let active = (0..num_sources)
+            .map(|offset| (self.active + offset) % num_sources)
+            .find(|idx| !self.sources[*idx].is_backing_off(now))?;
+
+        self.active = active;
+        Some(self.sources[active].principal)
+    }
+
+    /// Records a successful call to the given source.
+    pub fn record_success(&mut self, principal: Principal, latency_ms: u64) {
+        if let Some(source) = self.record_call(principal, latency_ms) {
+            source.consecutive_rejects = 0;
+        }
+    }
+
+    /// Records a call to the given source that was rejected at the given time, in seconds.
+    ///
+    /// The source backs off and, if it's the active source, requests rotate to the next one.
+    pub fn record_reject(&mut self, principal: Principal, now: u64, latency_ms: u64) {
+        let source = match self.record_call(principal, latency_ms) {
+            Some(source) => source,
+            None => return,
+        };
+        source.num_rejects += 1;
+        source.consecutive_rejects += 1;
+
+        let backoff_secs = INITIAL_BACKOFF_SECS
+            .checked_shl(source.consecutive_rejects - 1)
+            .unwrap_or(MAX_BACKOFF_SECS);
+        source.backoff_until = now + min(backoff_secs, MAX_BACKOFF_SECS);
+
+        if self.sources[self.active].principal == principal {
+            self.active = (self.active + 1) % self.sources.len();
+        }
+    }
+
+    // Updates the call stats of the given source and returns it, if it's in the list.
+    fn record_call(&mut self, principal: Principal, latency_ms: u64) -> Option<&mut BlockSource> {
+        // The list of sources may have been changed since the call was made.
+        let source = self
+            .sources
+            .iter_mut()
+            .find(|source| source.principal == principal)?;
+        source.num_calls += 1;
+        source.latency_ms_sum += latency_ms;
+        source.last_latency_ms = latency_ms;
+        Some(source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    #[test]
+    fn sends_requests_to_the_primary_source() {
+        let mut sources = BlockSources::new(principal(0), vec![principal(1)]);
+        assert_eq!(sources.primary(), principal(0));
+        assert_eq!(sources.fallbacks(), vec![principal(1)]);
+
+        assert_eq!(sources.select(0), Some(principal(0)));
+        sources.record_success(principal(0), 100);
+        assert_eq!(sources.select(0), Some(principal(0)));
+    }
+
+    #[test]
+    fn rotates_to_the_next_source_on_reject() {
+        let mut sources = BlockSources::new(principal(0), vec![principal(1), principal(2)]);
+
+        sources.record_reject(principal(0), 0, 10);
+        assert_eq!(sources.select(0), Some(principal(1)));
+
+        // The active source stays active once the previous one has recovered.
+        assert_eq!(sources.select(100), Some(principal(1)));
+
+        sources.record_reject(principal(1), 100, 10);
+        assert_eq!(sources.select(100), Some(principal(2)));
+
+        // Rotation wraps around to the start of the list.
+        sources.record_reject(principal(2), 100, 10);
+        assert_eq!(sources.select(100), Some(principal(0)));
+    }
+
+    #[test]
+    fn skips_sources_that_are_backing_off() {
+        let mut sources = BlockSources::new(principal(0), vec![principal(1), principal(2)]);
+
+        sources.record_reject(principal(1), 0, 10);
+        sources.record_reject(principal(0), 0, 10);
+        assert_eq!(sources.select(0), Some(principal(2)));
+    }
+
+    #[test]
+    fn backs_off_exponentially_on_consecutive_rejects() {
+        let mut sources = BlockSources::new(principal(0), vec![]);
+
+        let mut now = 1_000;
+        for expected_backoff in [1, 2, 4, 8, 16, 32, 60, 60] {
+            sources.record_reject(principal(0), now, 10);
+            assert_eq!(sources.select(now + expected_backoff - 1), None);
+            assert_eq!(sources.select(now + expected_backoff), Some(principal(0)));
+            now += expected_backoff;
+        }
+
+        // A successful call resets the backoff.
+        sources.record_success(principal(0), 10);
+        sources.record_reject(principal(0), now, 10);
+        assert_eq!(sources.select(now + 1), Some(principal(0)));
+    }
+
+    #[test]
+    fn the_backoff_is_capped_after_many_rejects() {
+        let mut sources = BlockSources::new(principal(0), vec![]);
+        for _ in 0..100 {
+            sources.record_reject(principal(0), 0, 10);
+        }
+        assert_eq!(sources.select(MAX_BACKOFF_SECS), Some(principal(0)));
+    }
+
+    #[test]
+    fn records_rejects_and_latencies() {
+        let mut sources = BlockSources::new(principal(0), vec![principal(1)]);
+
+        sources.record_success(principal(0), 100);
+        sources.record_reject(principal(0), 0, 300);
+        sources.record_success(principal(1), 50);
+
+        // Calls to unknown sources are ignored.
+        sources.record_reject(principal(2), 0, 10);
+
+        let stats: Vec<_> = sources
+            .iter()
+            .map(|source| {
+                (
+                    source.num_calls,
+                    source.num_rejects,
+                    source.consecutive_rejects,
+                    source.latency_ms_sum,
+                    source.last_latency_ms,
+                )
+            })
+            .collect();
+        assert_eq!(stats, vec![(2, 1, 1, 400, 300), (1, 0, 0, 50, 50)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "is specified more than once")]
+    fn rejects_duplicate_sources() {
+        BlockSources::new(principal(0), vec![principal(1), principal(0)]);
+    }
+}
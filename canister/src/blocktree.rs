@@ -1,6 +1,7 @@
+use bitcoin::util::uint::Uint256;
 use ic_btc_interface::Network;
 use ic_btc_types::{Block, BlockHash};
-use std::fmt;
+use std::{cmp::Ordering, fmt};
 mod serde;
 
 /// Represents a non-empty block chain as:
@@ -79,17 +80,45 @@ impl fmt::Display for EmptyChainError {
 pub struct BlockTree {
     pub root: Block,
     pub children: Vec<BlockTree>,
+    /// The cumulative work of the chain that starts at the root of the whole tree
+    /// and ends with this block (inclusive).
+    pub chainwork: Uint256,
+    /// The order in which the block was added to the tree. Blocks that were seen
+    /// earlier have a lower value.
+    pub first_seen: u64,
 }
 
 impl BlockTree {
     /// Creates a new `BlockTree` with the given block as its root.
     pub fn new(root: Block) -> Self {
+        Self::new_with_chainwork(root, Uint256::default(), 0)
+    }
+
+    // Creates a new `BlockTree` with the given block as its root, where `prev_chainwork`
+    // is the chainwork of the root's parent.
+    fn new_with_chainwork(root: Block, prev_chainwork: Uint256, first_seen: u64) -> Self {
         Self {
+            chainwork: prev_chainwork + root.work(),
             root,
             children: vec![],
+            first_seen,
         }
     }
 
+    /// Makes this subtree the whole tree by removing the work of the blocks that
+    /// preceded its root from the chainwork of all its blocks.
+    pub fn rebase_chainwork(&mut self) {
+        fn subtract(tree: &mut BlockTree, work: Uint256) {
+            tree.chainwork = tree.chainwork - work;
+            for child in tree.children.iter_mut() {
+                subtract(child, work);
+            }
+        }
+
+        let prev_chainwork = self.chainwork - self.root.work();
+        subtract(self, prev_chainwork);
+    }
+
     /// Returns all blocks in the tree with their depths
     /// separated by heights.
     pub fn blocks_with_depths_by_heights(&self) -> Vec<Vec<(&Block, u32)>> {
@@ -129,14 +158,27 @@ impl BlockTree {
             self.children.iter().map(|c| c.num_tips()).sum()
         }
     }
+
+    /// Returns the highest `first_seen` value in the tree, i.e. that of the block in the tree
+    /// that was seen last.
+    pub fn max_first_seen(&self) -> u64 {
+        self.children
+            .iter()
+            .map(|c| c.max_first_seen())
+            .fold(self.first_seen, std::cmp::max)
+    }
 }
 
-/// Extends the tree with the given block.
+/// Extends the tree with the given block, which is recorded as seen at `first_seen`.
 ///
 /// Blocks can extend the tree in the following cases:
 ///   * The block is already present in the tree (no-op).
 ///   * The block is a successor of a block already in the tree.
-pub fn extend(block_tree: &mut BlockTree, block: Block) -> Result<(), BlockDoesNotExtendTree> {
+pub fn extend(
+    block_tree: &mut BlockTree,
+    block: Block,
+    first_seen: u64,
+) -> Result<(), BlockDoesNotExtendTree> {
     if contains(block_tree, &block) {
         // The block is already present in the tree. Nothing to do.
         return Ok(());
@@ -150,13 +192,75 @@ pub fn extend(block_tree: &mut BlockTree, block: Block) -> Result<(), BlockDoesN
                 block.header().prev_blockhash.to_vec()
             );
             // Add the block as a successor.
-            block_subtree.children.push(BlockTree::new(block));
+            let chainwork = block_subtree.chainwork;
+            block_subtree
+                .children
+                .push(BlockTree::new_with_chainwork(block, chainwork, first_seen));
             Ok(())
         }
         None => Err(BlockDoesNotExtendTree(block.block_hash())),
     }
 }
 
+/// Compares two tips following Bitcoin's fork choice rule: the tip with more
+/// chainwork is better and, if both have the same chainwork, the tip that was
+/// seen first is better.
+pub fn compare_tips(a: &BlockTree, b: &BlockTree) -> Ordering {
+    a.chainwork
+        .cmp(&b.chainwork)
+        .then_with(|| b.first_seen.cmp(&a.first_seen))
+}
+
+/// Returns the subtree of the best tip in the tree as per `compare_tips`.
+pub fn best_tip(block_tree: &BlockTree) -> &BlockTree {
+    block_tree
+        .children
+        .iter()
+        .map(best_tip)
+        .fold(block_tree, |best, tip| {
+            // Only replace the best tip if the other one is strictly better, so that
+            // the result is deterministic even if two tips compare as equal.
+            if compare_tips(tip, best) == Ordering::Greater {
+                tip
+            } else {
+                best
+            }
+        })
+}
+
+/// Returns the chain from the root of the tree to its best tip, i.e. the tip
+/// with the most chainwork.
+pub fn main_chain(block_tree: &BlockTree) -> BlockChain {
+    get_chain_with_tip(block_tree, &best_tip(block_tree).root.block_hash())
+        .expect("the best tip must exist in the tree")
+}
+
+/// Returns the forks of the main chain, i.e. the subtrees whose root isn't on the main chain
+/// but whose parent is, along with the depth of their root.
+pub fn forks(block_tree: &BlockTree) -> Vec<(&BlockTree, u32)> {
+    let main_chain: Vec<BlockHash> = main_chain(block_tree)
+        .into_chain()
+        .into_iter()
+        .map(|block| block.block_hash())
+        .collect();
+
+    let mut forks = vec![];
+    let mut subtree = block_tree;
+    for (depth, block_hash) in main_chain.iter().enumerate().skip(1) {
+        let mut next_subtree = None;
+        for child in subtree.children.iter() {
+            if child.root.block_hash() == *block_hash {
+                next_subtree = Some(child);
+            } else {
+                forks.push((child, depth as u32));
+            }
+        }
+        subtree = next_subtree.expect("the main chain must exist in the tree");
+    }
+
+    forks
+}
+
 /// Returns all the blockchains in the tree.
 pub fn blockchains(block_tree: &BlockTree) -> Vec<BlockChain> {
     if block_tree.children.is_empty() {
@@ -272,6 +376,34 @@ pub fn find_mut<'a>(
     find_mut_helper(block_tree, blockhash, 0)
 }
 
+/// Removes the subtree whose root block has the given hash and returns it.
+///
+/// The root of the given tree itself cannot be removed. `None` is returned if no
+/// other block in the tree has the given hash.
+pub fn remove_subtree(block_tree: &mut BlockTree, block_hash: &BlockHash) -> Option<BlockTree> {
+    if let Some(idx) = block_tree
+        .children
+        .iter()
+        .position(|child| child.root.block_hash() == *block_hash)
+    {
+        return Some(block_tree.children.remove(idx));
+    }
+
+    block_tree
+        .children
+        .iter_mut()
+        .find_map(|child| remove_subtree(child, block_hash))
+}
+
+/// Returns all the blocks of the tree.
+pub fn blocks(block_tree: &BlockTree) -> Vec<&Block> {
+    let mut blocks = vec![&block_tree.root];
+    for child in block_tree.children.iter() {
+        blocks.extend(self::blocks(child));
+    }
+    blocks
+}
+
 // Returns true if a block exists in the tree, false otherwise.
 fn contains(block_tree: &BlockTree, block: &Block) -> bool {
     if block_tree.root.block_hash() == block.block_hash() {
@@ -322,6 +454,7 @@ mod test {
             extend(
                 &mut block_tree,
                 BlockBuilder::with_prev_header(&genesis_block_header).build(),
+                i as u64,
             )
             .unwrap();
             assert_eq!(blockchains(&block_tree).len(), i);
@@ -339,8 +472,8 @@ mod test {
 
         let mut block_tree = BlockTree::new(blocks[0].clone());
 
-        for block in blocks.iter() {
-            extend(&mut block_tree, block.clone()).unwrap();
+        for (i, block) in blocks.iter().enumerate() {
+            extend(&mut block_tree, block.clone(), i as u64).unwrap();
         }
 
         for (i, block) in blocks.iter().enumerate() {
@@ -379,8 +512,8 @@ mod test {
                 blocks.push(BlockBuilder::with_prev_header(blocks[i - 1].header()).build())
             }
 
-            for block in blocks.iter() {
-                extend(&mut block_tree, block.clone()).unwrap();
+            for (i, block) in blocks.iter().enumerate() {
+                extend(&mut block_tree, block.clone(), i as u64).unwrap();
             }
 
             for (i, block) in blocks.iter().enumerate() {
@@ -428,6 +561,7 @@ mod test {
             extend(
                 &mut block_tree,
                 BlockBuilder::with_prev_header(&genesis_block_header).build_with_mock_difficulty(i),
+                i,
             )
             .unwrap();
         }
@@ -437,6 +571,106 @@ mod test {
         assert_eq!(difficulty_based_depth(&block_tree, Network::Mainnet), 15);
     }
 
+    #[test]
+    fn remove_subtree_removes_block_and_descendants() {
+        let genesis_block = BlockBuilder::genesis().build();
+        let chain = BlockChainBuilder::fork(&genesis_block, 3).build();
+        let fork = BlockChainBuilder::fork(&chain[0], 2).build();
+
+        let mut block_tree = BlockTree::new(genesis_block.clone());
+        for (i, block) in chain.iter().chain(fork.iter()).enumerate() {
+            extend(&mut block_tree, block.clone(), i as u64).unwrap();
+        }
+
+        // The root of the tree cannot be removed.
+        assert!(remove_subtree(&mut block_tree, &genesis_block.block_hash()).is_none());
+
+        let removed = remove_subtree(&mut block_tree, &chain[1].block_hash()).unwrap();
+        assert_eq!(blocks(&removed), vec![&chain[1], &chain[2]]);
+        assert_eq!(
+            blocks(&block_tree),
+            vec![&genesis_block, &chain[0], &fork[0], &fork[1]]
+        );
+
+        // The removed blocks are no longer in the tree.
+        assert!(remove_subtree(&mut block_tree, &chain[2].block_hash()).is_none());
+        assert_eq!(main_chain(&block_tree).tip(), &fork[1]);
+    }
+
+    #[test]
+    fn chainwork_and_first_seen() {
+        let genesis_block = BlockBuilder::genesis().build_with_mock_difficulty(5);
+        let block_1 =
+            BlockBuilder::with_prev_header(genesis_block.header()).build_with_mock_difficulty(3);
+        let block_2 =
+            BlockBuilder::with_prev_header(block_1.header()).build_with_mock_difficulty(4);
+        let fork_block =
+            BlockBuilder::with_prev_header(genesis_block.header()).build_with_mock_difficulty(7);
+
+        let mut block_tree = BlockTree::new(genesis_block);
+        extend(&mut block_tree, block_1, 1).unwrap();
+        extend(&mut block_tree, fork_block.clone(), 2).unwrap();
+        extend(&mut block_tree, block_2.clone(), 3).unwrap();
+
+        let chainwork_and_first_seen =
+            |tree: &BlockTree| (tree.chainwork.low_u64(), tree.first_seen);
+        assert_eq!(chainwork_and_first_seen(&block_tree), (5, 0));
+        assert_eq!(chainwork_and_first_seen(&block_tree.children[0]), (8, 1));
+        assert_eq!(chainwork_and_first_seen(&block_tree.children[1]), (12, 2));
+        assert_eq!(
+            chainwork_and_first_seen(&block_tree.children[0].children[0]),
+            (12, 3)
+        );
+
+        // Both tips have the same chainwork, so the fork block wins as it was seen first.
+        assert_eq!(best_tip(&block_tree).root, fork_block);
+
+        // Rebasing on the first child removes the work of the genesis block.
+        let mut subtree = block_tree.children.swap_remove(0);
+        subtree.rebase_chainwork();
+        assert_eq!(chainwork_and_first_seen(&subtree), (3, 1));
+        assert_eq!(chainwork_and_first_seen(&subtree.children[0]), (7, 3));
+        assert_eq!(best_tip(&subtree).root, block_2);
+    }
+
+    #[test]
+    fn forks_of_the_main_chain() {
+        // Create a tree that looks like this:
+        //
+        // 0 -> 1 -> 2 -> 3
+        //  \    \-> a -> b
+        //   \       \-> c
+        //    \-> d
+        let chain = BlockChainBuilder::new(4).build();
+        let fork_1 = BlockChainBuilder::fork(&chain[1], 2).build();
+        let fork_2 = BlockChainBuilder::fork(&fork_1[0], 1).build();
+        let fork_3 = BlockChainBuilder::fork(&chain[0], 1).build();
+
+        let mut block_tree = BlockTree::new(chain[0].clone());
+        for (i, block) in chain[1..]
+            .iter()
+            .chain(fork_1.iter())
+            .chain(fork_2.iter())
+            .chain(fork_3.iter())
+            .enumerate()
+        {
+            extend(&mut block_tree, block.clone(), i as u64 + 1).unwrap();
+        }
+
+        let fork_roots: Vec<(BlockHash, u32)> = forks(&block_tree)
+            .into_iter()
+            .map(|(fork, depth)| (fork.root.block_hash(), depth))
+            .collect();
+        assert_eq!(
+            fork_roots,
+            vec![(fork_3[0].block_hash(), 1), (fork_1[0].block_hash(), 2)]
+        );
+
+        // A tree without forks.
+        let block_tree = BlockTree::new(chain[0].clone());
+        assert_eq!(forks(&block_tree), vec![]);
+    }
+
     #[test]
     fn test_blocks_with_depths_by_heights_only_root() {
         let genesis_block = BlockBuilder::genesis().build();
@@ -466,7 +700,7 @@ mod test {
 
         for (i, block) in chain.iter().enumerate() {
             expected_blocks_with_depths_by_heights[i].push((block, (chain_len - i) as u32));
-            extend(&mut block_tree, block.clone()).unwrap();
+            extend(&mut block_tree, block.clone(), i as u64).unwrap();
         }
 
         let actual_blocks_with_depths_by_heights = block_tree.blocks_with_depths_by_heights();
@@ -490,9 +724,9 @@ mod test {
         let fork = BlockChainBuilder::fork(&chain[0], 2).build();
 
         let mut block_tree = BlockTree::new(chain[0].clone());
-        extend(&mut block_tree, chain[1].clone()).unwrap();
-        extend(&mut block_tree, fork[0].clone()).unwrap();
-        extend(&mut block_tree, fork[1].clone()).unwrap();
+        extend(&mut block_tree, chain[1].clone(), 1).unwrap();
+        extend(&mut block_tree, fork[0].clone(), 2).unwrap();
+        extend(&mut block_tree, fork[1].clone(), 3).unwrap();
 
         let blocks_with_depths_by_heights = block_tree.blocks_with_depths_by_heights();
 
@@ -1,7 +1,7 @@
 use super::BlockTree;
 use ic_btc_types::Block;
 use serde::{
-    de::{Deserializer, SeqAccess, Visitor},
+    de::{Deserializer, Error, SeqAccess, Visitor},
     ser::SerializeSeq,
     Deserialize, Serialize, Serializer,
 };
@@ -11,11 +11,13 @@ use std::fmt;
 //
 // This flattening is necessary as a recursive data structure can cause a stack
 // overflow if the structure is very deep.
+//
+// The chainwork of the blocks isn't serialized, as it can be recomputed from the blocks.
 impl Serialize for BlockTree {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         // Flatten a block tree into a list.
-        fn flatten(tree: &BlockTree, flattened_tree: &mut Vec<(Block, usize)>) {
-            flattened_tree.push((tree.root.clone(), tree.children.len()));
+        fn flatten<'a>(tree: &'a BlockTree, flattened_tree: &mut Vec<(&'a Block, usize, u64)>) {
+            flattened_tree.push((&tree.root, tree.children.len(), tree.first_seen));
 
             for child in &tree.children {
                 flatten(child, flattened_tree);
@@ -53,20 +55,85 @@ impl<'de> Visitor<'de> for BlockTreeDeserializer {
 
     fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
         // Unflatten a block tree from a list back into a `BlockTree` struct.
-        fn build_tree<'a, A: SeqAccess<'a>>(seq: &mut A) -> BlockTree {
-            let (root, num_children) = seq
-                .next_element()
-                .expect("reading next element must succeed")
-                .expect("root must exist");
-
-            let mut block_tree = BlockTree::new(root);
+        fn build_tree<'a, A: SeqAccess<'a>>(
+            seq: &mut A,
+            tree: &mut BlockTree,
+            num_children: usize,
+        ) {
             for _ in 0..num_children {
-                block_tree.children.push(build_tree(seq));
-            }
+                let FlattenedNode {
+                    block,
+                    num_children,
+                    first_seen,
+                } = seq
+                    .next_element()
+                    .expect("reading next element must succeed")
+                    .expect("child must exist");
 
-            block_tree
+                let mut child = BlockTree::new_with_chainwork(block, tree.chainwork, first_seen);
+                build_tree(seq, &mut child, num_children);
+                tree.children.push(child);
+            }
         }
 
-        Ok(build_tree(&mut seq))
+        let FlattenedNode {
+            block,
+            num_children,
+            first_seen,
+        } = seq
+            .next_element()
+            .expect("reading next element must succeed")
+            .expect("root must exist");
+
+        let mut block_tree = BlockTree::new(block);
+        block_tree.first_seen = first_seen;
+        build_tree(&mut seq, &mut block_tree, num_children);
+
+        Ok(block_tree)
+    }
+}
+
+// A node of a flattened block tree.
+//
+// Block trees that were serialized before `first_seen` was introduced only contain
+// the block and its number of children. `first_seen` defaults to zero for these.
+struct FlattenedNode {
+    block: Block,
+    num_children: usize,
+    first_seen: u64,
+}
+
+impl<'de> Deserialize<'de> for FlattenedNode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(FlattenedNodeDeserializer)
+    }
+}
+
+struct FlattenedNodeDeserializer;
+
+impl<'de> Visitor<'de> for FlattenedNodeDeserializer {
+    type Value = FlattenedNode;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("A flattened blocktree node.")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let block = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let num_children = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        let first_seen = seq.next_element()?.unwrap_or_default();
+
+        Ok(FlattenedNode {
+            block,
+            num_children,
+            first_seen,
+        })
     }
 }
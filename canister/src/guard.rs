@@ -1,6 +1,9 @@
 use crate::with_state_mut;
 
 /// Ensures that there is only one request to fetch blocks at any given time.
+///
+/// Blocks continue to be ingested and processed while the request is in flight, but another
+/// request is only sent once its response has been saved.
 // Note: the struct has one private field to ensure that nobody can construct it
 // directly outside of this module.
 #[must_use]
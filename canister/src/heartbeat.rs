@@ -1,52 +1,132 @@
 use crate::{
-    runtime::{call_get_successors, print},
-    state::{self, ResponseToProcess},
+    memory_limits::{memory_pressure, MemoryPressure},
+    runtime::{
+        call_get_successors, inc_performance_counter, performance_counter, print, time, time_nanos,
+    },
+    state::{
+        self, InsertBlockError, ResponseProcessingCursor, ResponseToProcess, State, SyncingState,
+    },
     types::{
         GetSuccessorsCompleteResponse, GetSuccessorsRequest, GetSuccessorsRequestInitial,
-        GetSuccessorsResponse,
+        GetSuccessorsResponse, Slicing,
     },
+    unstable_blocks,
 };
 use crate::{with_state, with_state_mut};
 use bitcoin::consensus::Decodable;
-use bitcoin::Block as BitcoinBlock;
+use bitcoin::{Block as BitcoinBlock, BlockHeader};
 use ic_btc_interface::Flag;
 use ic_btc_types::{Block, BlockHash};
 
-/// The heartbeat of the Bitcoin canister.
+// The number of instructions a round of syncing can spend on the backfills, the processing
+// of a response and the audit combined. The instructions limit of a round is 20B, and the
+// budget is set lower to be conservative, as a slice can overshoot it, e.g. with the block
+// that's being inserted when the budget runs out.
+const MAX_INSTRUCTIONS_PER_ROUND: u64 = 15_000_000_000;
+
+/// What a round of syncing did, which determines when the next round is scheduled.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SyncOutcome {
+    /// Stable blocks were ingested into the UTXO set.
+    IngestedStableBlocks,
+
+    /// A call to fetch blocks was made, along with whether or not it returned new blocks.
+    FetchedBlocks { has_new_blocks: bool },
+
+    /// A complete response was processed.
+    ProcessedResponse,
+
+    /// There was nothing to do.
+    Idle,
+}
+
+/// A round of syncing of the Bitcoin canister.
+///
+/// The round fetches new blocks from the bitcoin network and inserts them into the state.
+/// Rounds are run on timers, which are scheduled by the `scheduler` based on the outcome
+/// of the previous round.
 ///
-/// The heartbeat fetches new blocks from the bitcoin network and inserts them into the state.
-pub async fn heartbeat() {
+/// Fetching is pipelined: while stable blocks are ingested or a response is processed, the
+/// next response is fetched, so that the canister doesn't wait on the network in between.
+pub async fn heartbeat() -> SyncOutcome {
     print("Starting heartbeat...");
+    if crate::api::is_snapshot_pending() {
+        // The stable memory must not change while the snapshot is downloaded.
+        print("A snapshot of stable memory is pending. Skipping the heartbeat.");
+        return SyncOutcome::Idle;
+    }
+
+    let round_start = performance_counter();
+    maybe_backfill_indices(round_start);
+
     if ingest_stable_blocks_into_utxoset() {
         // Exit the heartbeat if stable blocks had been ingested.
         // This is a precaution to not exceed the instructions limit.
         print("Done ingesting stable blocks.");
-        return;
+
+        // Fetch the next blocks while the stable blocks are being ingested.
+        maybe_fetch_blocks().await;
+        return SyncOutcome::IngestedStableBlocks;
     }
 
-    if maybe_fetch_blocks().await {
-        // Exit the heartbeat if new blocks have been fetched.
-        // This is a precaution to not exceed the instructions limit.
-        print("Done fetching new response.");
-        return;
+    // A complete response is processed before any more blocks are fetched, and the next
+    // blocks are then fetched while the response is processed.
+    if !with_state(has_complete_response) {
+        if let Some(outcome) = maybe_fetch_blocks().await {
+            // Exit the heartbeat if new blocks have been fetched.
+            // This is a precaution to not exceed the instructions limit.
+            print("Done fetching new response.");
+            return outcome;
+        }
     }
 
-    maybe_process_response();
+    let has_processed_response = maybe_process_response(round_start);
+
+    maybe_audit_utxos(round_start);
+
+    if has_processed_response {
+        if with_state(has_pending_work) {
+            // Fetch the next blocks while the response is processed or its blocks ingested.
+            maybe_fetch_blocks().await;
+        }
+        SyncOutcome::ProcessedResponse
+    } else {
+        SyncOutcome::Idle
+    }
 }
 
-// Fetches new blocks if there isn't a request in progress and no complete response to process.
-// Returns true if a call to the `blocks_source` has been made, false otherwise.
-async fn maybe_fetch_blocks() -> bool {
+/// Returns true if there are stable blocks to ingest or a complete response to process.
+pub fn has_pending_work(state: &State) -> bool {
+    state.utxos.ingesting_block.is_some()
+        || unstable_blocks::has_stable_block(&state.unstable_blocks)
+        || has_complete_response(state)
+}
+
+fn has_complete_response(state: &State) -> bool {
+    matches!(
+        state.syncing_state.response_to_process,
+        Some(ResponseToProcess::Complete(_))
+    )
+}
+
+// Fetches new blocks if there isn't a request in progress and no complete response waiting
+// behind the one that's being processed.
+// Returns the outcome if a call to one of the `blocks_sources` has been made, None otherwise.
+async fn maybe_fetch_blocks() -> Option<SyncOutcome> {
     if with_state(|s| s.syncing_state.syncing == Flag::Disabled) {
         // Syncing is disabled.
-        return false;
+        return None;
+    }
+
+    if with_state(|s| memory_pressure(&s.memory_limits)) >= MemoryPressure::AboveSoftLimit {
+        // Stop fetching new blocks until the memory is back within its soft limits. The
+        // blocks that were already fetched are still processed and ingested.
+        print("Memory is past its soft limit. Not fetching new blocks.");
+        return None;
     }
 
     // A guard to verify we aren't already fetching blocks.
-    let _guard = match crate::guard::FetchBlocksGuard::new() {
-        Some(guard) => guard,
-        None => return false,
-    };
+    let _guard = crate::guard::FetchBlocksGuard::new()?;
 
     // Request additional blocks.
     let maybe_request = maybe_get_successors_request();
@@ -54,79 +134,172 @@ async fn maybe_fetch_blocks() -> bool {
         Some(request) => request,
         None => {
             // No request to send at this time.
-            return false;
+            return None;
         }
     };
 
-    print(&format!("Sending request: {:?}", request));
+    let source = match with_state_mut(|s| s.blocks_sources.select(time())) {
+        Some(source) => source,
+        None => {
+            print("All the sources of blocks are backing off.");
+            return None;
+        }
+    };
+
+    print(&format!("Sending request to {}: {:?}", source, request));
 
-    let response: Result<(GetSuccessorsResponse,), _> =
-        call_get_successors(with_state(|s| s.blocks_source), request).await;
+    let start = time_nanos();
+    let response: Result<(GetSuccessorsResponse,), _> = call_get_successors(source, request).await;
+    let latency_ms = time_nanos().saturating_sub(start) / 1_000_000;
 
     print(&format!("Received response: {:?}", response));
 
     // Save the response.
-    with_state_mut(|s| {
-        let response = match response {
-            Ok((response,)) => response,
-            Err((code, msg)) => {
-                s.syncing_state.num_get_successors_rejects += 1;
-                print(&format!("Error fetching blocks: [{:?}] {}", code, msg));
-                s.syncing_state.response_to_process = None;
-                return;
+    let has_new_blocks = with_state_mut(|s| match response {
+        Ok((response,)) => {
+            s.blocks_sources.record_success(source, latency_ms);
+            save_response(&mut s.syncing_state, response)
+        }
+        Err((code, msg)) => {
+            s.syncing_state.num_get_successors_rejects += 1;
+            s.blocks_sources.record_reject(source, time(), latency_ms);
+            print(&format!("Error fetching blocks: [{:?}] {}", code, msg));
+
+            // Drop the partial response that was being fetched, if any, as it can no longer
+            // be completed.
+            let syncing_state = &mut s.syncing_state;
+            for slot in [
+                &mut syncing_state.response_to_process,
+                &mut syncing_state.pipelined_response,
+            ] {
+                if let Some(ResponseToProcess::Partial(..)) = slot {
+                    *slot = None;
+                }
             }
-        };
+            false
+        }
+    });
 
-        match response {
-            GetSuccessorsResponse::Complete(response) => {
-                // Received complete response.
-                assert!(
-                    s.syncing_state.response_to_process.is_none(),
-                    "Received complete response before processing previous response."
-                );
-                s.syncing_state.response_to_process = Some(ResponseToProcess::Complete(response));
-            }
-            GetSuccessorsResponse::Partial(partial_response) => {
-                // Received partial response.
-                assert!(
-                    s.syncing_state.response_to_process.is_none(),
-                    "Received partial response before processing previous response."
-                );
-                s.syncing_state.response_to_process =
-                    Some(ResponseToProcess::Partial(partial_response, 0));
-            }
-            GetSuccessorsResponse::FollowUp(mut block_bytes) => {
-                // Received a follow-up response.
-                // A follow-up response is only expected, and only makes sense, when there's
-                // a partial response to process.
-
-                let (mut partial_response, mut follow_up_index) = match s.syncing_state.response_to_process.take() {
-                    Some(ResponseToProcess::Partial(res, pages)) => (res, pages),
-                    other => unreachable!("Cannot receive follow-up response without a previous partial response. Previous response found: {:?}", other)
-                };
-
-                // Append block to partial response and increment # pages processed.
-                partial_response.partial_block.append(&mut block_bytes);
-                follow_up_index += 1;
-
-                // If the response is now complete, store a complete response to process.
-                // Otherwise, store the updated partial response.
-                s.syncing_state.response_to_process = Some(
-                    if follow_up_index == partial_response.remaining_follow_ups {
-                        ResponseToProcess::Complete(GetSuccessorsCompleteResponse {
-                            blocks: vec![partial_response.partial_block],
-                            next: partial_response.next,
-                        })
-                    } else {
-                        ResponseToProcess::Partial(partial_response, follow_up_index)
-                    },
-                );
+    // A request to fetch new blocks has been made.
+    Some(SyncOutcome::FetchedBlocks { has_new_blocks })
+}
+
+// Saves a response into the slot it was requested for: the response to process if it's
+// free, and the pipelined response behind it otherwise.
+// Returns true if the response has new blocks, false otherwise.
+fn save_response(syncing_state: &mut SyncingState, response: GetSuccessorsResponse) -> bool {
+    // A new response goes into the first free slot, and a follow-up response goes into
+    // the slot of the partial response it completes.
+    let slot = match (&response, &syncing_state.response_to_process) {
+        (GetSuccessorsResponse::FollowUp(_), Some(ResponseToProcess::Partial(..)))
+        | (GetSuccessorsResponse::Complete(_), None)
+        | (GetSuccessorsResponse::Partial(_), None) => &mut syncing_state.response_to_process,
+        _ => &mut syncing_state.pipelined_response,
+    };
+
+    match response {
+        GetSuccessorsResponse::Complete(response) => {
+            if response.blocks.is_empty() && response.next.is_empty() {
+                // There's nothing to process.
+                print("No new blocks or block headers received.");
+                return false;
             }
-        };
+
+            // Received complete response.
+            assert!(
+                slot.is_none(),
+                "Received complete response before processing previous response."
+            );
+            let has_new_blocks = !response.blocks.is_empty();
+            *slot = Some(ResponseToProcess::Complete(response));
+            has_new_blocks
+        }
+        GetSuccessorsResponse::Partial(partial_response) => {
+            // Received partial response.
+            assert!(
+                slot.is_none(),
+                "Received partial response before processing previous response."
+            );
+            *slot = Some(ResponseToProcess::Partial(partial_response, 0));
+            true
+        }
+        GetSuccessorsResponse::FollowUp(mut block_bytes) => {
+            // Received a follow-up response.
+            // A follow-up response is only expected, and only makes sense, when there's
+            // a partial response to process.
+
+            let (mut partial_response, mut follow_up_index) = match slot.take() {
+                Some(ResponseToProcess::Partial(res, pages)) => (res, pages),
+                other => unreachable!("Cannot receive follow-up response without a previous partial response. Previous response found: {:?}", other)
+            };
+
+            // Append block to partial response and increment # pages processed.
+            partial_response.partial_block.append(&mut block_bytes);
+            follow_up_index += 1;
+
+            // If the response is now complete, store a complete response to process.
+            // Otherwise, store the updated partial response.
+            *slot = Some(
+                if follow_up_index == partial_response.remaining_follow_ups {
+                    ResponseToProcess::Complete(GetSuccessorsCompleteResponse {
+                        blocks: vec![partial_response.partial_block],
+                        next: partial_response.next,
+                    })
+                } else {
+                    ResponseToProcess::Partial(partial_response, follow_up_index)
+                },
+            );
+            true
+        }
+    }
+}
+
+// Returns a predicate for time-slicing a step of the round that started at `round_start`,
+// which is true once the step has spent `max_instructions`, or once the round has spent
+// its budget.
+fn should_pause_step(round_start: u64, max_instructions: u64) -> impl FnMut() -> bool {
+    let limit = std::cmp::min(
+        performance_counter() + max_instructions,
+        round_start + MAX_INSTRUCTIONS_PER_ROUND,
+    );
+    move || inc_performance_counter() >= limit
+}
+
+// Audits the UTXO set for a slice of the heartbeat, if the audit is enabled.
+fn maybe_audit_utxos(round_start: u64) {
+    // The number of instructions to spend on the audit in a single heartbeat, which leaves
+    // enough room for the other work done in the heartbeat.
+    const MAX_INSTRUCTIONS_PER_SLICE: u64 = 1_000_000_000;
+
+    with_state_mut(|s| {
+        let mut should_pause = should_pause_step(round_start, MAX_INSTRUCTIONS_PER_SLICE);
+        s.utxo_auditor.run(&s.utxos, time(), &mut should_pause);
     });
+}
 
-    // A request to fetch new blocks has been made.
-    true
+// Backfills the indices of the UTXO set and the heights of the stable blocks for a slice of
+// the heartbeat, if a backfill is in progress. The backfills run before any blocks are
+// ingested, so that they make progress while the canister is catching up.
+fn maybe_backfill_indices(round_start: u64) {
+    // The number of instructions to spend on the backfills in a single heartbeat, which leaves
+    // enough room for ingesting blocks.
+    const MAX_INSTRUCTIONS_PER_SLICE: u64 = 1_000_000_000;
+
+    with_state_mut(|s| {
+        let mut should_pause = should_pause_step(round_start, MAX_INSTRUCTIONS_PER_SLICE);
+
+        if s.utxos.is_backfilling_indices()
+            && s.utxos.backfill_indices(&mut should_pause) == Slicing::Done(())
+        {
+            print("Done backfilling the indices of the UTXO set.");
+        }
+
+        if s.stable_block_headers.is_backfilling_heights()
+            && s.stable_block_headers.backfill_heights(&mut should_pause) == Slicing::Done(())
+        {
+            print("Done backfilling the heights of the stable blocks.");
+        }
+    });
 }
 
 fn ingest_stable_blocks_into_utxoset() -> bool {
@@ -134,51 +307,44 @@ fn ingest_stable_blocks_into_utxoset() -> bool {
 }
 
 // Process a `GetSuccessorsResponse` if one is available.
-fn maybe_process_response() {
+// Returns true if a complete response has been processed, false otherwise.
+//
+// Processing is time-sliced, and a response that's only partially processed is put back
+// into the state to be resumed in the next round. The processing can spend whatever is left
+// of the round's budget.
+fn maybe_process_response(round_start: u64) -> bool {
     with_state_mut(|state| {
         let response_to_process = state.syncing_state.response_to_process.take();
 
         match response_to_process {
             Some(ResponseToProcess::Complete(response)) => {
-                print(&format!(
-                    "Inserting {} blocks from response...",
-                    response.blocks.len()
-                ));
-                for block_bytes in response.blocks.iter() {
-                    // Deserialize the block.
-                    let block = match BitcoinBlock::consensus_decode(block_bytes.as_slice()) {
-                        Ok(block) => block,
-                        Err(err) => {
-                            print(&format!(
-                                "ERROR: Cannot deserialize block. Err: {:?}, Block bytes: {:?}. Full Response: {:?}",
-                                err,
-                                block_bytes,
-                                response,
-                            ));
-
-                            // Return, the remaining blocks in the response are dropped.
-                            state.syncing_state.num_block_deserialize_errors += 1;
-                            return;
-                        }
-                    };
-
-                    if let Err(err) = state::insert_block(state, Block::new(block)) {
-                        print(&format!(
-                            "ERROR: Failed to insert block. Err: {:?}, Block bytes: {:?}",
-                            err, block_bytes,
-                        ));
-
-                        // Return, the remaining blocks in the response are dropped.
-                        state.syncing_state.num_insert_block_errors += 1;
-                        return;
+                let start = performance_counter();
+                let mut should_pause = should_pause_step(round_start, MAX_INSTRUCTIONS_PER_ROUND);
+                let result = process_response_slice(state, &response, &mut should_pause);
+                state
+                    .metrics
+                    .response_processing
+                    .observe(performance_counter() - start);
+
+                match result {
+                    Slicing::Paused(()) => {
+                        print("Pausing processing of the response...");
+                        state.syncing_state.response_to_process =
+                            Some(ResponseToProcess::Complete(response));
+                    }
+                    Slicing::Done(()) => {
+                        // Forks are pruned once all the blocks of the response are inserted,
+                        // as blocks later in the response may extend them.
+                        state::prune_stale_forks(state);
+
+                        // The pipelined response, if any, is processed next.
+                        state.syncing_state.response_processing_cursor =
+                            ResponseProcessingCursor::default();
+                        state.syncing_state.response_to_process =
+                            state.syncing_state.pipelined_response.take();
                     }
                 }
-
-                print(&format!(
-                    "Inserting {} next block headers...",
-                    response.next.len()
-                ));
-                state::insert_next_block_headers(state, &response.next);
+                true
             }
             other => {
                 if other.is_some() {
@@ -192,42 +358,174 @@ fn maybe_process_response() {
 
                 // Not a complete response. Put it back into the state.
                 state.syncing_state.response_to_process = other;
+                false
             }
         }
-    });
+    })
 }
 
-// Retrieves a `GetSuccessorsRequest` to send to the adapter.
-fn maybe_get_successors_request() -> Option<GetSuccessorsRequest> {
-    with_state(|state| match &state.syncing_state.response_to_process {
-        Some(ResponseToProcess::Complete(_)) => {
-            // There's already a complete response waiting to be processed.
-            None
+// Inserts the blocks and then the next block headers of the response, resuming from the
+// processing cursor, until the response is processed or `should_pause` returns true.
+fn process_response_slice(
+    state: &mut State,
+    response: &GetSuccessorsCompleteResponse,
+    should_pause: &mut dyn FnMut() -> bool,
+) -> Slicing<(), ()> {
+    let mut cursor = state.syncing_state.response_processing_cursor;
+
+    print(&format!(
+        "Inserting {} blocks from response...",
+        response.blocks.len() - cursor.next_block_idx
+    ));
+    while let Some(block_bytes) = response.blocks.get(cursor.next_block_idx) {
+        if should_pause() {
+            state.syncing_state.response_processing_cursor = cursor;
+            return Slicing::Paused(());
         }
-        Some(ResponseToProcess::Partial(partial_response, follow_up_index)) => {
-            // There's a partial response. Create a follow-up request.
-            assert!(partial_response.remaining_follow_ups >= *follow_up_index);
-            Some(GetSuccessorsRequest::FollowUp(*follow_up_index))
+
+        // Deserialize the block.
+        let block = match BitcoinBlock::consensus_decode(block_bytes.as_slice()) {
+            Ok(block) => block,
+            Err(err) => {
+                print(&format!(
+                    "ERROR: Cannot deserialize block. Err: {:?}, Block bytes: {:?}. Full Response: {:?}",
+                    err,
+                    block_bytes,
+                    response,
+                ));
+
+                // Return, the remaining blocks in the response are dropped.
+                state.syncing_state.num_block_deserialize_errors += 1;
+                return Slicing::Done(());
+            }
+        };
+
+        if let Err(err) = state::validate_signet_block(state, &block) {
+            print(&format!(
+                "ERROR: Invalid signet block solution. Err: {:?}, Block bytes: {:?}",
+                err, block_bytes,
+            ));
+
+            // The block, and thus its descendants, can never become valid.
+            state
+                .invalid_blocks
+                .insert(BlockHash::from(block.block_hash()));
+
+            // Return, the remaining blocks in the response are dropped.
+            state.syncing_state.num_insert_block_errors += 1;
+            return Slicing::Done(());
         }
-        None => {
-            // No response is present. Send an initial request for new blocks.
-            let mut processed_block_hashes: Vec<BlockHash> = state::get_unstable_blocks(state)
-                .iter()
-                .map(|b| b.block_hash())
-                .collect();
 
-            // We are guaranteed that there's always at least one block.
-            let anchor = processed_block_hashes.remove(0);
+        match state::insert_block(state, Block::new(block)) {
+            Ok(()) => {}
+            Err(InsertBlockError::Pruned) => {
+                // The block belongs to a stale fork. Skip it.
+                print("Skipping a block of a pruned fork.");
+            }
+            Err(err) => {
+                print(&format!(
+                    "ERROR: Failed to insert block. Err: {:?}, Block bytes: {:?}",
+                    err, block_bytes,
+                ));
 
-            Some(GetSuccessorsRequest::Initial(GetSuccessorsRequestInitial {
-                network: state.network(),
-                anchor,
-                processed_block_hashes,
-            }))
+                // Return, the remaining blocks in the response are dropped.
+                state.syncing_state.num_insert_block_errors += 1;
+                return Slicing::Done(());
+            }
+        }
+
+        cursor.next_block_idx += 1;
+    }
+
+    print(&format!(
+        "Inserting {} next block headers...",
+        response.next.len() - cursor.next_header_idx
+    ));
+    match state::insert_next_block_headers(
+        state,
+        &response.next[cursor.next_header_idx..],
+        should_pause,
+    ) {
+        Slicing::Paused(num_inserted) => {
+            cursor.next_header_idx += num_inserted;
+            state.syncing_state.response_processing_cursor = cursor;
+            Slicing::Paused(())
+        }
+        Slicing::Done(()) => Slicing::Done(()),
+    }
+}
+
+// Retrieves a `GetSuccessorsRequest` to send to the adapter.
+fn maybe_get_successors_request() -> Option<GetSuccessorsRequest> {
+    with_state(|state| {
+        let syncing_state = &state.syncing_state;
+        match (
+            &syncing_state.response_to_process,
+            &syncing_state.pipelined_response,
+        ) {
+            (Some(ResponseToProcess::Complete(_)), Some(ResponseToProcess::Complete(_))) => {
+                // There's already a complete response waiting behind the one that's
+                // being processed.
+                None
+            }
+            (Some(ResponseToProcess::Partial(partial_response, follow_up_index)), _)
+            | (
+                Some(ResponseToProcess::Complete(_)),
+                Some(ResponseToProcess::Partial(partial_response, follow_up_index)),
+            ) => {
+                // There's a partial response. Create a follow-up request.
+                assert!(partial_response.remaining_follow_ups >= *follow_up_index);
+                Some(GetSuccessorsRequest::FollowUp(*follow_up_index))
+            }
+            (response_to_process, _) => {
+                // No response is waiting. Send an initial request for new blocks.
+                let mut processed_block_hashes: Vec<BlockHash> = state::get_unstable_blocks(state)
+                    .iter()
+                    .map(|b| b.block_hash())
+                    .collect();
+
+                // We are guaranteed that there's always at least one block.
+                let anchor = processed_block_hashes.remove(0);
+
+                // The blocks of pruned forks aren't in the tree anymore, but they shouldn't
+                // be fetched again.
+                processed_block_hashes.extend(state.unstable_blocks.pruned_block_hashes().cloned());
+
+                // The blocks of the response that's being processed aren't in the tree yet,
+                // but they don't need to be fetched again.
+                if let Some(ResponseToProcess::Complete(response)) = response_to_process {
+                    processed_block_hashes.extend(pending_block_hashes(
+                        response,
+                        &syncing_state.response_processing_cursor,
+                    ));
+                }
+
+                Some(GetSuccessorsRequest::Initial(GetSuccessorsRequestInitial {
+                    network: state.network(),
+                    anchor,
+                    processed_block_hashes,
+                }))
+            }
         }
     })
 }
 
+// Returns the hashes of the blocks of the response that haven't been processed yet.
+fn pending_block_hashes<'a>(
+    response: &'a GetSuccessorsCompleteResponse,
+    cursor: &ResponseProcessingCursor,
+) -> impl Iterator<Item = BlockHash> + 'a {
+    response.blocks[cursor.next_block_idx..]
+        .iter()
+        .filter_map(|block_bytes| {
+            // The header is at the start of the block, so the rest of the block needn't be
+            // deserialized.
+            BlockHeader::consensus_decode(block_bytes.as_slice())
+                .ok()
+                .map(|header| BlockHash::from(header.block_hash()))
+        })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -235,28 +533,11 @@ mod test {
         genesis_block, init,
         runtime::{self, GetSuccessorsReply},
         test_utils::{random_p2pkh_address, BlockBuilder, BlockChainBuilder, TransactionBuilder},
-        types::{Address, BlockBlob, GetSuccessorsCompleteResponse, GetSuccessorsPartialResponse},
+        types::{BlockBlob, GetSuccessorsCompleteResponse, GetSuccessorsPartialResponse},
         utxo_set::IngestingBlock,
     };
-    use bitcoin::BlockHeader;
-    use ic_btc_interface::{Config, Network};
-
-    fn build_block(prev_header: &BlockHeader, address: Address, num_transactions: u128) -> Block {
-        let mut block = BlockBuilder::with_prev_header(prev_header);
-        let mut value = 1;
-        for _ in 0..num_transactions {
-            block = block.with_transaction(
-                TransactionBuilder::coinbase()
-                    .with_output(&address, value)
-                    .build(),
-            );
-
-            // Increment the value so that all transaction IDs are unique.
-            value += 1;
-        }
-
-        block.build()
-    }
+    use ic_btc_interface::{Config, MemoryLimit, MemoryLimits, Network};
+    use ic_cdk::api::call::RejectionCode;
 
     #[async_std::test]
     async fn fetches_blocks_and_processes_response() {
@@ -281,10 +562,15 @@ mod test {
         )));
 
         // Fetch blocks.
-        heartbeat().await;
+        assert_eq!(
+            heartbeat().await,
+            SyncOutcome::FetchedBlocks {
+                has_new_blocks: true
+            }
+        );
 
         // Process response.
-        heartbeat().await;
+        assert_eq!(heartbeat().await, SyncOutcome::ProcessedResponse);
 
         // Assert that the block has been ingested.
         assert_eq!(with_state(state::main_chain_height), 1);
@@ -293,7 +579,7 @@ mod test {
         assert_eq!(with_state(|s| s.utxos.next_height()), 0);
 
         // Ingest the stable block (the genesis block) to the UTXO set.
-        heartbeat().await;
+        assert_eq!(heartbeat().await, SyncOutcome::IngestedStableBlocks);
 
         // Assert that the block has been ingested.
         assert_eq!(with_state(state::main_chain_height), 1);
@@ -329,13 +615,126 @@ mod test {
         )));
 
         // Try to fetch blocks
-        heartbeat().await;
-        heartbeat().await;
+        assert_eq!(heartbeat().await, SyncOutcome::Idle);
+        assert_eq!(heartbeat().await, SyncOutcome::Idle);
 
         // Assert that the block has not been ingested.
         assert_eq!(with_state(state::main_chain_height), 0);
     }
 
+    #[async_std::test]
+    async fn stops_fetching_blocks_past_the_soft_memory_limit() {
+        let network = Network::Regtest;
+
+        init(Config {
+            stability_threshold: 0,
+            network,
+            memory_limits: Some(MemoryLimits {
+                heap: Some(MemoryLimit {
+                    soft_limit_bytes: 100,
+                    hard_limit_bytes: 1_000,
+                }),
+                stable_memories: vec![],
+            }),
+            ..Default::default()
+        });
+
+        let block = BlockBuilder::with_prev_header(genesis_block(network).header()).build();
+        runtime::set_successors_response(GetSuccessorsReply::Ok(GetSuccessorsResponse::Complete(
+            GetSuccessorsCompleteResponse {
+                blocks: vec![encode(&block)],
+                next: vec![],
+            },
+        )));
+
+        assert_eq!(
+            heartbeat().await,
+            SyncOutcome::FetchedBlocks {
+                has_new_blocks: true
+            }
+        );
+
+        // Past the soft limit, the blocks that were fetched are still processed and ingested,
+        // but no more blocks are fetched.
+        runtime::set_heap_size(500);
+        assert_eq!(heartbeat().await, SyncOutcome::ProcessedResponse);
+        assert_eq!(heartbeat().await, SyncOutcome::IngestedStableBlocks);
+        assert_eq!(heartbeat().await, SyncOutcome::Idle);
+        assert_eq!(with_state(|s| s.utxos.next_height()), 1);
+        assert_eq!(
+            runtime::GET_SUCCESSORS_RESPONSES_INDEX.with(|i| *i.borrow()),
+            1
+        );
+
+        // Fetching resumes once the memory is back within its soft limit.
+        runtime::set_heap_size(0);
+        assert_eq!(
+            heartbeat().await,
+            SyncOutcome::FetchedBlocks {
+                has_new_blocks: false
+            }
+        );
+    }
+
+    #[async_std::test]
+    async fn reports_when_no_new_blocks_are_fetched() {
+        init(Config {
+            stability_threshold: 0,
+            network: Network::Regtest,
+            ..Default::default()
+        });
+
+        runtime::set_successors_response(GetSuccessorsReply::Ok(GetSuccessorsResponse::Complete(
+            GetSuccessorsCompleteResponse {
+                blocks: vec![],
+                next: vec![],
+            },
+        )));
+
+        assert_eq!(
+            heartbeat().await,
+            SyncOutcome::FetchedBlocks {
+                has_new_blocks: false
+            }
+        );
+
+        // An empty response has nothing to process, so it's dropped and new blocks are
+        // requested right away.
+        with_state(|s| assert_eq!(s.syncing_state.response_to_process, None));
+        assert_eq!(
+            heartbeat().await,
+            SyncOutcome::FetchedBlocks {
+                has_new_blocks: false
+            }
+        );
+    }
+
+    #[async_std::test]
+    async fn backfills_indices() {
+        init(Config {
+            stability_threshold: 0,
+            network: Network::Regtest,
+            ..Default::default()
+        });
+
+        runtime::set_successors_response(GetSuccessorsReply::Ok(GetSuccessorsResponse::Complete(
+            GetSuccessorsCompleteResponse {
+                blocks: vec![],
+                next: vec![],
+            },
+        )));
+
+        with_state_mut(|s| {
+            s.utxos.start_indices_backfill();
+            s.stable_block_headers.start_heights_backfill();
+        });
+        heartbeat().await;
+        assert!(!with_state(|s| s.utxos.is_backfilling_indices()));
+        assert!(!with_state(|s| s
+            .stable_block_headers
+            .is_backfilling_heights()));
+    }
+
     #[async_std::test]
     async fn time_slices_large_blocks() {
         let network = Network::Regtest;
@@ -346,13 +745,32 @@ mod test {
             ..Default::default()
         });
 
-        // Setup a chain of two blocks.
+        // Setup a chain of three blocks, where the transactions of the second block spend
+        // the outputs of the first block's coinbase.
         let address = random_p2pkh_address(network);
-        let block_1 = build_block(genesis_block(network).header(), address.clone(), 6);
-        let block_2 = build_block(block_1.header(), address, 1);
+        let mut coinbase = TransactionBuilder::coinbase().with_height(1);
+        for value in 1..=5 {
+            coinbase = coinbase.with_output(&address, value);
+        }
+        let coinbase = coinbase.build();
+        let block_1 = BlockBuilder::with_prev_header(genesis_block(network).header())
+            .with_transaction(coinbase.clone())
+            .build();
+
+        let mut block_2 = BlockBuilder::with_prev_header(block_1.header());
+        for (vout, output) in coinbase.output().iter().enumerate() {
+            block_2 = block_2.with_transaction(
+                TransactionBuilder::new()
+                    .with_input(ic_btc_types::OutPoint::new(coinbase.txid(), vout as u32))
+                    .with_output(&address, output.value)
+                    .build(),
+            );
+        }
+        let block_2 = block_2.build();
+        let block_3 = BlockBuilder::with_prev_header(block_2.header()).build();
 
         // Serialize the blocks.
-        let blocks: Vec<BlockBlob> = [block_1.clone(), block_2]
+        let blocks: Vec<BlockBlob> = [block_1.clone(), block_2.clone(), block_3]
             .iter()
             .map(|block| {
                 let mut block_bytes = vec![];
@@ -379,33 +797,34 @@ mod test {
         heartbeat().await;
 
         // Assert that the blocks have been ingested.
-        assert_eq!(with_state(state::main_chain_height), 2);
-
-        // Ingest stable blocks.
-        runtime::performance_counter_reset();
-        heartbeat().await;
-
-        // Assert that execution has been paused.
-        // Ingested the genesis block (1 tx) + 2 txs of block_1 into the UTXO set.
-        let partial_block = with_state(|s| s.utxos.ingesting_block.clone().unwrap());
-        assert_eq!(partial_block.block, block_1);
-        assert_eq!(partial_block.next_tx_idx, 2);
-        assert_eq!(partial_block.next_input_idx, 1);
-        assert_eq!(partial_block.next_output_idx, 0);
+        assert_eq!(with_state(state::main_chain_height), 3);
 
-        // Ingest more stable blocks.
-        runtime::performance_counter_reset();
-        heartbeat().await;
+        // Run the heartbeat a few rounds to ingest the two stable blocks.
+        // Three inputs/outputs are expected to be ingested per round, starting with the
+        // output of the genesis block.
+        let expected_states = vec![
+            IngestingBlock::new_with_args(block_1.clone(), 0, 1, 2),
+            IngestingBlock::new_with_args(block_2.clone(), 0, 1, 0),
+            IngestingBlock::new_with_args(block_2.clone(), 2, 0, 0),
+            IngestingBlock::new_with_args(block_2.clone(), 3, 1, 0),
+            IngestingBlock::new_with_args(block_2.clone(), 5, 0, 0),
+        ];
 
-        // Assert that execution has been paused. Ingested 3 more txs in block_1.
-        let partial_block = with_state(|s| s.utxos.ingesting_block.clone().unwrap());
-        assert_eq!(partial_block.block, block_1);
-        assert_eq!(partial_block.next_tx_idx, 5);
-        assert_eq!(partial_block.next_input_idx, 1);
-        assert_eq!(partial_block.next_output_idx, 0);
+        for expected_state in expected_states.into_iter() {
+            // Ingest stable blocks.
+            runtime::performance_counter_reset();
+            heartbeat().await;
 
-        // Only the genesis block has been fully processed, so the stable height is one.
-        assert_eq!(with_state(|s| s.utxos.next_height()), 1);
+            // Assert that execution has been paused.
+            let partial_block = with_state(|s| s.utxos.ingesting_block.clone().unwrap());
+            assert_eq!(partial_block.block, expected_state.block);
+            assert_eq!(partial_block.next_tx_idx, expected_state.next_tx_idx);
+            assert_eq!(partial_block.next_input_idx, expected_state.next_input_idx);
+            assert_eq!(
+                partial_block.next_output_idx,
+                expected_state.next_output_idx
+            );
+        }
 
         // Ingest more stable blocks.
         runtime::performance_counter_reset();
@@ -414,11 +833,8 @@ mod test {
         // Time slicing is complete.
         assert!(with_state(|s| s.utxos.ingesting_block.is_none()));
 
-        // Assert that the blocks have been ingested.
-        assert_eq!(with_state(state::main_chain_height), 2);
-
-        // The stable height is now updated to include `block_1`.
-        assert_eq!(with_state(|s| s.utxos.next_height()), 2);
+        // The stable height is now updated to include `block_2`.
+        assert_eq!(with_state(|s| s.utxos.next_height()), 3);
     }
 
     #[async_std::test]
@@ -437,7 +853,7 @@ mod test {
         let address_2 = random_p2pkh_address(network);
 
         // Create a transaction where a few inputs are given to address 1.
-        let mut tx_1 = TransactionBuilder::coinbase();
+        let mut tx_1 = TransactionBuilder::coinbase().with_height(1);
         for _ in 0..tx_cardinality {
             tx_1 = tx_1.with_output(&address_1, 1000);
         }
@@ -503,10 +919,10 @@ mod test {
         let expected_states = vec![
             IngestingBlock::new_with_args(block_1.clone(), 0, 1, 2),
             IngestingBlock::new_with_args(block_1.clone(), 0, 1, 5),
-            IngestingBlock::new_with_args(block_2.clone(), 0, 2, 0),
-            IngestingBlock::new_with_args(block_2.clone(), 0, 5, 0),
-            IngestingBlock::new_with_args(block_2.clone(), 0, 6, 2),
-            IngestingBlock::new_with_args(block_2.clone(), 0, 6, 5),
+            IngestingBlock::new_with_args(block_2.clone(), 1, 1, 0),
+            IngestingBlock::new_with_args(block_2.clone(), 1, 4, 0),
+            IngestingBlock::new_with_args(block_2.clone(), 1, 6, 1),
+            IngestingBlock::new_with_args(block_2.clone(), 1, 6, 4),
         ];
 
         for expected_state in expected_states.into_iter() {
@@ -589,6 +1005,7 @@ mod test {
         let block = BlockBuilder::with_prev_header(genesis_block(network).header())
             .with_transaction(
                 TransactionBuilder::coinbase()
+                    .with_height(1)
                     .with_output(&address, 1000)
                     .build(),
             )
@@ -714,7 +1131,46 @@ mod test {
     }
 
     #[async_std::test]
-    async fn block_headers_are_not_inserted_above_instructions_threshold() {
+    async fn backfills_processing_and_audit_share_the_budget_of_a_round() {
+        let network = Network::Regtest;
+        init(Config {
+            network,
+            ..Default::default()
+        });
+
+        // A backfill is in progress and the audit is enabled, so that every step of the
+        // round has work to do.
+        with_state_mut(|s| {
+            s.stable_block_headers.start_heights_backfill();
+            s.utxo_auditor.flag = Flag::Enabled;
+        });
+
+        let next_block_headers = BlockChainBuilder::new(50)
+            .build()
+            .into_iter()
+            .skip(1)
+            .map(|b| b.header().into())
+            .collect();
+        runtime::set_successors_response(GetSuccessorsReply::Ok(GetSuccessorsResponse::Complete(
+            GetSuccessorsCompleteResponse {
+                blocks: vec![],
+                next: next_block_headers,
+            },
+        )));
+        runtime::set_performance_counter_step(1_000_000_000);
+
+        // Fetch blocks.
+        heartbeat().await;
+
+        // The round that processes the response stays within its budget, or the mock
+        // performance counter would have panicked.
+        runtime::performance_counter_reset();
+        assert_eq!(heartbeat().await, SyncOutcome::ProcessedResponse);
+        assert!(performance_counter() <= MAX_INSTRUCTIONS_PER_ROUND + 1_000_000_000);
+    }
+
+    #[async_std::test]
+    async fn block_headers_insertion_is_resumed_above_instructions_threshold() {
         let network = Network::Regtest;
 
         init(Config {
@@ -745,19 +1201,299 @@ mod test {
         // Process response.
         heartbeat().await;
 
-        // Even though there were 50 next block headers, only 30 were processed due to reaching
-        // the instruction limit threshold.
+        // Even though there were 50 next block headers, only 14 were processed due to reaching
+        // the instructions budget of the round.
         assert_eq!(
             with_state(|s| s.unstable_blocks.next_block_headers_max_height()),
-            Some(30)
+            Some(14)
         );
 
-        // Run heartbeat again.
-        heartbeat().await;
-        // There were no more block headers processed.
+        with_state(|s| {
+            assert!(s.syncing_state.response_to_process.is_some());
+            assert_eq!(
+                s.syncing_state.response_processing_cursor,
+                ResponseProcessingCursor {
+                    next_block_idx: 0,
+                    next_header_idx: 14,
+                }
+            );
+        });
+
+        // Run heartbeat again, in new messages, until the response is processed.
+        for _ in 0..3 {
+            runtime::performance_counter_reset();
+            assert_eq!(heartbeat().await, SyncOutcome::ProcessedResponse);
+        }
+
+        // The remaining block headers have been processed.
         assert_eq!(
             with_state(|s| s.unstable_blocks.next_block_headers_max_height()),
-            Some(30)
+            Some(49)
+        );
+        with_state(|s| {
+            assert_eq!(s.syncing_state.response_to_process, None);
+            assert_eq!(
+                s.syncing_state.response_processing_cursor,
+                ResponseProcessingCursor::default()
+            );
+        });
+    }
+
+    #[test]
+    fn block_insertion_is_resumed_from_the_cursor() {
+        let network = Network::Regtest;
+        init(Config {
+            network,
+            ..Default::default()
+        });
+
+        let chain = BlockChainBuilder::fork(&genesis_block(network), 4).build();
+        let response = GetSuccessorsCompleteResponse {
+            blocks: chain
+                .iter()
+                .map(|block| {
+                    let mut block_bytes = vec![];
+                    block.consensus_encode(&mut block_bytes).unwrap();
+                    block_bytes
+                })
+                .collect(),
+            next: vec![],
+        };
+
+        // Pause after inserting two blocks.
+        let mut num_calls = 0;
+        let result = with_state_mut(|s| {
+            process_response_slice(s, &response, &mut || {
+                num_calls += 1;
+                num_calls > 2
+            })
+        });
+        assert_eq!(result, Slicing::Paused(()));
+        assert_eq!(with_state(state::main_chain_height), 2);
+        assert_eq!(
+            with_state(|s| s.syncing_state.response_processing_cursor),
+            ResponseProcessingCursor {
+                next_block_idx: 2,
+                next_header_idx: 0,
+            }
+        );
+
+        // Resume and insert the remaining blocks.
+        let result = with_state_mut(|s| process_response_slice(s, &response, &mut || false));
+        assert_eq!(result, Slicing::Done(()));
+        assert_eq!(with_state(state::main_chain_height), 4);
+    }
+
+    fn encode(block: &Block) -> BlockBlob {
+        let mut block_bytes = vec![];
+        block.consensus_encode(&mut block_bytes).unwrap();
+        block_bytes
+    }
+
+    #[test]
+    fn pipelined_request_skips_blocks_pending_processing() {
+        let network = Network::Regtest;
+        init(Config {
+            network,
+            ..Default::default()
+        });
+
+        let chain = BlockChainBuilder::fork(&genesis_block(network), 3).build();
+        with_state_mut(|s| {
+            s.syncing_state.response_to_process =
+                Some(ResponseToProcess::Complete(GetSuccessorsCompleteResponse {
+                    blocks: chain.iter().map(encode).collect(),
+                    next: vec![],
+                }));
+
+            // The first block has already been inserted into the tree.
+            state::insert_block(s, chain[0].clone()).unwrap();
+            s.syncing_state.response_processing_cursor = ResponseProcessingCursor {
+                next_block_idx: 1,
+                next_header_idx: 0,
+            };
+        });
+
+        assert_eq!(
+            maybe_get_successors_request(),
+            Some(GetSuccessorsRequest::Initial(GetSuccessorsRequestInitial {
+                network,
+                anchor: genesis_block(network).block_hash(),
+                processed_block_hashes: chain.iter().map(|b| b.block_hash()).collect(),
+            }))
+        );
+    }
+
+    #[test]
+    fn saves_pipelined_responses_behind_the_response_to_process() {
+        let network = Network::Regtest;
+        init(Config {
+            network,
+            ..Default::default()
+        });
+
+        let chain = BlockChainBuilder::fork(&genesis_block(network), 2).build();
+        let block_bytes = encode(&chain[1]);
+        with_state_mut(|s| {
+            s.syncing_state.response_to_process =
+                Some(ResponseToProcess::Complete(GetSuccessorsCompleteResponse {
+                    blocks: vec![encode(&chain[0])],
+                    next: vec![],
+                }));
+
+            // A partial response is saved behind the response that's being processed.
+            assert!(save_response(
+                &mut s.syncing_state,
+                GetSuccessorsResponse::Partial(GetSuccessorsPartialResponse {
+                    partial_block: block_bytes[0..40].to_vec(),
+                    next: vec![],
+                    remaining_follow_ups: 1,
+                })
+            ));
+        });
+
+        // The follow-up completes the pipelined response.
+        assert_eq!(
+            maybe_get_successors_request(),
+            Some(GetSuccessorsRequest::FollowUp(0))
+        );
+        with_state_mut(|s| {
+            assert!(save_response(
+                &mut s.syncing_state,
+                GetSuccessorsResponse::FollowUp(block_bytes[40..].to_vec())
+            ))
+        });
+        assert_eq!(maybe_get_successors_request(), None);
+
+        // Once the first response is processed, the pipelined response is processed next.
+        assert!(maybe_process_response(performance_counter()));
+        with_state(|s| {
+            assert_eq!(
+                s.syncing_state.response_to_process,
+                Some(ResponseToProcess::Complete(GetSuccessorsCompleteResponse {
+                    blocks: vec![block_bytes.clone()],
+                    next: vec![],
+                }))
+            );
+            assert_eq!(s.syncing_state.pipelined_response, None);
+        });
+        assert!(maybe_process_response(performance_counter()));
+        assert_eq!(with_state(state::main_chain_height), 2);
+    }
+
+    #[async_std::test]
+    async fn pipelining_increases_sync_throughput() {
+        // Simulates syncing a chain with one block per response, and counts the rounds
+        // needed to ingest it.
+        const NUM_BLOCKS: u32 = 20;
+        let network = Network::Regtest;
+        init(Config {
+            stability_threshold: 0,
+            network,
+            ..Default::default()
+        });
+
+        let chain = BlockChainBuilder::fork(&genesis_block(network), NUM_BLOCKS).build();
+        runtime::set_successors_responses(
+            chain
+                .iter()
+                .map(|block| {
+                    GetSuccessorsReply::Ok(GetSuccessorsResponse::Complete(
+                        GetSuccessorsCompleteResponse {
+                            blocks: vec![encode(block)],
+                            next: vec![],
+                        },
+                    ))
+                })
+                .collect(),
+        );
+
+        let mut num_rounds = 0;
+        while with_state(|s| s.utxos.next_height()) < NUM_BLOCKS {
+            heartbeat().await;
+            num_rounds += 1;
+            assert!(num_rounds <= 3 * NUM_BLOCKS, "syncing is stuck");
+        }
+
+        // Without pipelining, every block takes three rounds: one to fetch it, one to
+        // process it, and one to ingest it. With pipelining, the next block is fetched
+        // while the previous one is processed or ingested, which saves a round per block.
+        assert_eq!(num_rounds, 2 * NUM_BLOCKS + 1);
+    }
+
+    #[async_std::test]
+    async fn fails_over_to_the_next_block_source_on_reject() {
+        let fallback = candid::Principal::anonymous();
+        init(Config {
+            stability_threshold: 0,
+            network: Network::Regtest,
+            fallback_blocks_sources: Some(vec![fallback]),
+            ..Default::default()
+        });
+
+        runtime::set_successors_responses(vec![
+            GetSuccessorsReply::Err(RejectionCode::SysTransient, String::from("Unavailable.")),
+            GetSuccessorsReply::Ok(GetSuccessorsResponse::Complete(
+                GetSuccessorsCompleteResponse {
+                    blocks: vec![],
+                    next: vec![],
+                },
+            )),
+        ]);
+
+        assert_eq!(
+            heartbeat().await,
+            SyncOutcome::FetchedBlocks {
+                has_new_blocks: false
+            }
+        );
+
+        // The primary source is backing off, so the next request is sent to the fallback.
+        assert_eq!(
+            heartbeat().await,
+            SyncOutcome::FetchedBlocks {
+                has_new_blocks: false
+            }
+        );
+        with_state(|s| {
+            let sources: Vec<_> = s
+                .blocks_sources
+                .iter()
+                .map(|source| (source.principal, source.num_calls, source.num_rejects))
+                .collect();
+            assert_eq!(
+                sources,
+                vec![
+                    (candid::Principal::management_canister(), 1, 1),
+                    (fallback, 1, 0)
+                ]
+            );
+        });
+    }
+
+    #[async_std::test]
+    async fn does_not_fetch_blocks_while_all_sources_are_backing_off() {
+        init(Config {
+            stability_threshold: 0,
+            network: Network::Regtest,
+            ..Default::default()
+        });
+
+        // Back off for longer than the test can take.
+        with_state_mut(|s| {
+            for _ in 0..10 {
+                s.blocks_sources.record_reject(
+                    candid::Principal::management_canister(),
+                    runtime::time(),
+                    0,
+                );
+            }
+        });
+
+        assert_eq!(heartbeat().await, SyncOutcome::Idle);
+        assert_eq!(
+            runtime::GET_SUCCESSORS_RESPONSES_INDEX.with(|i| *i.borrow()),
+            0
         );
     }
 }
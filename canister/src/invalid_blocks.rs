@@ -0,0 +1,107 @@
+use ic_btc_types::BlockHash;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, VecDeque};
+
+/// The maximum number of block hashes kept in the cache.
+const MAX_INVALID_BLOCKS: usize = 1_000;
+
+/// A bounded cache of the hashes of blocks (and block headers) that are known to be invalid.
+///
+/// Blocks that are known to be invalid, or that descend from one, are rejected without
+/// running any validation. Once the cache is full, the oldest hashes are evicted first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InvalidBlocks {
+    hashes: BTreeSet<BlockHash>,
+    // The hashes in the order they were inserted, used for eviction.
+    insertion_order: VecDeque<BlockHash>,
+}
+
+impl InvalidBlocks {
+    /// Records the given block hash as invalid.
+    pub fn insert(&mut self, block_hash: BlockHash) {
+        if !self.hashes.insert(block_hash.clone()) {
+            // The block hash is already in the cache.
+            return;
+        }
+
+        self.insertion_order.push_back(block_hash);
+        if self.insertion_order.len() > MAX_INVALID_BLOCKS {
+            let evicted = self
+                .insertion_order
+                .pop_front()
+                .expect("insertion order cannot be empty");
+            self.hashes.remove(&evicted);
+        }
+    }
+
+    /// Returns true if the given block hash is known to be invalid.
+    pub fn contains(&self, block_hash: &BlockHash) -> bool {
+        self.hashes.contains(block_hash)
+    }
+
+    /// Returns the number of block hashes in the cache.
+    pub fn len(&self) -> usize {
+        self.insertion_order.len()
+    }
+
+    /// Returns true if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.insertion_order.is_empty()
+    }
+
+    /// Returns an iterator over the block hashes in the cache, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &BlockHash> {
+        self.insertion_order.iter()
+    }
+
+    /// Removes all the block hashes from the cache.
+    pub fn clear(&mut self) {
+        self.hashes.clear();
+        self.insertion_order.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn block_hash(i: usize) -> BlockHash {
+        let mut bytes = vec![0; 32];
+        bytes[..8].copy_from_slice(&(i as u64).to_le_bytes());
+        BlockHash::from(bytes)
+    }
+
+    #[test]
+    fn insert_and_clear() {
+        let mut invalid_blocks = InvalidBlocks::default();
+        invalid_blocks.insert(block_hash(1));
+        invalid_blocks.insert(block_hash(2));
+        invalid_blocks.insert(block_hash(1));
+
+        assert_eq!(invalid_blocks.len(), 2);
+        assert!(invalid_blocks.contains(&block_hash(1)));
+        assert!(invalid_blocks.contains(&block_hash(2)));
+        assert!(!invalid_blocks.contains(&block_hash(3)));
+
+        invalid_blocks.clear();
+        assert!(invalid_blocks.is_empty());
+        assert!(!invalid_blocks.contains(&block_hash(1)));
+    }
+
+    #[test]
+    fn oldest_hashes_are_evicted() {
+        let mut invalid_blocks = InvalidBlocks::default();
+        for i in 0..MAX_INVALID_BLOCKS + 10 {
+            invalid_blocks.insert(block_hash(i));
+        }
+
+        assert_eq!(invalid_blocks.len(), MAX_INVALID_BLOCKS);
+        for i in 0..10 {
+            assert!(!invalid_blocks.contains(&block_hash(i)));
+        }
+        for i in 10..MAX_INVALID_BLOCKS + 10 {
+            assert!(invalid_blocks.contains(&block_hash(i)));
+        }
+        assert_eq!(invalid_blocks.iter().next(), Some(&block_hash(10)));
+    }
+}
@@ -1,13 +1,17 @@
 mod address_utxoset;
 mod api;
 mod block_header_store;
+mod block_sources;
 mod blocktree;
 mod guard;
 mod heartbeat;
+mod invalid_blocks;
 pub mod memory;
+mod memory_limits;
 mod metrics;
 mod multi_iter;
 pub mod runtime;
+mod scheduler;
 pub mod state;
 #[cfg(test)]
 mod test_utils;
@@ -15,24 +19,33 @@ mod test_utils;
 mod tests;
 pub mod types;
 pub mod unstable_blocks;
+mod upgrade;
 mod utxo_set;
 mod validation;
 
 use crate::{
+    block_sources::BlockSources,
     runtime::{msg_cycles_accept, msg_cycles_available},
     state::State,
     types::{into_bitcoin_network, HttpRequest, HttpResponse},
 };
+pub use api::get_block_height;
+pub use api::get_deployments;
 pub use api::get_metrics;
+pub use api::get_utxo_audit_report;
 pub use api::send_transaction;
 pub use api::set_config;
+pub use api::{clear_invalid_blocks, get_invalid_blocks};
+pub use api::{get_audit_log, invalidate_block, reconsider_block};
+pub use api::{get_height_by_timestamp, get_median_time_past};
+pub use api::{get_stable_memory_chunk, get_stable_memory_manifest, take_stable_memory_snapshot};
 pub use heartbeat::heartbeat;
 use ic_btc_interface::{
     Config, Flag, GetBalanceError, GetBalanceRequest, GetCurrentFeePercentilesRequest,
-    GetUtxosError, GetUtxosRequest, GetUtxosResponse, MillisatoshiPerByte, Network, Satoshi,
+    GetUtxosError, GetUtxosRequest, GetUtxosResponse, MemoryLimits, MillisatoshiPerByte, Network,
+    Satoshi, SyncIntervals,
 };
 use ic_btc_types::Block;
-use ic_stable_structures::Memory;
 pub use memory::get_memory;
 use serde_bytes::ByteBuf;
 use state::main_chain_height;
@@ -88,12 +101,51 @@ pub fn init(config: Config) {
         genesis_block(config.network),
     ));
 
-    with_state_mut(|s| s.blocks_source = config.blocks_source);
     with_state_mut(|s| s.api_access = config.api_access);
     with_state_mut(|s| s.syncing_state.syncing = config.syncing);
     with_state_mut(|s| s.disable_api_if_not_fully_synced = config.disable_api_if_not_fully_synced);
     with_state_mut(|s| s.watchdog_canister = config.watchdog_canister);
-    with_state_mut(|s| s.fees = config.fees);
+    let blocks_sources = BlockSources::new(
+        config.blocks_source,
+        config.fallback_blocks_sources.unwrap_or_default(),
+    );
+    with_state_mut(|s| s.blocks_sources = blocks_sources);
+    let fees = config.fees;
+    with_state_mut(|s| s.fees = fees);
+    if let Some(utxo_audit) = config.utxo_audit {
+        with_state_mut(|s| s.utxo_auditor.flag = utxo_audit);
+    }
+    if let Some(sync_intervals) = config.sync_intervals {
+        verify_sync_intervals(&sync_intervals);
+        with_state_mut(|s| s.syncing_state.sync_intervals = sync_intervals);
+    }
+    let fork_pruning = config.fork_pruning;
+    with_state_mut(|s| s.unstable_blocks.set_fork_pruning(fork_pruning));
+    if let Some(memory_limits) = config.memory_limits {
+        verify_memory_limits(&memory_limits);
+        with_state_mut(|s| s.memory_limits = memory_limits);
+    }
+
+    let signet_challenge = config.signet_challenge;
+    if let Some(challenge) = &signet_challenge {
+        assert_eq!(
+            config.network,
+            Network::Signet,
+            "A signet challenge can only be set on signet."
+        );
+        assert!(
+            ic_btc_validation::validate_signet_challenge(&bitcoin::Script::from(challenge.clone()))
+                .is_ok(),
+            "The signet challenge isn't supported."
+        );
+    }
+    with_state_mut(|s| s.signet_challenge = signet_challenge);
+
+    if let Some(checkpoints) = config.checkpoints {
+        with_state_mut(|s| s.checkpoints = checkpoints.into_iter().map(Into::into).collect());
+    }
+
+    scheduler::start();
 }
 
 pub fn get_current_fee_percentiles(
@@ -137,45 +189,31 @@ pub fn get_config() -> Config {
     with_state(|s| Config {
         stability_threshold: s.unstable_blocks.stability_threshold() as u128,
         syncing: s.syncing_state.syncing,
-        blocks_source: s.blocks_source,
+        blocks_source: s.blocks_sources.primary(),
+        fallback_blocks_sources: Some(s.blocks_sources.fallbacks()),
         network: s.network(),
         fees: s.fees.clone(),
         api_access: s.api_access,
         disable_api_if_not_fully_synced: s.disable_api_if_not_fully_synced,
         watchdog_canister: s.watchdog_canister,
+        signet_challenge: s.signet_challenge.clone(),
+        checkpoints: Some(s.checkpoints.iter().cloned().map(Into::into).collect()),
+        utxo_audit: Some(s.utxo_auditor.flag),
+        sync_intervals: Some(s.syncing_state.sync_intervals),
+        memory_limits: Some(s.memory_limits.clone()),
+        fork_pruning: s.unstable_blocks.fork_pruning(),
     })
 }
 
 pub fn pre_upgrade() {
-    // Serialize the state.
-    let mut state_bytes = vec![];
-    with_state(|state| ciborium::ser::into_writer(state, &mut state_bytes))
-        .expect("failed to encode state");
-
-    // Write the length of the serialized bytes to memory, followed by the
-    // by the bytes themselves.
-    let len = state_bytes.len() as u32;
-    let memory = memory::get_upgrades_memory();
-    crate::memory::write(&memory, 0, &len.to_le_bytes());
-    crate::memory::write(&memory, 4, &state_bytes);
+    with_state(upgrade::write_state);
 }
 
 pub fn post_upgrade() {
-    let memory = memory::get_upgrades_memory();
-
-    // Read the length of the state bytes.
-    let mut state_len_bytes = [0; 4];
-    memory.read(0, &mut state_len_bytes);
-    let state_len = u32::from_le_bytes(state_len_bytes) as usize;
+    set_state(upgrade::read_state());
 
-    // Read the bytes
-    let mut state_bytes = vec![0; state_len];
-    memory.read(4, &mut state_bytes);
-
-    // Deserialize and set the state.
-    let state: State = ciborium::de::from_reader(&*state_bytes).expect("failed to decode state");
-
-    set_state(state);
+    // Timers don't survive upgrades, so syncing is restarted.
+    scheduler::start();
 }
 
 pub fn http_request(req: HttpRequest) -> HttpResponse {
@@ -192,9 +230,60 @@ pub fn http_request(req: HttpRequest) -> HttpResponse {
 
 /// Returns the genesis block of the given network.
 pub(crate) fn genesis_block(network: Network) -> Block {
-    Block::new(bitcoin::blockdata::constants::genesis_block(
-        into_bitcoin_network(network),
-    ))
+    match network {
+        Network::Mainnet | Network::Testnet | Network::Regtest | Network::Signet => Block::new(
+            bitcoin::blockdata::constants::genesis_block(into_bitcoin_network(network)),
+        ),
+        // Testnet4 isn't known to the `bitcoin` crate, so its genesis block is built here.
+        Network::Testnet4 => Block::new(testnet4_genesis_block()),
+    }
+}
+
+/// Returns the genesis block of testnet4, as defined in Bitcoin Core's chainparams.
+fn testnet4_genesis_block() -> bitcoin::Block {
+    use bitcoin::{
+        blockdata::{constants::COIN_VALUE, opcodes, script},
+        hashes::sha256d,
+        BlockHeader, OutPoint, Transaction, TxIn, TxOut, Witness,
+    };
+
+    let script_sig = script::Builder::new()
+        .push_scriptint(486604799)
+        .push_scriptint(4)
+        .push_slice(b"03/May/2024 000000000000000000001ebd58c244970b3aa9d783bb001011fbe8ea8e98e00e")
+        .into_script();
+    let script_pubkey = script::Builder::new()
+        .push_slice(&[0; 33])
+        .push_opcode(opcodes::all::OP_CHECKSIG)
+        .into_script();
+
+    let coinbase = Transaction {
+        version: 1,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig,
+            sequence: u32::MAX,
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: 50 * COIN_VALUE,
+            script_pubkey,
+        }],
+    };
+
+    let merkle_root: sha256d::Hash = coinbase.txid().into();
+    bitcoin::Block {
+        header: BlockHeader {
+            version: 1,
+            prev_blockhash: Default::default(),
+            merkle_root: merkle_root.into(),
+            time: 1714777860,
+            bits: 0x1d00ffff,
+            nonce: 393743547,
+        },
+        txdata: vec![coinbase],
+    }
 }
 
 pub(crate) fn charge_cycles(amount: u128) {
@@ -230,12 +319,86 @@ fn verify_network(network: Network) {
     });
 }
 
+// Verifies that the backoff of the sync intervals is well-formed.
+pub(crate) fn verify_sync_intervals(sync_intervals: &SyncIntervals) {
+    assert!(
+        sync_intervals.idle_min_ms <= sync_intervals.idle_max_ms,
+        "idle_min_ms must not be greater than idle_max_ms."
+    );
+}
+
+// Verifies that the memory limits are well-formed.
+pub(crate) fn verify_memory_limits(memory_limits: &MemoryLimits) {
+    let limits = memory_limits
+        .heap
+        .iter()
+        .chain(memory_limits.stable_memories.iter().map(|(_, limit)| limit));
+    for limit in limits {
+        assert!(
+            limit.soft_limit_bytes <= limit.hard_limit_bytes,
+            "soft_limit_bytes must not be greater than hard_limit_bytes."
+        );
+    }
+
+    for (memory_id, _) in memory_limits.stable_memories.iter() {
+        assert!(
+            *memory_id < memory::NUM_MEMORIES,
+            "Unknown memory id {}.",
+            memory_id
+        );
+    }
+}
+
+/// Verifies that the caller is a controller of the canister.
+pub(crate) async fn verify_controller(method_name: &str) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        use ic_cdk::api::management_canister::main::CanisterIdRecord;
+
+        let caller = ic_cdk::caller();
+        let controllers =
+            ic_cdk::api::management_canister::main::canister_status(CanisterIdRecord {
+                canister_id: ic_cdk::api::id(),
+            })
+            .await
+            .unwrap()
+            .0
+            .settings
+            .controllers;
+
+        if !controllers.contains(&caller) {
+            panic!("Only controllers can call {}", method_name);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = method_name;
+}
+
+/// Verifies that the caller is a controller without making any calls, which makes it usable
+/// in queries.
+pub(crate) fn verify_controller_query(method_name: &str) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+            panic!("Only controllers can call {}", method_name);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = method_name;
+}
+
 // Verifies that the access to bitcoin apis is enabled.
 fn verify_api_access() {
     with_state(|state| {
         if state.api_access == Flag::Disabled {
             panic!("Bitcoin API is disabled");
         }
+
+        if let Some(reason) = memory_limits::hard_limit_exceeded(&state.memory_limits) {
+            panic!("{}", reason);
+        }
     });
 }
 
@@ -284,6 +447,8 @@ mod test {
                 Just(Network::Mainnet),
                 Just(Network::Testnet),
                 Just(Network::Regtest),
+                Just(Network::Signet),
+                Just(Network::Testnet4),
             ],
         ) {
             init(Config {
@@ -306,7 +471,7 @@ mod test {
         fn upgrade(
             stability_threshold in 1..100u128,
             num_blocks in 1..250u32,
-            num_transactions_in_block in 1..100u32,
+            num_outputs_in_block in 1..100u32,
         ) {
             let network = Network::Regtest;
 
@@ -316,7 +481,7 @@ mod test {
                 ..Default::default()
             });
 
-            let blocks = build_regtest_chain(num_blocks, num_transactions_in_block);
+            let blocks = build_regtest_chain(num_blocks, num_outputs_in_block);
 
             // Insert all the blocks. Note that we skip the genesis block, as that
             // is already included as part of initializing the state.
@@ -341,6 +506,22 @@ mod test {
         }
     }
 
+    #[test]
+    fn testnet4_genesis_block_is_correct() {
+        // https://mempool.space/testnet4/block/0
+        assert_eq!(
+            genesis_block(Network::Testnet4).block_hash().to_string(),
+            "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043"
+        );
+        assert_eq!(
+            genesis_block(Network::Testnet4)
+                .header()
+                .merkle_root
+                .to_string(),
+            "7aa0a7ae1e223414cb807e40cd57e667b718e42aaf9306db9102fe28912b7b4e"
+        );
+    }
+
     #[test]
     #[should_panic(expected = "Network must be mainnet. Found testnet")]
     fn get_balance_incorrect_network() {
@@ -385,6 +566,7 @@ mod test {
             address: String::from(""),
             network: NetworkInRequest::Testnet,
             filter: None,
+            sort_order: None,
         })
         .unwrap();
     }
@@ -401,6 +583,7 @@ mod test {
             address: String::from(""),
             network: NetworkInRequest::Testnet,
             filter: None,
+            sort_order: None,
         })
         .unwrap();
     }
@@ -478,6 +661,7 @@ mod test {
             address: String::from(""),
             network: NetworkInRequest::Mainnet,
             filter: None,
+            sort_order: None,
         })
         .unwrap();
     }
@@ -495,6 +679,7 @@ mod test {
             address: String::from(""),
             network: NetworkInRequest::Mainnet,
             filter: None,
+            sort_order: None,
         })
         .unwrap();
     }
@@ -513,6 +698,32 @@ mod test {
         });
     }
 
+    #[test]
+    #[should_panic(
+        expected = "Bitcoin API is disabled because heap is 2000 bytes, which is past its hard limit of 1000 bytes."
+    )]
+    fn get_balance_disabled_past_the_hard_memory_limit() {
+        init(Config {
+            stability_threshold: 0,
+            network: Network::Mainnet,
+            memory_limits: Some(MemoryLimits {
+                heap: Some(ic_btc_interface::MemoryLimit {
+                    soft_limit_bytes: 500,
+                    hard_limit_bytes: 1_000,
+                }),
+                stable_memories: vec![],
+            }),
+            ..Default::default()
+        });
+        runtime::set_heap_size(2_000);
+        get_balance(GetBalanceRequest {
+            address: String::from(""),
+            network: NetworkInRequest::Mainnet,
+            min_confirmations: None,
+        })
+        .unwrap();
+    }
+
     #[test]
     fn init_sets_syncing_flag() {
         init(Config {
@@ -1,10 +1,12 @@
 use ic_btc_canister::types::{HttpRequest, HttpResponse};
 use ic_btc_interface::{
-    Config, GetBalanceRequest, GetCurrentFeePercentilesRequest, GetUtxosRequest,
-    MillisatoshiPerByte, SendTransactionRequest, SetConfigRequest,
+    AuditLogEntry, BlockHash, Config, Deployment, GetBalanceRequest,
+    GetCurrentFeePercentilesRequest, GetStableMemoryChunkRequest, GetUtxosRequest, Height,
+    MillisatoshiPerByte, SendTransactionRequest, SetConfigRequest, StableMemoryManifest,
+    UtxoAuditReport,
 };
 use ic_cdk::api::call::{reject, reply};
-use ic_cdk_macros::{heartbeat, init, inspect_message, post_upgrade, pre_upgrade, query, update};
+use ic_cdk_macros::{init, inspect_message, post_upgrade, pre_upgrade, query, update};
 
 #[cfg(target_arch = "wasm32")]
 mod printer;
@@ -31,11 +33,6 @@ fn post_upgrade() {
     ic_btc_canister::post_upgrade();
 }
 
-#[heartbeat]
-async fn heartbeat() {
-    ic_btc_canister::heartbeat().await
-}
-
 #[update(manual_reply = true)]
 pub fn bitcoin_get_balance(request: GetBalanceRequest) {
     match ic_btc_canister::get_balance(request) {
@@ -101,6 +98,86 @@ async fn set_config(request: SetConfigRequest) {
     ic_btc_canister::set_config(request).await
 }
 
+#[query]
+pub fn get_invalid_blocks() -> Vec<BlockHash> {
+    ic_btc_canister::get_invalid_blocks()
+}
+
+#[update]
+async fn clear_invalid_blocks() {
+    ic_btc_canister::clear_invalid_blocks().await
+}
+
+#[update(manual_reply = true)]
+async fn invalidate_block(block_hash: BlockHash) {
+    match ic_btc_canister::invalidate_block(block_hash).await {
+        Ok(()) => reply(()),
+        Err(e) => reject(format!("invalidate_block failed: {}", e).as_str()),
+    }
+}
+
+#[update(manual_reply = true)]
+async fn reconsider_block(block_hash: BlockHash) {
+    match ic_btc_canister::reconsider_block(block_hash).await {
+        Ok(()) => reply(()),
+        Err(e) => reject(format!("reconsider_block failed: {}", e).as_str()),
+    }
+}
+
+#[query]
+pub fn get_audit_log() -> Vec<AuditLogEntry> {
+    ic_btc_canister::get_audit_log()
+}
+
+#[query]
+pub fn get_deployments() -> Vec<Deployment> {
+    ic_btc_canister::get_deployments()
+}
+
+#[query(manual_reply = true)]
+pub fn get_median_time_past(height: Option<Height>) {
+    match ic_btc_canister::get_median_time_past(height) {
+        Ok(median_time_past) => reply((median_time_past,)),
+        Err(e) => reject(format!("get_median_time_past failed: {}", e).as_str()),
+    }
+}
+
+#[query(manual_reply = true)]
+pub fn get_block_height(block_hash: BlockHash) {
+    match ic_btc_canister::get_block_height(block_hash) {
+        Ok(response) => reply((response,)),
+        Err(e) => reject(format!("get_block_height failed: {}", e).as_str()),
+    }
+}
+
+#[query]
+pub fn get_height_by_timestamp(timestamp: u32) -> Option<Height> {
+    ic_btc_canister::get_height_by_timestamp(timestamp)
+}
+
+#[update]
+async fn take_stable_memory_snapshot() {
+    ic_btc_canister::take_stable_memory_snapshot().await
+}
+
+#[query]
+pub fn get_stable_memory_manifest() -> StableMemoryManifest {
+    ic_btc_canister::get_stable_memory_manifest()
+}
+
+#[query(manual_reply = true)]
+pub fn get_stable_memory_chunk(request: GetStableMemoryChunkRequest) {
+    match ic_btc_canister::get_stable_memory_chunk(request) {
+        Ok(chunk) => reply((chunk,)),
+        Err(e) => reject(format!("get_stable_memory_chunk failed: {}", e).as_str()),
+    }
+}
+
+#[query]
+pub fn get_utxo_audit_report() -> Option<UtxoAuditReport> {
+    ic_btc_canister::get_utxo_audit_report()
+}
+
 #[query]
 pub fn http_request(request: HttpRequest) -> HttpResponse {
     ic_btc_canister::http_request(request)
@@ -17,6 +17,19 @@ const MEDIUM_UTXOS: MemoryId = MemoryId::new(3);
 const BALANCES: MemoryId = MemoryId::new(4);
 const BLOCK_HEADERS: MemoryId = MemoryId::new(5);
 const BLOCK_HEIGHTS: MemoryId = MemoryId::new(6);
+const ADDRESS_UTXOS_BY_VALUE: MemoryId = MemoryId::new(7);
+const BLOCK_HASH_TO_HEIGHT: MemoryId = MemoryId::new(8);
+const UNSTABLE_BLOCK_BODIES: MemoryId = MemoryId::new(9);
+const UNSTABLE_TX_OUTS: MemoryId = MemoryId::new(10);
+const UNSTABLE_ADDED_OUTPOINTS: MemoryId = MemoryId::new(11);
+const UNSTABLE_REMOVED_OUTPOINTS: MemoryId = MemoryId::new(12);
+const LARGE_UTXOS: MemoryId = MemoryId::new(13);
+const ADDRESS_UTXOS_BY_HEIGHT_ASCENDING: MemoryId = MemoryId::new(14);
+
+/// The number of memories in use, which have ids `0..NUM_MEMORIES`.
+///
+/// NOTE: Must be updated whenever a new memory is added.
+pub const NUM_MEMORIES: u8 = 15;
 
 #[cfg(feature = "file_memory")]
 type InnerMemory = FileMemory;
@@ -96,6 +109,47 @@ pub fn get_block_heights_memory() -> Memory {
     with_memory_manager(|m| m.get(BLOCK_HEIGHTS))
 }
 
+pub fn get_address_utxos_by_value_memory() -> Memory {
+    with_memory_manager(|m| m.get(ADDRESS_UTXOS_BY_VALUE))
+}
+
+pub fn get_block_hash_to_height_memory() -> Memory {
+    with_memory_manager(|m| m.get(BLOCK_HASH_TO_HEIGHT))
+}
+
+pub fn get_unstable_block_bodies_memory() -> Memory {
+    with_memory_manager(|m| m.get(UNSTABLE_BLOCK_BODIES))
+}
+
+pub fn get_unstable_tx_outs_memory() -> Memory {
+    with_memory_manager(|m| m.get(UNSTABLE_TX_OUTS))
+}
+
+pub fn get_unstable_added_outpoints_memory() -> Memory {
+    with_memory_manager(|m| m.get(UNSTABLE_ADDED_OUTPOINTS))
+}
+
+pub fn get_unstable_removed_outpoints_memory() -> Memory {
+    with_memory_manager(|m| m.get(UNSTABLE_REMOVED_OUTPOINTS))
+}
+
+pub fn get_large_utxos_memory() -> Memory {
+    with_memory_manager(|m| m.get(LARGE_UTXOS))
+}
+
+pub fn get_address_utxos_by_height_ascending_memory() -> Memory {
+    with_memory_manager(|m| m.get(ADDRESS_UTXOS_BY_HEIGHT_ASCENDING))
+}
+
+/// Returns the memory with the given id, or `None` if the id isn't in use.
+pub fn get_memory_by_id(memory_id: u8) -> Option<Memory> {
+    if memory_id < NUM_MEMORIES {
+        Some(with_memory_manager(|m| m.get(MemoryId::new(memory_id))))
+    } else {
+        None
+    }
+}
+
 /// Writes the bytes at the specified offset, growing the memory size if needed.
 pub fn write<M: MemoryTrait>(memory: &M, offset: u64, bytes: &[u8]) {
     let last_byte = offset
@@ -0,0 +1,162 @@
+use crate::{memory, runtime};
+use ic_btc_interface::{MemoryLimit, MemoryLimits};
+use ic_stable_structures::Memory as _;
+
+const WASM_PAGE_SIZE: u64 = 65536;
+
+/// How the memory used by the canister compares to its limits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryPressure {
+    /// All the memories are within their soft limits.
+    Normal,
+
+    /// A memory is past its soft limit, so no new blocks are fetched.
+    AboveSoftLimit,
+
+    /// A memory is past its hard limit, so the API is disabled.
+    AboveHardLimit,
+}
+
+/// The size of a memory that has limits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// The name of the memory, i.e. "heap" or "stable_memory_<id>".
+    pub name: String,
+
+    /// The size of the memory in bytes.
+    pub size_bytes: u64,
+
+    /// The limits of the memory.
+    pub limit: MemoryLimit,
+}
+
+impl MemoryUsage {
+    /// Returns how the size of the memory compares to its limits.
+    pub fn pressure(&self) -> MemoryPressure {
+        if self.size_bytes > self.limit.hard_limit_bytes {
+            MemoryPressure::AboveHardLimit
+        } else if self.size_bytes > self.limit.soft_limit_bytes {
+            MemoryPressure::AboveSoftLimit
+        } else {
+            MemoryPressure::Normal
+        }
+    }
+}
+
+/// Returns the sizes of the memories that have limits.
+pub fn memory_usages(limits: &MemoryLimits) -> Vec<MemoryUsage> {
+    let heap = limits.heap.map(|limit| MemoryUsage {
+        name: String::from("heap"),
+        size_bytes: runtime::heap_size(),
+        limit,
+    });
+
+    let stable_memories = limits.stable_memories.iter().map(|(memory_id, limit)| {
+        let size_pages = memory::get_memory_by_id(*memory_id)
+            .map(|memory| memory.size())
+            .unwrap_or(0);
+        MemoryUsage {
+            name: format!("stable_memory_{}", memory_id),
+            size_bytes: size_pages * WASM_PAGE_SIZE,
+            limit: *limit,
+        }
+    });
+
+    heap.into_iter().chain(stable_memories).collect()
+}
+
+/// Returns the highest pressure across all the memories that have limits.
+pub fn memory_pressure(limits: &MemoryLimits) -> MemoryPressure {
+    memory_usages(limits)
+        .iter()
+        .map(MemoryUsage::pressure)
+        .max()
+        .unwrap_or(MemoryPressure::Normal)
+}
+
+/// Returns the reason the API is disabled if any memory is past its hard limit, None otherwise.
+pub fn hard_limit_exceeded(limits: &MemoryLimits) -> Option<String> {
+    memory_usages(limits)
+        .into_iter()
+        .find(|usage| usage.pressure() == MemoryPressure::AboveHardLimit)
+        .map(|usage| {
+            format!(
+                "Bitcoin API is disabled because {} is {} bytes, which is past its hard limit of {} bytes.",
+                usage.name, usage.size_bytes, usage.limit.hard_limit_bytes
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limit(soft_limit_bytes: u64, hard_limit_bytes: u64) -> MemoryLimit {
+        MemoryLimit {
+            soft_limit_bytes,
+            hard_limit_bytes,
+        }
+    }
+
+    #[test]
+    fn no_limits_means_no_pressure() {
+        runtime::set_heap_size(u64::MAX);
+        assert_eq!(
+            memory_pressure(&MemoryLimits::default()),
+            MemoryPressure::Normal
+        );
+        assert_eq!(hard_limit_exceeded(&MemoryLimits::default()), None);
+    }
+
+    #[test]
+    fn heap_pressure() {
+        let limits = MemoryLimits {
+            heap: Some(limit(100, 200)),
+            stable_memories: vec![],
+        };
+
+        for (heap_size, pressure) in [
+            (0, MemoryPressure::Normal),
+            (100, MemoryPressure::Normal),
+            (101, MemoryPressure::AboveSoftLimit),
+            (200, MemoryPressure::AboveSoftLimit),
+            (201, MemoryPressure::AboveHardLimit),
+        ] {
+            runtime::set_heap_size(heap_size);
+            assert_eq!(memory_pressure(&limits), pressure);
+        }
+
+        assert_eq!(
+            hard_limit_exceeded(&limits),
+            Some(String::from(
+                "Bitcoin API is disabled because heap is 201 bytes, which is past its hard limit of 200 bytes."
+            ))
+        );
+    }
+
+    #[test]
+    fn stable_memory_pressure() {
+        runtime::set_heap_size(0);
+        let limits = MemoryLimits {
+            heap: Some(limit(100, 200)),
+            stable_memories: vec![(0, limit(0, 2 * WASM_PAGE_SIZE))],
+        };
+
+        let upgrades_memory = memory::get_upgrades_memory();
+        memory::write(&upgrades_memory, 0, &[1]);
+        assert_eq!(memory_pressure(&limits), MemoryPressure::AboveSoftLimit);
+
+        memory::write(&upgrades_memory, 2 * WASM_PAGE_SIZE, &[1]);
+        assert_eq!(memory_pressure(&limits), MemoryPressure::AboveHardLimit);
+        assert_eq!(
+            memory_usages(&limits)
+                .into_iter()
+                .map(|usage| (usage.name, usage.size_bytes))
+                .collect::<Vec<_>>(),
+            vec![
+                (String::from("heap"), 0),
+                (String::from("stable_memory_0"), 3 * WASM_PAGE_SIZE)
+            ]
+        );
+    }
+}
@@ -26,6 +26,14 @@ pub struct Metrics {
 
     /// Instructions needed to insert a block into the pool of unstable blocks.
     pub block_insertion: InstructionHistogram,
+
+    /// Instructions needed to process a slice of a `GetSuccessorsResponse`.
+    #[serde(default = "response_processing_histogram")]
+    pub response_processing: InstructionHistogram,
+
+    /// The total number of unstable blocks that were pruned from stale forks.
+    #[serde(default)]
+    pub num_pruned_blocks: u64,
 }
 
 impl Default for Metrics {
@@ -66,10 +74,20 @@ impl Default for Metrics {
                 "ins_block_insertion",
                 "Instructions needed to insert a block into the pool of unstable blocks.",
             ),
+
+            response_processing: response_processing_histogram(),
+            num_pruned_blocks: 0,
         }
     }
 }
 
+fn response_processing_histogram() -> InstructionHistogram {
+    InstructionHistogram::new(
+        "ins_response_processing",
+        "Instructions needed to process a slice of a GetSuccessors response.",
+    )
+}
+
 /// A histogram for observing instruction counts.
 ///
 /// The histogram observes the values in buckets of:
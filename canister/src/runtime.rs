@@ -12,10 +12,11 @@ use serde::Deserialize;
 #[cfg(not(target_arch = "wasm32"))]
 use std::cell::RefCell;
 use std::future::Future;
+use std::time::Duration;
 
-// The instruction limit in system subnets is 50B.
+// The instructions limit of a round of syncing is 20B.
 #[cfg(not(target_arch = "wasm32"))]
-const INSTRUCTIONS_LIMIT: u64 = 50_000_000_000;
+const INSTRUCTIONS_LIMIT: u64 = 20_000_000_000;
 
 #[cfg(target_arch = "wasm32")]
 pub fn print(msg: &str) {
@@ -51,6 +52,8 @@ thread_local! {
     static PERFORMANCE_COUNTER_STEP: RefCell<u64> = RefCell::new(0);
 
     static CYCLES_BALANCE: RefCell<u64> = RefCell::new(0);
+
+    static HEAP_SIZE: RefCell<u64> = RefCell::new(0);
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -201,6 +204,45 @@ pub fn get_cycles_balance() -> u64 {
     CYCLES_BALANCE.with(|c| *c.borrow())
 }
 
+/// Returns the principal of the caller.
+#[cfg(target_arch = "wasm32")]
+pub fn caller() -> Principal {
+    ic_cdk::caller()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn caller() -> Principal {
+    Principal::anonymous()
+}
+
+/// Runs the given function once after the given delay.
+#[cfg(target_arch = "wasm32")]
+pub fn set_timer(delay: Duration, func: impl FnOnce() + 'static) {
+    ic_cdk_timers::set_timer(delay, func);
+}
+
+// Timers are not run in non-wasm environments. Tests drive the syncing explicitly instead.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_timer(_delay: Duration, _func: impl FnOnce() + 'static) {}
+
+/// Runs the given function repeatedly, with the given interval in between.
+#[cfg(target_arch = "wasm32")]
+pub fn set_timer_interval(interval: Duration, func: impl FnMut() + 'static) {
+    ic_cdk_timers::set_timer_interval(interval, func);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_timer_interval(_interval: Duration, _func: impl FnMut() + 'static) {}
+
+/// Runs the given future to completion, in the background.
+#[cfg(target_arch = "wasm32")]
+pub fn spawn(future: impl Future<Output = ()> + 'static) {
+    ic_cdk::spawn(future);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn(_future: impl Future<Output = ()> + 'static) {}
+
 /// Returns the current time in seconds.
 #[cfg(target_arch = "wasm32")]
 pub fn time() -> u64 {
@@ -217,3 +259,38 @@ pub fn time() -> u64 {
         .unwrap()
         .as_secs()
 }
+
+/// Returns the current time in nanoseconds.
+#[cfg(target_arch = "wasm32")]
+pub fn time_nanos() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn time_nanos() -> u64 {
+    use std::time::SystemTime;
+
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+/// Returns the size of the heap in bytes.
+#[cfg(target_arch = "wasm32")]
+pub fn heap_size() -> u64 {
+    const WASM_PAGE_SIZE: u64 = 65536;
+    core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+}
+
+/// Returns the size of the (mock) heap in bytes.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn heap_size() -> u64 {
+    HEAP_SIZE.with(|h| *h.borrow())
+}
+
+/// Sets the size of the (mock) heap in bytes.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn set_heap_size(size: u64) {
+    HEAP_SIZE.with(|h| h.replace(size));
+}
@@ -0,0 +1,257 @@
+//! Schedules the rounds of syncing with the bitcoin network on timers.
+//!
+//! Every round runs the phases of the `heartbeat` and then schedules the next round after an
+//! interval that adapts to what the round did (see `SyncIntervals`):
+//!
+//! * Rounds run right away while there are stable blocks to ingest.
+//! * Rounds run every `catching_up_ms` while new blocks are received.
+//! * Rounds back off exponentially from `idle_min_ms` to `idle_max_ms` otherwise.
+//!
+//! A round that waits for blocks to be fetched while there's other work pending hands off
+//! scheduling the next round, so that the work continues while the blocks are fetched.
+//!
+//! A round that traps doesn't schedule the next one, so a watchdog restarts the rounds if
+//! they have stalled.
+use crate::{
+    heartbeat::{has_pending_work, heartbeat, SyncOutcome},
+    runtime::{self, print},
+    state::State,
+    unstable_blocks, with_state,
+};
+use std::{cell::Cell, cmp::min, rc::Rc, time::Duration};
+
+/// How often the watchdog checks that the rounds haven't stalled.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The time, in seconds, after which a round that's overdue is considered stalled.
+const STALL_TIMEOUT_SECS: u64 = 10 * 60;
+
+thread_local! {
+    // The interval to wait for after the next round without new blocks, in milliseconds.
+    static IDLE_INTERVAL_MS: Cell<Option<u64>> = Cell::new(None);
+
+    // The time, in seconds, at which the next round is expected to start.
+    static NEXT_ROUND_DUE: Cell<u64> = Cell::new(0);
+
+    // Incremented whenever the rounds are (re)started, so that a stalled round that
+    // eventually resumes doesn't keep scheduling rounds alongside the new ones.
+    static GENERATION: Cell<u64> = Cell::new(0);
+}
+
+/// Starts running rounds of syncing, along with the watchdog.
+///
+/// Timers don't survive upgrades, so this is called in both `init` and `post_upgrade`.
+pub fn start() {
+    runtime::set_timer_interval(WATCHDOG_INTERVAL, restart_if_stalled);
+    restart();
+}
+
+fn restart() {
+    let generation = GENERATION.with(|g| {
+        g.set(g.get() + 1);
+        g.get()
+    });
+    IDLE_INTERVAL_MS.with(|i| i.set(None));
+    schedule(generation, Duration::ZERO);
+}
+
+fn restart_if_stalled() {
+    if runtime::time() > NEXT_ROUND_DUE.with(Cell::get) + STALL_TIMEOUT_SECS {
+        print("Syncing has stalled. Restarting...");
+        restart();
+    }
+}
+
+fn schedule(generation: u64, delay: Duration) {
+    NEXT_ROUND_DUE.with(|d| d.set(runtime::time() + delay.as_secs()));
+    runtime::set_timer(delay, move || run_round(generation));
+}
+
+fn run_round(generation: u64) {
+    let is_current = move || GENERATION.with(Cell::get) == generation;
+    if !is_current() {
+        // The rounds have been restarted since this round was scheduled.
+        return;
+    }
+
+    NEXT_ROUND_DUE.with(|d| d.set(runtime::time()));
+    let is_next_round_scheduled = Rc::new(Cell::new(false));
+
+    // The round runs until it first waits for a response, if any.
+    runtime::spawn({
+        let is_next_round_scheduled = is_next_round_scheduled.clone();
+        async move {
+            let outcome = heartbeat().await;
+            if is_current() && !is_next_round_scheduled.replace(true) {
+                schedule(generation, with_state(|s| next_interval(s, outcome)));
+            }
+        }
+    });
+
+    if !is_next_round_scheduled.get() && with_state(has_pending_work) {
+        // The round is waiting for blocks to be fetched. Don't hold up the pending work.
+        is_next_round_scheduled.set(true);
+        schedule(
+            generation,
+            with_state(|s| next_interval(s, SyncOutcome::Idle)),
+        );
+    }
+}
+
+// Returns the interval to wait for before the next round, given the outcome of the last one.
+fn next_interval(state: &State, outcome: SyncOutcome) -> Duration {
+    let intervals = &state.syncing_state.sync_intervals;
+
+    if state.utxos.ingesting_block.is_some()
+        || unstable_blocks::has_stable_block(&state.unstable_blocks)
+    {
+        // Ingest the stable blocks right away, as they're needed to serve up-to-date results.
+        return Duration::ZERO;
+    }
+
+    if has_pending_work(state) {
+        // Process the complete response that's waiting.
+        return Duration::from_millis(intervals.catching_up_ms);
+    }
+
+    match outcome {
+        SyncOutcome::FetchedBlocks {
+            has_new_blocks: true,
+        } => {
+            // The canister is catching up. Reset the backoff.
+            IDLE_INTERVAL_MS.with(|i| i.set(None));
+            Duration::from_millis(intervals.catching_up_ms)
+        }
+        SyncOutcome::IngestedStableBlocks | SyncOutcome::ProcessedResponse => {
+            // Follow up on the work that was done, without resetting the backoff.
+            Duration::from_millis(intervals.catching_up_ms)
+        }
+        SyncOutcome::Idle if state.syncing_state.is_fetching_blocks => {
+            // The response of a pipelined request is on its way. Check back on it without
+            // backing off.
+            Duration::from_millis(intervals.idle_min_ms)
+        }
+        SyncOutcome::FetchedBlocks {
+            has_new_blocks: false,
+        }
+        | SyncOutcome::Idle => {
+            // Nothing new. Back off.
+            let interval_ms = IDLE_INTERVAL_MS.with(|i| {
+                let interval_ms = min(
+                    i.get().unwrap_or(intervals.idle_min_ms),
+                    intervals.idle_max_ms,
+                );
+                i.set(Some(min(
+                    interval_ms.saturating_mul(2),
+                    intervals.idle_max_ms,
+                )));
+                interval_ms
+            });
+            Duration::from_millis(interval_ms)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        genesis_block, init, state::insert_block, test_utils::BlockBuilder, with_state_mut,
+    };
+    use ic_btc_interface::{Config, Network, SyncIntervals};
+
+    fn init_state(stability_threshold: u128) {
+        init(Config {
+            stability_threshold,
+            network: Network::Regtest,
+            sync_intervals: Some(SyncIntervals {
+                catching_up_ms: 100,
+                idle_min_ms: 500,
+                idle_max_ms: 3_000,
+            }),
+            ..Default::default()
+        });
+    }
+
+    fn next_interval_ms(outcome: SyncOutcome) -> u128 {
+        with_state(|s| next_interval(s, outcome)).as_millis()
+    }
+
+    const NO_NEW_BLOCKS: SyncOutcome = SyncOutcome::FetchedBlocks {
+        has_new_blocks: false,
+    };
+
+    const NEW_BLOCKS: SyncOutcome = SyncOutcome::FetchedBlocks {
+        has_new_blocks: true,
+    };
+
+    #[test]
+    fn backs_off_when_no_new_blocks_are_fetched() {
+        init_state(1);
+
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 500);
+        assert_eq!(next_interval_ms(SyncOutcome::Idle), 1_000);
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 2_000);
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 3_000);
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 3_000);
+
+        // New blocks reset the backoff.
+        assert_eq!(next_interval_ms(NEW_BLOCKS), 100);
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 500);
+    }
+
+    #[test]
+    fn processing_a_response_does_not_reset_the_backoff() {
+        init_state(1);
+
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 500);
+        assert_eq!(next_interval_ms(SyncOutcome::ProcessedResponse), 100);
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 1_000);
+        assert_eq!(next_interval_ms(SyncOutcome::IngestedStableBlocks), 100);
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 2_000);
+    }
+
+    #[test]
+    fn runs_right_away_when_stable_blocks_are_pending() {
+        init_state(0);
+        assert_eq!(next_interval_ms(SyncOutcome::Idle), 500);
+
+        // With a stability threshold of zero, the child of the anchor is stable right away.
+        let block =
+            BlockBuilder::with_prev_header(genesis_block(Network::Regtest).header()).build();
+        with_state_mut(|s| insert_block(s, block).unwrap());
+
+        assert_eq!(next_interval_ms(SyncOutcome::Idle), 0);
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 0);
+    }
+
+    #[test]
+    fn checks_back_on_pipelined_requests_without_backing_off() {
+        init_state(1);
+        with_state_mut(|s| s.syncing_state.is_fetching_blocks = true);
+
+        assert_eq!(next_interval_ms(SyncOutcome::Idle), 500);
+        assert_eq!(next_interval_ms(SyncOutcome::Idle), 500);
+
+        // Once the response arrives, the backoff resumes where it was.
+        with_state_mut(|s| s.syncing_state.is_fetching_blocks = false);
+        assert_eq!(next_interval_ms(SyncOutcome::Idle), 500);
+        assert_eq!(next_interval_ms(SyncOutcome::Idle), 1_000);
+    }
+
+    #[test]
+    fn picks_up_updated_intervals() {
+        init_state(1);
+        with_state_mut(|s| {
+            s.syncing_state.sync_intervals = SyncIntervals {
+                catching_up_ms: 7,
+                idle_min_ms: 10,
+                idle_max_ms: 15,
+            }
+        });
+
+        assert_eq!(next_interval_ms(NEW_BLOCKS), 7);
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 10);
+        assert_eq!(next_interval_ms(NO_NEW_BLOCKS), 15);
+    }
+}
@@ -1,22 +1,32 @@
 use crate::{
     address_utxoset::AddressUtxoSet,
     block_header_store::BlockHeaderStore,
+    block_sources::BlockSources,
+    invalid_blocks::InvalidBlocks,
     metrics::Metrics,
-    runtime::{inc_performance_counter, performance_counter, print, time},
+    runtime::{performance_counter, print, time},
     types::{
-        into_bitcoin_network, Address, BlockHeaderBlob, GetSuccessorsCompleteResponse,
-        GetSuccessorsPartialResponse, Slicing,
+        default_checkpoints, into_validation_network, Address, BlockHeaderBlob, Checkpoint,
+        GetSuccessorsCompleteResponse, GetSuccessorsPartialResponse, Slicing,
     },
     unstable_blocks::{self, UnstableBlocks},
+    utxo_set::UtxoAuditor,
     validation::ValidationContext,
     UtxoSet,
 };
-use bitcoin::{consensus::Decodable, BlockHeader};
+use bitcoin::{consensus::Decodable, Block as BitcoinBlock, BlockHeader, Script};
 use candid::Principal;
-use ic_btc_interface::{Fees, Flag, Height, MillisatoshiPerByte, Network};
+use ic_btc_interface::{
+    AuditLogEntry, Fees, Flag, Height, InvalidateBlockError, MemoryLimits, MillisatoshiPerByte,
+    Network, ReconsiderBlockError, SyncIntervals,
+};
 use ic_btc_types::{Block, BlockHash, OutPoint};
-use ic_btc_validation::{validate_header, ValidateHeaderError as InsertBlockError};
+use ic_btc_validation::{
+    validate_block, validate_header_with_checkpoints, Checkpoint as ValidationCheckpoint,
+    HeaderStore, ValidateBlockError, ValidateHeaderError, ValidateSignetBlockError,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 
 /// A structure used to maintain the entire state.
 // NOTE: `PartialEq` is only available in tests as it would be impractically
@@ -33,9 +43,9 @@ pub struct State {
     /// State used for syncing new blocks.
     pub syncing_state: SyncingState,
 
-    /// The canister from which blocks are retrieved.
-    /// Defaults to the management canister in production.
-    pub blocks_source: Principal,
+    /// The canisters from which blocks are retrieved.
+    /// Defaults to only the management canister in production.
+    pub blocks_sources: BlockSources,
 
     /// Cache for the current fee percentiles.
     pub fee_percentiles_cache: Option<FeePercentilesCache>,
@@ -60,6 +70,34 @@ pub struct State {
     /// The watchdog canister has the authority to disable the Bitcoin canister's API
     /// if it suspects that there is a problem.
     pub watchdog_canister: Option<Principal>,
+
+    /// The challenge script of a custom signet (BIP325), if any.
+    pub signet_challenge: Option<Vec<u8>>,
+
+    /// The checkpoints that block headers are validated against.
+    pub checkpoints: Vec<Checkpoint>,
+
+    /// The hashes of blocks that are known to be invalid.
+    #[serde(default)]
+    pub invalid_blocks: InvalidBlocks,
+
+    /// The hashes of blocks that were invalidated by a controller, along with their heights.
+    /// They're dropped once the anchor moves past them, at which point they can no longer
+    /// extend the tree, so only the invalidated blocks above the anchor are kept.
+    #[serde(default)]
+    pub invalidated_blocks: BTreeMap<BlockHash, Height>,
+
+    /// The most recent actions that controllers took on the canister.
+    #[serde(default)]
+    pub audit_log: VecDeque<AuditLogEntry>,
+
+    /// The background audit of the UTXO set.
+    #[serde(default)]
+    pub utxo_auditor: UtxoAuditor,
+
+    /// The limits on the memory used by the canister.
+    #[serde(default)]
+    pub memory_limits: MemoryLimits,
 }
 
 impl State {
@@ -77,7 +115,7 @@ impl State {
             utxos,
             unstable_blocks,
             syncing_state: SyncingState::default(),
-            blocks_source: Principal::management_canister(),
+            blocks_sources: BlockSources::new(Principal::management_canister(), vec![]),
             fee_percentiles_cache: None,
             stable_block_headers: BlockHeaderStore::init(),
             fees: Fees::default(),
@@ -85,6 +123,13 @@ impl State {
             api_access: Flag::Enabled,
             disable_api_if_not_fully_synced: Flag::Enabled,
             watchdog_canister: None,
+            signet_challenge: None,
+            checkpoints: default_checkpoints(network),
+            invalid_blocks: InvalidBlocks::default(),
+            invalidated_blocks: BTreeMap::new(),
+            audit_log: VecDeque::new(),
+            utxo_auditor: UtxoAuditor::default(),
+            memory_limits: MemoryLimits::default(),
         }
     }
 
@@ -103,17 +148,76 @@ impl State {
     }
 }
 
+/// An error thrown when trying to insert a block into the state.
+#[derive(Debug, PartialEq)]
+pub enum InsertBlockError {
+    /// The header of the block is invalid.
+    InvalidHeader(ValidateHeaderError),
+    /// The transactions of the block are invalid.
+    InvalidBlock(ValidateBlockError),
+    /// The block, or one of its ancestors, is known to be invalid.
+    KnownInvalid,
+    /// The block, or its parent, was invalidated by a controller.
+    Invalidated,
+    /// The block belongs to a stale fork that was pruned.
+    Pruned,
+}
+
+impl InsertBlockError {
+    // Returns true if the error proves that the block is invalid.
+    fn proves_invalid(&self) -> bool {
+        match self {
+            Self::InvalidHeader(err) => header_error_proves_invalid(err),
+            // The inputs of a block are resolved against its own chain (see `ValidationContext`),
+            // so an input that isn't found proves that the block is invalid.
+            Self::InvalidBlock(_) => true,
+            // The block is already known to be invalid.
+            Self::KnownInvalid => false,
+            // The block may become valid again once it's reconsidered.
+            Self::Invalidated => false,
+            // The block is only stale.
+            Self::Pruned => false,
+        }
+    }
+}
+
+impl From<ValidateHeaderError> for InsertBlockError {
+    fn from(err: ValidateHeaderError) -> Self {
+        Self::InvalidHeader(err)
+    }
+}
+
+impl From<ValidateBlockError> for InsertBlockError {
+    fn from(err: ValidateBlockError) -> Self {
+        Self::InvalidBlock(err)
+    }
+}
+
 /// Inserts a block into the state.
-/// Returns an error if the block doesn't extend any known block in the state.
+/// Returns an error if the block doesn't extend any known block in the state,
+/// or if the block is invalid.
 pub fn insert_block(state: &mut State, block: Block) -> Result<(), InsertBlockError> {
     let start = performance_counter();
-    validate_header(
-        &into_bitcoin_network(state.network()),
-        &ValidationContext::new(state, block.header())
-            .map_err(|_| InsertBlockError::PrevHeaderNotFound)?,
-        block.header(),
-        time(),
-    )?;
+    let block_hash = block.block_hash();
+    if is_invalidated(state, &block_hash, block.header()) {
+        return Err(InsertBlockError::Invalidated);
+    }
+
+    if unstable_blocks::mark_if_pruned(&mut state.unstable_blocks, &block_hash, block.header()) {
+        return Err(InsertBlockError::Pruned);
+    }
+
+    if is_known_invalid(state, &block_hash, block.header()) {
+        state.invalid_blocks.insert(block_hash);
+        return Err(InsertBlockError::KnownInvalid);
+    }
+
+    if let Err(err) = validate_block_for_insertion(state, &block) {
+        if err.proves_invalid() {
+            state.invalid_blocks.insert(block_hash);
+        }
+        return Err(err);
+    }
 
     unstable_blocks::push(&mut state.unstable_blocks, &state.utxos, block)
         .expect("Inserting a block with a validated header must succeed.");
@@ -123,6 +227,126 @@ pub fn insert_block(state: &mut State, block: Block) -> Result<(), InsertBlockEr
     Ok(())
 }
 
+/// Prunes the stale forks from the unstable blocks, as per the fork pruning policy.
+pub fn prune_stale_forks(state: &mut State) {
+    let num_pruned_blocks =
+        unstable_blocks::prune_stale_forks(&mut state.unstable_blocks, state.utxos.next_height());
+    if num_pruned_blocks > 0 {
+        print(&format!(
+            "Pruned {} blocks of stale forks.",
+            num_pruned_blocks
+        ));
+        state.metrics.num_pruned_blocks += num_pruned_blocks as u64;
+    }
+}
+
+// Validates the header and the transactions of a block that is to be inserted.
+fn validate_block_for_insertion(state: &State, block: &Block) -> Result<(), InsertBlockError> {
+    let network = into_validation_network(state.network());
+    let validation_context = ValidationContext::new(state, block.header())
+        .map_err(|_| ValidateHeaderError::PrevHeaderNotFound)?;
+    validate_header(state, &validation_context, block.header())?;
+
+    validate_block(
+        &network,
+        &validation_context,
+        block.internal_bitcoin_block(),
+        validation_context.height() + 1,
+    )?;
+
+    Ok(())
+}
+
+// Returns true if the block with the given hash and header, or its parent, was
+// invalidated by a controller.
+fn is_invalidated(state: &State, block_hash: &BlockHash, header: &BlockHeader) -> bool {
+    state.invalidated_blocks.contains_key(block_hash)
+        || state
+            .invalidated_blocks
+            .contains_key(&BlockHash::from(header.prev_blockhash))
+}
+
+// Returns true if the block with the given hash and header, or its parent, is known
+// to be invalid.
+fn is_known_invalid(state: &State, block_hash: &BlockHash, header: &BlockHeader) -> bool {
+    state.invalid_blocks.contains(block_hash)
+        || state
+            .invalid_blocks
+            .contains(&BlockHash::from(header.prev_blockhash))
+}
+
+// Returns true if the given header error proves that the header is invalid, as opposed
+// to errors that may no longer occur later on (e.g. a missing predecessor).
+fn header_error_proves_invalid(err: &ValidateHeaderError) -> bool {
+    !matches!(
+        err,
+        ValidateHeaderError::PrevHeaderNotFound
+            | ValidateHeaderError::HeaderIsTooFarInFuture { .. }
+    )
+}
+
+/// Invalidates an unstable block, removing it along with all its descendants.
+///
+/// The block, and any block that extends it, is rejected until it's reconsidered.
+pub fn invalidate_block(
+    state: &mut State,
+    block_hash: BlockHash,
+) -> Result<(), InvalidateBlockError> {
+    let anchor = unstable_blocks::get_main_chain(&state.unstable_blocks).first();
+    if anchor.block_hash() == block_hash {
+        return Err(InvalidateBlockError::AnchorBlock);
+    }
+
+    let height = unstable_blocks::get_chain_with_tip(&state.unstable_blocks, &block_hash)
+        .map(|chain| state.stable_height() + chain.len() as Height - 1)
+        .ok_or(InvalidateBlockError::UnknownBlock)?;
+    unstable_blocks::remove_subtree(&mut state.unstable_blocks, &block_hash)
+        .expect("The block must be in the tree.");
+    state.invalidated_blocks.insert(block_hash, height);
+    Ok(())
+}
+
+/// Reconsiders a block that was invalidated, so that it can be inserted again.
+pub fn reconsider_block(
+    state: &mut State,
+    block_hash: &BlockHash,
+) -> Result<(), ReconsiderBlockError> {
+    if state.invalidated_blocks.remove(block_hash).is_none() {
+        return Err(ReconsiderBlockError::NotInvalidated);
+    }
+    Ok(())
+}
+
+/// Validates a block header against the network and checkpoints of the state.
+fn validate_header(
+    state: &State,
+    store: &impl HeaderStore,
+    header: &BlockHeader,
+) -> Result<(), ValidateHeaderError> {
+    let checkpoints: Vec<ValidationCheckpoint> = state.checkpoints.iter().map(Into::into).collect();
+    validate_header_with_checkpoints(
+        &into_validation_network(state.network()),
+        store,
+        header,
+        time(),
+        &checkpoints,
+    )
+}
+
+/// Validates the signet solution of a block against the state's signet challenge.
+/// Blocks are always valid if no signet challenge is set.
+pub fn validate_signet_block(
+    state: &State,
+    block: &BitcoinBlock,
+) -> Result<(), ValidateSignetBlockError> {
+    match &state.signet_challenge {
+        Some(challenge) => {
+            ic_btc_validation::validate_signet_block(&Script::from(challenge.clone()), block)
+        }
+        None => Ok(()),
+    }
+}
+
 /// Pops any blocks in `UnstableBlocks` that are considered stable and ingests them to the UTXO set.
 ///
 /// NOTE: This method does a form of time-slicing to stay within the instruction limit, and
@@ -137,6 +361,11 @@ pub fn ingest_stable_blocks_into_utxoset(state: &mut State) -> bool {
 
         // Sanity check that we just popped the same block that was ingested.
         assert_eq!(popped_block.unwrap().block_hash(), ingested_block_hash);
+
+        // Blocks that were invalidated below the new anchor can no longer extend the tree.
+        state
+            .invalidated_blocks
+            .retain(|_, height| *height > stable_height);
     }
 
     let prev_state = (
@@ -169,9 +398,9 @@ pub fn ingest_stable_blocks_into_utxoset(state: &mut State) -> bool {
         // Store the block's header.
         state
             .stable_block_headers
-            .insert_block(new_stable_block, state.utxos.next_height());
+            .insert_block(&new_stable_block, state.utxos.next_height());
 
-        match state.utxos.ingest_block(new_stable_block.clone()) {
+        match state.utxos.ingest_block(new_stable_block) {
             Slicing::Paused(()) => return has_state_changed(state),
             Slicing::Done((ingested_block_hash, stats)) => {
                 state.metrics.block_ingestion_stats = stats;
@@ -183,16 +412,20 @@ pub fn ingest_stable_blocks_into_utxoset(state: &mut State) -> bool {
     has_state_changed(state)
 }
 
-pub fn insert_next_block_headers(state: &mut State, next_block_headers: &[BlockHeaderBlob]) {
-    // The limit at which no further next block headers are processed.
-    // Note that the actual limit available on system subnets is 50B. The threshold is set
-    // lower to be conservative.
-    const MAX_INSTRUCTIONS_THRESHOLD: u64 = 30_000_000_000;
-
-    for block_header_blob in next_block_headers.iter() {
-        if inc_performance_counter() > MAX_INSTRUCTIONS_THRESHOLD {
-            print("Reaching instruction threshold while inserting next block headers. Breaking...");
-            break;
+/// Inserts the given next block headers until they're all inserted or `should_pause`
+/// returns true.
+///
+/// Returns `Slicing::Paused(n)` if insertion was paused after the first `n` headers, in
+/// which case the remaining headers are to be inserted in a later call.
+pub fn insert_next_block_headers(
+    state: &mut State,
+    next_block_headers: &[BlockHeaderBlob],
+    should_pause: &mut dyn FnMut() -> bool,
+) -> Slicing<usize, ()> {
+    for (idx, block_header_blob) in next_block_headers.iter().enumerate() {
+        if should_pause() {
+            print("Reaching instruction threshold while inserting next block headers. Pausing...");
+            return Slicing::Paused(idx);
         }
 
         let block_header = match BlockHeader::consensus_decode(block_header_blob.as_slice()) {
@@ -202,7 +435,7 @@ pub fn insert_next_block_headers(state: &mut State, next_block_headers: &[BlockH
                     "ERROR: Failed decode block header. Err: {:?}, Block header: {:?}",
                     err, block_header_blob,
                 ));
-                return;
+                return Slicing::Done(());
             }
         };
 
@@ -211,16 +444,29 @@ pub fn insert_next_block_headers(state: &mut State, next_block_headers: &[BlockH
             continue;
         }
 
+        let block_hash = BlockHash::from(block_header.block_hash());
+        if is_invalidated(state, &block_hash, &block_header) {
+            print(&format!(
+                "ERROR: Block header was invalidated. Block header: {:?}",
+                block_header,
+            ));
+            return Slicing::Done(());
+        }
+
+        if is_known_invalid(state, &block_hash, &block_header) {
+            print(&format!(
+                "ERROR: Block header is known to be invalid. Block header: {:?}",
+                block_header,
+            ));
+            state.invalid_blocks.insert(block_hash);
+            return Slicing::Done(());
+        }
+
         let validation_result =
             match ValidationContext::new_with_next_block_headers(state, &block_header)
-                .map_err(|_| InsertBlockError::PrevHeaderNotFound)
+                .map_err(|_| ValidateHeaderError::PrevHeaderNotFound)
             {
-                Ok(store) => validate_header(
-                    &into_bitcoin_network(state.network()),
-                    &store,
-                    &block_header,
-                    time(),
-                ),
+                Ok(store) => validate_header(state, &store, &block_header),
                 Err(err) => Err(err),
             };
 
@@ -230,7 +476,10 @@ pub fn insert_next_block_headers(state: &mut State, next_block_headers: &[BlockH
                 err, block_header,
             ));
 
-            return;
+            if header_error_proves_invalid(&err) {
+                state.invalid_blocks.insert(block_hash);
+            }
+            return Slicing::Done(());
         }
 
         if let Err(err) = state
@@ -241,9 +490,11 @@ pub fn insert_next_block_headers(state: &mut State, next_block_headers: &[BlockH
                 "ERROR: Failed to insert next block header. Err: {:?}, Block header: {:?}",
                 err, block_header,
             ));
-            return;
+            return Slicing::Done(());
         }
     }
+
+    Slicing::Done(())
 }
 
 pub fn main_chain_height(state: &State) -> Height {
@@ -305,6 +556,11 @@ pub struct SyncingState {
     /// A response that needs to be processed.
     pub response_to_process: Option<ResponseToProcess>,
 
+    /// A response that's fetched while `response_to_process` is being processed, and that's
+    /// processed right after it.
+    #[serde(default)]
+    pub pipelined_response: Option<ResponseToProcess>,
+
     /// The number of rejects received when calling GetSuccessors.
     pub num_get_successors_rejects: u64,
 
@@ -313,6 +569,17 @@ pub struct SyncingState {
 
     /// The number of errors occurred when inserting a block.
     pub num_insert_block_errors: u64,
+
+    /// The intervals between rounds of syncing.
+    #[serde(default)]
+    pub sync_intervals: SyncIntervals,
+
+    /// How far the complete response in `response_to_process` has been processed.
+    ///
+    /// A response can take several rounds to process, so processing resumes from this
+    /// cursor in the next round.
+    #[serde(default)]
+    pub response_processing_cursor: ResponseProcessingCursor,
 }
 
 impl Default for SyncingState {
@@ -321,13 +588,28 @@ impl Default for SyncingState {
             syncing: Flag::Enabled,
             is_fetching_blocks: false,
             response_to_process: None,
+            pipelined_response: None,
             num_get_successors_rejects: 0,
             num_block_deserialize_errors: 0,
             num_insert_block_errors: 0,
+            sync_intervals: SyncIntervals::default(),
+            response_processing_cursor: ResponseProcessingCursor::default(),
         }
     }
 }
 
+/// The progress of processing a complete `GetSuccessorsResponse`.
+///
+/// The blocks of the response are inserted first, followed by its next block headers.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default, Clone, Copy)]
+pub struct ResponseProcessingCursor {
+    /// The index of the next block to insert.
+    pub next_block_idx: usize,
+
+    /// The index of the next block header to insert.
+    pub next_header_idx: usize,
+}
+
 /// Cache for storing last calculated fee percentiles
 ///
 /// Stores last tip block hash and fee percentiles associated with it.
@@ -340,7 +622,7 @@ pub struct FeePercentilesCache {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::test_utils::build_chain;
+    use crate::test_utils::{build_chain, BlockBuilder};
     use proptest::prelude::*;
 
     proptest! {
@@ -349,10 +631,10 @@ mod test {
         fn serialize_deserialize_state(
             stability_threshold in 1..150u32,
             num_blocks in 1..250u32,
-            num_transactions_in_block in 1..100u32,
+            num_outputs_in_block in 1..100u32,
         ) {
             let network = Network::Regtest;
-            let blocks = build_chain(network, num_blocks, num_transactions_in_block);
+            let blocks = build_chain(network, num_blocks, num_outputs_in_block);
 
             let mut state = State::new(stability_threshold, network, blocks[0].clone());
 
@@ -370,13 +652,44 @@ mod test {
         }
     }
 
+    #[test]
+    fn insert_block_rejects_forks_below_checkpoint() {
+        let network = Network::Regtest;
+        let blocks = build_chain(network, 4, 1);
+        let mut state = State::new(2, network, blocks[0].clone());
+        state.checkpoints = vec![Checkpoint {
+            height: 2,
+            block_hash: blocks[2].block_hash(),
+        }];
+
+        for block in blocks[1..].iter() {
+            insert_block(&mut state, block.clone()).unwrap();
+        }
+
+        // A block that conflicts with the checkpoint is rejected.
+        let fork_block = BlockBuilder::with_prev_header(blocks[1].header()).build();
+        assert_eq!(
+            insert_block(&mut state, fork_block.clone()),
+            Err(InsertBlockError::InvalidHeader(
+                ValidateHeaderError::ForkBelowCheckpoint {
+                    checkpoint_height: 2
+                }
+            ))
+        );
+
+        // Without the checkpoint, the same block is accepted.
+        state.checkpoints = vec![];
+        state.invalid_blocks.clear();
+        insert_block(&mut state, fork_block).unwrap();
+    }
+
     #[test]
     fn block_ingestion_stats_are_updated() {
         let stability_threshold = 0;
         let num_blocks = 3;
-        let num_transactions_per_block = 10;
+        let num_outputs_per_block = 10;
         let network = Network::Regtest;
-        let blocks = build_chain(network, num_blocks, num_transactions_per_block);
+        let blocks = build_chain(network, num_blocks, num_outputs_per_block);
 
         let mut state = State::new(stability_threshold, network, blocks[0].clone());
 
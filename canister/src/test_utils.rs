@@ -56,30 +56,27 @@ pub fn random_p2wsh_address(network: Network) -> Address {
     .into()
 }
 
-/// Builds a random chain with the given number of block and transactions.
+/// Builds a random chain with the given number of blocks, where the coinbase of
+/// each block pays the given number of outputs to the same address.
 /// The genesis block used in the chain is also random.
-pub fn build_chain(
-    network: Network,
-    num_blocks: u32,
-    num_transactions_per_block: u32,
-) -> Vec<Block> {
+pub fn build_chain(network: Network, num_blocks: u32, num_outputs_per_block: u32) -> Vec<Block> {
     build_chain_with_genesis_block(
         network,
         BlockBuilder::genesis().build(),
         num_blocks,
-        num_transactions_per_block,
+        num_outputs_per_block,
     )
 }
 
-/// Builds a random chain with the given number of block and transactions
+/// Builds a random chain with the given number of blocks and outputs per block
 /// and starting with the Regtest genesis block.
-pub fn build_regtest_chain(num_blocks: u32, num_transactions_per_block: u32) -> Vec<Block> {
+pub fn build_regtest_chain(num_blocks: u32, num_outputs_per_block: u32) -> Vec<Block> {
     let network = Network::Regtest;
     build_chain_with_genesis_block(
         network,
         genesis_block(network),
         num_blocks,
-        num_transactions_per_block,
+        num_outputs_per_block,
     )
 }
 
@@ -87,7 +84,7 @@ fn build_chain_with_genesis_block(
     network: Network,
     genesis_block: Block,
     num_blocks: u32,
-    num_transactions_per_block: u32,
+    num_outputs_per_block: u32,
 ) -> Vec<Block> {
     let address = random_p2pkh_address(network);
     let mut blocks = vec![genesis_block.clone()];
@@ -95,25 +92,18 @@ fn build_chain_with_genesis_block(
     let mut value = 1;
 
     // Since we start with a genesis block, we need `num_blocks - 1` additional blocks.
-    for _ in 0..num_blocks - 1 {
-        let mut block_builder = BlockBuilder::with_prev_header(prev_block.header());
-        let mut transactions = vec![];
-        for _ in 0..num_transactions_per_block {
-            transactions.push(
-                TransactionBuilder::coinbase()
-                    .with_output(&address, value)
-                    .build(),
-            );
-            // Vary the value of the transaction to ensure that
+    for height in 1..num_blocks {
+        let mut coinbase = TransactionBuilder::coinbase().with_height(height);
+        for _ in 0..num_outputs_per_block {
+            coinbase = coinbase.with_output(&address, value);
+            // Vary the value of the outputs to ensure that
             // we get unique outpoints in the blockchain.
             value += 1;
         }
 
-        for transaction in transactions.iter() {
-            block_builder = block_builder.with_transaction(transaction.clone());
-        }
-
-        let block = block_builder.build();
+        let block = BlockBuilder::with_prev_header(prev_block.header())
+            .with_transaction(coinbase.build())
+            .build();
         blocks.push(block.clone());
         prev_block = block;
     }
@@ -211,6 +201,12 @@ impl TransactionBuilder {
         }
     }
 
+    pub fn with_height(self, height: u32) -> Self {
+        Self {
+            builder: self.builder.with_height(height),
+        }
+    }
+
     pub fn with_input(self, previous_output: OutPoint) -> Self {
         Self {
             builder: self.builder.with_input(previous_output.into(), None),
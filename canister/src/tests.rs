@@ -23,6 +23,8 @@ use std::str::FromStr;
 use std::{collections::HashMap, io::BufReader, path::PathBuf};
 use std::{fs::File, panic::catch_unwind};
 mod confirmation_counts;
+mod signet;
+mod upgrades;
 
 async fn process_chain(network: Network, blocks_file: &str, num_blocks: u32) {
     let mut chain: Vec<BitcoinBlock> = vec![];
@@ -51,6 +53,8 @@ async fn process_chain(network: Network, blocks_file: &str, num_blocks: u32) {
             match network {
                 Network::Mainnet => 0xD9B4BEF9,
                 Network::Testnet | Network::Regtest => 0x0709110B,
+                Network::Signet => 0x40CF030A,
+                Network::Testnet4 => 0x283F161C,
             }
         );
 
@@ -169,7 +173,8 @@ async fn mainnet_100k_blocks() {
     assert_eq!(
         get_utxos(GetUtxosRequest {
             address: "1PgZsaGjvssNCqHHisshLoCFeUjxPhutTh".to_string(),
-            filter: None
+            filter: None,
+            sort_order: None,
         })
         .unwrap(),
         GetUtxosResponse {
@@ -209,7 +214,8 @@ async fn mainnet_100k_blocks() {
     assert_eq!(
         get_utxos(GetUtxosRequest {
             address: "12tGGuawKdkw5NeDEzS3UANhCRa1XggBbK".to_string(),
-            filter: None
+            filter: None,
+            sort_order: None,
         })
         .unwrap(),
         GetUtxosResponse {
@@ -270,7 +276,8 @@ async fn mainnet_100k_blocks() {
     assert_eq!(
         get_utxos(GetUtxosRequest {
             address: "1K791w8Y1CXwyG3zAf9EzpoZvpYH8Z2Rro".to_string(),
-            filter: Some(UtxosFilter::MinConfirmations(6))
+            filter: Some(UtxosFilter::MinConfirmations(6)),
+            sort_order: None,
         })
         .unwrap(),
         GetUtxosResponse {
@@ -425,25 +432,32 @@ async fn time_slices_large_block_with_multiple_transactions() {
     let address_2 = random_p2pkh_address(network);
 
     let tx_1 = TransactionBuilder::coinbase()
+        .with_height(1)
         .with_output(&address_1, 1000)
         .with_output(&address_1, 1000)
         .build();
 
     let tx_2 = TransactionBuilder::new()
+        .with_input(ic_btc_types::OutPoint::new(tx_1.txid(), 0))
+        .with_input(ic_btc_types::OutPoint::new(tx_1.txid(), 1))
         .with_output(&address_2, 1000)
         .with_output(&address_2, 1000)
         .build();
 
     let block_1 = BlockBuilder::with_prev_header(genesis_block(network).header())
         .with_transaction(tx_1)
+        .build();
+
+    // A block with the coinbase added by the builder and `tx_2`.
+    let block_2 = BlockBuilder::with_prev_header(block_1.header())
         .with_transaction(tx_2)
         .build();
 
-    // An additional block so that the previous block is ingested into the stable UTXO set.
-    let block_2 = BlockBuilder::with_prev_header(block_1.header()).build();
+    // An additional block so that the previous blocks are ingested into the stable UTXO set.
+    let block_3 = BlockBuilder::with_prev_header(block_2.header()).build();
 
     // Serialize the blocks.
-    let blocks: Vec<BlockBlob> = [block_1.clone(), block_2.clone()]
+    let blocks: Vec<BlockBlob> = [block_1.clone(), block_2.clone(), block_3]
         .iter()
         .map(|block| {
             let mut block_bytes = vec![];
@@ -469,13 +483,14 @@ async fn time_slices_large_block_with_multiple_transactions() {
     // Process response.
     heartbeat().await;
 
-    // Assert that the block has been ingested.
-    assert_eq!(with_state(main_chain_height), 2);
+    // Assert that the blocks have been ingested.
+    assert_eq!(with_state(main_chain_height), 3);
 
     // Run the heartbeat a few rounds to ingest the blocks.
     let expected_states = vec![
         IngestingBlock::new_with_args(block_1.clone(), 0, 1, 1),
-        IngestingBlock::new_with_args(block_1.clone(), 1, 1, 1),
+        IngestingBlock::new_with_args(block_2.clone(), 1, 0, 0),
+        IngestingBlock::new_with_args(block_2.clone(), 1, 2, 0),
     ];
 
     for expected_state in expected_states.into_iter() {
@@ -498,8 +513,8 @@ async fn time_slices_large_block_with_multiple_transactions() {
     runtime::performance_counter_reset();
     heartbeat().await;
 
-    // The stable height is now updated to include `block_1`.
-    assert_eq!(with_state(|s| s.utxos.next_height()), 2);
+    // The stable height is now updated to include `block_1` and `block_2`.
+    assert_eq!(with_state(|s| s.utxos.next_height()), 3);
 
     // Query the balance, expecting address 1 to be empty and address 2 to be non-empty.
     assert_eq!(
@@ -508,7 +523,7 @@ async fn time_slices_large_block_with_multiple_transactions() {
             min_confirmations: None
         })
         .unwrap(),
-        2000
+        0
     );
 
     assert_eq!(
@@ -1,9 +1,11 @@
 use crate::{
     api::get_utxos,
     heartbeat,
+    heartbeat::has_pending_work,
     runtime::{set_successors_responses, GetSuccessorsReply},
     test_utils::BlockChainBuilder,
     types::{GetSuccessorsCompleteResponse, GetSuccessorsResponse, GetUtxosRequest},
+    with_state,
 };
 use async_std::task::block_on;
 use ic_btc_interface::{Network, UtxosFilter};
@@ -34,6 +36,7 @@ proptest! {
         let res = get_utxos(GetUtxosRequest {
             address: ADDRESS.to_string(),
             filter: None,
+            sort_order: None,
         }).unwrap();
 
         assert_eq!(res.tip_height, chain_len - 1);
@@ -43,6 +46,7 @@ proptest! {
             let res = get_utxos(GetUtxosRequest {
                 address: ADDRESS.to_string(),
                 filter: Some(UtxosFilter::MinConfirmations(i)),
+                sort_order: None,
             }).unwrap();
 
             let block_depth = chain_len - i;
@@ -84,6 +88,7 @@ proptest! {
         let res = get_utxos(GetUtxosRequest {
             address: ADDRESS.to_string(),
             filter: None,
+            sort_order: None,
         }).unwrap();
 
         assert_eq!(res.tip_height, chain_len - 1);
@@ -93,6 +98,7 @@ proptest! {
             let res = get_utxos(GetUtxosRequest {
                 address: ADDRESS.to_string(),
                 filter: Some(UtxosFilter::MinConfirmations(i)),
+                sort_order: None,
             }).unwrap();
 
             let block_depth = chain_len - i;
@@ -131,6 +137,7 @@ async fn multiple_forks() {
     let res = get_utxos(GetUtxosRequest {
         address: ADDRESS.to_string(),
         filter: None,
+        sort_order: None,
     })
     .unwrap();
 
@@ -141,6 +148,7 @@ async fn multiple_forks() {
     let res = get_utxos(GetUtxosRequest {
         address: ADDRESS.to_string(),
         filter: Some(UtxosFilter::MinConfirmations(2)),
+        sort_order: None,
     })
     .unwrap();
 
@@ -171,7 +179,10 @@ fn ingest_blocks<'a>(blocks: impl Iterator<Item = &'a Block>) {
     loop {
         block_on(async { heartbeat().await });
 
-        if crate::runtime::GET_SUCCESSORS_RESPONSES_INDEX.with(|i| *i.borrow()) > responses_len {
+        // Stop once all the responses have been fetched, processed, and ingested.
+        if crate::runtime::GET_SUCCESSORS_RESPONSES_INDEX.with(|i| *i.borrow()) > responses_len
+            && !with_state(has_pending_work)
+        {
             break;
         }
     }
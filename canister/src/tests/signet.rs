@@ -0,0 +1,195 @@
+//! Tests syncing the canister on signet.
+//!
+//! The blocks below were mined on top of the signet genesis block at the network's minimum
+//! difficulty. Each block contains a single coinbase transaction paying 50 BTC to `ADDRESS`.
+//! The blocks in `BLOCKS` have no witness commitment, while the ones in
+//! `BLOCKS_WITH_WITNESS_COMMITMENT` have one, but no signet solution.
+use crate::{
+    api::{get_balance, get_utxos},
+    heartbeat,
+    heartbeat::has_pending_work,
+    runtime::{self, GetSuccessorsReply},
+    state::main_chain_height,
+    types::{
+        GetBalanceRequest, GetSuccessorsCompleteResponse, GetSuccessorsResponse, GetUtxosRequest,
+    },
+    with_state,
+};
+use ic_btc_interface::Network;
+use ic_btc_types::BlockHash;
+use std::str::FromStr;
+
+const ADDRESS: &str = "mtDDmFkqWmiaiXfxRnAwHnsvYiUkkE3Sct";
+
+const BLOCKS: [&str; 6] = [
+    "00000020f61eee3b63a380a477a063af32b2bbc97c9ff9f01f2c4225e9739881080000004cf547aee70440362416743d40671b53c8a51ac654afcb97c11a33dd85b67b7258914d5fae77031e998219000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0651047369676effffffff0100f2052a010000001976a9148b3f0f8c6b7b2d2ff0a1a2e0c8d9f7a6b5c4d3e288ac00000000",
+    "000000206a5a51335077d14ed7352255a5a7ea98329596d41deb3627f7e73e1248030000ef482c68e4f2b04872966b39dffa8769611f951e069f560c2c1efd82752b2470b0934d5fae77031e85d021000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0652047369676effffffff0100f2052a010000001976a9148b3f0f8c6b7b2d2ff0a1a2e0c8d9f7a6b5c4d3e288ac00000000",
+    "00000020d7dbeae16cfff6696610e9a837ba7ca8d917e2361978adb8762b5ae34f000000d1948c8cad1f88e7fae00c2926a9e2cacb1b912b67abaab9dd40a78ec07947ea08964d5fae77031e7cea14010101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0653047369676effffffff0100f2052a010000001976a9148b3f0f8c6b7b2d2ff0a1a2e0c8d9f7a6b5c4d3e288ac00000000",
+    "000000202a695382a7f6c4bfd50a5b9e1f3d5fa86904ae8ccc8301a9de0d78a2030200004d92187537837fb6e9f6659777763456f0db8ba1e8878e7e30fd854523f6e3fe60984d5fae77031eab2f2f000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0654047369676effffffff0100f2052a010000001976a9148b3f0f8c6b7b2d2ff0a1a2e0c8d9f7a6b5c4d3e288ac00000000",
+    "000000202779a9f69d769265b9a3b063aca663a595fa131e347e1c9f4e7b588ef00100001537c255a8cdfd396ee9e49ce28f7579e316d2132e9d5b3e6b9d313bd5918f2bb89a4d5fae77031e1bc7a5000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0655047369676effffffff0100f2052a010000001976a9148b3f0f8c6b7b2d2ff0a1a2e0c8d9f7a6b5c4d3e288ac00000000",
+    "00000020c0be2411fd8e67a231a29bb4b3792e2f73c38e2548b2d52f24dd1e5cb80100003afca55cdc41d2b808c05196e97ea6126a2b0860803adb829d15ba869c881b9e109d4d5fae77031e718d11000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0656047369676effffffff0100f2052a010000001976a9148b3f0f8c6b7b2d2ff0a1a2e0c8d9f7a6b5c4d3e288ac00000000",
+];
+
+const BLOCKS_WITH_WITNESS_COMMITMENT: [&str; 2] = [
+    "00000020f61eee3b63a380a477a063af32b2bbc97c9ff9f01f2c4225e9739881080000008b0f5646590f4d62c7f54c17948c33b413d52722c87cf4c07cf2d8478e75229758914d5fae77031e56f4230001010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff0651047369676effffffff0200f2052a010000001976a9148b3f0f8c6b7b2d2ff0a1a2e0c8d9f7a6b5c4d3e288ac0000000000000000266a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf90120000000000000000000000000000000000000000000000000000000000000000000000000",
+    "00000020f30d2026caad0f904839486bd2da41cbd90e2dea0483645dfe5b30c29e010000764eb1027ba8628601290208901ec5946e3e5eec66d3a7cb8634d18c4bd06ffab0934d5fae77031ebd541f0001010000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff0652047369676effffffff0200f2052a010000001976a9148b3f0f8c6b7b2d2ff0a1a2e0c8d9f7a6b5c4d3e288ac0000000000000000266a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf90120000000000000000000000000000000000000000000000000000000000000000000000000",
+];
+
+// A challenge that's satisfied by any solution.
+const CHALLENGE_TRUE: [u8; 1] = [0x51];
+
+// A 1-of-1 multisig challenge.
+const CHALLENGE_MULTISIG: &str =
+    "51210279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f8179851ae";
+
+// Feeds the given blocks to the canister, one block per `GetSuccessors` response.
+async fn ingest_blocks(blocks: &[&str]) {
+    let responses: Vec<_> = blocks
+        .iter()
+        .map(|block| {
+            GetSuccessorsReply::Ok(GetSuccessorsResponse::Complete(
+                GetSuccessorsCompleteResponse {
+                    blocks: vec![hex::decode(block).unwrap()],
+                    next: vec![],
+                },
+            ))
+        })
+        .collect();
+    let responses_len = responses.len();
+    runtime::set_successors_responses(responses);
+
+    // Run the heartbeat until we process all the blocks.
+    loop {
+        heartbeat().await;
+
+        // Stop once all the responses have been fetched, processed, and ingested.
+        if runtime::GET_SUCCESSORS_RESPONSES_INDEX.with(|i| *i.borrow()) > responses_len
+            && !with_state(has_pending_work)
+        {
+            break;
+        }
+    }
+}
+
+#[async_std::test]
+async fn syncs_signet_blocks() {
+    crate::init(crate::Config {
+        stability_threshold: 2,
+        network: Network::Signet,
+        ..Default::default()
+    });
+
+    ingest_blocks(&BLOCKS).await;
+
+    assert_eq!(with_state(main_chain_height), BLOCKS.len() as u32);
+
+    let response = get_utxos(GetUtxosRequest {
+        address: ADDRESS.to_string(),
+        filter: None,
+        sort_order: None,
+    })
+    .unwrap();
+    assert_eq!(response.utxos.len(), BLOCKS.len());
+    assert_eq!(response.tip_height, BLOCKS.len() as u32);
+    assert_eq!(
+        BlockHash::from(response.tip_block_hash),
+        BlockHash::from_str("000003162eca0b69f4abeb6a19e78086ea0d57d7fd00bcbbe4b2edfa4e078131")
+            .unwrap()
+    );
+
+    assert_eq!(
+        get_balance(GetBalanceRequest {
+            address: ADDRESS.to_string(),
+            min_confirmations: None,
+        })
+        .unwrap(),
+        BLOCKS.len() as u64 * 5_000_000_000
+    );
+}
+
+#[async_std::test]
+async fn signet_challenge_accepts_valid_blocks() {
+    crate::init(crate::Config {
+        stability_threshold: 2,
+        network: Network::Signet,
+        signet_challenge: Some(CHALLENGE_TRUE.to_vec()),
+        ..Default::default()
+    });
+
+    ingest_blocks(&BLOCKS_WITH_WITNESS_COMMITMENT).await;
+
+    assert_eq!(
+        with_state(main_chain_height),
+        BLOCKS_WITH_WITNESS_COMMITMENT.len() as u32
+    );
+    assert_eq!(with_state(|s| s.syncing_state.num_insert_block_errors), 0);
+    assert_eq!(
+        get_balance(GetBalanceRequest {
+            address: ADDRESS.to_string(),
+            min_confirmations: None,
+        })
+        .unwrap(),
+        BLOCKS_WITH_WITNESS_COMMITMENT.len() as u64 * 5_000_000_000
+    );
+}
+
+#[async_std::test]
+async fn signet_challenge_rejects_invalid_blocks() {
+    // Blocks without a witness commitment are invalid, even with a trivial challenge.
+    crate::init(crate::Config {
+        stability_threshold: 2,
+        network: Network::Signet,
+        signet_challenge: Some(CHALLENGE_TRUE.to_vec()),
+        ..Default::default()
+    });
+
+    ingest_blocks(&BLOCKS).await;
+
+    assert_eq!(with_state(main_chain_height), 0);
+    assert_eq!(
+        with_state(|s| s.syncing_state.num_insert_block_errors),
+        BLOCKS.len() as u64
+    );
+
+    // The blocks are known to be invalid.
+    assert_eq!(with_state(|s| s.invalid_blocks.len()), BLOCKS.len());
+
+    // Blocks without a solution don't satisfy a multisig challenge.
+    crate::init(crate::Config {
+        stability_threshold: 2,
+        network: Network::Signet,
+        signet_challenge: Some(hex::decode(CHALLENGE_MULTISIG).unwrap()),
+        ..Default::default()
+    });
+
+    ingest_blocks(&BLOCKS_WITH_WITNESS_COMMITMENT).await;
+
+    assert_eq!(with_state(main_chain_height), 0);
+    assert_eq!(
+        with_state(|s| s.syncing_state.num_insert_block_errors),
+        BLOCKS_WITH_WITNESS_COMMITMENT.len() as u64
+    );
+}
+
+#[test]
+#[should_panic(expected = "The signet challenge isn't supported.")]
+fn unsupported_signet_challenge_cannot_be_set() {
+    // A P2WPKH challenge.
+    let mut challenge = vec![0x00, 0x14];
+    challenge.extend([0; 20]);
+    crate::init(crate::Config {
+        network: Network::Signet,
+        signet_challenge: Some(challenge),
+        ..Default::default()
+    });
+}
+
+#[test]
+#[should_panic(expected = "A signet challenge can only be set on signet.")]
+fn signet_challenge_cannot_be_set_on_other_networks() {
+    crate::init(crate::Config {
+        network: Network::Testnet,
+        signet_challenge: Some(CHALLENGE_TRUE.to_vec()),
+        ..Default::default()
+    });
+}
@@ -0,0 +1,234 @@
+//! Tests upgrading from golden states of older versions.
+//!
+//! A golden state is a snapshot of the canister's stable memory, taken after `pre_upgrade`
+//! was called on a canister running a given version of the state. The snapshots are kept
+//! in `test-data/upgrades`, one per version, in a sparse format that only contains the
+//! pages of stable memory that aren't zero:
+//!
+//! | offset (8 bytes) | length (4 bytes) | bytes | offset (8 bytes) | ...
+//!
+//! where the offset and length are little-endian.
+use crate::{
+    genesis_block, init,
+    memory::{get_memory, set_memory},
+    post_upgrade, pre_upgrade,
+    state::{ingest_stable_blocks_into_utxoset, insert_block},
+    test_utils::{random_p2pkh_address, BlockBuilder, BlockChainBuilder, TransactionBuilder},
+    types::Slicing,
+    upgrade::CURRENT_VERSION,
+    utxo_set::UtxoAuditor,
+    with_state, with_state_mut, Config, STATE,
+};
+use ic_btc_interface::{Flag, Network, UtxoAuditReport};
+use ic_btc_types::{OutPoint, Transaction};
+use ic_stable_structures::{DefaultMemoryImpl, Memory};
+use std::{convert::TryInto, fs, path::PathBuf};
+
+const WASM_PAGE_SIZE: u64 = 65536;
+
+fn golden_states_dir() -> PathBuf {
+    PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("test-data/upgrades")
+}
+
+// Returns the golden states, sorted by version.
+fn golden_states() -> Vec<(u32, Vec<u8>)> {
+    let mut states: Vec<(u32, Vec<u8>)> = fs::read_dir(golden_states_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter_map(|path| {
+            let version = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix("state_v")?
+                .strip_suffix(".bin")?
+                .parse()
+                .ok()?;
+            Some((version, fs::read(&path).unwrap()))
+        })
+        .collect();
+    states.sort();
+    states
+}
+
+// Encodes the pages of the given memory that aren't zero.
+fn dump_memory<M: Memory>(memory: &M) -> Vec<u8> {
+    let mut dump = vec![];
+    let mut page = vec![0; WASM_PAGE_SIZE as usize];
+    for idx in 0..memory.size() {
+        let offset = idx * WASM_PAGE_SIZE;
+        memory.read(offset, &mut page);
+        if page.iter().any(|byte| *byte != 0) {
+            dump.extend_from_slice(&offset.to_le_bytes());
+            dump.extend_from_slice(&(page.len() as u32).to_le_bytes());
+            dump.extend_from_slice(&page);
+        }
+    }
+    dump
+}
+
+// Decodes a memory that was encoded with `dump_memory`.
+fn restore_memory(mut dump: &[u8]) -> DefaultMemoryImpl {
+    let memory = DefaultMemoryImpl::default();
+    while !dump.is_empty() {
+        let offset = u64::from_le_bytes(dump[0..8].try_into().unwrap());
+        let length = u32::from_le_bytes(dump[8..12].try_into().unwrap()) as usize;
+        crate::memory::write(&memory, offset, &dump[12..12 + length]);
+        dump = &dump[12 + length..];
+    }
+    memory
+}
+
+#[test]
+fn post_upgrade_from_golden_states() {
+    let states = golden_states();
+    assert_eq!(
+        states.first().map(|(version, _)| *version),
+        Some(0),
+        "Found no golden state of version 0."
+    );
+    for (version, _) in states.iter() {
+        assert!(
+            *version <= CURRENT_VERSION,
+            "Found a golden state of unknown version {}.",
+            version
+        );
+    }
+
+    for (version, dump) in states {
+        set_memory(restore_memory(&dump));
+        post_upgrade();
+
+        // The upgraded state is readable and survives another upgrade unchanged.
+        with_state(|s| {
+            assert_eq!(s.network(), Network::Regtest, "version {}", version);
+            assert!(s.utxos.next_height() > 0, "version {}", version);
+            assert!(!s.utxos.utxos.large_utxos.is_empty(), "version {}", version);
+        });
+
+        // Once the backfills started by the migrations are done, the stable blocks can be
+        // looked up by hash and the UTXO set is consistent with its indices.
+        with_state_mut(|s| {
+            assert_eq!(s.utxos.backfill_indices(&mut || false), Slicing::Done(()));
+            assert_eq!(
+                s.stable_block_headers.backfill_heights(&mut || false),
+                Slicing::Done(())
+            );
+
+            for (height, block_hash) in s.stable_block_headers.block_heights.iter() {
+                assert_eq!(
+                    s.stable_block_headers.get_height(&block_hash),
+                    Some(height),
+                    "version {}",
+                    version
+                );
+            }
+
+            let mut auditor = UtxoAuditor::default();
+            auditor.flag = Flag::Enabled;
+            auditor.run(&s.utxos, 0, &mut || false);
+            let report = auditor.last_report.unwrap();
+            assert_eq!(
+                report,
+                UtxoAuditReport {
+                    num_utxos: report.num_utxos,
+                    num_addresses: report.num_addresses,
+                    ..Default::default()
+                },
+                "version {}",
+                version
+            );
+        });
+        pre_upgrade();
+        let old_state = STATE.with(|cell| cell.take().unwrap());
+        post_upgrade();
+        with_state(|new_state| assert!(new_state == &old_state, "version {}", version));
+    }
+}
+
+/// Generates the golden state of the current version.
+///
+/// Run with `cargo test -p ic-btc-canister generate_golden_state -- --ignored` once a
+/// version is released, and commit the resulting file.
+#[test]
+#[ignore]
+fn generate_golden_state() {
+    let network = Network::Regtest;
+    init(Config {
+        stability_threshold: 2,
+        network,
+        ..Default::default()
+    });
+
+    // A chain with stable blocks, unstable blocks and a fork, so that every part of the
+    // state is populated. The large UTXOs created by a stable block are spent by unstable
+    // blocks on both branches of the fork, and a large UTXO is created and spent within
+    // the unstable blocks.
+    let genesis = genesis_block(network);
+    let block_1 = BlockBuilder::with_prev_header(genesis.header()).build();
+    let tx_2 = large_outputs_tx(OutPoint::new(block_1.txdata()[0].txid(), 0), 2);
+    let block_2 = BlockBuilder::with_prev_header(block_1.header())
+        .with_transaction(tx_2.clone())
+        .build();
+    let mut chain = vec![block_1, block_2];
+    chain.extend(BlockChainBuilder::fork(&chain[1], 2).build());
+
+    let tx_5 = large_outputs_tx(OutPoint::new(tx_2.txid(), 0), 1);
+    let block_5 = BlockBuilder::with_prev_header(chain[3].header())
+        .with_transaction(tx_5.clone())
+        .build();
+    let block_6 = BlockBuilder::with_prev_header(block_5.header())
+        .with_transaction(spend_tx(OutPoint::new(tx_5.txid(), 0)))
+        .build();
+    let block_7 = BlockBuilder::with_prev_header(block_6.header()).build();
+    chain.extend([block_5, block_6, block_7]);
+
+    let fork_1 = BlockBuilder::with_prev_header(chain[4].header())
+        .with_transaction(spend_tx(OutPoint::new(tx_2.txid(), 1)))
+        .build();
+    let fork_2 = BlockBuilder::with_prev_header(fork_1.header()).build();
+
+    with_state_mut(|s| {
+        // The blocks are ingested once the fork is inserted, so that the block it forks
+        // from doesn't become stable.
+        for block in chain.iter().chain(&[fork_1, fork_2]) {
+            insert_block(s, block.clone()).unwrap();
+        }
+        ingest_stable_blocks_into_utxoset(s);
+
+        // The large UTXOs of the stable block are in the UTXO set.
+        assert_eq!(s.utxos.next_height(), 5);
+        assert!(s.utxos.get_utxo(&OutPoint::new(tx_2.txid(), 0)).is_some());
+        assert!(s.utxos.get_utxo(&OutPoint::new(tx_2.txid(), 1)).is_some());
+        assert_eq!(s.unstable_blocks.num_tips(), 2);
+    });
+    pre_upgrade();
+
+    fs::write(
+        golden_states_dir().join(format!("state_v{}.bin", CURRENT_VERSION)),
+        dump_memory(&get_memory()),
+    )
+    .unwrap();
+}
+
+// Returns a transaction that spends the given output into the given number of outputs
+// with a script that's too large to be stored with the small or medium UTXOs.
+fn large_outputs_tx(previous_output: OutPoint, num_outputs: usize) -> Transaction {
+    let mut tx = ic_btc_test_utils::TransactionBuilder::new()
+        .with_input(previous_output.into(), None)
+        .build();
+    tx.output = (0..num_outputs)
+        .map(|_| bitcoin::TxOut {
+            value: 1,
+            script_pubkey: bitcoin::Script::from(vec![0; 1_000]),
+        })
+        .collect();
+    Transaction::new(tx)
+}
+
+// Returns a transaction that spends the given output.
+fn spend_tx(previous_output: OutPoint) -> Transaction {
+    TransactionBuilder::new()
+        .with_input(previous_output)
+        .with_output(&random_p2pkh_address(Network::Regtest), 1)
+        .build()
+}
@@ -1,13 +1,18 @@
 use bitcoin::{
-    Address as BitcoinAddress, Network as BitcoinNetwork, Script, TxOut as BitcoinTxOut,
+    secp256k1::PublicKey, Address as BitcoinAddress, Network as BitcoinNetwork, Script,
+    TxOut as BitcoinTxOut,
 };
 use candid::CandidType;
 use ic_btc_interface::{
-    Address as AddressStr, GetBalanceRequest as PublicGetBalanceRequest,
-    GetUtxosRequest as PublicGetUtxosRequest, Height, Network, Satoshi, UtxosFilter,
-    UtxosFilterInRequest,
+    Address as AddressStr, Checkpoint as PublicCheckpoint,
+    GetBalanceRequest as PublicGetBalanceRequest, GetUtxosRequest as PublicGetUtxosRequest, Height,
+    Network, Satoshi, UtxosFilter, UtxosFilterInRequest, UtxosSortOrder,
 };
 use ic_btc_types::{BlockHash, OutPoint, Txid};
+use ic_btc_validation::{
+    default_checkpoints as default_validation_checkpoints, Checkpoint as ValidationCheckpoint,
+    Network as ValidationNetwork,
+};
 use ic_stable_structures::{storable::Blob, BoundedStorable, Storable as StableStructuresStorable};
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
@@ -19,9 +24,13 @@ use std::{
     str::FromStr,
 };
 
-// The expected length in bytes of the page.
+// The expected length in bytes of a page that is sorted by height in descending order.
 const EXPECTED_PAGE_LENGTH: usize = 72;
 
+// The expected length in bytes of a page that is sorted in any other order. Such pages
+// additionally encode the sort order (1 byte) and the value of the UTXO (8 bytes).
+const EXPECTED_SORTED_PAGE_LENGTH: usize = EXPECTED_PAGE_LENGTH + 1 + 8;
+
 /// A Bitcoin transaction's output.
 #[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct TxOut {
@@ -43,28 +52,62 @@ pub struct Page {
     pub tip_block_hash: BlockHash,
     pub height: Height,
     pub outpoint: OutPoint,
+    pub value: Satoshi,
+    pub sort_order: UtxosSortOrder,
 }
 
 impl Page {
     pub fn to_bytes(&self) -> Vec<u8> {
-        vec![
+        let mut bytes: Vec<u8> = vec![
             self.tip_block_hash.clone().to_vec(),
             Storable::to_bytes(&self.height).to_vec(),
             OutPoint::to_bytes(&self.outpoint).to_vec(),
         ]
         .into_iter()
         .flatten()
-        .collect()
+        .collect();
+
+        // Pages sorted by height in descending order keep the original encoding
+        // so that pages issued before sort orders were introduced remain valid.
+        let sort_order_tag = match self.sort_order {
+            UtxosSortOrder::HeightDescending => return bytes,
+            UtxosSortOrder::HeightAscending => 1,
+            UtxosSortOrder::ValueDescending => 2,
+        };
+        bytes.push(sort_order_tag);
+        bytes.extend_from_slice(&self.value.to_be_bytes());
+        bytes
     }
 
     pub fn from_bytes(mut bytes: Vec<u8>) -> Result<Self, String> {
-        if bytes.len() != EXPECTED_PAGE_LENGTH {
-            return Err(format!(
-                "Could not parse the page, the length is {}, but the expected length is {}.",
-                bytes.len(),
-                EXPECTED_PAGE_LENGTH
-            ));
-        }
+        let (sort_order, value) = match bytes.len() {
+            EXPECTED_PAGE_LENGTH => (UtxosSortOrder::HeightDescending, 0),
+            EXPECTED_SORTED_PAGE_LENGTH => {
+                let value_bytes = bytes.split_off(EXPECTED_PAGE_LENGTH + 1);
+                let value = u64::from_be_bytes(
+                    value_bytes
+                        .try_into()
+                        .map_err(|err| format!("Could not parse page value: {:?}", err))?,
+                );
+                let sort_order = match bytes.pop() {
+                    Some(1) => UtxosSortOrder::HeightAscending,
+                    Some(2) => UtxosSortOrder::ValueDescending,
+                    other => {
+                        return Err(format!(
+                            "Could not parse page sort order: unknown tag {:?}",
+                            other
+                        ))
+                    }
+                };
+                (sort_order, value)
+            }
+            len => {
+                return Err(format!(
+                "Could not parse the page, the length is {}, but the expected length is {} or {}.",
+                len, EXPECTED_PAGE_LENGTH, EXPECTED_SORTED_PAGE_LENGTH
+            ))
+            }
+        };
 
         // The first 32 bytes represent the encoded `BlockHash`, the next 4 the
         // `Height` and the remaining the encoded `OutPoint`.
@@ -90,6 +133,8 @@ impl Page {
             tip_block_hash,
             height,
             outpoint: OutPoint::from_bytes(Cow::Owned(outpoint_bytes)),
+            value,
+            sort_order,
         })
     }
 }
@@ -101,22 +146,89 @@ pub trait Storable {
     fn from_bytes(bytes: Vec<u8>) -> Self;
 }
 
+// The encoding of a `(TxOut, Height)` starts with a tag that indicates how the script is
+// encoded, which is followed by the value and the script, and it ends with the height.
+//
+// Scripts that follow a standard template are compressed to the data in them, as is done
+// in Bitcoin Core, and the tag takes the place of the most significant byte of the value,
+// which is always zero as values cannot exceed the supply of 21M bitcoins (< 2^56 satoshis).
+//
+// Other scripts are stored as is, along with the value in all its 8 bytes. Since the most
+// significant byte of the value is zero, this is the same encoding as that of the UTXOs
+// that were stored before scripts were compressed, which therefore needn't be migrated and
+// remain readable, while the UTXOs that are created from then on are compressed.
+const SCRIPT_TAG_RAW: u8 = 0;
+const SCRIPT_TAG_P2PKH: u8 = 1;
+const SCRIPT_TAG_P2SH: u8 = 2;
+const SCRIPT_TAG_P2WPKH: u8 = 3;
+const SCRIPT_TAG_P2WSH: u8 = 4;
+const SCRIPT_TAG_P2TR: u8 = 5;
+// P2PK scripts with a compressed public key, whose prefix is either 0x02 or 0x03.
+const SCRIPT_TAG_P2PK_EVEN: u8 = 6;
+const SCRIPT_TAG_P2PK_ODD: u8 = 7;
+// P2PK scripts with an uncompressed public key, which is stored in its compressed form.
+const SCRIPT_TAG_P2PK_UNCOMPRESSED_EVEN: u8 = 8;
+const SCRIPT_TAG_P2PK_UNCOMPRESSED_ODD: u8 = 9;
+// A script that's stored as is along with a value whose most significant byte isn't zero.
+// Such values aren't valid, but are nonetheless stored without loss.
+const SCRIPT_TAG_RAW_WITH_LARGE_VALUE: u8 = u8::MAX;
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_DUP: u8 = 0x76;
+const OP_EQUAL: u8 = 0x87;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_HASH160: u8 = 0xa9;
+const OP_CHECKSIG: u8 = 0xac;
+
 impl Storable for (TxOut, Height) {
     fn to_bytes(&self) -> Vec<u8> {
-        vec![
-            self.0.value.to_bytes().to_vec(), // Store the value (8 bytes)
-            self.0.script_pubkey.clone(),     // Then the script (size varies)
-            Storable::to_bytes(&self.1),      // Then the height (4 bytes)
-        ]
-        .into_iter()
-        .flatten()
-        .collect()
+        let (tx_out, height) = self;
+        let value = tx_out.value.to_be_bytes();
+
+        let mut bytes = match compress_script(&tx_out.script_pubkey) {
+            Some((tag, payload)) if value[0] == 0 => {
+                // Store the tag in place of the value's most significant byte.
+                [&[tag][..], &value[1..], &payload].concat()
+            }
+            _ if value[0] == 0 => [&value[..], &tx_out.script_pubkey].concat(),
+            _ => [
+                &[SCRIPT_TAG_RAW_WITH_LARGE_VALUE][..],
+                &value,
+                &tx_out.script_pubkey,
+            ]
+            .concat(),
+        };
+
+        bytes.extend_from_slice(&Storable::to_bytes(height)); // Then the height (4 bytes)
+        bytes
     }
 
     fn from_bytes(mut bytes: Vec<u8>) -> Self {
         let height = <Height as Storable>::from_bytes(bytes.split_off(bytes.len() - 4));
-        let script_pubkey = bytes.split_off(8);
-        let value = u64::from_bytes(Cow::Owned(bytes));
+
+        let (value, script_pubkey) = match bytes[0] {
+            SCRIPT_TAG_RAW => {
+                let script_pubkey = bytes.split_off(8);
+                (u64::from_be_bytes(bytes.try_into().unwrap()), script_pubkey)
+            }
+            SCRIPT_TAG_RAW_WITH_LARGE_VALUE => {
+                let script_pubkey = bytes.split_off(9);
+                (
+                    u64::from_be_bytes(bytes[1..].try_into().unwrap()),
+                    script_pubkey,
+                )
+            }
+            tag => {
+                let payload = bytes.split_off(8);
+                bytes[0] = 0;
+                (
+                    u64::from_be_bytes(bytes.try_into().unwrap()),
+                    decompress_script(tag, &payload),
+                )
+            }
+        };
+
         (
             TxOut {
                 value,
@@ -127,6 +239,69 @@ impl Storable for (TxOut, Height) {
     }
 }
 
+// Returns the tag and the data of the given script if it follows a standard template,
+// or `None` otherwise.
+fn compress_script(script: &[u8]) -> Option<(u8, Vec<u8>)> {
+    match script {
+        [OP_DUP, OP_HASH160, 20, hash @ .., OP_EQUALVERIFY, OP_CHECKSIG] if hash.len() == 20 => {
+            Some((SCRIPT_TAG_P2PKH, hash.to_vec()))
+        }
+        [OP_HASH160, 20, hash @ .., OP_EQUAL] if hash.len() == 20 => {
+            Some((SCRIPT_TAG_P2SH, hash.to_vec()))
+        }
+        [OP_0, 20, hash @ ..] if hash.len() == 20 => Some((SCRIPT_TAG_P2WPKH, hash.to_vec())),
+        [OP_0, 32, hash @ ..] if hash.len() == 32 => Some((SCRIPT_TAG_P2WSH, hash.to_vec())),
+        [OP_1, 32, key @ ..] if key.len() == 32 => Some((SCRIPT_TAG_P2TR, key.to_vec())),
+        [33, 0x02, key @ .., OP_CHECKSIG] if key.len() == 32 => {
+            Some((SCRIPT_TAG_P2PK_EVEN, key.to_vec()))
+        }
+        [33, 0x03, key @ .., OP_CHECKSIG] if key.len() == 32 => {
+            Some((SCRIPT_TAG_P2PK_ODD, key.to_vec()))
+        }
+        [65, key @ .., OP_CHECKSIG] if key.len() == 65 && key[0] == 0x04 => {
+            // The key can only be restored from its compressed form if it's valid.
+            let key = PublicKey::from_slice(key).ok()?.serialize();
+            let tag = if key[0] == 0x02 {
+                SCRIPT_TAG_P2PK_UNCOMPRESSED_EVEN
+            } else {
+                SCRIPT_TAG_P2PK_UNCOMPRESSED_ODD
+            };
+            Some((tag, key[1..].to_vec()))
+        }
+        _ => None,
+    }
+}
+
+// Returns the script with the given tag and data, as returned by `compress_script`.
+fn decompress_script(tag: u8, payload: &[u8]) -> Vec<u8> {
+    match tag {
+        SCRIPT_TAG_P2PKH => [
+            &[OP_DUP, OP_HASH160, 20][..],
+            payload,
+            &[OP_EQUALVERIFY, OP_CHECKSIG],
+        ]
+        .concat(),
+        SCRIPT_TAG_P2SH => [&[OP_HASH160, 20][..], payload, &[OP_EQUAL]].concat(),
+        SCRIPT_TAG_P2WPKH => [&[OP_0, 20][..], payload].concat(),
+        SCRIPT_TAG_P2WSH => [&[OP_0, 32][..], payload].concat(),
+        SCRIPT_TAG_P2TR => [&[OP_1, 32][..], payload].concat(),
+        SCRIPT_TAG_P2PK_EVEN => [&[33, 0x02][..], payload, &[OP_CHECKSIG]].concat(),
+        SCRIPT_TAG_P2PK_ODD => [&[33, 0x03][..], payload, &[OP_CHECKSIG]].concat(),
+        SCRIPT_TAG_P2PK_UNCOMPRESSED_EVEN | SCRIPT_TAG_P2PK_UNCOMPRESSED_ODD => {
+            let prefix = if tag == SCRIPT_TAG_P2PK_UNCOMPRESSED_EVEN {
+                0x02
+            } else {
+                0x03
+            };
+            let key = PublicKey::from_slice(&[&[prefix][..], payload].concat())
+                .expect("a stored public key must be valid")
+                .serialize_uncompressed();
+            [&[65][..], &key, &[OP_CHECKSIG]].concat()
+        }
+        _ => panic!("Unknown script tag {}.", tag),
+    }
+}
+
 impl StableStructuresStorable for Address {
     fn to_bytes(&self) -> Cow<[u8]> {
         Cow::Borrowed(self.0.as_bytes())
@@ -250,6 +425,227 @@ impl RangeBounds<Blob<{ AddressUtxo::MAX_SIZE as usize }>> for AddressUtxoRange
     }
 }
 
+/// A key of the index of an address's UTXOs sorted by value.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct AddressValueUtxo {
+    pub address: Address,
+    pub value: Satoshi,
+    pub outpoint: OutPoint,
+}
+
+impl AddressValueUtxo {
+    // The value is represented as an XOR'ed big endian byte array
+    // so that stored entries are sorted in descending value order.
+    fn value_to_bytes(value: Satoshi) -> [u8; 8] {
+        (value ^ u64::MAX).to_be_bytes()
+    }
+
+    fn value_from_bytes(bytes: &[u8]) -> Satoshi {
+        u64::from_be_bytes(bytes.try_into().expect("value_bytes must of length 8")) ^ u64::MAX
+    }
+}
+
+impl StableStructuresStorable for AddressValueUtxo {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        let bytes = vec![
+            Address::to_bytes(&self.address).to_vec(),
+            Self::value_to_bytes(self.value).to_vec(),
+            OutPoint::to_bytes(&self.outpoint).to_vec(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        std::borrow::Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let len = bytes.len();
+        let outpoint_offset = len - OutPoint::size() as usize;
+        let value_offset = outpoint_offset - 8;
+        Self {
+            address: Address::from_bytes(Cow::Borrowed(&bytes[0..value_offset])),
+            value: Self::value_from_bytes(&bytes[value_offset..outpoint_offset]),
+            outpoint: OutPoint::from_bytes(Cow::Borrowed(&bytes[outpoint_offset..])),
+        }
+    }
+}
+
+impl BoundedStorable for AddressValueUtxo {
+    const MAX_SIZE: u32 = Address::MAX_SIZE + 8 /* value bytes */ + OutPoint::MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct AddressValueUtxoRange {
+    start_bound: Blob<{ AddressValueUtxo::MAX_SIZE as usize }>,
+    end_bound: Blob<{ AddressValueUtxo::MAX_SIZE as usize }>,
+}
+
+impl AddressValueUtxoRange {
+    /// Given an address and UTXO, returns a range that matches with all of the address's UTXOs
+    /// that are >= the given UTXO.
+    ///
+    /// The UTXOs are sorted by value in descending order, and then by outpoint.
+    pub fn new(address: &Address, utxo: &Option<Utxo>) -> Self {
+        let (start_value, start_outpoint) = match utxo {
+            Some(utxo) => (utxo.value, utxo.outpoint.clone()),
+
+            // No UTXO specified. Start with the minimum value possible for a value and OutPoint.
+            // Values are sorted in descending order, so u64::MAX is considered its minimum.
+            None => (u64::MAX, OutPoint::new(Txid::from(vec![0; 32]), 0)),
+        };
+
+        // The end of the range is the maximum value possible for a value and OutPoint.
+        // Values are sorted in descending order, so `0` is considered its maximum.
+        let (end_value, end_outpoint) = (0, OutPoint::new(Txid::from(vec![255; 32]), u32::MAX));
+
+        let start_bound = Blob::try_from(
+            AddressValueUtxo {
+                address: address.clone(),
+                value: start_value,
+                outpoint: start_outpoint,
+            }
+            .to_bytes()
+            .as_ref(),
+        )
+        .unwrap();
+
+        let end_bound = Blob::try_from(
+            AddressValueUtxo {
+                address: address.clone(),
+                value: end_value,
+                outpoint: end_outpoint,
+            }
+            .to_bytes()
+            .as_ref(),
+        )
+        .unwrap();
+
+        Self {
+            start_bound,
+            end_bound,
+        }
+    }
+}
+
+impl RangeBounds<Blob<{ AddressValueUtxo::MAX_SIZE as usize }>> for AddressValueUtxoRange {
+    fn start_bound(&self) -> Bound<&Blob<{ AddressValueUtxo::MAX_SIZE as usize }>> {
+        Bound::Included(&self.start_bound)
+    }
+
+    fn end_bound(&self) -> Bound<&Blob<{ AddressValueUtxo::MAX_SIZE as usize }>> {
+        Bound::Included(&self.end_bound)
+    }
+}
+
+/// A key of the index of an address's UTXOs sorted by height in ascending order.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct AddressHeightAscendingUtxo {
+    pub address: Address,
+    pub height: Height,
+    pub outpoint: OutPoint,
+}
+
+impl StableStructuresStorable for AddressHeightAscendingUtxo {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        // Unlike in `AddressUtxo`, the height is represented as a plain big endian byte array
+        // so that stored entries are sorted in ascending height order.
+        let bytes = vec![
+            Address::to_bytes(&self.address).to_vec(),
+            self.height.to_be_bytes().to_vec(),
+            OutPoint::to_bytes(&self.outpoint).to_vec(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        std::borrow::Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let len = bytes.len();
+        let outpoint_offset = len - OutPoint::size() as usize;
+        let height_offset = outpoint_offset - 4;
+        Self {
+            address: Address::from_bytes(Cow::Borrowed(&bytes[0..height_offset])),
+            height: u32::from_be_bytes(
+                bytes[height_offset..outpoint_offset]
+                    .try_into()
+                    .expect("height_bytes must of length 4"),
+            ),
+            outpoint: OutPoint::from_bytes(Cow::Borrowed(&bytes[outpoint_offset..])),
+        }
+    }
+}
+
+impl BoundedStorable for AddressHeightAscendingUtxo {
+    const MAX_SIZE: u32 = Address::MAX_SIZE + 4 /* height bytes */ + OutPoint::MAX_SIZE;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+pub struct AddressHeightAscendingUtxoRange {
+    start_bound: Blob<{ AddressHeightAscendingUtxo::MAX_SIZE as usize }>,
+    end_bound: Blob<{ AddressHeightAscendingUtxo::MAX_SIZE as usize }>,
+}
+
+impl AddressHeightAscendingUtxoRange {
+    /// Given an address and UTXO, returns a range that matches with all of the address's UTXOs
+    /// that come at or after the given UTXO.
+    ///
+    /// The UTXOs are sorted by height in ascending order, and then by outpoint.
+    pub fn new(address: &Address, utxo: &Option<Utxo>) -> Self {
+        let (start_height, start_outpoint) = match utxo {
+            Some(utxo) => (utxo.height, utxo.outpoint.clone()),
+
+            // No UTXO specified. Start with the minimum value possible for a height and OutPoint.
+            None => (0, OutPoint::new(Txid::from(vec![0; 32]), 0)),
+        };
+
+        // The end of the range is the maximum value possible for a height and OutPoint.
+        let (end_height, end_outpoint) =
+            (u32::MAX, OutPoint::new(Txid::from(vec![255; 32]), u32::MAX));
+
+        let start_bound = Blob::try_from(
+            AddressHeightAscendingUtxo {
+                address: address.clone(),
+                height: start_height,
+                outpoint: start_outpoint,
+            }
+            .to_bytes()
+            .as_ref(),
+        )
+        .unwrap();
+
+        let end_bound = Blob::try_from(
+            AddressHeightAscendingUtxo {
+                address: address.clone(),
+                height: end_height,
+                outpoint: end_outpoint,
+            }
+            .to_bytes()
+            .as_ref(),
+        )
+        .unwrap();
+
+        Self {
+            start_bound,
+            end_bound,
+        }
+    }
+}
+
+impl RangeBounds<Blob<{ AddressHeightAscendingUtxo::MAX_SIZE as usize }>>
+    for AddressHeightAscendingUtxoRange
+{
+    fn start_bound(&self) -> Bound<&Blob<{ AddressHeightAscendingUtxo::MAX_SIZE as usize }>> {
+        Bound::Included(&self.start_bound)
+    }
+
+    fn end_bound(&self) -> Bound<&Blob<{ AddressHeightAscendingUtxo::MAX_SIZE as usize }>> {
+        Bound::Included(&self.end_bound)
+    }
+}
+
 impl Storable for Height {
     fn to_bytes(&self) -> Vec<u8> {
         // The height is represented as an XOR'ed big endian byte array
@@ -489,6 +885,7 @@ impl From<PublicGetBalanceRequest> for GetBalanceRequest {
 pub struct GetUtxosRequest {
     pub address: AddressStr,
     pub filter: Option<UtxosFilter>,
+    pub sort_order: Option<UtxosSortOrder>,
 }
 
 impl From<PublicGetUtxosRequest> for GetUtxosRequest {
@@ -504,6 +901,7 @@ impl From<PublicGetUtxosRequest> for GetUtxosRequest {
                     UtxosFilter::Page(page)
                 }
             }),
+            sort_order: request.sort_order,
         }
     }
 }
@@ -563,11 +961,122 @@ impl PartialOrd for Utxo {
     }
 }
 
+/// A wrapper around a `Utxo` that is ordered by value in descending order, then by outpoint.
+#[derive(Debug, PartialEq, Eq)]
+pub struct UtxoByValue(pub Utxo);
+
+impl Ord for UtxoByValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Sort by value in descending order.
+        other
+            .0
+            .value
+            .cmp(&self.0.value)
+            // Then sort by outpoint.
+            .then_with(|| self.0.outpoint.cmp(&other.0.outpoint))
+            // Then by height.
+            .then_with(|| self.0.height.cmp(&other.0.height))
+    }
+}
+
+impl PartialOrd for UtxoByValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A wrapper around a `Utxo` that is ordered by height in ascending order, then by outpoint.
+#[derive(Debug, PartialEq, Eq)]
+pub struct UtxoByHeightAscending(pub Utxo);
+
+impl Ord for UtxoByHeightAscending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Sort by height in ascending order.
+        self.0
+            .height
+            .cmp(&other.0.height)
+            // Then sort by outpoint.
+            .then_with(|| self.0.outpoint.cmp(&other.0.outpoint))
+            // Then by value.
+            .then_with(|| self.0.value.cmp(&other.0.value))
+    }
+}
+
+impl PartialOrd for UtxoByHeightAscending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 pub fn into_bitcoin_network(network: Network) -> BitcoinNetwork {
     match network {
         Network::Mainnet => BitcoinNetwork::Bitcoin,
         Network::Testnet => BitcoinNetwork::Testnet,
         Network::Regtest => BitcoinNetwork::Regtest,
+        Network::Signet => BitcoinNetwork::Signet,
+        // Testnet4 uses the same address format as testnet.
+        Network::Testnet4 => BitcoinNetwork::Testnet,
+    }
+}
+
+pub fn into_validation_network(network: Network) -> ValidationNetwork {
+    match network {
+        Network::Mainnet => ValidationNetwork::Bitcoin,
+        Network::Testnet => ValidationNetwork::Testnet,
+        Network::Regtest => ValidationNetwork::Regtest,
+        Network::Signet => ValidationNetwork::Signet,
+        Network::Testnet4 => ValidationNetwork::Testnet4,
+    }
+}
+
+/// A block hash that the chain is required to contain at a given height.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub height: Height,
+    pub block_hash: BlockHash,
+}
+
+impl From<PublicCheckpoint> for Checkpoint {
+    fn from(checkpoint: PublicCheckpoint) -> Self {
+        Self {
+            height: checkpoint.height,
+            block_hash: BlockHash::from(checkpoint.block_hash),
+        }
+    }
+}
+
+impl From<Checkpoint> for PublicCheckpoint {
+    fn from(checkpoint: Checkpoint) -> Self {
+        Self {
+            height: checkpoint.height,
+            block_hash: checkpoint.block_hash.to_vec(),
+        }
+    }
+}
+
+impl From<ValidationCheckpoint> for Checkpoint {
+    fn from(checkpoint: ValidationCheckpoint) -> Self {
+        Self {
+            height: checkpoint.height,
+            block_hash: BlockHash::from(checkpoint.hash),
+        }
+    }
+}
+
+/// Returns the checkpoints that the given network is validated against by default.
+pub fn default_checkpoints(network: Network) -> Vec<Checkpoint> {
+    default_validation_checkpoints(&into_validation_network(network))
+        .into_iter()
+        .map(Checkpoint::from)
+        .collect()
+}
+
+impl From<&Checkpoint> for ValidationCheckpoint {
+    fn from(checkpoint: &Checkpoint) -> Self {
+        Self {
+            height: checkpoint.height,
+            hash: (&checkpoint.block_hash).into(),
+        }
     }
 }
 
@@ -658,3 +1167,114 @@ fn address_handles_script_edge_case() {
         Err(InvalidAddress)
     );
 }
+
+#[test]
+fn signet_addresses_can_be_parsed() {
+    // Signet shares the address format of testnet, so the address derived from a script
+    // must be the same as the one parsed from its string representation.
+    let script =
+        Script::from(hex::decode("76a9148b3f0f8c6b7b2d2ff0a1a2e0c8d9f7a6b5c4d3e288ac").unwrap());
+    let address = Address::from_script(&script, Network::Signet).unwrap();
+
+    assert_eq!(
+        address,
+        Address::from_script(&script, Network::Testnet).unwrap()
+    );
+    assert_eq!(Address::from_str(&address.to_string()), Ok(address));
+}
+
+#[test]
+fn tx_out_scripts_are_compressed() {
+    use bitcoin::{
+        hashes::Hash,
+        secp256k1::{rand::rngs::OsRng, Secp256k1},
+        PubkeyHash, PublicKey as BitcoinPublicKey, ScriptHash, WPubkeyHash, WScriptHash,
+    };
+
+    let (_, key) = Secp256k1::new().generate_keypair(&mut OsRng::new().unwrap());
+    let p2pk = |compressed| {
+        Script::new_p2pk(&BitcoinPublicKey {
+            compressed,
+            inner: key,
+        })
+    };
+
+    for (script, compressed_script_size) in [
+        (
+            Script::new_p2pkh(&PubkeyHash::from_slice(&[1; 20]).unwrap()),
+            20,
+        ),
+        (
+            Script::new_p2sh(&ScriptHash::from_slice(&[2; 20]).unwrap()),
+            20,
+        ),
+        (
+            Script::new_v0_p2wpkh(&WPubkeyHash::from_slice(&[3; 20]).unwrap()),
+            20,
+        ),
+        (
+            Script::new_v0_p2wsh(&WScriptHash::from_slice(&[4; 32]).unwrap()),
+            32,
+        ),
+        (Script::from([&[0x51, 32][..], &[5; 32]].concat()), 32),
+        (p2pk(true), 32),
+        (p2pk(false), 32),
+        // Scripts that don't follow a template are stored as is.
+        (Script::from(vec![0x6a, 1, 2, 3]), 4),
+        (
+            Script::from([&[65, 0x04][..], &[0; 64], &[0xac]].concat()),
+            67,
+        ),
+    ] {
+        let utxo = (
+            TxOut {
+                value: 21_000_000 * 100_000_000,
+                script_pubkey: script.to_bytes(),
+            },
+            123,
+        );
+
+        let bytes = utxo.to_bytes();
+        assert_eq!(bytes.len(), 8 + compressed_script_size + 4);
+        assert_eq!(<(TxOut, Height)>::from_bytes(bytes), utxo);
+    }
+}
+
+#[test]
+fn tx_outs_stored_before_compression_are_readable() {
+    use bitcoin::{hashes::Hash, WScriptHash};
+
+    let script = Script::new_v0_p2wsh(&WScriptHash::from_slice(&[1; 32]).unwrap());
+    let utxo = (
+        TxOut {
+            value: 1_000,
+            script_pubkey: script.to_bytes(),
+        },
+        7,
+    );
+
+    // The value, followed by the script as is, followed by the height.
+    let legacy_bytes = [
+        &StableStructuresStorable::to_bytes(&utxo.0.value)[..],
+        &utxo.0.script_pubkey,
+        &Storable::to_bytes(&utxo.1),
+    ]
+    .concat();
+
+    assert!(utxo.to_bytes().len() < legacy_bytes.len());
+    assert_eq!(<(TxOut, Height)>::from_bytes(legacy_bytes), utxo);
+}
+
+#[test]
+fn tx_out_encoding_roundtrips() {
+    use proptest::prelude::*;
+
+    proptest!(|(
+        value in any::<u64>(),
+        script_pubkey in proptest::collection::vec(any::<u8>(), 0..100),
+        height in any::<u32>(),
+    )| {
+        let utxo = (TxOut { value, script_pubkey }, height);
+        assert_eq!(<(TxOut, Height)>::from_bytes(utxo.to_bytes()), utxo);
+    });
+}
@@ -1,3 +1,4 @@
+mod block_bodies;
 mod outpoints_cache;
 use crate::{
     blocktree::{self, BlockChain, BlockDoesNotExtendTree, BlockTree},
@@ -5,11 +6,13 @@ use crate::{
     types::{Address, TxOut},
     UtxoSet,
 };
-use bitcoin::BlockHeader;
-use ic_btc_interface::{Height, Network};
+use bitcoin::{util::uint::Uint256, Block as BitcoinBlock, BlockHeader};
+use block_bodies::BlockBodies;
+use ic_btc_interface::{ForkPruning, Height, Network};
 use ic_btc_types::{Block, BlockHash, OutPoint};
 use outpoints_cache::OutPointsCache;
 use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::BTreeMap};
 
 mod next_block_headers;
 use self::next_block_headers::NextBlockHeaders;
@@ -18,17 +21,40 @@ const TESTNET_MAX_SOLO_CHAIN_LENGTH: u128 = 1000;
 
 /// A data structure for maintaining all unstable blocks.
 ///
-/// A block `b` is considered stable if:
-///   depth(block) ≥ stability_threshold
-///   ∀ b', height(b') = height(b): depth(b) - depth(b’) ≥ stability_threshold
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// A block `b` on the main chain is considered stable if:
+///   work(b) ≥ stability_threshold * work(anchor)
+///   ∀ b', height(b') = height(b): work(b) - work(b’) ≥ stability_threshold * work(anchor)
+///
+/// where work(b) is the cumulative work of the chain from `b` to the best tip that extends it.
+///
+/// Only the skeleton of the tree is kept on the heap, i.e. the blocks in the tree have
+/// their headers but no transactions. The bodies of the blocks and the outpoints cache
+/// are kept in stable memory, so that they don't need to be serialized on upgrades.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(test, derive(PartialEq))]
 pub struct UnstableBlocks {
     stability_threshold: u32,
     tree: BlockTree,
+    // NOTE: Stable structures don't need to be serialized.
+    #[serde(skip, default = "BlockBodies::init")]
+    block_bodies: BlockBodies,
     outpoints_cache: OutPointsCache,
     network: Network,
     // The headers of the blocks that are expected to be received.
     next_block_headers: NextBlockHeaders,
+    // The policy for pruning stale forks, if any.
+    #[serde(default)]
+    fork_pruning: Option<ForkPruning>,
+    // The hashes of the blocks of pruned forks, along with the height of the root of their
+    // fork. They're kept until the anchor moves past the root of their fork.
+    #[serde(default)]
+    pruned_blocks: BTreeMap<BlockHash, Height>,
+    // The `first_seen` value of the next block that extends the tree. It only increases, even
+    // when blocks are removed from the tree, so that a block always counts as seen after all
+    // the blocks that were seen before it. States that were serialized before it was
+    // introduced don't have it, in which case it's derived from the tree.
+    #[serde(default)]
+    next_first_seen: Option<u64>,
 }
 
 impl UnstableBlocks {
@@ -39,28 +65,54 @@ impl UnstableBlocks {
             .insert(utxos, &anchor, utxos.next_height())
             .expect("anchor block must be valid.");
 
+        let mut block_bodies = BlockBodies::new();
+        block_bodies.insert(&anchor);
+
         Self {
             stability_threshold,
-            tree: BlockTree::new(anchor.clone()),
+            tree: BlockTree::new(header_only(&anchor)),
+            block_bodies,
             outpoints_cache,
             network,
             next_block_headers: NextBlockHeaders::default(),
+            fork_pruning: None,
+            pruned_blocks: BTreeMap::new(),
+            next_first_seen: Some(1),
         }
     }
 
+    // Returns the `first_seen` value of the block that was seen last.
+    fn last_seen(&self) -> u64 {
+        match self.next_first_seen {
+            Some(next_first_seen) => next_first_seen - 1,
+            None => self.tree.max_first_seen(),
+        }
+    }
+
+    // Returns the `first_seen` value of the next block that extends the tree and advances it.
+    fn take_next_first_seen(&mut self) -> u64 {
+        let first_seen = self.last_seen() + 1;
+        self.next_first_seen = Some(first_seen + 1);
+        first_seen
+    }
+
     /// Retrieves the `TxOut` associated with the given `outpoint`, along with its height.
-    pub fn get_tx_out(&self, outpoint: &OutPoint) -> Option<(&TxOut, Height)> {
+    pub fn get_tx_out(&self, outpoint: &OutPoint) -> Option<(TxOut, Height)> {
         self.outpoints_cache.get_tx_out(outpoint)
     }
 
     /// Retrieves the list of outpoints that were added for the given address in the given block.
-    pub fn get_added_outpoints(&self, block_hash: &BlockHash, address: &Address) -> &[OutPoint] {
+    pub fn get_added_outpoints(&self, block_hash: &BlockHash, address: &Address) -> Vec<OutPoint> {
         self.outpoints_cache
             .get_added_outpoints(block_hash, address)
     }
 
     /// Retrieves the list of outpoints that were removed for the given address in the given block.
-    pub fn get_removed_outpoints(&self, block_hash: &BlockHash, address: &Address) -> &[OutPoint] {
+    pub fn get_removed_outpoints(
+        &self,
+        block_hash: &BlockHash,
+        address: &Address,
+    ) -> Vec<OutPoint> {
         self.outpoints_cache
             .get_removed_outpoints(block_hash, address)
     }
@@ -73,6 +125,19 @@ impl UnstableBlocks {
         self.stability_threshold = stability_threshold;
     }
 
+    pub fn fork_pruning(&self) -> Option<ForkPruning> {
+        self.fork_pruning
+    }
+
+    pub fn set_fork_pruning(&mut self, fork_pruning: Option<ForkPruning>) {
+        self.fork_pruning = fork_pruning;
+    }
+
+    /// Returns the hashes of the blocks of the forks that were pruned.
+    pub fn pruned_block_hashes(&self) -> impl Iterator<Item = &BlockHash> {
+        self.pruned_blocks.keys()
+    }
+
     pub fn anchor_difficulty(&self) -> u64 {
         self.tree.root.difficulty(self.network)
     }
@@ -81,6 +146,12 @@ impl UnstableBlocks {
         self.anchor_difficulty() as u128 * self.stability_threshold as u128
     }
 
+    /// Returns the work that the chain of a child of the anchor needs for the child
+    /// to be stable, i.e. the work of the anchor times the stability threshold.
+    pub fn stability_threshold_work(&self) -> Uint256 {
+        self.tree.root.work().mul_u32(self.stability_threshold)
+    }
+
     /// Returns the number of tips available in the current block tree.
     pub fn num_tips(&self) -> u32 {
         self.tree.num_tips()
@@ -163,11 +234,89 @@ impl UnstableBlocks {
         chain.reverse();
         chain
     }
+
+    /// Moves the bodies of the blocks in the tree into stable memory and rebuilds the
+    /// outpoints cache.
+    ///
+    /// States that were serialized before the bodies of the unstable blocks were moved
+    /// to stable memory hold the full blocks in the tree. This is a no-op otherwise.
+    pub fn migrate_to_stable_memory(&mut self, utxos: &UtxoSet) {
+        fn migrate_subtree(
+            tree: &mut BlockTree,
+            block_bodies: &mut BlockBodies,
+            outpoints_cache: &mut OutPointsCache,
+            utxos: &UtxoSet,
+            height: Height,
+        ) {
+            outpoints_cache
+                .insert(utxos, &tree.root, height)
+                .expect("inserting to outpoints cache must succeed.");
+            block_bodies.insert(&tree.root);
+            tree.root = header_only(&tree.root);
+
+            for child in tree.children.iter_mut() {
+                migrate_subtree(child, block_bodies, outpoints_cache, utxos, height + 1);
+            }
+        }
+
+        // Every block has at least a coinbase transaction, so an anchor without
+        // transactions means that the tree has already been migrated.
+        if self.tree.root.txdata().is_empty() {
+            return;
+        }
+
+        print("Moving the unstable blocks to stable memory...");
+        self.block_bodies = BlockBodies::new();
+        self.outpoints_cache = OutPointsCache::new();
+        migrate_subtree(
+            &mut self.tree,
+            &mut self.block_bodies,
+            &mut self.outpoints_cache,
+            utxos,
+            utxos.next_height(),
+        );
+    }
+}
+
+// Returns a copy of the given block without its transactions, which is what's stored
+// in the tree of unstable blocks.
+fn header_only(block: &Block) -> Block {
+    #[allow(unused_mut)]
+    let mut skeleton = Block::new(BitcoinBlock {
+        header: *block.header(),
+        txdata: vec![],
+    });
+    #[cfg(test)]
+    {
+        skeleton.mock_difficulty = block.mock_difficulty;
+    }
+    skeleton
 }
 
-/// Returns a reference to the `anchor` block iff ∃ a child `C` of `anchor` that is stable.
-pub fn peek(blocks: &UnstableBlocks) -> Option<&Block> {
-    get_stable_child(blocks).map(|_| &blocks.tree.root)
+/// Loads the full block of the given block in the tree.
+///
+/// NOTE: The given block must be one of the unstable blocks, e.g. a block of the main chain.
+pub fn load_block(blocks: &UnstableBlocks, skeleton: &Block) -> Block {
+    #[allow(unused_mut)]
+    let mut block = blocks
+        .block_bodies
+        .get(&skeleton.block_hash())
+        .unwrap_or_else(|| panic!("body of block {:?} must exist.", skeleton.block_hash()));
+    #[cfg(test)]
+    {
+        block.mock_difficulty = skeleton.mock_difficulty;
+    }
+    block
+}
+
+/// Returns true iff ∃ a child `C` of `anchor` that is stable, i.e. iff `peek` returns a block.
+pub fn has_stable_block(blocks: &UnstableBlocks) -> bool {
+    get_stable_child(blocks).is_some()
+}
+
+/// Returns the `anchor` block iff ∃ a child `C` of `anchor` that is stable.
+pub fn peek(blocks: &UnstableBlocks) -> Option<Block> {
+    get_stable_child(blocks).map(|_| load_block(blocks, &blocks.tree.root))
 }
 
 /// Pops the `anchor` block iff ∃ a child `C` of the `anchor` block that
@@ -176,16 +325,34 @@ pub fn peek(blocks: &UnstableBlocks) -> Option<&Block> {
 pub fn pop(blocks: &mut UnstableBlocks, stable_height: Height) -> Option<Block> {
     match get_stable_child(blocks) {
         Some(stable_child_idx) => {
-            let old_anchor = blocks.tree.root.clone();
+            let old_anchor = load_block(blocks, &blocks.tree.root);
 
             // Replace the unstable block tree with that of the stable child.
-            blocks.tree = blocks.tree.children.swap_remove(stable_child_idx);
+            let stable_child = blocks.tree.children.swap_remove(stable_child_idx);
+            let siblings = std::mem::replace(&mut blocks.tree, stable_child);
+            blocks.tree.rebase_chainwork();
 
-            // Remove the outpoints of the old anchor from the cache.
+            // Remove the outpoints and the body of the old anchor.
             blocks.outpoints_cache.remove(&old_anchor);
+            blocks.block_bodies.remove(&old_anchor.block_hash());
+
+            // Remove the outpoints and the bodies of the discarded siblings and their
+            // descendants.
+            for sibling in siblings.children.iter() {
+                for skeleton in blocktree::blocks(sibling) {
+                    let block = load_block(blocks, skeleton);
+                    blocks.outpoints_cache.remove(&block);
+                    blocks.block_bodies.remove(&block.block_hash());
+                }
+            }
 
             blocks.next_block_headers.remove_until_height(stable_height);
 
+            // Forks that were pruned below the new anchor can no longer extend the tree.
+            blocks
+                .pruned_blocks
+                .retain(|_, fork_height| *fork_height > stable_height);
+
             Some(old_anchor)
         }
         None => None,
@@ -198,9 +365,8 @@ pub fn push(
     utxos: &UtxoSet,
     block: Block,
 ) -> Result<(), BlockDoesNotExtendTree> {
-    let (parent_block_tree, depth) =
-        blocktree::find_mut(&mut blocks.tree, &block.header().prev_blockhash.into())
-            .ok_or_else(|| BlockDoesNotExtendTree(block.block_hash()))?;
+    let (_, depth) = blocktree::find_mut(&mut blocks.tree, &block.header().prev_blockhash.into())
+        .ok_or_else(|| BlockDoesNotExtendTree(block.block_hash()))?;
 
     let height = utxos.next_height() + depth + 1;
 
@@ -209,71 +375,130 @@ pub fn push(
         .insert(utxos, &block, height)
         .expect("inserting to outpoints cache must succeed.");
 
+    blocks.block_bodies.insert(&block);
+
     let block_hash = block.block_hash();
 
-    blocktree::extend(parent_block_tree, block)?;
+    let first_seen = blocks.take_next_first_seen();
+    blocktree::extend(&mut blocks.tree, header_only(&block), first_seen)?;
 
     blocks.next_block_headers.remove(&block_hash);
 
     Ok(())
 }
 
-/// Returns the best guess on what the main blockchain is.
+/// Removes the block with the given hash along with all its descendants, including
+/// the headers of the descendants that are yet to be received.
 ///
-/// The most likely chain to be "main", we hypothesize, is the longest
-/// chain of blocks with an "uncontested" tip. As in, there exists no other
-/// block at the same height as the tip.
-pub fn get_main_chain(blocks: &UnstableBlocks) -> BlockChain {
-    // Get all the blockchains that extend the anchor.
-    let blockchains: Vec<BlockChain> = blocktree::blockchains(&blocks.tree);
+/// Returns the removed blocks, or `None` if the block isn't an unstable block other
+/// than the anchor.
+pub fn remove_subtree(blocks: &mut UnstableBlocks, block_hash: &BlockHash) -> Option<Vec<Block>> {
+    let subtree = blocktree::remove_subtree(&mut blocks.tree, block_hash)?;
+    let removed_blocks: Vec<Block> = blocktree::blocks(&subtree)
+        .into_iter()
+        .map(|block| load_block(blocks, block))
+        .collect();
 
-    // Find the length of the longest blockchain.
-    let mut longest_blockchain_len = 0;
-    for blockchain in blockchains.iter() {
-        longest_blockchain_len = longest_blockchain_len.max(blockchain.len());
+    for block in removed_blocks.iter() {
+        blocks.outpoints_cache.remove(block);
+        blocks.block_bodies.remove(&block.block_hash());
     }
 
-    // Get all the longest blockchains.
-    let longest_blockchains: Vec<Vec<&'_ Block>> = blockchains
+    let removed_hashes: Vec<BlockHash> = removed_blocks
+        .iter()
+        .map(|block| block.block_hash())
+        .collect();
+    blocks
+        .next_block_headers
+        .remove_descendants(&removed_hashes);
+
+    Some(removed_blocks)
+}
+
+/// Prunes the forks that are stale as per the fork pruning policy, if any. A fork is stale if
+/// the work of its best chain trails that of the main chain by too much, or if it hasn't been
+/// extended for too long.
+///
+/// Returns the number of blocks that were pruned.
+pub fn prune_stale_forks(blocks: &mut UnstableBlocks, stable_height: Height) -> usize {
+    let policy = match blocks.fork_pruning {
+        Some(policy) => policy,
+        None => return 0,
+    };
+
+    let main_chainwork = blocktree::best_tip(&blocks.tree).chainwork;
+    let max_work_deficit = blocks.tree.root.work().mul_u32(policy.max_work_deficit);
+    let last_seen = blocks.last_seen();
+
+    let stale_forks: Vec<(BlockHash, u32)> = blocktree::forks(&blocks.tree)
         .into_iter()
-        .filter(|bc| bc.len() == longest_blockchain_len)
-        .map(|bc| bc.into_chain())
+        .filter(|(fork, _)| {
+            main_chainwork - blocktree::best_tip(fork).chainwork > max_work_deficit
+                || last_seen - fork.max_first_seen() > policy.max_blocks_without_extension as u64
+        })
+        .map(|(fork, depth)| (fork.root.block_hash(), depth))
         .collect();
 
-    // A `BlockChain` contains at least one block which means we can safely index at
-    // height 0 of the chain.
-    let mut main_chain = BlockChain::new(longest_blockchains[0][0]);
-    for height_idx in 1..longest_blockchain_len {
-        // If all the blocks on the same height are identical, then this block is part of the
-        // "main" chain.
-        let block = longest_blockchains[0][height_idx];
-        let block_hash = block.block_hash();
-        for chain in longest_blockchains.iter().skip(1) {
-            if chain[height_idx].block_hash() != block_hash {
-                return main_chain;
-            }
+    let mut num_pruned_blocks = 0;
+    for (fork_root, depth) in stale_forks {
+        let pruned_blocks =
+            remove_subtree(blocks, &fork_root).expect("a fork must be in the tree.");
+        num_pruned_blocks += pruned_blocks.len();
+        for block in pruned_blocks {
+            blocks
+                .pruned_blocks
+                .insert(block.block_hash(), stable_height + depth);
         }
-
-        main_chain.push(block);
     }
 
-    main_chain
+    num_pruned_blocks
 }
 
-/// Returns the length of the "main chain".
-/// See `get_main_chain` for what defines a main chain.
-pub fn get_main_chain_length(blocks: &UnstableBlocks) -> usize {
-    let blocks_by_height = blocks.blocks_with_depths_by_heights();
+/// Returns true if the block with the given hash and header was pruned, or extends a fork
+/// that was pruned, in which case it's recorded as pruned as well.
+pub fn mark_if_pruned(
+    blocks: &mut UnstableBlocks,
+    block_hash: &BlockHash,
+    header: &BlockHeader,
+) -> bool {
+    if blocks.pruned_blocks.contains_key(block_hash) {
+        return true;
+    }
 
-    // Traverse the heights in reverse order. The highest height with a single block corresponds to
-    // the tip of the main chain.
-    for height in (0..blocks_by_height.len()).rev() {
-        if blocks_by_height[height].len() == 1 {
-            return height + 1;
+    match blocks
+        .pruned_blocks
+        .get(&BlockHash::from(header.prev_blockhash))
+    {
+        Some(fork_height) => {
+            let fork_height = *fork_height;
+            blocks.pruned_blocks.insert(block_hash.clone(), fork_height);
+            true
         }
+        None => false,
     }
+}
 
-    unreachable!("There must be at least one height with exactly one block.");
+/// Returns the full block with the given hash if it's an unstable block.
+///
+/// NOTE: The blocks returned by the other functions of this module only have their headers.
+pub fn get_block(blocks: &UnstableBlocks, block_hash: &BlockHash) -> Option<Block> {
+    blocktree::blocks(&blocks.tree)
+        .into_iter()
+        .find(|block| block.block_hash() == *block_hash)
+        .map(|block| load_block(blocks, block))
+}
+
+/// Returns the main blockchain, i.e. the chain from the anchor to the tip with the
+/// most cumulative work. If multiple tips have the same cumulative work, the tip
+/// that was seen first is chosen, as in Bitcoin Core.
+pub fn get_main_chain(blocks: &UnstableBlocks) -> BlockChain {
+    blocktree::main_chain(&blocks.tree)
+}
+
+/// Returns the length of the "main chain".
+/// See `get_main_chain` for what defines a main chain.
+pub fn get_main_chain_length(blocks: &UnstableBlocks) -> usize {
+    get_main_chain(blocks).len()
 }
 
 pub fn get_blocks(blocks: &UnstableBlocks) -> Vec<&Block> {
@@ -294,89 +519,94 @@ pub fn get_chain_with_tip<'a>(
 }
 
 // Returns the index of the `anchor`'s stable child if it exists.
+//
+// Only the child that is part of the main chain can be stable. It is stable if the work
+// of its chain is at least `stability_threshold` times the work of the anchor, and if its
+// chain has at least that much more work than the chains of all the other children.
 fn get_stable_child(blocks: &UnstableBlocks) -> Option<usize> {
-    // Compute the difficulty based depth of all the children.
-    let network = blocks.get_network();
+    let anchor_chainwork = blocks.tree.chainwork;
 
-    let mut depths: Vec<_> = blocks
+    // The best tip that extends each of the children.
+    let tips: Vec<&BlockTree> = blocks
         .tree
         .children
         .iter()
-        .enumerate()
-        .map(|(idx, child)| (blocktree::difficulty_based_depth(child, network), idx))
+        .map(blocktree::best_tip)
         .collect();
 
-    // Sort by depth.
-    depths.sort_by_key(|(depth, _child_idx)| *depth);
-
-    let normalized_stability_threshold = blocks.normalized_stability_threshold();
-
-    match depths.last() {
-        Some((deepest_depth, child_idx)) => {
-            match network {
-                Network::Testnet | Network::Regtest => {
-                    // The difficulty in the Bitcoin testnet/regtest can be reset to the minimum
-                    // in case a block hasn't been found for 20 minutes. This can be problematic.
-                    // Consider the following scenario:
-                    //
-                    // * Assume a `stability_threshold` of 144.
-                    // * The anchor at height `h` has difficulty of 4642.
-                    // * The anchor will be marked as stable if the difficulty-based depth of
-                    //   the successor blocks is `stability_threshold * difficulty(anchor)` = 668,448
-                    // * The difficulty is reset to the minimum of 1.
-                    // * The canister will now need to maintain a chain of length 668,448 just to
-                    //   mark the anchor block as stable!
-                    //
-                    // Very long chains can cause the shadow stacks to overflow, resulting in a
-                    // broken canister.
-                    //
-                    // The pragmatic solution in this case is to bound the length of the chain. If
-                    // there's only one chain and it starts exceeding a certain length, we assume
-                    // that the anchor is stable even if the difficulty requirement hasn't been
-                    // met.
-                    //
-                    // This scenario is only relevant for testnets, so this addition is safe and
-                    // has not impact on the behavior of the mainnet canister.
-                    if depths.len() == 1
-                        && blocktree::depth(&blocks.tree.children[*child_idx])
-                            > TESTNET_MAX_SOLO_CHAIN_LENGTH
-                    {
-                        print(
-                            "Detected a solo chain > {TESTNET_MAX_SOLO_CHAIN_LENGTH}. Assuming the root is stable...",
-                        );
-                        return Some(*child_idx);
-                    }
-                }
-                Network::Mainnet => {
-                    // The difficulty on mainnet is much more stable and is bounded to change by a
-                    // factor of 4, so there is no limit that needs to be imposed.
-                }
-            }
+    if tips.is_empty() {
+        // The anchor has no children. Nothing to return.
+        return None;
+    }
 
-            // The deepest child tree must have a depth >= normalized_stability_threshold.
-            if *deepest_depth < normalized_stability_threshold {
-                // Need a depth of at least >= normalized_stability_threshold.
-                return None;
-            }
+    // The child that is part of the main chain.
+    let mut main_child_idx = 0;
+    for (idx, tip) in tips.iter().enumerate().skip(1) {
+        if blocktree::compare_tips(tip, tips[main_child_idx]) == Ordering::Greater {
+            main_child_idx = idx;
+        }
+    }
 
-            // If there is more than one child, the difference in depth
-            // between the deepest child and all the others must be >= normalized_stability_threshold.
-            if depths.len() >= 2 {
-                if let Some((second_deepest_depth, _)) = depths.get(depths.len() - 2) {
-                    if deepest_depth - second_deepest_depth < normalized_stability_threshold {
-                        // Difference must be >= normalized_stability_threshold.
-                        return None;
-                    }
-                }
+    match blocks.get_network() {
+        Network::Testnet | Network::Testnet4 | Network::Regtest => {
+            // The difficulty in the Bitcoin testnet/regtest can be reset to the minimum
+            // in case a block hasn't been found for 20 minutes. This can be problematic.
+            // Consider the following scenario:
+            //
+            // * Assume a `stability_threshold` of 144.
+            // * The anchor at height `h` has difficulty of 4642.
+            // * The anchor will be marked as stable if the work of the successor blocks
+            //   is `stability_threshold * work(anchor)`, i.e. 668,448 blocks of minimum
+            //   difficulty.
+            // * The difficulty is reset to the minimum of 1.
+            // * The canister will now need to maintain a chain of length 668,448 just to
+            //   mark the anchor block as stable!
+            //
+            // Very long chains can cause the shadow stacks to overflow, resulting in a
+            // broken canister.
+            //
+            // The pragmatic solution in this case is to bound the length of the chain. If
+            // there's only one chain and it starts exceeding a certain length, we assume
+            // that the anchor is stable even if the work requirement hasn't been met.
+            //
+            // This scenario is only relevant for testnets, so this addition is safe and
+            // has not impact on the behavior of the mainnet canister.
+            if tips.len() == 1
+                && blocktree::depth(&blocks.tree.children[main_child_idx])
+                    > TESTNET_MAX_SOLO_CHAIN_LENGTH
+            {
+                print(
+                    "Detected a solo chain > {TESTNET_MAX_SOLO_CHAIN_LENGTH}. Assuming the root is stable...",
+                );
+                return Some(main_child_idx);
             }
-
-            Some(*child_idx)
         }
-        None => {
-            // The anchor has no children. Nothing to return.
-            None
+        Network::Mainnet | Network::Signet => {
+            // The difficulty on mainnet is much more stable and is bounded to change by a
+            // factor of 4, so there is no limit that needs to be imposed. Signet doesn't
+            // have the minimum difficulty rule of testnet, so the same applies.
+        }
+    }
+
+    let stability_threshold_work = blocks.stability_threshold_work();
+
+    // The chain of the main child must have a work >= stability_threshold_work.
+    let main_chain_work = tips[main_child_idx].chainwork - anchor_chainwork;
+    if main_chain_work < stability_threshold_work {
+        return None;
+    }
+
+    // The difference in work between the chain of the main child and the chains of all
+    // the other children must be >= stability_threshold_work.
+    for (idx, tip) in tips.iter().enumerate() {
+        if idx != main_child_idx
+            && main_chain_work - (tip.chainwork - anchor_chainwork) < stability_threshold_work
+        {
+            return None;
         }
     }
+
+    Some(main_child_idx)
 }
 
 #[cfg(test)]
@@ -384,6 +614,16 @@ mod test {
     use super::*;
     use crate::test_utils::{BlockBuilder, BlockChainBuilder};
     use ic_btc_interface::Network;
+    use proptest::prelude::*;
+
+    // Returns the full blocks of the main chain.
+    fn main_chain_blocks(blocks: &UnstableBlocks) -> Vec<Block> {
+        get_main_chain(blocks)
+            .into_chain()
+            .into_iter()
+            .map(|block| load_block(blocks, block))
+            .collect()
+    }
 
     #[test]
     fn empty() {
@@ -412,7 +652,7 @@ mod test {
 
         // Block 0 (the anchor) now has one stable child (Block 1).
         // Block 0 should be returned when calling `pop`.
-        assert_eq!(peek(&forest), Some(&block_0));
+        assert_eq!(peek(&forest), Some(block_0.clone()));
         assert_eq!(pop(&mut forest, 0), Some(block_0));
 
         // Block 1 is now the anchor. It doesn't have stable
@@ -450,12 +690,12 @@ mod test {
             145
         );
 
-        assert_eq!(peek(&forest), Some(&block_0));
+        assert_eq!(peek(&forest), Some(block_0.clone()));
         assert_eq!(pop(&mut forest, 0), Some(block_0));
 
         // block_1 (the anchor) now has one stable child (block_2).
         // block_1 should be returned when calling `pop`.
-        assert_eq!(peek(&forest), Some(&block_1));
+        assert_eq!(peek(&forest), Some(block_1.clone()));
         assert_eq!(pop(&mut forest, 0), Some(block_1));
 
         // block_2 is now the anchor. It doesn't have stable
@@ -495,14 +735,14 @@ mod test {
         push(&mut forest, &utxos, block_2).unwrap();
         //Now, fork2 has a difficulty_based_depth of 3, while fork1 has a difficulty_based_depth of 1,
         //hence we can get a stable child.
-        assert_eq!(peek(&forest), Some(&genesis_block));
+        assert_eq!(peek(&forest), Some(genesis_block.clone()));
         assert_eq!(pop(&mut forest, 0), Some(genesis_block));
-        assert_eq!(forest.tree.root, forked_block);
+        assert_eq!(forest.tree.root.block_hash(), forked_block.block_hash());
 
         //fork2 is still stable, hence we can get a stable child.
-        assert_eq!(peek(&forest), Some(&forked_block));
+        assert_eq!(peek(&forest), Some(forked_block.clone()));
         assert_eq!(pop(&mut forest, 0), Some(forked_block));
-        assert_eq!(forest.tree.root, block_1);
+        assert_eq!(forest.tree.root.block_hash(), block_1.block_hash());
 
         // No stable children for fork2.
         assert_eq!(peek(&forest), None);
@@ -564,9 +804,9 @@ mod test {
             30
         );
 
-        assert_eq!(peek(&forest), Some(&genesis_block));
+        assert_eq!(peek(&forest), Some(genesis_block.clone()));
         assert_eq!(pop(&mut forest, 0), Some(genesis_block));
-        assert_eq!(forest.tree.root, fork2_block);
+        assert_eq!(forest.tree.root.block_hash(), fork2_block.block_hash());
 
         // fork2_block should have a stable child block_2, because
         // its difficulty_based_depth is 25,
@@ -577,7 +817,7 @@ mod test {
             25
         );
 
-        assert_eq!(peek(&forest), Some(&fork2_block));
+        assert_eq!(peek(&forest), Some(fork2_block.clone()));
         assert_eq!(pop(&mut forest, 0), Some(fork2_block));
 
         // No stable child for block_2, because it does not have any children.
@@ -598,9 +838,9 @@ mod test {
             75
         );
 
-        assert_eq!(peek(&forest), Some(&block_2));
+        assert_eq!(peek(&forest), Some(block_2.clone()));
         assert_eq!(pop(&mut forest, 0), Some(block_2));
-        assert_eq!(forest.tree.root, block_3);
+        assert_eq!(forest.tree.root.block_hash(), block_3.block_hash());
 
         // No stable child for block_3, because it does not have any children.
         assert_eq!(peek(&forest), None);
@@ -619,14 +859,240 @@ mod test {
         push(&mut forest, &utxos, block_1.clone()).unwrap();
         push(&mut forest, &utxos, block_2).unwrap();
 
-        assert_eq!(peek(&forest), Some(&block_0));
+        assert_eq!(peek(&forest), Some(block_0.clone()));
         assert_eq!(pop(&mut forest, 0), Some(block_0));
-        assert_eq!(peek(&forest), Some(&block_1));
+        assert_eq!(peek(&forest), Some(block_1.clone()));
         assert_eq!(pop(&mut forest, 0), Some(block_1));
         assert_eq!(peek(&forest), None);
         assert_eq!(pop(&mut forest, 0), None);
     }
 
+    #[test]
+    fn remove_subtree_prunes_branch() {
+        let block_0 = BlockBuilder::genesis().build();
+        let block_1 = BlockBuilder::with_prev_header(block_0.header()).build();
+        let block_2 = BlockBuilder::with_prev_header(block_1.header()).build();
+        let fork_block = BlockBuilder::with_prev_header(block_0.header()).build();
+        let next_header = *BlockBuilder::with_prev_header(block_2.header())
+            .build()
+            .header();
+
+        let network = Network::Mainnet;
+        let utxos = UtxoSet::new(network);
+        let mut forest = UnstableBlocks::new(&utxos, 1, block_0.clone(), network);
+        push(&mut forest, &utxos, block_1.clone()).unwrap();
+        push(&mut forest, &utxos, block_2.clone()).unwrap();
+        push(&mut forest, &utxos, fork_block.clone()).unwrap();
+        forest.insert_next_block_header(next_header, 0).unwrap();
+
+        assert_eq!(main_chain_blocks(&forest).last(), Some(&block_2));
+
+        // The anchor cannot be removed.
+        assert_eq!(remove_subtree(&mut forest, &block_0.block_hash()), None);
+
+        assert_eq!(
+            remove_subtree(&mut forest, &block_1.block_hash()),
+            Some(vec![block_1, block_2.clone()])
+        );
+        assert_eq!(main_chain_blocks(&forest), vec![block_0, fork_block]);
+
+        // The outpoints and the next block headers of the removed blocks are removed.
+        let coinbase_outpoint = OutPoint::new(block_2.txdata()[0].txid(), 0);
+        assert_eq!(forest.get_tx_out(&coinbase_outpoint), None);
+        assert!(!forest.has_next_block_header(&next_header));
+        assert_eq!(forest.next_block_headers_max_height(), None);
+    }
+
+    #[test]
+    fn blocks_are_seen_after_removed_blocks() {
+        let block_0 = BlockBuilder::genesis().build();
+        let block_1 = BlockBuilder::with_prev_header(block_0.header()).build();
+        let block_2 = BlockBuilder::with_prev_header(block_0.header()).build();
+        let block_3 = BlockBuilder::with_prev_header(block_0.header()).build();
+
+        let network = Network::Mainnet;
+        let utxos = UtxoSet::new(network);
+        let mut forest = UnstableBlocks::new(&utxos, 1, block_0, network);
+        push(&mut forest, &utxos, block_1).unwrap();
+        push(&mut forest, &utxos, block_2.clone()).unwrap();
+        remove_subtree(&mut forest, &block_2.block_hash()).unwrap();
+        push(&mut forest, &utxos, block_3).unwrap();
+
+        // The block that was seen last keeps counting as seen after the removed block.
+        let first_seen: Vec<u64> = forest
+            .tree
+            .children
+            .iter()
+            .map(|child| child.first_seen)
+            .collect();
+        assert_eq!(first_seen, vec![1, 3]);
+        assert_eq!(forest.last_seen(), 3);
+    }
+
+    #[test]
+    fn pop_removes_bodies_of_discarded_blocks() {
+        let block_0 = BlockBuilder::genesis().build();
+        let block_1 = BlockBuilder::with_prev_header(block_0.header()).build();
+        let block_2 = BlockBuilder::with_prev_header(block_1.header()).build();
+        let fork_block = BlockBuilder::with_prev_header(block_0.header()).build();
+
+        let network = Network::Mainnet;
+        let utxos = UtxoSet::new(network);
+        let mut forest = UnstableBlocks::new(&utxos, 1, block_0.clone(), network);
+        push(&mut forest, &utxos, block_1.clone()).unwrap();
+        push(&mut forest, &utxos, block_2.clone()).unwrap();
+        push(&mut forest, &utxos, fork_block.clone()).unwrap();
+        assert_eq!(forest.block_bodies.len(), 4);
+
+        // Popping the anchor discards the fork, so only blocks 1 and 2 remain.
+        assert_eq!(pop(&mut forest, 0), Some(block_0));
+        assert_eq!(forest.block_bodies.len(), 2);
+        assert_eq!(get_block(&forest, &fork_block.block_hash()), None);
+        assert_eq!(get_block(&forest, &block_1.block_hash()), Some(block_1));
+        assert_eq!(get_block(&forest, &block_2.block_hash()), Some(block_2));
+    }
+
+    #[test]
+    fn pop_removes_outpoints_of_discarded_blocks() {
+        let block_0 = BlockBuilder::genesis().build();
+        let block_1 = BlockBuilder::with_prev_header(block_0.header()).build();
+        let block_2 = BlockBuilder::with_prev_header(block_1.header()).build();
+        let fork_block = BlockBuilder::with_prev_header(block_0.header()).build();
+        let fork_outpoint = OutPoint::new(fork_block.txdata()[0].txid(), 0);
+
+        let network = Network::Mainnet;
+        let utxos = UtxoSet::new(network);
+        let mut forest = UnstableBlocks::new(&utxos, 1, block_0.clone(), network);
+        push(&mut forest, &utxos, block_1).unwrap();
+        push(&mut forest, &utxos, block_2).unwrap();
+        push(&mut forest, &utxos, fork_block).unwrap();
+        assert!(forest.get_tx_out(&fork_outpoint).is_some());
+
+        assert_eq!(pop(&mut forest, 0), Some(block_0));
+        assert_eq!(forest.get_tx_out(&fork_outpoint), None);
+    }
+
+    #[test]
+    fn prunes_forks_that_fall_behind_in_work() {
+        let chain = BlockChainBuilder::new(5).build();
+        let fork_block = BlockBuilder::with_prev_header(chain[0].header()).build();
+        let fork_outpoint = OutPoint::new(fork_block.txdata()[0].txid(), 0);
+
+        let network = Network::Mainnet;
+        let utxos = UtxoSet::new(network);
+        let mut forest = UnstableBlocks::new(&utxos, 1, chain[0].clone(), network);
+        for block in chain[1..].iter() {
+            push(&mut forest, &utxos, block.clone()).unwrap();
+        }
+        push(&mut forest, &utxos, fork_block.clone()).unwrap();
+
+        // Pruning is disabled by default.
+        assert_eq!(prune_stale_forks(&mut forest, 0), 0);
+
+        // The fork is three blocks behind the main chain.
+        forest.set_fork_pruning(Some(ForkPruning {
+            max_work_deficit: 3,
+            max_blocks_without_extension: u32::MAX,
+        }));
+        assert_eq!(prune_stale_forks(&mut forest, 0), 0);
+
+        forest.set_fork_pruning(Some(ForkPruning {
+            max_work_deficit: 2,
+            max_blocks_without_extension: u32::MAX,
+        }));
+        assert_eq!(prune_stale_forks(&mut forest, 0), 1);
+        assert_eq!(
+            forest.pruned_block_hashes().collect::<Vec<_>>(),
+            vec![&fork_block.block_hash()]
+        );
+        assert_eq!(get_block(&forest, &fork_block.block_hash()), None);
+        assert_eq!(forest.get_tx_out(&fork_outpoint), None);
+        assert_eq!(forest.num_tips(), 1);
+
+        // Blocks that extend the pruned fork are pruned as well.
+        let fork_child = BlockBuilder::with_prev_header(fork_block.header()).build();
+        assert!(mark_if_pruned(
+            &mut forest,
+            &fork_child.block_hash(),
+            fork_child.header()
+        ));
+        assert_eq!(forest.pruned_block_hashes().count(), 2);
+
+        let chain_child = BlockBuilder::with_prev_header(chain[4].header()).build();
+        assert!(!mark_if_pruned(
+            &mut forest,
+            &chain_child.block_hash(),
+            chain_child.header()
+        ));
+
+        // The pruned blocks are forgotten once the tree moves past the fork.
+        assert_eq!(pop(&mut forest, 1), Some(chain[0].clone()));
+        assert_eq!(forest.pruned_block_hashes().count(), 0);
+    }
+
+    #[test]
+    fn prunes_forks_that_have_not_been_extended() {
+        let chain = BlockChainBuilder::new(5).build();
+        let fork_block = BlockBuilder::with_prev_header(chain[0].header()).build();
+
+        let network = Network::Mainnet;
+        let utxos = UtxoSet::new(network);
+        let mut forest = UnstableBlocks::new(&utxos, 1, chain[0].clone(), network);
+
+        // The fork is received before the four blocks of the main chain.
+        push(&mut forest, &utxos, fork_block.clone()).unwrap();
+        for block in chain[1..].iter() {
+            push(&mut forest, &utxos, block.clone()).unwrap();
+        }
+
+        forest.set_fork_pruning(Some(ForkPruning {
+            max_work_deficit: u32::MAX,
+            max_blocks_without_extension: 4,
+        }));
+        assert_eq!(prune_stale_forks(&mut forest, 0), 0);
+
+        forest.set_fork_pruning(Some(ForkPruning {
+            max_work_deficit: u32::MAX,
+            max_blocks_without_extension: 3,
+        }));
+        assert_eq!(prune_stale_forks(&mut forest, 0), 1);
+        assert_eq!(get_block(&forest, &fork_block.block_hash()), None);
+        assert_eq!(get_main_chain_length(&forest), 5);
+    }
+
+    #[test]
+    fn migrate_to_stable_memory() {
+        let block_0 = BlockBuilder::genesis().build();
+        let block_1 = BlockBuilder::with_prev_header(block_0.header()).build();
+        let block_2 = BlockBuilder::with_prev_header(block_0.header()).build();
+
+        let network = Network::Mainnet;
+        let utxos = UtxoSet::new(network);
+        let mut forest = UnstableBlocks::new(&utxos, 1, block_0.clone(), network);
+
+        // Simulate a state where the full blocks are stored in the tree.
+        let mut tree = BlockTree::new(block_0.clone());
+        blocktree::extend(&mut tree, block_1.clone(), 1).unwrap();
+        blocktree::extend(&mut tree, block_2.clone(), 2).unwrap();
+        forest.tree = tree;
+        forest.block_bodies = BlockBodies::new();
+        forest.outpoints_cache = OutPointsCache::new();
+
+        forest.migrate_to_stable_memory(&utxos);
+
+        // Only the headers are kept in the tree, while the bodies and the outpoints
+        // are available in stable memory.
+        assert!(blocktree::blocks(&forest.tree)
+            .iter()
+            .all(|block| block.txdata().is_empty()));
+        for block in [&block_0, &block_1, &block_2] {
+            assert_eq!(get_block(&forest, &block.block_hash()), Some(block.clone()));
+            let coinbase_outpoint = OutPoint::new(block.txdata()[0].txid(), 0);
+            assert!(forest.get_tx_out(&coinbase_outpoint).is_some());
+        }
+        assert_eq!(main_chain_blocks(&forest), vec![block_0, block_1]);
+    }
+
     // Creating a forest that looks like this:
     //
     // * -> 1 -> 2
@@ -644,10 +1110,7 @@ mod test {
 
         push(&mut forest, &utxos, block_1.clone()).unwrap();
         push(&mut forest, &utxos, block_2.clone()).unwrap();
-        assert_eq!(
-            get_main_chain(&forest),
-            BlockChain::new_with_successors(&block_0, vec![&block_1, &block_2])
-        );
+        assert_eq!(main_chain_blocks(&forest), vec![block_0, block_1, block_2]);
     }
 
     // Creating a forest that looks like this:
@@ -655,7 +1118,8 @@ mod test {
     // * -> 1
     // * -> 2
     //
-    // Both blocks 1 and 2 contest with each other -> main chain is empty.
+    // Both blocks 1 and 2 have the same work. Block 1 was seen first, so it's
+    // part of the main chain.
     #[test]
     fn get_main_chain_two_contesting_trees() {
         let block_0 = BlockBuilder::genesis().build();
@@ -666,9 +1130,9 @@ mod test {
         let utxos = UtxoSet::new(network);
         let mut forest = UnstableBlocks::new(&utxos, 1, block_0.clone(), network);
 
-        push(&mut forest, &utxos, block_1).unwrap();
+        push(&mut forest, &utxos, block_1.clone()).unwrap();
         push(&mut forest, &utxos, block_2).unwrap();
-        assert_eq!(get_main_chain(&forest), BlockChain::new(&block_0));
+        assert_eq!(main_chain_blocks(&forest), vec![block_0, block_1]);
     }
 
     // Creating the following forest:
@@ -691,10 +1155,7 @@ mod test {
         push(&mut forest, &utxos, block_1).unwrap();
         push(&mut forest, &utxos, block_2.clone()).unwrap();
         push(&mut forest, &utxos, block_3.clone()).unwrap();
-        assert_eq!(
-            get_main_chain(&forest),
-            BlockChain::new_with_successors(&block_0, vec![&block_2, &block_3])
-        );
+        assert_eq!(main_chain_blocks(&forest), vec![block_0, block_2, block_3]);
     }
 
     // Creating the following forest:
@@ -702,8 +1163,7 @@ mod test {
     // * -> 1 -> 2 -> 3
     //       \-> a -> b
     //
-    // "1" should be returned in this case, as its the longest chain
-    // without a contested tip.
+    // "1 -> 2 -> 3" should be returned in this case, as it's the chain with the most work.
     #[test]
     fn get_main_chain_fork_at_first_block() {
         let block_0 = BlockBuilder::genesis().build();
@@ -718,13 +1178,13 @@ mod test {
         let mut forest = UnstableBlocks::new(&utxos, 1, block_0.clone(), network);
 
         push(&mut forest, &utxos, block_1.clone()).unwrap();
-        push(&mut forest, &utxos, block_2).unwrap();
-        push(&mut forest, &utxos, block_3).unwrap();
+        push(&mut forest, &utxos, block_2.clone()).unwrap();
+        push(&mut forest, &utxos, block_3.clone()).unwrap();
         push(&mut forest, &utxos, block_a).unwrap();
         push(&mut forest, &utxos, block_b).unwrap();
         assert_eq!(
-            get_main_chain(&forest),
-            BlockChain::new_with_successors(&block_0, vec![&block_1])
+            main_chain_blocks(&forest),
+            vec![block_0, block_1, block_2, block_3]
         );
     }
 
@@ -734,10 +1194,11 @@ mod test {
     //       \-> a -> b
     //   -> x -> y -> z
     //
-    // All blocks are contested.
+    // "x -> y -> z" and "1 -> 2 -> 3" have the same work, and "x -> y -> z" is
+    // the main chain as it was seen first.
     //
     // Then add block `c` that extends block `b`, at that point
-    // `1 -> a -> b -> c` becomes the only longest chain, and therefore
+    // `1 -> a -> b -> c` becomes the chain with the most work, and therefore
     // the "main" chain.
     #[test]
     fn get_main_chain_multiple_forks() {
@@ -755,15 +1216,18 @@ mod test {
         let utxos = UtxoSet::new(network);
         let mut forest = UnstableBlocks::new(&utxos, 1, block_0.clone(), network);
 
-        push(&mut forest, &utxos, block_x).unwrap();
-        push(&mut forest, &utxos, block_y).unwrap();
-        push(&mut forest, &utxos, block_z).unwrap();
+        push(&mut forest, &utxos, block_x.clone()).unwrap();
+        push(&mut forest, &utxos, block_y.clone()).unwrap();
+        push(&mut forest, &utxos, block_z.clone()).unwrap();
         push(&mut forest, &utxos, block_1.clone()).unwrap();
         push(&mut forest, &utxos, block_2).unwrap();
         push(&mut forest, &utxos, block_3).unwrap();
         push(&mut forest, &utxos, block_a.clone()).unwrap();
         push(&mut forest, &utxos, block_b.clone()).unwrap();
-        assert_eq!(get_main_chain(&forest), BlockChain::new(&block_0));
+        assert_eq!(
+            main_chain_blocks(&forest),
+            vec![block_0.clone(), block_x, block_y, block_z]
+        );
 
         // Now add block c to b.
         let block_c = BlockBuilder::with_prev_header(block_b.header()).build();
@@ -771,12 +1235,13 @@ mod test {
 
         // Now the main chain should be "1 -> a -> b -> c"
         assert_eq!(
-            get_main_chain(&forest),
-            BlockChain::new_with_successors(&block_0, vec![&block_1, &block_a, &block_b, &block_c])
+            main_chain_blocks(&forest),
+            vec![block_0, block_1, block_a, block_b, block_c]
         );
     }
 
-    // Same as the above test, with a different insertion order.
+    // Same as the above test, with a different insertion order, which makes
+    // "1 -> 2 -> 3" the main chain as it's now seen first.
     #[test]
     fn get_main_chain_multiple_forks_2() {
         let block_0 = BlockBuilder::genesis().build();
@@ -793,15 +1258,18 @@ mod test {
         let utxos = UtxoSet::new(network);
         let mut forest = UnstableBlocks::new(&utxos, 1, block_0.clone(), network);
 
-        push(&mut forest, &utxos, block_1).unwrap();
-        push(&mut forest, &utxos, block_2).unwrap();
-        push(&mut forest, &utxos, block_3).unwrap();
+        push(&mut forest, &utxos, block_1.clone()).unwrap();
+        push(&mut forest, &utxos, block_2.clone()).unwrap();
+        push(&mut forest, &utxos, block_3.clone()).unwrap();
         push(&mut forest, &utxos, block_a).unwrap();
         push(&mut forest, &utxos, block_b).unwrap();
         push(&mut forest, &utxos, block_x).unwrap();
         push(&mut forest, &utxos, block_y).unwrap();
         push(&mut forest, &utxos, block_z).unwrap();
-        assert_eq!(get_main_chain(&forest), BlockChain::new(&block_0));
+        assert_eq!(
+            main_chain_blocks(&forest),
+            vec![block_0, block_1, block_2, block_3]
+        );
     }
 
     #[test]
@@ -811,7 +1279,7 @@ mod test {
         let utxos = UtxoSet::new(network);
         let forest = UnstableBlocks::new(&utxos, 1, block_0.clone(), network);
 
-        assert_eq!(get_main_chain(&forest), BlockChain::new(&block_0));
+        assert_eq!(main_chain_blocks(&forest), vec![block_0]);
     }
 
     #[test]
@@ -935,6 +1403,127 @@ mod test {
         // Even though the chain's difficulty-based depth doesn't exceed the normalized stability
         // threshold, the anchor block can now be popped because the chain's length has exceeded
         // the maximum allowed.
-        assert_eq!(peek(&unstable_blocks), Some(&chain[0]));
+        assert_eq!(peek(&unstable_blocks), Some(chain[0].clone()));
+    }
+
+    // A reference implementation of Bitcoin Core's fork choice rule.
+    //
+    // Takes the blocks in the order they were seen, where each block is given as the index
+    // of its parent and its work. The first block is the anchor, and its parent is ignored.
+    // Returns the indices of the blocks in the chain from the anchor to the tip with the most
+    // cumulative work, where ties are broken in favor of the tip that was seen first.
+    fn reference_main_chain(blocks: &[(usize, u64)]) -> Vec<usize> {
+        let mut chainwork = vec![blocks[0].1 as u128];
+        let mut best_tip = 0;
+        for (idx, (parent, work)) in blocks.iter().enumerate().skip(1) {
+            chainwork.push(chainwork[*parent] + *work as u128);
+            if chainwork[idx] > chainwork[best_tip] {
+                best_tip = idx;
+            }
+        }
+
+        let mut chain = vec![best_tip];
+        while chain[chain.len() - 1] != 0 {
+            chain.push(blocks[chain[chain.len() - 1]].0);
+        }
+        chain.reverse();
+        chain
+    }
+
+    // Builds blocks with mock difficulties from the given parent indices and difficulties.
+    fn build_blocks(tree: &[(usize, u64)]) -> Vec<Block> {
+        let mut blocks = vec![BlockBuilder::genesis().build_with_mock_difficulty(tree[0].1)];
+        for (parent, difficulty) in tree.iter().skip(1) {
+            let block = BlockBuilder::with_prev_header(blocks[*parent].header())
+                .build_with_mock_difficulty(*difficulty);
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    // A strategy for a random tree of blocks in the format of `reference_main_chain`.
+    fn arb_tree() -> impl Strategy<Value = Vec<(usize, u64)>> {
+        prop::collection::vec((any::<usize>(), 1..10u64), 1..40).prop_map(|blocks| {
+            blocks
+                .into_iter()
+                .enumerate()
+                .map(|(idx, (parent, difficulty))| {
+                    (if idx == 0 { 0 } else { parent % idx }, difficulty)
+                })
+                .collect()
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(100))]
+        #[test]
+        fn main_chain_matches_reference(tree in arb_tree()) {
+            let blocks = build_blocks(&tree);
+
+            let network = Network::Mainnet;
+            let utxos = UtxoSet::new(network);
+            let mut forest = UnstableBlocks::new(&utxos, 1, blocks[0].clone(), network);
+            for block in blocks.iter().skip(1) {
+                push(&mut forest, &utxos, block.clone()).unwrap();
+            }
+
+            let expected_main_chain: Vec<_> = reference_main_chain(&tree)
+                .into_iter()
+                .map(|idx| blocks[idx].clone())
+                .collect();
+            prop_assert_eq!(main_chain_blocks(&forest), expected_main_chain.clone());
+            prop_assert_eq!(get_main_chain_length(&forest), expected_main_chain.len());
+        }
+
+        #[test]
+        fn stability_matches_reference(
+            tree in arb_tree(),
+            stability_threshold in 0..5u32,
+        ) {
+            let blocks = build_blocks(&tree);
+
+            let network = Network::Mainnet;
+            let utxos = UtxoSet::new(network);
+            let mut forest =
+                UnstableBlocks::new(&utxos, stability_threshold, blocks[0].clone(), network);
+            for block in blocks.iter().skip(1) {
+                push(&mut forest, &utxos, block.clone()).unwrap();
+            }
+
+            // The cumulative work of the chain from each block to the best tip that extends it.
+            let mut subtree_work: Vec<u128> = tree.iter().map(|(_, work)| *work as u128).collect();
+            let mut best_descendant_work = vec![0u128; tree.len()];
+            for idx in (1..tree.len()).rev() {
+                subtree_work[idx] += best_descendant_work[idx];
+                let parent = tree[idx].0;
+                best_descendant_work[parent] = best_descendant_work[parent].max(subtree_work[idx]);
+            }
+
+            // As per the reference, the child of the anchor on the main chain is stable iff
+            // its chain has enough work, and enough work more than the chains of its siblings.
+            let main_chain = reference_main_chain(&tree);
+            let threshold = tree[0].1 as u128 * stability_threshold as u128;
+            let expected_stable = main_chain.len() > 1 && {
+                let main_child = main_chain[1];
+                let main_work = subtree_work[main_child];
+                main_work >= threshold
+                    && (1..tree.len())
+                        .filter(|idx| tree[*idx].0 == 0 && *idx != main_child)
+                        .all(|idx| main_work - subtree_work[idx] >= threshold)
+            };
+
+            if expected_stable {
+                prop_assert_eq!(pop(&mut forest, 0), Some(blocks[0].clone()));
+                prop_assert_eq!(forest.tree.root.block_hash(), blocks[main_chain[1]].block_hash());
+
+                // The chainwork is rebased on the new anchor and the main chain is unchanged.
+                prop_assert_eq!(forest.tree.chainwork, forest.tree.root.work());
+                let expected_main_chain: Vec<_> =
+                    main_chain[1..].iter().map(|idx| blocks[*idx].clone()).collect();
+                prop_assert_eq!(main_chain_blocks(&forest), expected_main_chain);
+            } else {
+                prop_assert_eq!(pop(&mut forest, 0), None);
+            }
+        }
     }
 }
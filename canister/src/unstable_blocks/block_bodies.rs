@@ -0,0 +1,150 @@
+use crate::memory::{get_unstable_block_bodies_memory, Memory};
+use bitcoin::{consensus::Decodable, Block as BitcoinBlock};
+use ic_btc_types::{Block, BlockHash};
+use ic_stable_structures::{storable::Blob, StableBTreeMap};
+use std::convert::TryFrom;
+
+// The size of a chunk of an encoded block.
+const CHUNK_SIZE: usize = 4096;
+
+// The size of a chunk's key, which is the block hash followed by the chunk's index.
+const CHUNK_KEY_SIZE: usize = 32 + 4;
+
+/// Stores the bodies of unstable blocks in stable memory.
+///
+/// Blocks can be up to 4MB in size, which is far larger than what a value in a
+/// `StableBTreeMap` can reasonably be bounded to. Blocks are therefore stored in their
+/// consensus encoding, split into chunks of `CHUNK_SIZE` bytes, where each chunk is keyed
+/// by the block's hash and the index of the chunk.
+pub struct BlockBodies {
+    chunks: StableBTreeMap<Blob<CHUNK_KEY_SIZE>, Blob<CHUNK_SIZE>, Memory>,
+}
+
+impl BlockBodies {
+    /// Creates an empty store, discarding any blocks that were previously stored.
+    pub fn new() -> Self {
+        Self {
+            chunks: StableBTreeMap::new(get_unstable_block_bodies_memory()),
+        }
+    }
+
+    /// Loads the blocks that are already stored in stable memory.
+    pub fn init() -> Self {
+        Self {
+            chunks: init_chunks(),
+        }
+    }
+
+    /// Stores the given block.
+    pub fn insert(&mut self, block: &Block) {
+        let block_hash = block.block_hash();
+        let mut bytes = vec![];
+        block
+            .consensus_encode(&mut bytes)
+            .expect("encoding a block must succeed");
+
+        for (idx, chunk) in bytes.chunks(CHUNK_SIZE).enumerate() {
+            self.chunks.insert(
+                chunk_key(&block_hash, idx as u32),
+                Blob::try_from(chunk).unwrap(),
+            );
+        }
+    }
+
+    /// Returns the block with the given hash, if it exists.
+    pub fn get(&self, block_hash: &BlockHash) -> Option<Block> {
+        let bytes: Vec<u8> = self
+            .chunks
+            .range(chunk_key(block_hash, 0)..=chunk_key(block_hash, u32::MAX))
+            .flat_map(|(_, chunk)| chunk.as_slice().to_vec())
+            .collect();
+
+        if bytes.is_empty() {
+            return None;
+        }
+
+        Some(Block::new(
+            BitcoinBlock::consensus_decode(bytes.as_slice()).expect("stored block must be valid"),
+        ))
+    }
+
+    /// Removes the block with the given hash.
+    pub fn remove(&mut self, block_hash: &BlockHash) {
+        let keys: Vec<_> = self
+            .chunks
+            .range(chunk_key(block_hash, 0)..=chunk_key(block_hash, u32::MAX))
+            .map(|(key, _)| key)
+            .collect();
+
+        for key in keys {
+            self.chunks.remove(&key);
+        }
+    }
+
+    /// Returns the number of blocks stored.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|(key, _)| key.as_slice()[32..] == [0; 4])
+            .count()
+    }
+}
+
+// NOTE: `PartialEq` is only available in tests as it would be impractically
+// expensive in production.
+#[cfg(test)]
+impl PartialEq for BlockBodies {
+    fn eq(&self, other: &Self) -> bool {
+        use crate::test_utils::is_stable_btreemap_equal;
+        is_stable_btreemap_equal(&self.chunks, &other.chunks)
+    }
+}
+
+// Returns the key of the chunk with the given index of the given block.
+fn chunk_key(block_hash: &BlockHash, idx: u32) -> Blob<CHUNK_KEY_SIZE> {
+    let mut key = block_hash.clone().to_vec();
+    key.extend_from_slice(&idx.to_be_bytes());
+    Blob::try_from(key.as_slice()).unwrap()
+}
+
+fn init_chunks() -> StableBTreeMap<Blob<CHUNK_KEY_SIZE>, Blob<CHUNK_SIZE>, Memory> {
+    StableBTreeMap::init(get_unstable_block_bodies_memory())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{random_p2pkh_address, BlockBuilder, TransactionBuilder};
+    use ic_btc_interface::Network;
+
+    #[test]
+    fn insert_get_and_remove() {
+        let network = Network::Mainnet;
+        let block_0 = BlockBuilder::genesis().build();
+
+        // A block that spans multiple chunks.
+        let mut builder = BlockBuilder::with_prev_header(block_0.header());
+        for _ in 0..100 {
+            builder = builder.with_transaction(
+                TransactionBuilder::coinbase()
+                    .with_output(&random_p2pkh_address(network), 1000)
+                    .build(),
+            );
+        }
+        let block_1 = builder.build();
+
+        let mut bodies = BlockBodies::new();
+        bodies.insert(&block_0);
+        bodies.insert(&block_1);
+        assert_eq!(bodies.len(), 2);
+
+        assert_eq!(bodies.get(&block_0.block_hash()), Some(block_0.clone()));
+        assert_eq!(bodies.get(&block_1.block_hash()), Some(block_1.clone()));
+
+        bodies.remove(&block_1.block_hash());
+        assert_eq!(bodies.get(&block_1.block_hash()), None);
+        assert_eq!(bodies.get(&block_0.block_hash()), Some(block_0));
+        assert_eq!(bodies.len(), 1);
+    }
+}
@@ -2,7 +2,7 @@ use bitcoin::BlockHeader;
 use ic_btc_interface::Height;
 use ic_btc_types::BlockHash;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
 pub struct NextBlockHeaders {
@@ -47,6 +47,26 @@ impl NextBlockHeaders {
         }
     }
 
+    /// Removes the headers that descend from any of the given blocks.
+    pub fn remove_descendants(&mut self, blocks: &[BlockHash]) {
+        let mut removed: BTreeSet<BlockHash> = blocks.iter().cloned().collect();
+        let mut to_remove = vec![];
+
+        // A header's height is greater than that of its predecessor, so iterating by height
+        // visits the predecessors of a header before the header itself.
+        for hash in self.height_to_hash.values().flatten() {
+            let (_, header) = &self.hash_to_height_and_header[hash];
+            if removed.contains(&BlockHash::from(header.prev_blockhash)) {
+                removed.insert(hash.clone());
+                to_remove.push(hash.clone());
+            }
+        }
+
+        for hash in to_remove {
+            self.remove(&hash);
+        }
+    }
+
     pub fn get_max_height(&self) -> Option<Height> {
         self.height_to_hash.iter().last().map(|(height, _)| *height)
     }
@@ -215,6 +235,34 @@ mod test {
         assert_eq!(block_headers.get_max_height(), None);
     }
 
+    #[test]
+    fn test_remove_descendants() {
+        let mut block_headers: NextBlockHeaders = Default::default();
+
+        let genesis = *BlockBuilder::genesis().build().header();
+        let header1 = *BlockBuilder::with_prev_header(&genesis).build().header();
+        let hash1 = BlockHash::from(header1.block_hash());
+        let header2 = *BlockBuilder::with_prev_header(&header1).build().header();
+        let header3 = *BlockBuilder::with_prev_header(&header2).build().header();
+        let fork_header = *BlockBuilder::with_prev_header(&genesis).build().header();
+        let fork_hash = BlockHash::from(fork_header.block_hash());
+
+        block_headers.insert(header1, 1);
+        block_headers.insert(header2, 2);
+        block_headers.insert(header3, 3);
+        block_headers.insert(fork_header, 1);
+
+        // Only the descendants of the given blocks are removed.
+        block_headers.remove_descendants(&[hash1.clone()]);
+        assert_eq!(block_headers.hash_to_height_and_header.len(), 2);
+        assert!(block_headers.get_header(&hash1).is_some());
+        assert!(block_headers.get_header(&fork_hash).is_some());
+        assert_eq!(block_headers.get_max_height(), Some(1));
+
+        block_headers.remove_descendants(&[BlockHash::from(genesis.block_hash())]);
+        assert_eq!(block_headers, NextBlockHeaders::default());
+    }
+
     #[test]
     pub(crate) fn test_remove_block_until_height() {
         let mut block_headers: NextBlockHeaders = Default::default();
@@ -1,65 +1,89 @@
 use crate::{
-    types::{Address, TxOut},
+    memory::{
+        get_unstable_added_outpoints_memory, get_unstable_removed_outpoints_memory,
+        get_unstable_tx_outs_memory, Memory,
+    },
+    state::UTXO_VALUE_MAX_SIZE_MEDIUM,
+    types::{Address, Storable, TxOut},
     UtxoSet,
 };
 use ic_btc_interface::Height;
 use ic_btc_types::{Block, BlockHash, OutPoint};
+use ic_stable_structures::{storable::Blob, BoundedStorable, StableBTreeMap};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    convert::{TryFrom, TryInto},
+};
+
+// The max size of an encoded `TxOutInfo` that's stored in stable memory, which is
+// an encoded medium UTXO followed by the count (4 bytes).
+const TX_OUT_INFO_MAX_SIZE: usize = UTXO_VALUE_MAX_SIZE_MEDIUM + 4;
+
+// The size of a key in the added/removed outpoints maps, which is the block hash
+// (32 bytes), the length of the address (1 byte), the address, and the index of
+// the outpoint (4 bytes).
+const ADDRESS_OUTPOINT_KEY_SIZE: usize = 32 + 1 + Address::MAX_SIZE as usize + 4;
+
+type AddressOutPoints = StableBTreeMap<Blob<ADDRESS_OUTPOINT_KEY_SIZE>, OutPoint, Memory>;
 
 /// A cache maintaining data related to outpoints in unstable blocks.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+///
+/// The cache is stored in stable memory, as it grows with the number of unstable
+/// blocks, which can be large during long forks or with high stability thresholds.
+#[derive(Serialize, Deserialize)]
 pub struct OutPointsCache {
     /// Caches outpoints and their corresponding transaction outputs.
-    tx_outs: BTreeMap<OutPoint, TxOutInfo>,
+    // NOTE: Stable structures don't need to be serialized.
+    #[serde(skip, default = "init_tx_outs")]
+    tx_outs: StableBTreeMap<OutPoint, Blob<TX_OUT_INFO_MAX_SIZE>, Memory>,
+
+    /// Caches the transaction outputs with scripts that are too large to be stored in
+    /// `tx_outs`. As with the "large" UTXOs, these are rare, so a standard `BTreeMap`
+    /// suffices.
+    #[serde(default)]
+    large_tx_outs: BTreeMap<OutPoint, TxOutInfo>,
 
     /// Caches the outpoints added for each address in a block.
-    added_outpoints: BTreeMap<BlockHash, BTreeMap<Address, Vec<OutPoint>>>,
+    // NOTE: Stable structures don't need to be serialized.
+    #[serde(skip, default = "init_added_outpoints")]
+    added_outpoints: AddressOutPoints,
 
     /// Caches the outpoints removed for each address in a block.
-    removed_outpoints: BTreeMap<BlockHash, BTreeMap<Address, Vec<OutPoint>>>,
+    // NOTE: Stable structures don't need to be serialized.
+    #[serde(skip, default = "init_removed_outpoints")]
+    removed_outpoints: AddressOutPoints,
 }
 
 impl OutPointsCache {
+    /// Creates an empty cache, discarding anything that was previously cached.
     pub fn new() -> Self {
         Self {
-            tx_outs: BTreeMap::new(),
-            added_outpoints: BTreeMap::new(),
-            removed_outpoints: BTreeMap::new(),
+            tx_outs: StableBTreeMap::new(get_unstable_tx_outs_memory()),
+            large_tx_outs: BTreeMap::new(),
+            added_outpoints: StableBTreeMap::new(get_unstable_added_outpoints_memory()),
+            removed_outpoints: StableBTreeMap::new(get_unstable_removed_outpoints_memory()),
         }
     }
 
     /// Retrieves the list of outpoints that were added for the given address in the given block.
-    pub fn get_added_outpoints(&self, block_hash: &BlockHash, address: &Address) -> &[OutPoint] {
-        self.added_outpoints
-            .get(block_hash)
-            .map(|address_utxos| {
-                address_utxos
-                    .get(address)
-                    .map(|outpoints| outpoints.as_slice())
-                    .unwrap_or(&[])
-            })
-            .unwrap_or(&[])
+    pub fn get_added_outpoints(&self, block_hash: &BlockHash, address: &Address) -> Vec<OutPoint> {
+        get_address_outpoints(&self.added_outpoints, block_hash, address)
     }
 
     /// Retrieves the list of outpoints that were removed for the given address in the given block.
-    pub fn get_removed_outpoints(&self, block_hash: &BlockHash, address: &Address) -> &[OutPoint] {
-        self.removed_outpoints
-            .get(block_hash)
-            .map(|address_utxos| {
-                address_utxos
-                    .get(address)
-                    .map(|outpoints| outpoints.as_slice())
-                    .unwrap_or(&[])
-            })
-            .unwrap_or(&[])
+    pub fn get_removed_outpoints(
+        &self,
+        block_hash: &BlockHash,
+        address: &Address,
+    ) -> Vec<OutPoint> {
+        get_address_outpoints(&self.removed_outpoints, block_hash, address)
     }
 
     /// Retrieves the `TxOut` associated with the given `outpoint`, along with its height.
-    pub fn get_tx_out(&self, outpoint: &OutPoint) -> Option<(&TxOut, Height)> {
-        self.tx_outs
-            .get(outpoint)
-            .map(|info| (&info.txout, info.height))
+    pub fn get_tx_out(&self, outpoint: &OutPoint) -> Option<(TxOut, Height)> {
+        self.get_tx_out_info(outpoint)
+            .map(|info| (info.txout, info.height))
     }
 
     /// Inserts the outpoints in a block, along with their transaction outputs, into the cache.
@@ -95,7 +119,7 @@ impl OutPointsCache {
 
                 // Lookup the `TxOut` in the current cache.
                 let (txout, height) = match self.get_tx_out(&outpoint) {
-                    Some((txout, height)) => (txout.clone(), height),
+                    Some(tx_out) => tx_out,
 
                     // Lookup the `TxOut` in the current block.
                     None => match tx_outs.get(&outpoint) {
@@ -148,17 +172,16 @@ impl OutPointsCache {
         }
 
         // Merge all the transaction outputs of this block into the cache.
-        for (outpoint, tx_out_info) in tx_outs {
-            self.tx_outs
-                .entry(outpoint)
-                .and_modify(|t| t.count += tx_out_info.count)
-                .or_insert(tx_out_info);
+        for (outpoint, mut tx_out_info) in tx_outs {
+            if let Some(existing) = self.get_tx_out_info(&outpoint) {
+                tx_out_info.count += existing.count;
+            }
+            self.insert_tx_out_info(outpoint, tx_out_info);
         }
 
-        self.added_outpoints
-            .insert(block.block_hash(), added_outpoints);
-        self.removed_outpoints
-            .insert(block.block_hash(), removed_outpoints);
+        let block_hash = block.block_hash();
+        insert_address_outpoints(&mut self.added_outpoints, &block_hash, added_outpoints);
+        insert_address_outpoints(&mut self.removed_outpoints, &block_hash, removed_outpoints);
 
         Ok(())
     }
@@ -169,7 +192,7 @@ impl OutPointsCache {
     /// from the cache when there are no more blocks referencing it.
     pub fn remove(&mut self, block: &Block) {
         fn decrement_count_and_maybe_remove(cache: &mut OutPointsCache, outpoint: &OutPoint) {
-            let entry = cache.tx_outs.get_mut(outpoint).unwrap_or_else(|| {
+            let mut entry = cache.get_tx_out_info(outpoint).unwrap_or_else(|| {
                 panic!(
                     "outpoint {:?} must be present in the outpoints cache.",
                     outpoint
@@ -182,6 +205,9 @@ impl OutPointsCache {
             // Remove the outpoint if there are no more blocks in the cache referencing it.
             if entry.count == 0 {
                 cache.tx_outs.remove(outpoint);
+                cache.large_tx_outs.remove(outpoint);
+            } else {
+                cache.insert_tx_out_info(outpoint.clone(), entry);
             }
         }
 
@@ -207,11 +233,161 @@ impl OutPointsCache {
         }
 
         let block_hash = block.block_hash();
-        self.added_outpoints.remove(&block_hash);
-        self.removed_outpoints.remove(&block_hash);
+        remove_address_outpoints(&mut self.added_outpoints, &block_hash);
+        remove_address_outpoints(&mut self.removed_outpoints, &block_hash);
+    }
+
+    fn get_tx_out_info(&self, outpoint: &OutPoint) -> Option<TxOutInfo> {
+        if let Some(info) = self.tx_outs.get(outpoint) {
+            return Some(TxOutInfo::from_bytes(info.as_slice().to_vec()));
+        }
+
+        self.large_tx_outs.get(outpoint).cloned()
+    }
+
+    fn insert_tx_out_info(&mut self, outpoint: OutPoint, info: TxOutInfo) {
+        let info_encoded = info.to_bytes();
+
+        if info_encoded.len() <= TX_OUT_INFO_MAX_SIZE {
+            self.tx_outs
+                .insert(outpoint, Blob::try_from(info_encoded.as_slice()).unwrap());
+        } else {
+            self.large_tx_outs.insert(outpoint, info);
+        }
+    }
+
+    /// Returns all the cached transaction outputs.
+    #[cfg(test)]
+    fn tx_outs(&self) -> BTreeMap<OutPoint, TxOutInfo> {
+        let mut tx_outs: BTreeMap<_, _> = self
+            .tx_outs
+            .iter()
+            .map(|(outpoint, info)| (outpoint, TxOutInfo::from_bytes(info.as_slice().to_vec())))
+            .collect();
+        tx_outs.extend(self.large_tx_outs.clone());
+        tx_outs
+    }
+
+    /// Returns all the cached added outpoints, grouped by block and address.
+    #[cfg(test)]
+    fn added_outpoints(&self) -> BTreeMap<BlockHash, BTreeMap<Address, Vec<OutPoint>>> {
+        group_address_outpoints(&self.added_outpoints)
+    }
+
+    /// Returns all the cached removed outpoints, grouped by block and address.
+    #[cfg(test)]
+    fn removed_outpoints(&self) -> BTreeMap<BlockHash, BTreeMap<Address, Vec<OutPoint>>> {
+        group_address_outpoints(&self.removed_outpoints)
     }
 }
 
+// NOTE: `PartialEq` is only available in tests as it would be impractically
+// expensive in production.
+#[cfg(test)]
+impl PartialEq for OutPointsCache {
+    fn eq(&self, other: &Self) -> bool {
+        use crate::test_utils::is_stable_btreemap_equal;
+        is_stable_btreemap_equal(&self.tx_outs, &other.tx_outs)
+            && self.large_tx_outs == other.large_tx_outs
+            && is_stable_btreemap_equal(&self.added_outpoints, &other.added_outpoints)
+            && is_stable_btreemap_equal(&self.removed_outpoints, &other.removed_outpoints)
+    }
+}
+
+// Returns the key of the outpoint at the given index among the outpoints of an address
+// in a block. With this layout, the outpoints of an address in a block are contiguous
+// and ordered by their index.
+fn address_outpoint_key(
+    block_hash: &BlockHash,
+    address: &Address,
+    idx: u32,
+) -> Blob<ADDRESS_OUTPOINT_KEY_SIZE> {
+    let address_bytes = ic_stable_structures::Storable::to_bytes(address);
+    let mut key = block_hash.clone().to_vec();
+    key.push(address_bytes.len() as u8);
+    key.extend_from_slice(&address_bytes);
+    key.extend_from_slice(&idx.to_be_bytes());
+    Blob::try_from(key.as_slice()).unwrap()
+}
+
+fn get_address_outpoints(
+    map: &AddressOutPoints,
+    block_hash: &BlockHash,
+    address: &Address,
+) -> Vec<OutPoint> {
+    map.range(
+        address_outpoint_key(block_hash, address, 0)
+            ..=address_outpoint_key(block_hash, address, u32::MAX),
+    )
+    .map(|(_, outpoint)| outpoint)
+    .collect()
+}
+
+fn insert_address_outpoints(
+    map: &mut AddressOutPoints,
+    block_hash: &BlockHash,
+    address_outpoints: BTreeMap<Address, Vec<OutPoint>>,
+) {
+    for (address, outpoints) in address_outpoints {
+        for (idx, outpoint) in outpoints.into_iter().enumerate() {
+            map.insert(
+                address_outpoint_key(block_hash, &address, idx as u32),
+                outpoint,
+            );
+        }
+    }
+}
+
+fn remove_address_outpoints(map: &mut AddressOutPoints, block_hash: &BlockHash) {
+    // All the keys of a block are prefixed with its hash followed by the length of an
+    // address, which is always smaller than `u8::MAX`.
+    let start = block_hash.clone().to_vec();
+    let mut end = start.clone();
+    end.push(u8::MAX);
+
+    let keys: Vec<_> = map
+        .range(Blob::try_from(start.as_slice()).unwrap()..=Blob::try_from(end.as_slice()).unwrap())
+        .map(|(key, _)| key)
+        .collect();
+
+    for key in keys {
+        map.remove(&key);
+    }
+}
+
+#[cfg(test)]
+fn group_address_outpoints(
+    map: &AddressOutPoints,
+) -> BTreeMap<BlockHash, BTreeMap<Address, Vec<OutPoint>>> {
+    let mut grouped: BTreeMap<BlockHash, BTreeMap<Address, Vec<OutPoint>>> = BTreeMap::new();
+    for (key, outpoint) in map.iter() {
+        let key = key.as_slice();
+        let address_len = key[32] as usize;
+        let address = <Address as ic_stable_structures::Storable>::from_bytes(
+            std::borrow::Cow::Borrowed(&key[33..33 + address_len]),
+        );
+        grouped
+            .entry(BlockHash::from(key[..32].to_vec()))
+            .or_default()
+            .entry(address)
+            .or_default()
+            .push(outpoint);
+    }
+    grouped
+}
+
+fn init_tx_outs() -> StableBTreeMap<OutPoint, Blob<TX_OUT_INFO_MAX_SIZE>, Memory> {
+    StableBTreeMap::init(get_unstable_tx_outs_memory())
+}
+
+fn init_added_outpoints() -> AddressOutPoints {
+    StableBTreeMap::init(get_unstable_added_outpoints_memory())
+}
+
+fn init_removed_outpoints() -> AddressOutPoints {
+    StableBTreeMap::init(get_unstable_removed_outpoints_memory())
+}
+
 #[derive(Debug, PartialEq)]
 pub struct TxOutNotFound(OutPoint);
 
@@ -231,16 +407,51 @@ struct TxOutInfo {
     count: u32,
 }
 
+impl Storable for TxOutInfo {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.txout.clone(), self.height).to_bytes(); // Store the txout and height
+        bytes.extend_from_slice(&self.count.to_be_bytes()); // Then the count (4 bytes)
+        bytes
+    }
+
+    fn from_bytes(mut bytes: Vec<u8>) -> Self {
+        let count = u32::from_be_bytes(bytes.split_off(bytes.len() - 4).try_into().unwrap());
+        let (txout, height) = <(TxOut, Height)>::from_bytes(bytes);
+        Self {
+            txout,
+            height,
+            count,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::test_utils::{random_p2pkh_address, BlockBuilder, TransactionBuilder};
     use ic_btc_interface::Network;
 
+    type AddressOutPoints = BTreeMap<BlockHash, BTreeMap<Address, Vec<OutPoint>>>;
+
+    // Returns the cached transaction outputs, added outpoints, and removed outpoints.
+    fn contents(
+        cache: &OutPointsCache,
+    ) -> (
+        BTreeMap<OutPoint, TxOutInfo>,
+        AddressOutPoints,
+        AddressOutPoints,
+    ) {
+        (
+            cache.tx_outs(),
+            cache.added_outpoints(),
+            cache.removed_outpoints(),
+        )
+    }
+
     #[test]
     fn empty_when_initialized() {
         let cache = OutPointsCache::new();
-        assert_eq!(cache.tx_outs, maplit::btreemap! {},);
+        assert_eq!(cache.tx_outs(), maplit::btreemap! {});
     }
 
     #[test]
@@ -248,6 +459,7 @@ mod test {
         let network = Network::Mainnet;
         let address_1 = random_p2pkh_address(network);
         let address_2 = random_p2pkh_address(network);
+        let address_3 = random_p2pkh_address(network);
 
         let tx_0 = TransactionBuilder::coinbase()
             .with_output(&address_1, 1000)
@@ -268,7 +480,7 @@ mod test {
             vout: 0,
         };
         assert_eq!(
-            cache.tx_outs,
+            cache.tx_outs(),
             maplit::btreemap! {
                 outpoint_0.clone() => TxOutInfo {
                     txout: (&tx_0.output()[0]).into(),
@@ -279,12 +491,17 @@ mod test {
         );
 
         // Insert a block that consumes the output of the genesis block.
+        let coinbase_1 = TransactionBuilder::coinbase()
+            .with_height(1)
+            .with_output(&address_3, 1000)
+            .build();
         let tx_1 = TransactionBuilder::new()
             .with_input(outpoint_0.clone())
             .with_output(&address_2, 2000)
             .build();
 
         let block_1 = BlockBuilder::with_prev_header(block_0.header())
+            .with_transaction(coinbase_1.clone())
             .with_transaction(tx_1.clone())
             .build();
 
@@ -294,12 +511,16 @@ mod test {
             txid: tx_1.txid(),
             vout: 0,
         };
+        let coinbase_outpoint_1 = OutPoint {
+            txid: coinbase_1.txid(),
+            vout: 0,
+        };
 
         // The outpoints info cache contains the outpoints of block 0 and block 1.
         assert_eq!(
-            cache,
-            OutPointsCache {
-                tx_outs: maplit::btreemap! {
+            contents(&cache),
+            (
+                maplit::btreemap! {
                     outpoint_0.clone() => TxOutInfo {
                         txout: (&tx_0.output()[0]).into(),
                         height: 0,
@@ -309,31 +530,36 @@ mod test {
                         txout: (&tx_1.output()[0]).into(),
                         height: 1,
                         count: 1
+                    },
+                    coinbase_outpoint_1.clone() => TxOutInfo {
+                        txout: (&coinbase_1.output()[0]).into(),
+                        height: 1,
+                        count: 1
                     }
                 },
-                added_outpoints: maplit::btreemap! {
+                maplit::btreemap! {
                     block_0.block_hash() => maplit::btreemap! {
                         address_1.clone() => vec![OutPoint::new(tx_0.txid(), 0)]
                     },
                     block_1.block_hash() => maplit::btreemap! {
-                        address_2.clone() => vec![OutPoint::new(tx_1.txid(), 0)]
+                        address_2.clone() => vec![OutPoint::new(tx_1.txid(), 0)],
+                        address_3.clone() => vec![OutPoint::new(coinbase_1.txid(), 0)]
                     },
                 },
-                removed_outpoints: maplit::btreemap! {
-                    block_0.block_hash() => maplit::btreemap! {},
+                maplit::btreemap! {
                     block_1.block_hash() => maplit::btreemap! {
                         address_1.clone() => vec![OutPoint::new(tx_0.txid(), 0)]
                     },
                 },
-            }
+            )
         );
 
         cache.remove(&block_0);
 
         assert_eq!(
-            cache,
-            OutPointsCache {
-                tx_outs: maplit::btreemap! {
+            contents(&cache),
+            (
+                maplit::btreemap! {
                     outpoint_0 => TxOutInfo {
                         txout: (&tx_0.output()[0]).into(),
                         height: 0,
@@ -343,30 +569,36 @@ mod test {
                         txout: (&tx_1.output()[0]).into(),
                         height: 1,
                         count: 1
+                    },
+                    coinbase_outpoint_1 => TxOutInfo {
+                        txout: (&coinbase_1.output()[0]).into(),
+                        height: 1,
+                        count: 1
                     }
                 },
-                added_outpoints: maplit::btreemap! {
+                maplit::btreemap! {
                     block_1.block_hash() => maplit::btreemap! {
-                        address_2 => vec![OutPoint::new(tx_1.txid(), 0)]
+                        address_2 => vec![OutPoint::new(tx_1.txid(), 0)],
+                        address_3 => vec![OutPoint::new(coinbase_1.txid(), 0)]
                     },
                 },
-                removed_outpoints: maplit::btreemap! {
+                maplit::btreemap! {
                     block_1.block_hash() => maplit::btreemap! {
                         address_1 => vec![OutPoint::new(tx_0.txid(), 0)]
                     },
                 },
-            }
+            )
         );
 
         // Removing block 1 makes the cache empty again.
         cache.remove(&block_1);
         assert_eq!(
-            cache,
-            OutPointsCache {
-                tx_outs: maplit::btreemap! {},
-                added_outpoints: maplit::btreemap! {},
-                removed_outpoints: maplit::btreemap! {}
-            }
+            contents(&cache),
+            (
+                maplit::btreemap! {},
+                maplit::btreemap! {},
+                maplit::btreemap! {}
+            )
         );
     }
 
@@ -457,24 +689,22 @@ mod test {
 
         // The cache doesn't contain anything from block 1
         assert_eq!(
-            cache,
-            OutPointsCache {
-                tx_outs: maplit::btreemap! {
+            contents(&cache),
+            (
+                maplit::btreemap! {
                     outpoint_0 => TxOutInfo {
                         txout: (&tx_0.output()[0]).into(),
                         height: 0,
                         count: 1
                     },
                 },
-                added_outpoints: maplit::btreemap! {
+                maplit::btreemap! {
                     block_0.block_hash() => maplit::btreemap! {
                         address_1 => vec![OutPoint::new(tx_0.txid(), 0)]
                     },
                 },
-                removed_outpoints: maplit::btreemap! {
-                    block_0.block_hash() => maplit::btreemap! {}
-                },
-            }
+                maplit::btreemap! {},
+            )
         );
     }
 }
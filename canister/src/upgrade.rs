@@ -0,0 +1,285 @@
+//! The format in which the state is persisted in stable memory across upgrades.
+//!
+//! The state is stored in the upgrades memory as follows:
+//!
+//! | magic (4 bytes) | version (4 bytes) | length (8 bytes) | checksum (32 bytes) | state |
+//!
+//! where the version and length are little-endian, the state is encoded with CBOR, and the
+//! checksum is the SHA-256 of the encoded state.
+//!
+//! Version 0 is the legacy format, which has no header and instead starts with the length of
+//! the state as a little-endian `u32`.
+mod migrations;
+
+use crate::{
+    memory::{get_upgrades_memory, Memory},
+    runtime::print,
+    state::State,
+};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use ciborium::value::Value;
+use ic_stable_structures::Memory as _;
+use migrations::{Migration, MIGRATIONS};
+use std::{
+    convert::TryInto,
+    io::{BufWriter, Write},
+};
+
+/// The magic number at the beginning of the upgrades memory.
+const MAGIC: [u8; 4] = *b"BTCS";
+
+/// The current version of the format.
+pub const CURRENT_VERSION: u32 = 1;
+
+const HEADER_SIZE: u64 = 4 + 4 + 8 + 32;
+
+// The size of the chunks the encoded state is written to stable memory in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Writes the state to the upgrades memory in the current version of the format.
+///
+/// The state is streamed into stable memory in chunks as it's encoded, so that the whole
+/// encoded state never needs to be held in the heap.
+pub fn write_state(state: &State) {
+    let memory = get_upgrades_memory();
+    let mut writer = BufWriter::with_capacity(
+        CHUNK_SIZE,
+        StableWriter {
+            memory: memory.clone(),
+            offset: HEADER_SIZE,
+            hasher: sha256::Hash::engine(),
+        },
+    );
+    ciborium::ser::into_writer(state, &mut writer).expect("failed to encode state");
+    let writer = writer
+        .into_inner()
+        .unwrap_or_else(|_| panic!("failed to flush state"));
+
+    let length = writer.offset - HEADER_SIZE;
+    let checksum = sha256::Hash::from_engine(writer.hasher);
+
+    let mut header = vec![];
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    header.extend_from_slice(&length.to_le_bytes());
+    header.extend_from_slice(&checksum.into_inner());
+    crate::memory::write(&memory, 0, &header);
+
+    print(&format!(
+        "Wrote state of {} bytes in version {}.",
+        length, CURRENT_VERSION
+    ));
+}
+
+/// Reads the state from the upgrades memory, migrating it to the current version if needed.
+pub fn read_state() -> State {
+    let (version, bytes) = read_encoded_state(&get_upgrades_memory());
+    decode_state(version, &bytes, MIGRATIONS)
+}
+
+// Reads the version and the encoded state from the given memory, verifying its checksum.
+fn read_encoded_state(memory: &Memory) -> (u32, Vec<u8>) {
+    let mut magic = [0; 4];
+    memory.read(0, &mut magic);
+
+    if magic != MAGIC {
+        // The legacy format, which only has the length of the state.
+        let state_len = u32::from_le_bytes(magic) as usize;
+        let mut bytes = vec![0; state_len];
+        memory.read(4, &mut bytes);
+        return (0, bytes);
+    }
+
+    let mut header = [0; HEADER_SIZE as usize];
+    memory.read(0, &mut header);
+    let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let length = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let checksum = &header[16..48];
+
+    assert!(
+        version <= CURRENT_VERSION,
+        "Cannot read state of version {}. The latest known version is {}.",
+        version,
+        CURRENT_VERSION
+    );
+
+    let mut bytes = vec![0; length as usize];
+    memory.read(HEADER_SIZE, &mut bytes);
+    assert_eq!(
+        sha256::Hash::hash(&bytes).into_inner(),
+        checksum,
+        "The checksum of the state doesn't match."
+    );
+
+    (version, bytes)
+}
+
+// Decodes a state of the given version, applying the migrations needed to bring it to the
+// current version.
+fn decode_state(version: u32, bytes: &[u8], migrations: &[Migration]) -> State {
+    let pending: Vec<&Migration> = migrations
+        .iter()
+        .filter(|migration| migration.from_version >= version)
+        .collect();
+
+    for migration in pending.iter() {
+        print(&format!(
+            "Migrating state from version {}: {}.",
+            migration.from_version, migration.description
+        ));
+    }
+
+    let mut state: State = if pending.iter().all(|migration| migration.heap.is_none()) {
+        // The schema of the heap state is unchanged. Decode the state directly.
+        ciborium::de::from_reader(bytes).expect("failed to decode state")
+    } else {
+        let mut value: Value = ciborium::de::from_reader(bytes).expect("failed to decode state");
+        for heap in pending.iter().filter_map(|migration| migration.heap) {
+            value = heap(value);
+        }
+        migrations::from_value(value)
+    };
+
+    for stable in pending.iter().filter_map(|migration| migration.stable) {
+        stable(&mut state);
+    }
+
+    state
+}
+
+// Writes to stable memory at an increasing offset while computing the checksum of the
+// written bytes.
+struct StableWriter {
+    memory: Memory,
+    offset: u64,
+    hasher: sha256::HashEngine,
+}
+
+impl Write for StableWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        crate::memory::write(&self.memory, self.offset, buf);
+        self.hasher.input(buf);
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::genesis_block;
+    use candid::Principal;
+    use ic_btc_interface::Network;
+
+    fn new_state() -> State {
+        State::new(2, Network::Regtest, genesis_block(Network::Regtest))
+    }
+
+    #[test]
+    fn write_and_read() {
+        let state = new_state();
+        write_state(&state);
+
+        let (version, _) = read_encoded_state(&get_upgrades_memory());
+        assert_eq!(version, CURRENT_VERSION);
+        assert!(read_state() == state);
+    }
+
+    #[test]
+    fn reads_legacy_format() {
+        let state = new_state();
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(&state, &mut bytes).unwrap();
+
+        let memory = get_upgrades_memory();
+        crate::memory::write(&memory, 0, &(bytes.len() as u32).to_le_bytes());
+        crate::memory::write(&memory, 4, &bytes);
+
+        // The state is already in the current schema, so no migrations are applied to it.
+        assert_eq!(read_encoded_state(&memory), (0, bytes.clone()));
+        assert!(decode_state(0, &bytes, &[]) == state);
+    }
+
+    #[test]
+    #[should_panic(expected = "The checksum of the state doesn't match.")]
+    fn rejects_corrupted_state() {
+        write_state(&new_state());
+
+        // Flip a byte of the encoded state.
+        let memory = get_upgrades_memory();
+        let mut byte = [0];
+        memory.read(HEADER_SIZE, &mut byte);
+        crate::memory::write(&memory, HEADER_SIZE, &[byte[0] ^ 0xFF]);
+
+        read_state();
+    }
+
+    #[test]
+    fn applies_pending_migrations() {
+        // A migration that renames a field, which simulates a state from an older
+        // version where the field had a different name.
+        fn rename_api_access(value: Value) -> Value {
+            match value {
+                Value::Map(entries) => Value::Map(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| match key {
+                            Value::Text(name) if name == "old_api_access" => {
+                                (Value::Text("api_access".to_string()), value)
+                            }
+                            key => (key, value),
+                        })
+                        .collect(),
+                ),
+                _ => panic!("state must be a map"),
+            }
+        }
+
+        let state = new_state();
+        let mut value = migrations::to_value(&state);
+        if let Value::Map(entries) = &mut value {
+            for (key, _) in entries.iter_mut() {
+                if key == &Value::Text("api_access".to_string()) {
+                    *key = Value::Text("old_api_access".to_string());
+                }
+            }
+        }
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(&value, &mut bytes).unwrap();
+
+        // A migration of the stable structures, which is simulated here by setting a field.
+        fn set_watchdog_canister(state: &mut State) {
+            state.watchdog_canister = Some(Principal::anonymous());
+        }
+
+        let migrations = &[Migration {
+            from_version: 0,
+            description: "Rename old_api_access",
+            heap: Some(rename_api_access),
+            stable: Some(set_watchdog_canister),
+        }];
+
+        // The migration is only applied to states that predate it.
+        let mut migrated_state = new_state();
+        migrated_state.watchdog_canister = Some(Principal::anonymous());
+        assert!(decode_state(0, &bytes, migrations) == migrated_state);
+
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(&state, &mut bytes).unwrap();
+        assert!(decode_state(1, &bytes, migrations) == state);
+    }
+
+    #[test]
+    fn migrations_are_ordered() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert!(migration.from_version < CURRENT_VERSION);
+            if i > 0 {
+                assert!(MIGRATIONS[i - 1].from_version < migration.from_version);
+            }
+        }
+    }
+}
@@ -0,0 +1,377 @@
+//! The registry of migrations between versions of the state.
+//!
+//! Each version of the state has a corresponding migration to the next version, which
+//! converts both the state that's in the heap and, if needed, the layout of the stable
+//! structures. To introduce a new version:
+//!
+//! 1. Bump `CURRENT_VERSION`, unless the current version hasn't been released yet, in which
+//!    case its migration is extended instead.
+//! 2. Add a migration from the previous version to `MIGRATIONS`.
+//! 3. Once the new version is released, generate its golden state (see the tests in
+//!    `tests/upgrades.rs`) and commit it.
+use crate::{state::State, types::default_checkpoints};
+use ciborium::value::Value;
+use ic_btc_interface::Network;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A migration of the state from one version to the next.
+pub struct Migration {
+    /// The version of the state the migration applies to.
+    pub from_version: u32,
+
+    /// A short description of what the migration does, which is printed when it's applied.
+    pub description: &'static str,
+
+    /// Converts the encoded heap state before it's decoded.
+    ///
+    /// Only needed when the schema of the heap state changes in a way that serde defaults
+    /// cannot handle, e.g. when a field is renamed or its type changes.
+    pub heap: Option<fn(Value) -> Value>,
+
+    /// Converts the stable structures after the heap state is decoded, e.g. by moving
+    /// data to a new memory or re-encoding the values of a `StableBTreeMap`.
+    pub stable: Option<fn(&mut State)>,
+}
+
+/// The registry of migrations, in increasing order of the version they apply to.
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    from_version: 0,
+    description: "Add default checkpoints and a list of blocks sources, move the large UTXOs \
+        and the unstable blocks to stable memory, and backfill the new indices",
+    heap: Some(migrate_heap_from_v0),
+    stable: Some(migrate_stable_from_v0),
+}];
+
+// Converts the heap state of version 0, which predates the versioning of the state.
+fn migrate_heap_from_v0(value: Value) -> Value {
+    let value = add_default_checkpoints(value);
+    let value = replace_blocks_source_with_blocks_sources(value);
+    rename_large_utxos_to_legacy_large_utxos(value)
+}
+
+// Converts the stable structures of version 0.
+//
+// The large UTXOs are moved to stable memory first, as the outpoints cache of the unstable
+// blocks is rebuilt from the UTXO set when they're moved to stable memory.
+fn migrate_stable_from_v0(state: &mut State) {
+    move_large_utxos_to_stable_memory(state);
+    move_unstable_blocks_to_stable_memory(state);
+    backfill_address_indices(state);
+    backfill_block_heights(state);
+}
+
+// Adds the checkpoints of the state's network, which the state didn't have before.
+fn add_default_checkpoints(value: Value) -> Value {
+    let network: Network = match &value {
+        Value::Map(entries) => entries
+            .iter()
+            .find(|(key, _)| key == &Value::Text("utxos".to_string()))
+            .and_then(|(_, utxos)| match utxos {
+                Value::Map(entries) => entries
+                    .iter()
+                    .find(|(key, _)| key == &Value::Text("network".to_string()))
+                    .map(|(_, network)| from_value(network.clone())),
+                _ => None,
+            })
+            .expect("state must have a network"),
+        _ => panic!("state must be a map"),
+    };
+
+    match value {
+        Value::Map(mut entries) => {
+            entries.push((
+                Value::Text("checkpoints".to_string()),
+                to_value(&default_checkpoints(network)),
+            ));
+            Value::Map(entries)
+        }
+        _ => panic!("state must be a map"),
+    }
+}
+
+fn move_unstable_blocks_to_stable_memory(state: &mut State) {
+    state.unstable_blocks.migrate_to_stable_memory(&state.utxos);
+}
+
+// Replaces the `blocks_source` principal with `blocks_sources` that only contain it.
+fn replace_blocks_source_with_blocks_sources(value: Value) -> Value {
+    match value {
+        Value::Map(entries) => Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| match key {
+                    Value::Text(name) if name == "blocks_source" => (
+                        Value::Text("blocks_sources".to_string()),
+                        Value::Map(vec![(
+                            Value::Text("sources".to_string()),
+                            Value::Array(vec![Value::Map(vec![(
+                                Value::Text("principal".to_string()),
+                                value,
+                            )])]),
+                        )]),
+                    ),
+                    key => (key, value),
+                })
+                .collect(),
+        ),
+        _ => panic!("state must be a map"),
+    }
+}
+
+// Renames the `large_utxos` map of the UTXOs so that it's decoded as the legacy large UTXOs,
+// which are then moved to stable memory by `move_large_utxos_to_stable_memory`.
+fn rename_large_utxos_to_legacy_large_utxos(value: Value) -> Value {
+    map_entries(value, |key, value| match key {
+        Value::Text(name) if name == "utxos" => (
+            Value::Text(name),
+            // The UTXOs are nested in the UTXO set.
+            map_entries(value, |key, value| match key {
+                Value::Text(name) if name == "utxos" => (
+                    Value::Text(name),
+                    map_entries(value, |key, value| match key {
+                        Value::Text(name) if name == "large_utxos" => {
+                            (Value::Text("legacy_large_utxos".to_string()), value)
+                        }
+                        key => (key, value),
+                    }),
+                ),
+                key => (key, value),
+            }),
+        ),
+        key => (key, value),
+    })
+}
+
+fn move_large_utxos_to_stable_memory(state: &mut State) {
+    state.utxos.utxos.migrate_large_utxos_to_stable_memory();
+}
+
+// Starts backfilling the indices of the addresses' UTXOs, which only have the UTXOs that were
+// inserted since they were introduced. The backfill is time-sliced across heartbeats.
+fn backfill_address_indices(state: &mut State) {
+    state.utxos.start_indices_backfill();
+}
+
+// Starts backfilling the heights of the stable blocks by hash, which only have the blocks that
+// were inserted since they were introduced. The backfill is time-sliced across heartbeats.
+fn backfill_block_heights(state: &mut State) {
+    state.stable_block_headers.start_heights_backfill();
+}
+
+/// Converts the given data to a value.
+///
+/// The data is encoded with CBOR and then decoded as a value, rather than using
+/// `Value::serialized`, so that it's encoded the same way as the state in stable memory. The
+/// serializer of values is human-readable, which changes how the bitcoin types are encoded.
+pub(super) fn to_value<T: Serialize>(data: &T) -> Value {
+    let mut bytes = vec![];
+    ciborium::ser::into_writer(data, &mut bytes).expect("failed to encode value");
+    ciborium::de::from_reader(bytes.as_slice()).expect("failed to decode value")
+}
+
+/// Converts the given value to data, the inverse of `to_value`.
+pub(super) fn from_value<T: DeserializeOwned>(value: Value) -> T {
+    let mut bytes = vec![];
+    ciborium::ser::into_writer(&value, &mut bytes).expect("failed to encode value");
+    ciborium::de::from_reader(bytes.as_slice()).expect("failed to decode value")
+}
+
+// Applies the given function to the entries of a map.
+fn map_entries(value: Value, f: impl Fn(Value, Value) -> (Value, Value)) -> Value {
+    match value {
+        Value::Map(entries) => Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| f(key, value))
+                .collect(),
+        ),
+        _ => panic!("value must be a map"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        block_sources::BlockSources,
+        blocktree::{self, BlockTree},
+        genesis_block,
+        state::{ingest_stable_blocks_into_utxoset, insert_block},
+        test_utils::{random_p2pkh_address, BlockBuilder, TransactionBuilder},
+        types::TxOut,
+        unstable_blocks,
+        upgrade::decode_state,
+    };
+    use candid::Principal;
+    use ic_btc_types::{OutPoint, Transaction, Txid};
+    use std::collections::BTreeMap;
+
+    // Returns the value of the entry with the given name in a map.
+    fn entry_mut<'a>(value: &'a mut Value, name: &str) -> &'a mut Value {
+        match value {
+            Value::Map(entries) => entries
+                .iter_mut()
+                .find(|(key, _)| key == &Value::Text(name.to_string()))
+                .map(|(_, value)| value)
+                .expect("entry must exist"),
+            _ => panic!("value must be a map"),
+        }
+    }
+
+    #[test]
+    fn adds_default_checkpoints() {
+        let state = State::new(2, Network::Mainnet, genesis_block(Network::Mainnet));
+
+        // Encode the state as it was in version 0, which had no checkpoints.
+        let mut value = to_value(&state);
+        if let Value::Map(entries) = &mut value {
+            entries.retain(|(key, _)| key != &Value::Text("checkpoints".to_string()));
+        }
+
+        let migrated_state: State = from_value(add_default_checkpoints(value));
+        assert!(!migrated_state.checkpoints.is_empty());
+        assert_eq!(
+            migrated_state.checkpoints,
+            default_checkpoints(Network::Mainnet)
+        );
+    }
+
+    #[test]
+    fn replaces_blocks_source_with_blocks_sources() {
+        let blocks_source = Principal::from_slice(&[1, 2, 3]);
+        let mut state = State::new(2, Network::Regtest, genesis_block(Network::Regtest));
+        state.blocks_sources = BlockSources::new(blocks_source, vec![]);
+
+        // Encode the state as it was in version 0, where it had a single blocks source.
+        let mut value = to_value(&state);
+        if let Value::Map(entries) = &mut value {
+            for (key, value) in entries.iter_mut() {
+                if key == &Value::Text("blocks_sources".to_string()) {
+                    *key = Value::Text("blocks_source".to_string());
+                    *value = to_value(&blocks_source);
+                }
+            }
+        }
+
+        let migrated_state: State = from_value(replace_blocks_source_with_blocks_sources(value));
+        assert!(migrated_state == state);
+    }
+
+    #[test]
+    fn moves_large_utxos_to_stable_memory() {
+        let state = State::new(2, Network::Regtest, genesis_block(Network::Regtest));
+        let outpoint = OutPoint::new(Txid::from(vec![1; 32]), 0);
+        let utxo = (
+            TxOut {
+                value: 1,
+                script_pubkey: vec![0; 1_000],
+            },
+            7,
+        );
+
+        // Encode the state as it was in version 0, where the large UTXOs are a map in the heap.
+        let mut large_utxos = BTreeMap::new();
+        large_utxos.insert(outpoint.clone(), utxo.clone());
+        let mut value = to_value(&state);
+        *entry_mut(
+            entry_mut(entry_mut(&mut value, "utxos"), "utxos"),
+            "large_utxos",
+        ) = to_value(&large_utxos);
+
+        let mut migrated_state: State = from_value(rename_large_utxos_to_legacy_large_utxos(value));
+        assert_eq!(migrated_state.utxos.utxos_len(), 0);
+
+        move_large_utxos_to_stable_memory(&mut migrated_state);
+        assert_eq!(migrated_state.utxos.utxos_len(), 1);
+        assert_eq!(migrated_state.utxos.utxos.get(&outpoint), Some(utxo));
+        assert_eq!(migrated_state.utxos.utxos.large_utxos.iter().count(), 1);
+    }
+
+    #[test]
+    fn migrates_unstable_blocks_that_spend_large_utxos() {
+        let network = Network::Regtest;
+
+        // A chain where the second block spends a large UTXO of the first one.
+        let mut coinbase = ic_btc_test_utils::TransactionBuilder::coinbase().build();
+        coinbase.output.push(bitcoin::TxOut {
+            value: 1,
+            script_pubkey: bitcoin::Script::from(vec![0; 1_000]),
+        });
+        let coinbase = Transaction::new(coinbase);
+        let large_outpoint = OutPoint::new(coinbase.txid(), 1);
+        let block_0 = BlockBuilder::genesis().with_transaction(coinbase).build();
+        let block_1 = BlockBuilder::with_prev_header(block_0.header())
+            .with_transaction(
+                TransactionBuilder::new()
+                    .with_input(large_outpoint.clone())
+                    .with_output(&random_p2pkh_address(network), 1)
+                    .build(),
+            )
+            .build();
+        let block_2 = BlockBuilder::with_prev_header(block_1.header()).build();
+
+        let mut state = State::new(2, network, block_0);
+        for block in [block_1.clone(), block_2.clone()] {
+            insert_block(&mut state, block).unwrap();
+        }
+        ingest_stable_blocks_into_utxoset(&mut state);
+        assert_eq!(state.utxos.next_height(), 1);
+        let large_utxo = state.utxos.utxos.get(&large_outpoint).unwrap();
+
+        // Encode the state as it was in version 0, where the large UTXOs are a map in the
+        // heap and the tree of unstable blocks has the full blocks.
+        state.utxos.utxos.large_utxos.remove(&large_outpoint);
+        let mut large_utxos = BTreeMap::new();
+        large_utxos.insert(large_outpoint.clone(), large_utxo.clone());
+        let mut tree = BlockTree::new(block_1.clone());
+        blocktree::extend(&mut tree, block_2.clone(), 1).unwrap();
+
+        let mut value = to_value(&state);
+        *entry_mut(
+            entry_mut(entry_mut(&mut value, "utxos"), "utxos"),
+            "large_utxos",
+        ) = to_value(&large_utxos);
+        *entry_mut(entry_mut(&mut value, "unstable_blocks"), "tree") = to_value(&tree);
+        if let Value::Map(entries) = &mut value {
+            entries.retain(|(key, _)| key != &Value::Text("checkpoints".to_string()));
+            for (key, value) in entries.iter_mut() {
+                if key == &Value::Text("blocks_sources".to_string()) {
+                    *key = Value::Text("blocks_source".to_string());
+                    *value = to_value(&Principal::anonymous());
+                }
+            }
+        }
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(&value, &mut bytes).unwrap();
+
+        let migrated_state = decode_state(0, &bytes, MIGRATIONS);
+        assert_eq!(
+            migrated_state.utxos.utxos.get(&large_outpoint),
+            Some(large_utxo)
+        );
+        for block in [block_1, block_2] {
+            assert_eq!(
+                unstable_blocks::get_block(&migrated_state.unstable_blocks, &block.block_hash()),
+                Some(block)
+            );
+        }
+    }
+
+    #[test]
+    fn starts_backfilling_address_indices() {
+        let mut state = State::new(2, Network::Regtest, genesis_block(Network::Regtest));
+        assert!(!state.utxos.is_backfilling_indices());
+
+        backfill_address_indices(&mut state);
+        assert!(state.utxos.is_backfilling_indices());
+    }
+
+    #[test]
+    fn starts_backfilling_block_heights() {
+        let mut state = State::new(2, Network::Regtest, genesis_block(Network::Regtest));
+        assert!(!state.stable_block_headers.is_backfilling_heights());
+
+        backfill_block_heights(&mut state);
+        assert!(state.stable_block_headers.is_backfilling_heights());
+    }
+}
@@ -2,16 +2,24 @@ use crate::{
     memory::Memory,
     multi_iter::MultiIter,
     runtime::{inc_performance_counter, performance_counter, print},
-    types::{Address, AddressUtxo, AddressUtxoRange, Slicing, TxOut, Utxo},
+    types::{
+        Address, AddressHeightAscendingUtxo, AddressHeightAscendingUtxoRange, AddressUtxo,
+        AddressUtxoRange, AddressValueUtxo, AddressValueUtxoRange, Slicing, TxOut, Utxo,
+    },
 };
 use bitcoin::{Script, TxOut as BitcoinTxOut};
 use ic_btc_interface::{Height, Network, Satoshi};
 use ic_btc_types::{Block, BlockHash, OutPoint, Transaction, Txid};
 use ic_stable_structures::{storable::Blob, BoundedStorable, StableBTreeMap, Storable as _};
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeSet, iter::Iterator, str::FromStr};
+use std::{cmp::Reverse, collections::BTreeSet, iter::Iterator, str::FromStr};
+mod audit;
+mod backfill;
+mod large_utxos;
 mod utxos;
 mod utxos_delta;
+pub use audit::UtxoAuditor;
+use backfill::IndicesBackfill;
 use std::convert::TryFrom;
 use utxos::Utxos;
 use utxos_delta::UtxosDelta;
@@ -34,6 +42,23 @@ pub struct UtxoSet {
     #[serde(skip, default = "init_address_utxos")]
     address_utxos: StableBTreeMap<Blob<{ AddressUtxo::MAX_SIZE as usize }>, (), Memory>,
 
+    // An index for retrieving an address's UTXOs sorted by value.
+    // NOTE: Stable structures don't need to be serialized.
+    #[serde(skip, default = "init_address_utxos_by_value")]
+    address_utxos_by_value:
+        StableBTreeMap<Blob<{ AddressValueUtxo::MAX_SIZE as usize }>, (), Memory>,
+
+    // An index for retrieving an address's UTXOs sorted by height in ascending order.
+    //
+    // It has the same entries as `address_utxos` in the reverse order of heights, so it takes
+    // as much memory. It's needed nonetheless as the `StableBTreeMap` can only be iterated
+    // forwards, so a page of the oldest UTXOs of an address would otherwise require visiting
+    // all the UTXOs of the address, which isn't feasible for addresses with many UTXOs.
+    // NOTE: Stable structures don't need to be serialized.
+    #[serde(skip, default = "init_address_utxos_by_height_ascending")]
+    address_utxos_by_height_ascending:
+        StableBTreeMap<Blob<{ AddressHeightAscendingUtxo::MAX_SIZE as usize }>, (), Memory>,
+
     // A map of an address and its current balance.
     // NOTE: Stable structures don't need to be serialized.
     #[serde(skip, default = "init_balances")]
@@ -55,6 +80,10 @@ pub struct UtxoSet {
 
     /// A block that is currently being ingested into the UtxoSet. Used for time slicing.
     pub ingesting_block: Option<IngestingBlock>,
+
+    // The backfill of the indices that are derived from the address index, if in progress.
+    #[serde(default)]
+    indices_backfill: Option<IndicesBackfill>,
 }
 
 impl UtxoSet {
@@ -63,10 +92,13 @@ impl UtxoSet {
             utxos: Utxos::default(),
             balances: init_balances(),
             address_utxos: init_address_utxos(),
+            address_utxos_by_value: init_address_utxos_by_value(),
+            address_utxos_by_height_ascending: init_address_utxos_by_height_ascending(),
             network,
             next_height: 0,
             ingesting_block: None,
             should_time_slice: default_should_time_slice(),
+            indices_backfill: None,
         }
     }
 
@@ -235,6 +267,134 @@ impl UtxoSet {
         MultiIter::new(stable_outpoints, removed_outpoints.into_iter().cloned())
     }
 
+    /// Returns an iterator with the outpoints of the given address sorted by value in
+    /// descending order, then by outpoint.
+    /// An optional offset can be specified for pagination.
+    pub fn get_address_outpoints_by_value(
+        &self,
+        address: &Address,
+        offset: &Option<Utxo>,
+    ) -> impl Iterator<Item = OutPoint> + '_ {
+        assert!(
+            !self.is_backfilling_indices(),
+            "The UTXOs cannot be sorted by value until the index is backfilled."
+        );
+
+        // If there is an ingesting block, retrieve all the outpoints it added/removed.
+        let (added_outpoints, removed_outpoints) = match &self.ingesting_block {
+            Some(b) => {
+                let offset_key = offset
+                    .as_ref()
+                    .map(|offset| (Reverse(offset.value), offset.outpoint.clone()));
+
+                // The removed outpoints are sorted the same way as the stable index so that
+                // both can be merged.
+                let mut removed_outpoints: Vec<_> = b
+                    .utxos_delta
+                    .get_removed_outpoints(address)
+                    .into_iter()
+                    .map(|outpoint| {
+                        let (tx_out, _) =
+                            b.utxos_delta.get_utxo(outpoint).expect("UTXO must exist");
+                        (Reverse(tx_out.value), outpoint.clone())
+                    })
+                    .filter(|key| match &offset_key {
+                        Some(offset_key) => key >= offset_key,
+                        None => true,
+                    })
+                    .collect();
+                removed_outpoints.sort();
+
+                (
+                    b.utxos_delta.get_added_outpoints(address),
+                    removed_outpoints,
+                )
+            }
+            None => (BTreeSet::new(), vec![]),
+        };
+
+        // Retrieve all address's outpoints from the stable index, removing any outpoints
+        // that were added by the ingesting block.
+        let stable_outpoints = self
+            .address_utxos_by_value
+            .range(AddressValueUtxoRange::new(address, offset))
+            .map(|(address_utxo_blob, _)| {
+                let address_utxo = AddressValueUtxo::from_bytes(std::borrow::Cow::Borrowed(
+                    address_utxo_blob.as_slice(),
+                ));
+                (Reverse(address_utxo.value), address_utxo.outpoint)
+            })
+            .filter(move |(_, outpoint)| !added_outpoints.contains(outpoint));
+
+        // Return the stable outpoints along with the outpoints removed by the ingesting block.
+        MultiIter::new(stable_outpoints, removed_outpoints.into_iter())
+            .map(|(_, outpoint)| outpoint)
+    }
+
+    /// Returns an iterator with the outpoints of the given address sorted by height in
+    /// ascending order, then by outpoint.
+    /// An optional offset can be specified for pagination.
+    pub fn get_address_outpoints_by_height_ascending(
+        &self,
+        address: &Address,
+        offset: &Option<Utxo>,
+    ) -> impl Iterator<Item = OutPoint> + '_ {
+        assert!(
+            !self.is_backfilling_indices(),
+            "The UTXOs cannot be sorted by ascending height until the index is backfilled."
+        );
+
+        // If there is an ingesting block, retrieve all the outpoints it added/removed.
+        let (added_outpoints, removed_outpoints) = match &self.ingesting_block {
+            Some(b) => {
+                let offset_key = offset
+                    .as_ref()
+                    .map(|offset| (offset.height, offset.outpoint.clone()));
+
+                // The removed outpoints are sorted the same way as the stable index so that
+                // both can be merged.
+                let mut removed_outpoints: Vec<_> = b
+                    .utxos_delta
+                    .get_removed_outpoints(address)
+                    .into_iter()
+                    .map(|outpoint| {
+                        let (_, height) =
+                            b.utxos_delta.get_utxo(outpoint).expect("UTXO must exist");
+                        (*height, outpoint.clone())
+                    })
+                    .filter(|key| match &offset_key {
+                        Some(offset_key) => key >= offset_key,
+                        None => true,
+                    })
+                    .collect();
+                removed_outpoints.sort();
+
+                (
+                    b.utxos_delta.get_added_outpoints(address),
+                    removed_outpoints,
+                )
+            }
+            None => (BTreeSet::new(), vec![]),
+        };
+
+        // Retrieve all address's outpoints from the stable index, removing any outpoints
+        // that were added by the ingesting block.
+        let stable_outpoints = self
+            .address_utxos_by_height_ascending
+            .range(AddressHeightAscendingUtxoRange::new(address, offset))
+            .map(|(address_utxo_blob, _)| {
+                let address_utxo = AddressHeightAscendingUtxo::from_bytes(
+                    std::borrow::Cow::Borrowed(address_utxo_blob.as_slice()),
+                );
+                (address_utxo.height, address_utxo.outpoint)
+            })
+            .filter(move |(_, outpoint)| !added_outpoints.contains(outpoint));
+
+        // Return the stable outpoints along with the outpoints removed by the ingesting block.
+        MultiIter::new(stable_outpoints, removed_outpoints.into_iter())
+            .map(|(_, outpoint)| outpoint)
+    }
+
     /// Returns the number of UTXOs in the set.
     pub fn utxos_len(&self) -> u64 {
         self.utxos.len()
@@ -329,6 +489,46 @@ impl UtxoSet {
                             input.previous_output
                         );
 
+                        let found = self.address_utxos_by_value.remove(
+                            &Blob::try_from(
+                                AddressValueUtxo {
+                                    address: address.clone(),
+                                    value: txout.value,
+                                    outpoint: outpoint.clone(),
+                                }
+                                .to_bytes()
+                                .as_ref(),
+                            )
+                            .unwrap(),
+                        );
+
+                        // The UTXO may not be in the index yet if it's still being backfilled.
+                        assert!(
+                            found.is_some() || self.is_backfilling_indices(),
+                            "Outpoint {:?} not found in the value index.",
+                            input.previous_output
+                        );
+
+                        let found = self.address_utxos_by_height_ascending.remove(
+                            &Blob::try_from(
+                                AddressHeightAscendingUtxo {
+                                    address: address.clone(),
+                                    height,
+                                    outpoint: outpoint.clone(),
+                                }
+                                .to_bytes()
+                                .as_ref(),
+                            )
+                            .unwrap(),
+                        );
+
+                        // The UTXO may not be in the index yet if it's still being backfilled.
+                        assert!(
+                            found.is_some() || self.is_backfilling_indices(),
+                            "Outpoint {:?} not found in the height ascending index.",
+                            input.previous_output
+                        );
+
                         // Update the balance of the address.
                         if txout.value != 0 {
                             let address_balance =
@@ -413,6 +613,34 @@ impl UtxoSet {
                 (),
             );
 
+            self.address_utxos_by_value.insert(
+                Blob::try_from(
+                    AddressValueUtxo {
+                        address: address.clone(),
+                        value: tx_out.value,
+                        outpoint: outpoint.clone(),
+                    }
+                    .to_bytes()
+                    .as_ref(),
+                )
+                .unwrap(),
+                (),
+            );
+
+            self.address_utxos_by_height_ascending.insert(
+                Blob::try_from(
+                    AddressHeightAscendingUtxo {
+                        address: address.clone(),
+                        height: self.next_height,
+                        outpoint: outpoint.clone(),
+                    }
+                    .to_bytes()
+                    .as_ref(),
+                )
+                .unwrap(),
+                (),
+            );
+
             // Update the balance of the address.
             let address_balance = self.balances.get(&address).unwrap_or(0);
             self.balances
@@ -450,6 +678,16 @@ fn init_address_utxos() -> StableBTreeMap<Blob<{ AddressUtxo::MAX_SIZE as usize
     StableBTreeMap::init(crate::memory::get_address_utxos_memory())
 }
 
+fn init_address_utxos_by_value(
+) -> StableBTreeMap<Blob<{ AddressValueUtxo::MAX_SIZE as usize }>, (), Memory> {
+    StableBTreeMap::init(crate::memory::get_address_utxos_by_value_memory())
+}
+
+fn init_address_utxos_by_height_ascending(
+) -> StableBTreeMap<Blob<{ AddressHeightAscendingUtxo::MAX_SIZE as usize }>, (), Memory> {
+    StableBTreeMap::init(crate::memory::get_address_utxos_by_height_ascending_memory())
+}
+
 fn init_balances() -> StableBTreeMap<Address, u64, Memory> {
     StableBTreeMap::init(crate::memory::get_balances_memory())
 }
@@ -546,7 +784,13 @@ impl PartialEq for UtxoSet {
             && self.network == other.network
             && self.next_height == other.next_height
             && self.ingesting_block == other.ingesting_block
+            && self.indices_backfill == other.indices_backfill
             && is_stable_btreemap_equal(&self.address_utxos, &other.address_utxos)
+            && is_stable_btreemap_equal(&self.address_utxos_by_value, &other.address_utxos_by_value)
+            && is_stable_btreemap_equal(
+                &self.address_utxos_by_height_ascending,
+                &other.address_utxos_by_height_ascending,
+            )
             && is_stable_btreemap_equal(&self.balances, &other.balances)
     }
 }
@@ -590,7 +834,15 @@ mod test {
 
     #[test]
     fn tx_without_outputs_leaves_utxo_set_unchanged() {
-        for network in [Network::Mainnet, Network::Regtest, Network::Testnet].iter() {
+        for network in [
+            Network::Mainnet,
+            Network::Regtest,
+            Network::Testnet,
+            Network::Signet,
+            Network::Testnet4,
+        ]
+        .iter()
+        {
             let mut utxo = UtxoSet::new(*network);
 
             // no output coinbase
@@ -609,20 +861,25 @@ mod test {
 
     #[test]
     fn filter_provably_unspendable_utxos() {
-        for network in [Network::Mainnet, Network::Regtest, Network::Testnet].iter() {
+        for network in [
+            Network::Mainnet,
+            Network::Regtest,
+            Network::Testnet,
+            Network::Signet,
+            Network::Testnet4,
+        ]
+        .iter()
+        {
             let mut utxo = UtxoSet::new(*network);
 
-            // A provably unspendable tx.
+            // A provably unspendable coinbase.
+            let mut coinbase: bitcoin::Transaction = TransactionBuilder::coinbase().build().into();
+            coinbase.output = vec![BitcoinTxOut {
+                value: 50_0000_0000,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }];
             let block = BlockBuilder::genesis()
-                .with_transaction(Transaction::new(bitcoin::Transaction {
-                    output: vec![BitcoinTxOut {
-                        value: 50_0000_0000,
-                        script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
-                    }],
-                    input: vec![],
-                    version: 1,
-                    lock_time: 0,
-                }))
+                .with_transaction(Transaction::new(coinbase))
                 .build();
 
             assert_eq!(
@@ -778,6 +1035,135 @@ mod test {
         );
     }
 
+    #[test]
+    fn utxos_are_sorted_by_value() {
+        let address = random_p2pkh_address(Network::Testnet);
+
+        let mut utxo = UtxoSet::new(Network::Testnet);
+
+        // Insert some entries into the map with different values in some random order.
+        for value in [17u64, 0, u64::MAX, 4, 2].iter() {
+            utxo.address_utxos_by_value.insert(
+                Blob::try_from(
+                    AddressValueUtxo {
+                        address: address.clone(),
+                        value: *value,
+                        outpoint: OutPoint::new(Txid::from(vec![0; 32]), 0),
+                    }
+                    .to_bytes()
+                    .as_ref(),
+                )
+                .unwrap(),
+                (),
+            );
+        }
+
+        // Verify that the entries returned are sorted in descending value.
+        assert_eq!(
+            utxo.address_utxos_by_value
+                .range(AddressValueUtxoRange::new(&address, &None))
+                .map(|(address_utxo_blob, _)| {
+                    let address_utxo = AddressValueUtxo::from_bytes(std::borrow::Cow::Borrowed(
+                        address_utxo_blob.as_slice(),
+                    ));
+                    address_utxo.value
+                })
+                .collect::<Vec<_>>(),
+            vec![u64::MAX, 17, 4, 2, 0]
+        );
+    }
+
+    #[test]
+    fn utxos_are_sorted_by_ascending_height() {
+        let address = random_p2pkh_address(Network::Testnet);
+
+        let mut utxo = UtxoSet::new(Network::Testnet);
+
+        // Insert some entries into the map with different heights in some random order.
+        for height in [17u32, 0, 31, 4, 2].iter() {
+            utxo.address_utxos_by_height_ascending.insert(
+                Blob::try_from(
+                    AddressHeightAscendingUtxo {
+                        address: address.clone(),
+                        height: *height,
+                        outpoint: OutPoint::new(Txid::from(vec![0; 32]), 0),
+                    }
+                    .to_bytes()
+                    .as_ref(),
+                )
+                .unwrap(),
+                (),
+            );
+        }
+
+        // Verify that the entries returned are sorted in ascending height.
+        assert_eq!(
+            utxo.address_utxos_by_height_ascending
+                .range(AddressHeightAscendingUtxoRange::new(&address, &None))
+                .map(|(address_utxo_blob, _)| {
+                    let address_utxo = AddressHeightAscendingUtxo::from_bytes(
+                        std::borrow::Cow::Borrowed(address_utxo_blob.as_slice()),
+                    );
+                    address_utxo.height
+                })
+                .collect::<Vec<_>>(),
+            vec![0, 2, 4, 17, 31]
+        );
+    }
+
+    #[test]
+    fn value_index_is_maintained_during_ingestion() {
+        let network = Network::Testnet;
+        let address_1 = random_p2pkh_address(network);
+        let address_2 = random_p2pkh_address(network);
+
+        let mut utxo_set = UtxoSet::new(network);
+
+        let coinbase_tx = TransactionBuilder::coinbase()
+            .with_output(&address_1, 1000)
+            .with_output(&address_1, 3000)
+            .build();
+        ingest_tx(&mut utxo_set, &coinbase_tx);
+
+        assert_eq!(
+            utxo_set
+                .get_address_outpoints_by_value(&address_1, &None)
+                .collect::<Vec<_>>(),
+            vec![
+                OutPoint::new(coinbase_tx.txid(), 1),
+                OutPoint::new(coinbase_tx.txid(), 0)
+            ]
+        );
+
+        // Spend the larger output.
+        let tx = TransactionBuilder::new()
+            .with_input(OutPoint::new(coinbase_tx.txid(), 1))
+            .with_output(&address_2, 3000)
+            .build();
+        ingest_tx(&mut utxo_set, &tx);
+
+        assert_eq!(
+            utxo_set
+                .get_address_outpoints_by_value(&address_1, &None)
+                .collect::<Vec<_>>(),
+            vec![OutPoint::new(coinbase_tx.txid(), 0)]
+        );
+        assert_eq!(
+            utxo_set
+                .get_address_outpoints_by_value(&address_2, &None)
+                .collect::<Vec<_>>(),
+            vec![OutPoint::new(tx.txid(), 0)]
+        );
+        assert_eq!(
+            utxo_set.address_utxos_by_value.len(),
+            utxo_set.address_utxos.len()
+        );
+        assert_eq!(
+            utxo_set.address_utxos_by_height_ascending.len(),
+            utxo_set.address_utxos.len()
+        );
+    }
+
     #[test]
     #[should_panic]
     fn inserting_same_outpoint_panics() {
@@ -995,7 +1381,7 @@ mod test {
                 .with_transaction(tx_0.clone())
                 .build();
 
-            // Block 1: Contains transactions 1 and 2.
+            // Block 1: Contains transactions 1 and 2, after the coinbase added by the builder.
             let block_1 = BlockBuilder::with_prev_header(block_0.header())
                 .with_transaction(tx_1.clone())
                 .with_transaction(tx_2.clone())
@@ -1092,9 +1478,10 @@ mod test {
             assert_eq!(utxo_set.get_balance(&address_1), 0);
             assert_eq!(utxo_set.get_balance(&address_2), 0);
             assert_eq!(utxo_set.get_balance(&address_3), tx_cardinality);
+            // The inputs and outputs of transactions 1 and 2, and the output of the coinbase.
             assert_eq!(
                 num_rounds,
-                ((tx_cardinality * 4) as f32 / ingestion_rate as f32).ceil() as u32
+                ((tx_cardinality * 4 + 1) as f32 / ingestion_rate as f32).ceil() as u32
             );
 
             assert_eq!(
@@ -0,0 +1,782 @@
+use super::UtxoSet;
+use crate::{
+    memory::Memory,
+    state::UTXO_KEY_SIZE,
+    types::{
+        Address, AddressHeightAscendingUtxo, AddressUtxo, AddressUtxoRange, AddressValueUtxo,
+        Slicing, Storable, TxOut,
+    },
+};
+use bitcoin::Script;
+use ic_btc_interface::{Flag, Height, Satoshi, UtxoAuditReport};
+use ic_btc_types::OutPoint;
+use ic_stable_structures::{
+    storable::Blob, BoundedStorable, StableBTreeMap, Storable as StableStructuresStorable,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    ops::{Bound, RangeBounds},
+};
+
+/// Audits the UTXO set against its indices in the background, across many heartbeats.
+///
+/// The audit runs in rounds, where each round walks the following structures in order:
+///
+/// 1. The small, medium and large UTXOs, verifying that every UTXO that's owned by an
+///    address is in all the address indices.
+/// 2. The address index, verifying that every entry matches a UTXO and that the balance
+///    of every address is the sum of its UTXOs.
+/// 3. The index of addresses by value, verifying that every entry matches a UTXO.
+/// 4. The index of addresses by ascending height, verifying that every entry matches a UTXO.
+/// 5. The balances, verifying that every address with a balance has UTXOs.
+///
+/// Every check looks at the UTXO set at a single point in time, so the audit is paused
+/// while a block is being ingested, and a block that's ingested between checks doesn't
+/// cause any false mismatches. The balance of an address is the only check that spans
+/// several entries, so the UTXOs of an address are summed again if a block is ingested
+/// while they're being summed. The audit is also paused while the indices are being
+/// backfilled, as they're incomplete until then.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct UtxoAuditor {
+    pub flag: Flag,
+
+    // The position in the current round to resume the audit from.
+    cursor: Cursor,
+
+    // The results of the current round so far.
+    current: UtxoAuditReport,
+
+    /// The results of the last complete round.
+    pub last_report: Option<UtxoAuditReport>,
+
+    /// The number of complete rounds.
+    pub num_rounds: u64,
+}
+
+impl Default for UtxoAuditor {
+    fn default() -> Self {
+        Self {
+            flag: Flag::Disabled,
+            cursor: Cursor::default(),
+            current: UtxoAuditReport::default(),
+            last_report: None,
+            num_rounds: 0,
+        }
+    }
+}
+
+// The structure that's being audited, along with the key of the last entry audited in it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+enum Cursor {
+    // A new round is about to start.
+    #[default]
+    Start,
+    SmallUtxos(Option<Vec<u8>>),
+    MediumUtxos(Option<Vec<u8>>),
+    LargeUtxos(Option<OutPoint>),
+    AddressUtxos {
+        last_key: Option<Vec<u8>>,
+        // The address whose UTXOs are being summed, if any.
+        address: Option<AddressAudit>,
+    },
+    AddressUtxosByValue(Option<Vec<u8>>),
+    AddressUtxosByHeightAscending(Option<Vec<u8>>),
+    Balances(Option<Vec<u8>>),
+}
+
+// The audit of the entries of an address in the address index.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct AddressAudit {
+    address: Address,
+    // The sum of the UTXOs of the address visited so far.
+    sum: Satoshi,
+    // The number of entries of the address visited so far that don't match a UTXO.
+    stale_entries: u64,
+    // The `next_height` of the UTXO set when the audit of the address started.
+    next_height: Height,
+}
+
+impl AddressAudit {
+    fn new(address: Address, next_height: Height) -> Self {
+        Self {
+            address,
+            sum: 0,
+            stale_entries: 0,
+            next_height,
+        }
+    }
+
+    // Adds the results of the audit to the report once all the entries of the address
+    // were visited.
+    fn finish(self, report: &mut UtxoAuditReport, utxo_set: &UtxoSet) {
+        report.stale_address_index_entries += self.stale_entries;
+        audit_balance(report, utxo_set, &self.address, self.sum);
+    }
+}
+
+impl UtxoAuditor {
+    /// Audits the UTXO set until `should_pause` returns true, resuming from where the
+    /// previous call left off.
+    pub fn run(&mut self, utxo_set: &UtxoSet, now: u64, should_pause: &mut dyn FnMut() -> bool) {
+        if self.flag == Flag::Disabled
+            || utxo_set.ingesting_block.is_some()
+            || utxo_set.is_backfilling_indices()
+        {
+            return;
+        }
+
+        loop {
+            let next_cursor = match self.cursor.clone() {
+                Cursor::Start => {
+                    self.current = UtxoAuditReport {
+                        started_at: now,
+                        ..Default::default()
+                    };
+                    Cursor::SmallUtxos(None)
+                }
+                Cursor::SmallUtxos(last_key) => {
+                    match self.audit_utxos(
+                        utxo_set,
+                        &utxo_set.utxos.small_utxos,
+                        last_key,
+                        should_pause,
+                    ) {
+                        Slicing::Paused(last_key) => {
+                            self.cursor = Cursor::SmallUtxos(last_key);
+                            return;
+                        }
+                        Slicing::Done(()) => Cursor::MediumUtxos(None),
+                    }
+                }
+                Cursor::MediumUtxos(last_key) => {
+                    match self.audit_utxos(
+                        utxo_set,
+                        &utxo_set.utxos.medium_utxos,
+                        last_key,
+                        should_pause,
+                    ) {
+                        Slicing::Paused(last_key) => {
+                            self.cursor = Cursor::MediumUtxos(last_key);
+                            return;
+                        }
+                        Slicing::Done(()) => Cursor::LargeUtxos(None),
+                    }
+                }
+                Cursor::LargeUtxos(last_outpoint) => {
+                    match self.audit_large_utxos(utxo_set, last_outpoint, should_pause) {
+                        Slicing::Paused(last_outpoint) => {
+                            self.cursor = Cursor::LargeUtxos(last_outpoint);
+                            return;
+                        }
+                        Slicing::Done(()) => Cursor::AddressUtxos {
+                            last_key: None,
+                            address: None,
+                        },
+                    }
+                }
+                Cursor::AddressUtxos { last_key, address } => {
+                    match self.audit_address_utxos(utxo_set, last_key, address, should_pause) {
+                        Slicing::Paused(cursor) => {
+                            self.cursor = cursor;
+                            return;
+                        }
+                        Slicing::Done(()) => Cursor::AddressUtxosByValue(None),
+                    }
+                }
+                Cursor::AddressUtxosByValue(last_key) => {
+                    match self.audit_address_utxos_by_value(utxo_set, last_key, should_pause) {
+                        Slicing::Paused(last_key) => {
+                            self.cursor = Cursor::AddressUtxosByValue(last_key);
+                            return;
+                        }
+                        Slicing::Done(()) => Cursor::AddressUtxosByHeightAscending(None),
+                    }
+                }
+                Cursor::AddressUtxosByHeightAscending(last_key) => {
+                    match self.audit_address_utxos_by_height_ascending(
+                        utxo_set,
+                        last_key,
+                        should_pause,
+                    ) {
+                        Slicing::Paused(last_key) => {
+                            self.cursor = Cursor::AddressUtxosByHeightAscending(last_key);
+                            return;
+                        }
+                        Slicing::Done(()) => Cursor::Balances(None),
+                    }
+                }
+                Cursor::Balances(last_key) => {
+                    match self.audit_balances(utxo_set, last_key, should_pause) {
+                        Slicing::Paused(last_key) => {
+                            self.cursor = Cursor::Balances(last_key);
+                            return;
+                        }
+                        Slicing::Done(()) => {
+                            // The round is complete.
+                            self.current.completed_at = now;
+                            self.last_report = Some(std::mem::take(&mut self.current));
+                            self.num_rounds += 1;
+                            self.cursor = Cursor::Start;
+                            return;
+                        }
+                    }
+                }
+            };
+
+            self.cursor = next_cursor;
+        }
+    }
+
+    // Audits the UTXOs of the given map, starting after the given key.
+    fn audit_utxos<const N: usize>(
+        &mut self,
+        utxo_set: &UtxoSet,
+        utxos: &StableBTreeMap<Blob<UTXO_KEY_SIZE>, Blob<N>, Memory>,
+        last_key: Option<Vec<u8>>,
+        should_pause: &mut dyn FnMut() -> bool,
+    ) -> Slicing<Option<Vec<u8>>, ()> {
+        let report = &mut self.current;
+        visit(utxos, last_key, should_pause, |key, value| {
+            let outpoint = OutPoint::from_bytes(Cow::Borrowed(key.as_slice()));
+            let (tx_out, height) = <(TxOut, Height)>::from_bytes(value.as_slice().to_vec());
+            audit_utxo(report, utxo_set, outpoint, &tx_out, height);
+        })
+    }
+
+    // Audits the large UTXOs, starting after the given outpoint.
+    fn audit_large_utxos(
+        &mut self,
+        utxo_set: &UtxoSet,
+        last_outpoint: Option<OutPoint>,
+        should_pause: &mut dyn FnMut() -> bool,
+    ) -> Slicing<Option<OutPoint>, ()> {
+        let large_utxos = utxo_set
+            .utxos
+            .large_utxos
+            .range_after(last_outpoint.as_ref());
+
+        let mut last_outpoint = last_outpoint;
+        for (outpoint, (tx_out, height)) in large_utxos {
+            if should_pause() {
+                return Slicing::Paused(last_outpoint);
+            }
+
+            audit_utxo(
+                &mut self.current,
+                utxo_set,
+                outpoint.clone(),
+                &tx_out,
+                height,
+            );
+            last_outpoint = Some(outpoint);
+        }
+
+        Slicing::Done(())
+    }
+
+    // Audits the entries of the address index, starting after the given key.
+    fn audit_address_utxos(
+        &mut self,
+        utxo_set: &UtxoSet,
+        last_key: Option<Vec<u8>>,
+        address: Option<AddressAudit>,
+        should_pause: &mut dyn FnMut() -> bool,
+    ) -> Slicing<Cursor, ()> {
+        let next_height = utxo_set.next_height();
+        let (start, mut current) = match &address {
+            Some(audit) if audit.next_height != next_height => {
+                // A block was ingested since the address started being audited. Audit its
+                // entries again from the start, so that its balance is verified against
+                // the UTXO set at a single point in time.
+                let range = AddressUtxoRange::new(&audit.address, &None);
+                (
+                    range.start_bound().cloned(),
+                    Some(AddressAudit::new(audit.address.clone(), next_height)),
+                )
+            }
+            _ => (after(&last_key), address.clone()),
+        };
+
+        let report = &mut self.current;
+        let result = visit_range(&utxo_set.address_utxos, start, should_pause, |key, ()| {
+            let address_utxo = AddressUtxo::from_bytes(Cow::Borrowed(key.as_slice()));
+
+            // Finish the audit of the previous address once all its entries are visited.
+            match &current {
+                Some(audit) if audit.address == address_utxo.address => {}
+                _ => {
+                    if let Some(audit) = current.take() {
+                        audit.finish(report, utxo_set);
+                    }
+                    report.num_addresses += 1;
+                    current = Some(AddressAudit::new(address_utxo.address.clone(), next_height));
+                }
+            }
+
+            let audit = current.as_mut().expect("an address must be audited");
+            match utxo_set.utxos.get(&address_utxo.outpoint) {
+                Some((tx_out, height))
+                    if height == address_utxo.height
+                        && owner(utxo_set, &tx_out).as_ref() == Some(&address_utxo.address) =>
+                {
+                    audit.sum += tx_out.value;
+                }
+                _ => audit.stale_entries += 1,
+            }
+        });
+
+        match result {
+            // Nothing was visited, so the audit resumes from the same position.
+            Slicing::Paused(None) => Slicing::Paused(Cursor::AddressUtxos { last_key, address }),
+            Slicing::Paused(last_key) => Slicing::Paused(Cursor::AddressUtxos {
+                last_key,
+                address: current,
+            }),
+            Slicing::Done(()) => {
+                if let Some(audit) = current {
+                    audit.finish(report, utxo_set);
+                }
+                Slicing::Done(())
+            }
+        }
+    }
+
+    // Audits the entries of the index of addresses by value, starting after the given key.
+    fn audit_address_utxos_by_value(
+        &mut self,
+        utxo_set: &UtxoSet,
+        last_key: Option<Vec<u8>>,
+        should_pause: &mut dyn FnMut() -> bool,
+    ) -> Slicing<Option<Vec<u8>>, ()> {
+        let report = &mut self.current;
+        visit(
+            &utxo_set.address_utxos_by_value,
+            last_key,
+            should_pause,
+            |key, ()| {
+                let address_utxo = AddressValueUtxo::from_bytes(Cow::Borrowed(key.as_slice()));
+
+                match utxo_set.utxos.get(&address_utxo.outpoint) {
+                    Some((tx_out, _))
+                        if tx_out.value == address_utxo.value
+                            && owner(utxo_set, &tx_out).as_ref() == Some(&address_utxo.address) => {
+                    }
+                    _ => report.stale_value_index_entries += 1,
+                }
+            },
+        )
+    }
+
+    // Audits the entries of the index of addresses by ascending height, starting after the
+    // given key.
+    fn audit_address_utxos_by_height_ascending(
+        &mut self,
+        utxo_set: &UtxoSet,
+        last_key: Option<Vec<u8>>,
+        should_pause: &mut dyn FnMut() -> bool,
+    ) -> Slicing<Option<Vec<u8>>, ()> {
+        let report = &mut self.current;
+        visit(
+            &utxo_set.address_utxos_by_height_ascending,
+            last_key,
+            should_pause,
+            |key, ()| {
+                let address_utxo =
+                    AddressHeightAscendingUtxo::from_bytes(Cow::Borrowed(key.as_slice()));
+
+                match utxo_set.utxos.get(&address_utxo.outpoint) {
+                    Some((tx_out, height))
+                        if height == address_utxo.height
+                            && owner(utxo_set, &tx_out).as_ref() == Some(&address_utxo.address) => {
+                    }
+                    _ => report.stale_height_ascending_index_entries += 1,
+                }
+            },
+        )
+    }
+
+    // Audits the balances, starting after the given key.
+    //
+    // Balances of addresses that have UTXOs were already verified while auditing the address
+    // index, so only addresses without any UTXOs need to be checked here.
+    fn audit_balances(
+        &mut self,
+        utxo_set: &UtxoSet,
+        last_key: Option<Vec<u8>>,
+        should_pause: &mut dyn FnMut() -> bool,
+    ) -> Slicing<Option<Vec<u8>>, ()> {
+        let report = &mut self.current;
+        visit(&utxo_set.balances, last_key, should_pause, |address, _| {
+            let has_utxos = utxo_set
+                .address_utxos
+                .range(AddressUtxoRange::new(address, &None))
+                .next()
+                .is_some();
+            if !has_utxos {
+                report.balance_mismatches += 1;
+            }
+        })
+    }
+}
+
+// Verifies that the given UTXO is in the address indices if it's owned by an address.
+fn audit_utxo(
+    report: &mut UtxoAuditReport,
+    utxo_set: &UtxoSet,
+    outpoint: OutPoint,
+    tx_out: &TxOut,
+    height: Height,
+) {
+    report.num_utxos += 1;
+
+    let address = match owner(utxo_set, tx_out) {
+        Some(address) => address,
+        None => return,
+    };
+
+    let address_utxo = AddressUtxo {
+        address: address.clone(),
+        height,
+        outpoint: outpoint.clone(),
+    };
+    if !utxo_set.address_utxos.contains_key(&to_blob(&address_utxo)) {
+        report.utxos_missing_from_address_index += 1;
+    }
+
+    let address_value_utxo = AddressValueUtxo {
+        address: address.clone(),
+        value: tx_out.value,
+        outpoint: outpoint.clone(),
+    };
+    if !utxo_set
+        .address_utxos_by_value
+        .contains_key(&to_blob(&address_value_utxo))
+    {
+        report.utxos_missing_from_value_index += 1;
+    }
+
+    let address_height_ascending_utxo = AddressHeightAscendingUtxo {
+        address,
+        height,
+        outpoint,
+    };
+    if !utxo_set
+        .address_utxos_by_height_ascending
+        .contains_key(&to_blob(&address_height_ascending_utxo))
+    {
+        report.utxos_missing_from_height_ascending_index += 1;
+    }
+}
+
+// Verifies that the balance of the given address equals the sum of its UTXOs.
+fn audit_balance(
+    report: &mut UtxoAuditReport,
+    utxo_set: &UtxoSet,
+    address: &Address,
+    sum: Satoshi,
+) {
+    if utxo_set.balances.get(address).unwrap_or(0) != sum {
+        report.balance_mismatches += 1;
+    }
+}
+
+// Returns the address that owns the given output, if any.
+fn owner(utxo_set: &UtxoSet, tx_out: &TxOut) -> Option<Address> {
+    Address::from_script(
+        &Script::from(tx_out.script_pubkey.clone()),
+        utxo_set.network,
+    )
+    .ok()
+}
+
+pub(super) fn to_blob<T: BoundedStorable, const N: usize>(value: &T) -> Blob<N> {
+    Blob::try_from(value.to_bytes().as_ref()).unwrap()
+}
+
+// Visits the entries of the given map that come after the given key, until `should_pause`
+// returns true. Returns the key of the last visited entry if paused.
+pub(super) fn visit<K, V>(
+    map: &StableBTreeMap<K, V, Memory>,
+    last_key: Option<Vec<u8>>,
+    should_pause: &mut dyn FnMut() -> bool,
+    f: impl FnMut(&K, V),
+) -> Slicing<Option<Vec<u8>>, ()>
+where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable,
+{
+    match visit_range(map, after(&last_key), should_pause, f) {
+        // Nothing was visited, so the last visited key is unchanged.
+        Slicing::Paused(None) => Slicing::Paused(last_key),
+        result => result,
+    }
+}
+
+// Visits the entries of the given map from the given bound, until `should_pause` returns
+// true. Returns the key of the last visited entry, if any, if paused.
+fn visit_range<K, V>(
+    map: &StableBTreeMap<K, V, Memory>,
+    start: Bound<K>,
+    should_pause: &mut dyn FnMut() -> bool,
+    mut f: impl FnMut(&K, V),
+) -> Slicing<Option<Vec<u8>>, ()>
+where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable,
+{
+    let mut last_key = None;
+    for (key, value) in map.range((start, Bound::Unbounded)) {
+        if should_pause() {
+            return Slicing::Paused(last_key);
+        }
+
+        f(&key, value);
+        last_key = Some(key.to_bytes().to_vec());
+    }
+
+    Slicing::Done(())
+}
+
+// Returns the bound of the entries that come after the given key.
+fn after<K: BoundedStorable>(last_key: &Option<Vec<u8>>) -> Bound<K> {
+    match last_key {
+        Some(key) => Bound::Excluded(K::from_bytes(Cow::Borrowed(key))),
+        None => Bound::Unbounded,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{random_p2pkh_address, BlockBuilder, TransactionBuilder};
+    use ic_btc_interface::Network;
+
+    const NOW: u64 = 1_000;
+
+    // Builds a UTXO set with UTXOs of several addresses, one of which was spent.
+    fn build_utxo_set() -> UtxoSet {
+        let network = Network::Regtest;
+        let mut utxo_set = UtxoSet::new(network);
+
+        let coinbase = TransactionBuilder::coinbase()
+            .with_output(&random_p2pkh_address(network), 1000)
+            .with_output(&random_p2pkh_address(network), 2000)
+            .build();
+        let block_0 = BlockBuilder::genesis()
+            .with_transaction(coinbase.clone())
+            .build();
+
+        let tx = TransactionBuilder::new()
+            .with_input(OutPoint::new(coinbase.txid(), 0))
+            .with_output(&random_p2pkh_address(network), 500)
+            .with_output(&random_p2pkh_address(network), 400)
+            .build();
+        let block_1 = BlockBuilder::with_prev_header(block_0.header())
+            .with_transaction(TransactionBuilder::coinbase().build())
+            .with_transaction(tx)
+            .build();
+
+        for block in [block_0, block_1] {
+            assert!(matches!(utxo_set.ingest_block(block), Slicing::Done(_)));
+        }
+
+        // A large UTXO that isn't owned by an address.
+        utxo_set.utxos.insert(
+            OutPoint::new(coinbase.txid(), 7),
+            (
+                TxOut {
+                    value: 1,
+                    script_pubkey: vec![0; 300],
+                },
+                0,
+            ),
+        );
+
+        utxo_set
+    }
+
+    fn enabled_auditor() -> UtxoAuditor {
+        UtxoAuditor {
+            flag: Flag::Enabled,
+            ..Default::default()
+        }
+    }
+
+    fn run_to_completion(auditor: &mut UtxoAuditor, utxo_set: &UtxoSet) -> UtxoAuditReport {
+        auditor.run(utxo_set, NOW, &mut || false);
+        assert_eq!(auditor.cursor, Cursor::Start);
+        auditor.last_report.clone().unwrap()
+    }
+
+    #[test]
+    fn consistent_utxo_set_has_no_mismatches() {
+        let utxo_set = build_utxo_set();
+        let mut auditor = enabled_auditor();
+
+        assert_eq!(
+            run_to_completion(&mut auditor, &utxo_set),
+            UtxoAuditReport {
+                started_at: NOW,
+                completed_at: NOW,
+                num_utxos: utxo_set.utxos_len(),
+                num_addresses: 4,
+                ..Default::default()
+            }
+        );
+        assert_eq!(auditor.num_rounds, 1);
+    }
+
+    #[test]
+    fn detects_mismatches() {
+        let mut utxo_set = build_utxo_set();
+        let address_utxos: Vec<_> = utxo_set
+            .address_utxos
+            .iter()
+            .map(|(key, _)| AddressUtxo::from_bytes(Cow::Borrowed(key.as_slice())))
+            .collect();
+
+        // Remove a UTXO from the address index.
+        utxo_set.address_utxos.remove(&to_blob(&address_utxos[0]));
+
+        // Add an entry to the index by value that doesn't match any UTXO.
+        utxo_set.address_utxos_by_value.insert(
+            to_blob(&AddressValueUtxo {
+                address: address_utxos[1].address.clone(),
+                value: 1,
+                outpoint: OutPoint::new(address_utxos[1].outpoint.txid.clone(), 100),
+            }),
+            (),
+        );
+
+        // Remove a UTXO from the index by ascending height, and add an entry with the wrong
+        // height to it.
+        utxo_set
+            .address_utxos_by_height_ascending
+            .remove(&to_blob(&AddressHeightAscendingUtxo {
+                address: address_utxos[3].address.clone(),
+                height: address_utxos[3].height,
+                outpoint: address_utxos[3].outpoint.clone(),
+            }));
+        utxo_set.address_utxos_by_height_ascending.insert(
+            to_blob(&AddressHeightAscendingUtxo {
+                address: address_utxos[3].address.clone(),
+                height: address_utxos[3].height + 1,
+                outpoint: address_utxos[3].outpoint.clone(),
+            }),
+            (),
+        );
+
+        // Change the balance of an address, and add a balance to an address without UTXOs.
+        let balance = utxo_set.balances.get(&address_utxos[2].address).unwrap();
+        utxo_set
+            .balances
+            .insert(address_utxos[2].address.clone(), balance + 1);
+        utxo_set
+            .balances
+            .insert(random_p2pkh_address(Network::Regtest), 1);
+
+        let report = run_to_completion(&mut enabled_auditor(), &utxo_set);
+        assert_eq!(report.utxos_missing_from_address_index, 1);
+        assert_eq!(report.utxos_missing_from_value_index, 0);
+        assert_eq!(report.stale_address_index_entries, 0);
+        assert_eq!(report.stale_value_index_entries, 1);
+        assert_eq!(report.utxos_missing_from_height_ascending_index, 1);
+        assert_eq!(report.stale_height_ascending_index_entries, 1);
+        // The address whose UTXO was removed from the index, the address whose balance was
+        // changed, and the address without UTXOs.
+        assert_eq!(report.balance_mismatches, 3);
+    }
+
+    #[test]
+    fn resumes_from_cursor() {
+        let utxo_set = build_utxo_set();
+        let expected_report = run_to_completion(&mut enabled_auditor(), &utxo_set);
+
+        // Pause the audit after every entry.
+        let mut auditor = enabled_auditor();
+        let mut num_runs = 0;
+        while auditor.num_rounds == 0 {
+            let mut calls = 0;
+            auditor.run(&utxo_set, NOW, &mut || {
+                calls += 1;
+                calls > 1
+            });
+            num_runs += 1;
+        }
+
+        assert!(num_runs > 1);
+        assert_eq!(auditor.last_report, Some(expected_report));
+    }
+
+    #[test]
+    fn restarts_address_when_a_block_is_ingested() {
+        let network = Network::Regtest;
+        let mut utxo_set = UtxoSet::new(network);
+        let address = random_p2pkh_address(network);
+
+        let block_0 = BlockBuilder::genesis()
+            .with_transaction(
+                TransactionBuilder::coinbase()
+                    .with_output(&address, 1000)
+                    .with_output(&address, 2000)
+                    .build(),
+            )
+            .build();
+        assert!(matches!(
+            utxo_set.ingest_block(block_0.clone()),
+            Slicing::Done(_)
+        ));
+
+        // Pause the audit after every entry until it's in the middle of the address.
+        let mut auditor = enabled_auditor();
+        loop {
+            let mut calls = 0;
+            auditor.run(&utxo_set, NOW, &mut || {
+                calls += 1;
+                calls > 1
+            });
+            if let Cursor::AddressUtxos {
+                address: Some(_), ..
+            } = &auditor.cursor
+            {
+                break;
+            }
+        }
+
+        // Ingest a block with a UTXO of the address, which comes before the UTXOs that were
+        // already visited in the address index.
+        let block_1 = BlockBuilder::with_prev_header(block_0.header())
+            .with_transaction(
+                TransactionBuilder::coinbase()
+                    .with_output(&address, 3000)
+                    .build(),
+            )
+            .build();
+        assert!(matches!(utxo_set.ingest_block(block_1), Slicing::Done(_)));
+
+        auditor.run(&utxo_set, NOW, &mut || false);
+        let report = auditor.last_report.unwrap();
+        assert_eq!(report.balance_mismatches, 0);
+        assert_eq!(report.stale_address_index_entries, 0);
+        assert_eq!(report.num_addresses, 1);
+    }
+
+    #[test]
+    fn does_not_run_when_disabled_or_ingesting() {
+        let mut utxo_set = build_utxo_set();
+
+        let mut auditor = UtxoAuditor::default();
+        auditor.run(&utxo_set, NOW, &mut || false);
+        assert_eq!(auditor, UtxoAuditor::default());
+
+        utxo_set.ingesting_block = Some(crate::utxo_set::IngestingBlock::new(
+            BlockBuilder::genesis().build(),
+        ));
+        let mut auditor = enabled_auditor();
+        auditor.run(&utxo_set, NOW, &mut || false);
+        assert_eq!(auditor, enabled_auditor());
+    }
+}
@@ -0,0 +1,214 @@
+use super::{
+    audit::{to_blob, visit},
+    UtxoSet,
+};
+use crate::types::{AddressHeightAscendingUtxo, AddressUtxo, AddressValueUtxo, Slicing};
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Fills the indices of the addresses' UTXOs that are derived from the address index, across
+/// many heartbeats.
+///
+/// The indices only have the UTXOs that were inserted since they were introduced, so they're
+/// backfilled from the address index after an upgrade. Blocks can be ingested while the
+/// backfill is in progress, as every UTXO is inserted into and removed from all the indices
+/// at once.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct IndicesBackfill {
+    // The key of the last entry of the address index that was backfilled.
+    last_key: Option<Vec<u8>>,
+}
+
+impl UtxoSet {
+    /// Starts backfilling the indices from the start of the address index.
+    pub fn start_indices_backfill(&mut self) {
+        self.indices_backfill = Some(IndicesBackfill::default());
+    }
+
+    /// Returns true if the indices are being backfilled.
+    pub fn is_backfilling_indices(&self) -> bool {
+        self.indices_backfill.is_some()
+    }
+
+    /// Backfills the indices until `should_pause` returns true, resuming from where the
+    /// previous call left off.
+    ///
+    /// Returns `Slicing::Done` once all the entries of the address index are backfilled, or
+    /// if there's no backfill in progress.
+    pub fn backfill_indices(&mut self, should_pause: &mut dyn FnMut() -> bool) -> Slicing<(), ()> {
+        let backfill = match self.indices_backfill.take() {
+            Some(backfill) => backfill,
+            None => return Slicing::Done(()),
+        };
+
+        let utxos = &self.utxos;
+        let address_utxos_by_value = &mut self.address_utxos_by_value;
+        let address_utxos_by_height_ascending = &mut self.address_utxos_by_height_ascending;
+        let result = visit(
+            &self.address_utxos,
+            backfill.last_key,
+            should_pause,
+            |key, ()| {
+                let address_utxo = AddressUtxo::from_bytes(Cow::Borrowed(key.as_slice()));
+                if let Some((tx_out, _)) = utxos.get(&address_utxo.outpoint) {
+                    address_utxos_by_value.insert(
+                        to_blob(&AddressValueUtxo {
+                            address: address_utxo.address.clone(),
+                            value: tx_out.value,
+                            outpoint: address_utxo.outpoint.clone(),
+                        }),
+                        (),
+                    );
+                    address_utxos_by_height_ascending.insert(
+                        to_blob(&AddressHeightAscendingUtxo {
+                            address: address_utxo.address,
+                            height: address_utxo.height,
+                            outpoint: address_utxo.outpoint,
+                        }),
+                        (),
+                    );
+                }
+            },
+        );
+
+        match result {
+            Slicing::Paused(last_key) => {
+                self.indices_backfill = Some(IndicesBackfill { last_key });
+                Slicing::Paused(())
+            }
+            Slicing::Done(()) => Slicing::Done(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{random_p2pkh_address, BlockBuilder, TransactionBuilder};
+    use ic_btc_interface::Network;
+    use ic_btc_types::OutPoint;
+
+    // Returns the entries of the index of addresses by value.
+    fn value_index(utxo_set: &UtxoSet) -> Vec<AddressValueUtxo> {
+        utxo_set
+            .address_utxos_by_value
+            .iter()
+            .map(|(key, _)| AddressValueUtxo::from_bytes(Cow::Borrowed(key.as_slice())))
+            .collect()
+    }
+
+    // Returns the entries of the index of addresses by ascending height.
+    fn height_ascending_index(utxo_set: &UtxoSet) -> Vec<AddressHeightAscendingUtxo> {
+        utxo_set
+            .address_utxos_by_height_ascending
+            .iter()
+            .map(|(key, _)| AddressHeightAscendingUtxo::from_bytes(Cow::Borrowed(key.as_slice())))
+            .collect()
+    }
+
+    // Removes all the entries of the backfilled indices, as in a state that was upgraded from
+    // a version without them.
+    fn clear_indices(utxo_set: &mut UtxoSet) {
+        for address_utxo in value_index(utxo_set) {
+            utxo_set
+                .address_utxos_by_value
+                .remove(&to_blob(&address_utxo));
+        }
+        for address_utxo in height_ascending_index(utxo_set) {
+            utxo_set
+                .address_utxos_by_height_ascending
+                .remove(&to_blob(&address_utxo));
+        }
+    }
+
+    #[test]
+    fn backfills_indices() {
+        let network = Network::Regtest;
+        let mut utxo_set = UtxoSet::new(network);
+        let block = BlockBuilder::genesis()
+            .with_transaction(
+                TransactionBuilder::coinbase()
+                    .with_output(&random_p2pkh_address(network), 1000)
+                    .with_output(&random_p2pkh_address(network), 2000)
+                    .with_output(&random_p2pkh_address(network), 3000)
+                    .build(),
+            )
+            .build();
+        assert!(matches!(utxo_set.ingest_block(block), Slicing::Done(_)));
+
+        let expected_value_index = value_index(&utxo_set);
+        let expected_height_ascending_index = height_ascending_index(&utxo_set);
+        clear_indices(&mut utxo_set);
+
+        // Pause the backfill after every entry.
+        utxo_set.start_indices_backfill();
+        let mut num_runs = 0;
+        loop {
+            let mut calls = 0;
+            num_runs += 1;
+            let result = utxo_set.backfill_indices(&mut || {
+                calls += 1;
+                calls > 1
+            });
+            if result == Slicing::Done(()) {
+                break;
+            }
+        }
+
+        assert_eq!(num_runs, 3);
+        assert_eq!(value_index(&utxo_set), expected_value_index);
+        assert_eq!(
+            height_ascending_index(&utxo_set),
+            expected_height_ascending_index
+        );
+    }
+
+    #[test]
+    fn ingests_blocks_while_backfilling() {
+        let network = Network::Regtest;
+        let mut utxo_set = UtxoSet::new(network);
+        let address = random_p2pkh_address(network);
+
+        let coinbase = TransactionBuilder::coinbase()
+            .with_output(&address, 1000)
+            .with_output(&address, 2000)
+            .build();
+        let block_0 = BlockBuilder::genesis()
+            .with_transaction(coinbase.clone())
+            .build();
+        assert!(matches!(
+            utxo_set.ingest_block(block_0.clone()),
+            Slicing::Done(_)
+        ));
+
+        clear_indices(&mut utxo_set);
+        utxo_set.start_indices_backfill();
+
+        // Spend a UTXO that isn't backfilled yet.
+        let tx = TransactionBuilder::new()
+            .with_input(OutPoint::new(coinbase.txid(), 0))
+            .with_output(&address, 500)
+            .build();
+        let block_1 = BlockBuilder::with_prev_header(block_0.header())
+            .with_transaction(tx)
+            .build();
+        assert!(matches!(utxo_set.ingest_block(block_1), Slicing::Done(_)));
+
+        assert_eq!(utxo_set.backfill_indices(&mut || false), Slicing::Done(()));
+        assert!(!utxo_set.is_backfilling_indices());
+
+        let values: Vec<_> = value_index(&utxo_set)
+            .into_iter()
+            .map(|address_utxo| address_utxo.value)
+            .collect();
+        assert_eq!(values.len() as u64, utxo_set.address_utxos_len());
+        assert_eq!(
+            height_ascending_index(&utxo_set).len() as u64,
+            utxo_set.address_utxos_len()
+        );
+        assert!(values.contains(&2000));
+        assert!(values.contains(&500));
+        assert!(!values.contains(&1000));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{
+    memory::{get_large_utxos_memory, Memory},
+    state::UTXO_KEY_SIZE,
+    types::{Storable, TxOut},
+};
+use ic_btc_interface::Height;
+use ic_btc_types::OutPoint;
+use ic_stable_structures::{
+    btreemap, storable::Blob, StableBTreeMap, Storable as StableStructuresStorable,
+};
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, convert::TryFrom, iter::Peekable, ops::Bound};
+
+// The size of a chunk of an encoded UTXO.
+const CHUNK_SIZE: usize = 512;
+
+// The size of a chunk's key, which is the outpoint followed by the chunk's index.
+const CHUNK_KEY_SIZE: usize = UTXO_KEY_SIZE + 4;
+
+/// Stores the UTXOs that are too large for the buckets of `Utxos` in stable memory.
+///
+/// Scripts can be up to 10k bytes in size, which is far larger than what a value in a
+/// `StableBTreeMap` can reasonably be bounded to. UTXOs are therefore stored in their
+/// encoding, split into chunks of `CHUNK_SIZE` bytes, where each chunk is keyed by the
+/// UTXO's outpoint and the index of the chunk.
+#[derive(Serialize, Deserialize)]
+pub struct LargeUtxos {
+    // NOTE: Stable structures don't need to be serialized.
+    #[serde(skip, default = "init_chunks")]
+    chunks: StableBTreeMap<Blob<CHUNK_KEY_SIZE>, Blob<CHUNK_SIZE>, Memory>,
+
+    // The number of UTXOs stored, which is kept so that it needn't be computed by
+    // iterating over the chunks.
+    num_utxos: u64,
+}
+
+impl Default for LargeUtxos {
+    fn default() -> Self {
+        Self {
+            chunks: init_chunks(),
+            num_utxos: 0,
+        }
+    }
+}
+
+// NOTE: `PartialEq` is only available in tests as it would be impractically
+// expensive in production.
+#[cfg(test)]
+impl PartialEq for LargeUtxos {
+    fn eq(&self, other: &Self) -> bool {
+        use crate::test_utils::is_stable_btreemap_equal;
+        self.num_utxos == other.num_utxos && is_stable_btreemap_equal(&self.chunks, &other.chunks)
+    }
+}
+
+impl LargeUtxos {
+    /// Inserts a UTXO into the map.
+    /// Returns true if there was a previous value for the key in the map, false otherwise.
+    pub fn insert(&mut self, key: OutPoint, value: (TxOut, Height)) -> bool {
+        let found = self.remove(&key).is_some();
+
+        for (idx, chunk) in value.to_bytes().chunks(CHUNK_SIZE).enumerate() {
+            self.chunks
+                .insert(chunk_key(&key, idx as u32), Blob::try_from(chunk).unwrap());
+        }
+        self.num_utxos += 1;
+
+        found
+    }
+
+    /// Returns the value associated with the given outpoint if it exists.
+    pub fn get(&self, key: &OutPoint) -> Option<(TxOut, Height)> {
+        let bytes: Vec<u8> = self
+            .chunks
+            .range(chunk_key(key, 0)..=chunk_key(key, u32::MAX))
+            .flat_map(|(_, chunk)| chunk.as_slice().to_vec())
+            .collect();
+
+        if bytes.is_empty() {
+            return None;
+        }
+
+        Some(<(TxOut, Height)>::from_bytes(bytes))
+    }
+
+    /// Removes a key from the map, returning the previous value at the key if it exists.
+    pub fn remove(&mut self, key: &OutPoint) -> Option<(TxOut, Height)> {
+        let value = self.get(key)?;
+
+        let chunk_keys: Vec<_> = self
+            .chunks
+            .range(chunk_key(key, 0)..=chunk_key(key, u32::MAX))
+            .map(|(chunk_key, _)| chunk_key)
+            .collect();
+        for chunk_key in chunk_keys {
+            self.chunks.remove(&chunk_key);
+        }
+        self.num_utxos -= 1;
+
+        Some(value)
+    }
+
+    /// Gets an iterator over the entries of the map, sorted by the encoding of their outpoints.
+    pub fn iter(&self) -> Iter<'_> {
+        self.range_after(None)
+    }
+
+    /// Gets an iterator over the entries of the map whose outpoints come after the given
+    /// outpoint, sorted by the encoding of their outpoints.
+    pub fn range_after(&self, outpoint: Option<&OutPoint>) -> Iter<'_> {
+        let start = match outpoint {
+            Some(outpoint) => Bound::Excluded(chunk_key(outpoint, u32::MAX)),
+            None => Bound::Unbounded,
+        };
+
+        Iter {
+            chunks: self.chunks.range((start, Bound::Unbounded)).peekable(),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.num_utxos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_utxos == 0
+    }
+}
+
+/// An iterator over the entries in [`LargeUtxos`].
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Iter<'a> {
+    chunks: Peekable<btreemap::Iter<'a, Blob<CHUNK_KEY_SIZE>, Blob<CHUNK_SIZE>, Memory>>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = (OutPoint, (TxOut, Height));
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, chunk) = self.chunks.next()?;
+        let outpoint_bytes = &key.as_slice()[..UTXO_KEY_SIZE];
+
+        // Concatenate the chunks of the UTXO, which are stored consecutively.
+        let mut bytes = chunk.as_slice().to_vec();
+        while let Some((_, chunk)) = self
+            .chunks
+            .next_if(|(next_key, _)| &next_key.as_slice()[..UTXO_KEY_SIZE] == outpoint_bytes)
+        {
+            bytes.extend_from_slice(chunk.as_slice());
+        }
+
+        Some((
+            OutPoint::from_bytes(Cow::Borrowed(outpoint_bytes)),
+            <(TxOut, Height)>::from_bytes(bytes),
+        ))
+    }
+}
+
+// Returns the key of the chunk with the given index of the given outpoint's UTXO.
+fn chunk_key(outpoint: &OutPoint, idx: u32) -> Blob<CHUNK_KEY_SIZE> {
+    let mut key = outpoint.to_bytes().to_vec();
+    key.extend_from_slice(&idx.to_be_bytes());
+    Blob::try_from(key.as_slice()).unwrap()
+}
+
+fn init_chunks() -> StableBTreeMap<Blob<CHUNK_KEY_SIZE>, Blob<CHUNK_SIZE>, Memory> {
+    StableBTreeMap::init(get_large_utxos_memory())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ic_btc_types::Txid;
+
+    fn outpoint(id: u8, vout: u32) -> OutPoint {
+        OutPoint::new(Txid::from(vec![id; 32]), vout)
+    }
+
+    fn utxo(script_size: usize, height: Height) -> (TxOut, Height) {
+        (
+            TxOut {
+                value: script_size as u64,
+                script_pubkey: vec![height as u8; script_size],
+            },
+            height,
+        )
+    }
+
+    #[test]
+    fn insert_get_and_remove() {
+        let mut large_utxos = LargeUtxos::default();
+        assert!(large_utxos.is_empty());
+
+        // UTXOs that fit in a single chunk and that span multiple chunks.
+        let small = utxo(300, 1);
+        let big = utxo(10_000, 2);
+        assert!(!large_utxos.insert(outpoint(1, 0), small.clone()));
+        assert!(!large_utxos.insert(outpoint(1, 1), big.clone()));
+        assert_eq!(large_utxos.len(), 2);
+
+        assert_eq!(large_utxos.get(&outpoint(1, 0)), Some(small.clone()));
+        assert_eq!(large_utxos.get(&outpoint(1, 1)), Some(big.clone()));
+        assert_eq!(large_utxos.get(&outpoint(2, 0)), None);
+
+        assert_eq!(large_utxos.remove(&outpoint(1, 1)), Some(big));
+        assert_eq!(large_utxos.remove(&outpoint(1, 1)), None);
+        assert_eq!(large_utxos.get(&outpoint(1, 1)), None);
+        assert_eq!(large_utxos.get(&outpoint(1, 0)), Some(small));
+        assert_eq!(large_utxos.len(), 1);
+    }
+
+    #[test]
+    fn overwriting_a_utxo_removes_its_old_chunks() {
+        let mut large_utxos = LargeUtxos::default();
+        assert!(!large_utxos.insert(outpoint(1, 0), utxo(10_000, 1)));
+        assert!(large_utxos.insert(outpoint(1, 0), utxo(300, 2)));
+
+        assert_eq!(large_utxos.len(), 1);
+        assert_eq!(large_utxos.chunks.len(), 1);
+        assert_eq!(large_utxos.get(&outpoint(1, 0)), Some(utxo(300, 2)));
+    }
+
+    #[test]
+    fn iter_and_range_after() {
+        let mut large_utxos = LargeUtxos::default();
+        let utxos = vec![
+            (outpoint(1, 0), utxo(1_000, 1)),
+            (outpoint(1, 1), utxo(300, 2)),
+            (outpoint(2, 0), utxo(5_000, 3)),
+        ];
+        for (outpoint, utxo) in utxos.iter() {
+            large_utxos.insert(outpoint.clone(), utxo.clone());
+        }
+
+        assert_eq!(large_utxos.iter().collect::<Vec<_>>(), utxos);
+        assert_eq!(
+            large_utxos
+                .range_after(Some(&outpoint(1, 0)))
+                .collect::<Vec<_>>(),
+            utxos[1..]
+        );
+        assert_eq!(
+            large_utxos
+                .range_after(Some(&outpoint(2, 0)))
+                .collect::<Vec<_>>(),
+            vec![]
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use super::large_utxos::LargeUtxos;
 use crate::{
     memory::{get_utxos_medium_memory, get_utxos_small_memory, Memory},
     state::{UTXO_KEY_SIZE, UTXO_VALUE_MAX_SIZE_MEDIUM, UTXO_VALUE_MAX_SIZE_SMALL},
@@ -45,6 +46,13 @@ use std::convert::TryFrom;
 ///    1) "Small" to store UTXOs with script size <= 25 bytes.
 ///    2) "Medium" to store UTXOs with script size > 25 bytes && <= 201 bytes.
 ///    3) "Large" to store UTXOs with script size > 201 bytes.
+///
+///    The bucket of a UTXO is determined by the size of its encoding, in which the
+///    scripts that follow a standard template are compressed (see `types`).
+///
+///    Large UTXOs were originally stored in a standard `BTreeMap`, but their number
+///    has since grown considerably, so they're stored in stable memory as well (see
+///    `LargeUtxos`).
 #[derive(Serialize, Deserialize)]
 pub struct Utxos {
     // A map storing the UTXOs that are "small" in size.
@@ -58,9 +66,13 @@ pub struct Utxos {
     pub medium_utxos: StableBTreeMap<Blob<UTXO_KEY_SIZE>, Blob<UTXO_VALUE_MAX_SIZE_MEDIUM>, Memory>,
 
     // A map storing the UTXOs that are "large" in size.
-    // The number of entries stored in this map is tiny (see docs above), so a
-    // standard `BTreeMap` suffices.
-    pub large_utxos: BTreeMap<OutPoint, (TxOut, Height)>,
+    #[serde(default)]
+    pub large_utxos: LargeUtxos,
+
+    // The large UTXOs of a state that predates storing them in stable memory, which
+    // are moved to `large_utxos` when the state is migrated (see `upgrade`).
+    #[serde(default, skip_serializing)]
+    legacy_large_utxos: BTreeMap<OutPoint, (TxOut, Height)>,
 }
 
 impl Default for Utxos {
@@ -68,7 +80,8 @@ impl Default for Utxos {
         Self {
             small_utxos: init_small_utxos(),
             medium_utxos: init_medium_utxos(),
-            large_utxos: BTreeMap::default(),
+            large_utxos: LargeUtxos::default(),
+            legacy_large_utxos: BTreeMap::default(),
         }
     }
 }
@@ -82,6 +95,7 @@ impl PartialEq for Utxos {
         is_stable_btreemap_equal(&self.small_utxos, &other.small_utxos)
             && is_stable_btreemap_equal(&self.medium_utxos, &other.medium_utxos)
             && self.large_utxos == other.large_utxos
+            && self.legacy_large_utxos == other.legacy_large_utxos
     }
 }
 
@@ -106,7 +120,7 @@ impl Utxos {
                 )
                 .is_some()
         } else {
-            self.large_utxos.insert(key, value).is_some()
+            self.large_utxos.insert(key, value)
         }
     }
 
@@ -122,7 +136,11 @@ impl Utxos {
             return Some(<(TxOut, Height)>::from_bytes(value.as_slice().to_vec()));
         }
 
-        self.large_utxos.get(key).cloned()
+        // The large UTXOs of a state that's being migrated may not be moved to stable memory
+        // yet, e.g. while the outpoints cache is rebuilt by an earlier migration.
+        self.large_utxos
+            .get(key)
+            .or_else(|| self.legacy_large_utxos.get(key).cloned())
     }
 
     /// Removes a key from the map, returning the previous value at the key if it exists.
@@ -137,9 +155,7 @@ impl Utxos {
             return Some(<(TxOut, Height)>::from_bytes(value.as_slice().to_vec()));
         }
 
-        self.large_utxos
-            .remove(key)
-            .map(|(txout, height)| (txout, height))
+        self.large_utxos.remove(key)
     }
 
     /// Gets an iterator over the entries of the map.
@@ -150,13 +166,21 @@ impl Utxos {
     }
 
     pub fn len(&self) -> u64 {
-        self.large_utxos.len() as u64 + self.small_utxos.len() + self.medium_utxos.len()
+        self.large_utxos.len() + self.small_utxos.len() + self.medium_utxos.len()
     }
 
     #[cfg(test)]
     pub fn is_empty(&self) -> bool {
         self.large_utxos.is_empty() && self.small_utxos.is_empty() && self.medium_utxos.is_empty()
     }
+
+    /// Moves the large UTXOs of a state that predates storing them in stable memory
+    /// into stable memory.
+    pub fn migrate_large_utxos_to_stable_memory(&mut self) {
+        for (outpoint, utxo) in std::mem::take(&mut self.legacy_large_utxos) {
+            self.large_utxos.insert(outpoint, utxo);
+        }
+    }
 }
 
 /// An iterator over the entries in [`Utxos`].
@@ -165,7 +189,7 @@ impl Utxos {
 pub struct Iter<'a, M: MemoryTrait> {
     small_utxos_iter: btreemap::Iter<'a, Blob<UTXO_KEY_SIZE>, Blob<UTXO_VALUE_MAX_SIZE_SMALL>, M>,
     medium_utxos_iter: btreemap::Iter<'a, Blob<UTXO_KEY_SIZE>, Blob<UTXO_VALUE_MAX_SIZE_MEDIUM>, M>,
-    large_utxos_iter: std::collections::btree_map::Iter<'a, OutPoint, (TxOut, Height)>,
+    large_utxos_iter: super::large_utxos::Iter<'a>,
 }
 
 #[cfg(test)]
@@ -201,9 +225,7 @@ impl<M: MemoryTrait + Clone> Iterator for Iter<'_, M> {
         }
 
         // Finally, iterate over the large utxos.
-        self.large_utxos_iter
-            .next()
-            .map(|(k, v)| (k.clone(), v.clone()))
+        self.large_utxos_iter.next()
     }
 }
 
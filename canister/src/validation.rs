@@ -1,6 +1,11 @@
 use crate::{blocktree::BlockDoesNotExtendTree, state::State, unstable_blocks};
 use bitcoin::BlockHeader;
-use ic_btc_validation::HeaderStore;
+use ic_btc_types::{Block, OutPoint};
+use ic_btc_validation::{HeaderStore, TxOutStore};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
 
 /// A structure passed to the validation crate to validate a specific block header.
 pub struct ValidationContext<'a> {
@@ -8,22 +13,77 @@ pub struct ValidationContext<'a> {
     // BlockHash is stored in order to avoid repeatedly calling to
     // BlockHeader::block_hash() which is expensive.
     chain: Vec<(&'a BlockHeader, ic_btc_types::BlockHash)>,
+    // The unstable blocks of the chain, against which the outputs spent by a block are
+    // resolved.
+    blocks: Vec<&'a Block>,
+    // The outputs created and spent by the unstable blocks of the chain, which are only
+    // loaded once an output that an unstable block created or spent is looked up.
+    chain_tx_outs: RefCell<Option<ChainTxOuts>>,
+}
+
+// The outputs created and spent by a chain of unstable blocks.
+#[derive(Default)]
+struct ChainTxOuts {
+    created: BTreeMap<OutPoint, u64>,
+    spent: BTreeSet<OutPoint>,
+}
+
+impl ChainTxOuts {
+    fn load(state: &State, blocks: &[&Block]) -> Self {
+        let mut chain_tx_outs = Self::default();
+        for block in blocks {
+            // The blocks of the tree only have their headers, so the full block is loaded.
+            let block = unstable_blocks::load_block(&state.unstable_blocks, block);
+            for tx in block.txdata() {
+                for input in tx.input() {
+                    if !input.previous_output.is_null() {
+                        chain_tx_outs.spent.insert((&input.previous_output).into());
+                    }
+                }
+
+                for (vout, output) in tx.output().iter().enumerate() {
+                    chain_tx_outs
+                        .created
+                        .insert(OutPoint::new(tx.txid(), vout as u32), output.value);
+                }
+            }
+        }
+        chain_tx_outs
+    }
 }
 
 impl<'a> ValidationContext<'a> {
+    /// Initialize a `ValidationContext` for the main chain, i.e. the stable block headers
+    /// followed by the main chain of the unstable blocks.
+    pub fn new_for_main_chain(state: &'a State) -> Self {
+        let blocks = unstable_blocks::get_main_chain(&state.unstable_blocks).into_chain();
+        Self::with_blocks(state, blocks)
+    }
+
     /// Initialize a `ValidationContext` for the given block header.
     pub fn new(state: &'a State, header: &BlockHeader) -> Result<Self, BlockDoesNotExtendTree> {
         // Retrieve the chain that the given header extends.
         // The given header must extend one of the unstable blocks.
         let prev_block_hash = header.prev_blockhash.into();
-        let chain = unstable_blocks::get_chain_with_tip(&state.unstable_blocks, &prev_block_hash)
+        let blocks = unstable_blocks::get_chain_with_tip(&state.unstable_blocks, &prev_block_hash)
             .ok_or_else(|| BlockDoesNotExtendTree(header.block_hash().into()))?
-            .into_chain()
+            .into_chain();
+
+        Ok(Self::with_blocks(state, blocks))
+    }
+
+    fn with_blocks(state: &'a State, blocks: Vec<&'a Block>) -> Self {
+        let chain = blocks
             .iter()
             .map(|block| (block.header(), block.block_hash()))
             .collect();
 
-        Ok(Self { state, chain })
+        Self {
+            state,
+            chain,
+            blocks,
+            chain_tx_outs: RefCell::new(None),
+        }
     }
 
     /// Initialize a `ValidationContext` for the given block header.
@@ -85,14 +145,48 @@ impl<'a> HeaderStore for ValidationContext<'a> {
     }
 }
 
+/// Implements the `TxOutStore` trait that's used for validating blocks.
+///
+/// The outputs are resolved against the chain of the context, so that the outputs that
+/// were created on another fork, or that were already spent on the chain, aren't found.
+impl<'a> TxOutStore for ValidationContext<'a> {
+    fn get_tx_out_value(&self, outpoint: &bitcoin::OutPoint) -> Option<u64> {
+        let outpoint = OutPoint::from(outpoint);
+        let get_utxo_value = || {
+            self.state
+                .utxos
+                .get_utxo(&outpoint)
+                .map(|(txout, _)| txout.value)
+        };
+
+        // An output that no unstable block created or spent is resolved with the UTXO set.
+        if self.state.unstable_blocks.get_tx_out(&outpoint).is_none() {
+            return get_utxo_value();
+        }
+
+        let mut chain_tx_outs = self.chain_tx_outs.borrow_mut();
+        let chain_tx_outs =
+            chain_tx_outs.get_or_insert_with(|| ChainTxOuts::load(self.state, &self.blocks));
+        if chain_tx_outs.spent.contains(&outpoint) {
+            return None;
+        }
+
+        match chain_tx_outs.created.get(&outpoint) {
+            Some(value) => Some(*value),
+            None => get_utxo_value(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::{
-        state::{ingest_stable_blocks_into_utxoset, insert_block},
-        test_utils::{build_chain, BlockBuilder},
+        state::{ingest_stable_blocks_into_utxoset, insert_block, InsertBlockError},
+        test_utils::{build_chain, random_p2pkh_address, BlockBuilder, TransactionBuilder},
     };
     use ic_btc_interface::Network;
+    use ic_btc_validation::ValidateBlockError;
     use proptest::prelude::*;
     use std::str::FromStr;
 
@@ -142,6 +236,86 @@ mod test {
         ));
     }
 
+    #[test]
+    fn resolves_outputs_against_the_chain_of_the_block() {
+        let network = Network::Regtest;
+        let genesis = BlockBuilder::genesis().build();
+        let genesis_outpoint = OutPoint::new(genesis.txdata()[0].txid(), 0);
+        let mut state = State::new(10, network, genesis.clone());
+
+        // Two forks that both spend the output of the genesis block.
+        let spend_genesis_output = || {
+            TransactionBuilder::new()
+                .with_input(genesis_outpoint.clone())
+                .with_output(&random_p2pkh_address(network), 1)
+                .build()
+        };
+        let tx_1 = spend_genesis_output();
+        let fork_1 = BlockBuilder::with_prev_header(genesis.header())
+            .with_transaction(tx_1.clone())
+            .build();
+        let fork_2 = BlockBuilder::with_prev_header(genesis.header())
+            .with_transaction(spend_genesis_output())
+            .build();
+        insert_block(&mut state, fork_1.clone()).unwrap();
+        insert_block(&mut state, fork_2.clone()).unwrap();
+
+        let tx_1_outpoint = OutPoint::new(tx_1.txid(), 0);
+        let child_1 = BlockBuilder::with_prev_header(fork_1.header()).build();
+        let child_2 = BlockBuilder::with_prev_header(fork_2.header()).build();
+        let on_fork_1 = ValidationContext::new(&state, child_1.header()).unwrap();
+        let on_fork_2 = ValidationContext::new(&state, child_2.header()).unwrap();
+
+        // The output of the genesis block was already spent on both forks.
+        let genesis_outpoint = bitcoin::OutPoint::from(genesis_outpoint.clone());
+        assert_eq!(on_fork_1.get_tx_out_value(&genesis_outpoint), None);
+        assert_eq!(on_fork_2.get_tx_out_value(&genesis_outpoint), None);
+
+        // The output created on the first fork is only found on that fork.
+        let tx_1_outpoint = bitcoin::OutPoint::from(tx_1_outpoint);
+        assert_eq!(on_fork_1.get_tx_out_value(&tx_1_outpoint), Some(1));
+        assert_eq!(on_fork_2.get_tx_out_value(&tx_1_outpoint), None);
+    }
+
+    #[test]
+    fn blocks_spending_outputs_of_another_fork_are_invalid() {
+        let network = Network::Regtest;
+        let genesis = BlockBuilder::genesis().build();
+        let mut state = State::new(10, network, genesis.clone());
+
+        let tx = TransactionBuilder::new()
+            .with_input(OutPoint::new(genesis.txdata()[0].txid(), 0))
+            .with_output(&random_p2pkh_address(network), 1)
+            .build();
+        let fork_1 = BlockBuilder::with_prev_header(genesis.header())
+            .with_transaction(tx.clone())
+            .build();
+        let fork_2 = BlockBuilder::with_prev_header(genesis.header()).build();
+        insert_block(&mut state, fork_1.clone()).unwrap();
+        insert_block(&mut state, fork_2.clone()).unwrap();
+
+        // Spending the output created on the first fork is only valid on that fork.
+        let spend_tx_output = |prev_block: &Block| {
+            BlockBuilder::with_prev_header(prev_block.header())
+                .with_transaction(
+                    TransactionBuilder::new()
+                        .with_input(OutPoint::new(tx.txid(), 0))
+                        .with_output(&random_p2pkh_address(network), 1)
+                        .build(),
+                )
+                .build()
+        };
+        let invalid_block = spend_tx_output(&fork_2);
+        assert_eq!(
+            insert_block(&mut state, invalid_block.clone()),
+            Err(InsertBlockError::InvalidBlock(
+                ValidateBlockError::InputNotFound(OutPoint::new(tx.txid(), 0).into())
+            ))
+        );
+        assert!(state.invalid_blocks.contains(&invalid_block.block_hash()));
+        assert_eq!(insert_block(&mut state, spend_tx_output(&fork_1)), Ok(()));
+    }
+
     proptest! {
         #![proptest_config(ProptestConfig::with_cases(10))]
         #[test]
@@ -149,9 +323,9 @@ mod test {
             stability_threshold in 1..150u32,
             num_blocks in 2..250u32,
         ) {
-            let num_transactions_in_block = 1;
+            let num_outputs_in_block = 1;
             let network = Network::Regtest;
-            let blocks = build_chain(network, num_blocks, num_transactions_in_block);
+            let blocks = build_chain(network, num_blocks, num_outputs_in_block);
 
             let mut state = State::new(stability_threshold, network, blocks[0].clone());
 
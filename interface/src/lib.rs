@@ -21,6 +21,10 @@ pub enum Network {
     Testnet,
     #[serde(rename = "regtest")]
     Regtest,
+    #[serde(rename = "signet")]
+    Signet,
+    #[serde(rename = "testnet4")]
+    Testnet4,
 }
 
 impl fmt::Display for Network {
@@ -29,6 +33,8 @@ impl fmt::Display for Network {
             Self::Mainnet => write!(f, "mainnet"),
             Self::Testnet => write!(f, "testnet"),
             Self::Regtest => write!(f, "regtest"),
+            Self::Signet => write!(f, "signet"),
+            Self::Testnet4 => write!(f, "testnet4"),
         }
     }
 }
@@ -41,6 +47,8 @@ impl FromStr for Network {
             "mainnet" => Ok(Network::Mainnet),
             "testnet" => Ok(Network::Testnet),
             "regtest" => Ok(Network::Regtest),
+            "signet" => Ok(Network::Signet),
+            "testnet4" => Ok(Network::Testnet4),
             _ => Err("Bad network".to_string()),
         }
     }
@@ -52,6 +60,8 @@ impl From<Network> for NetworkInRequest {
             Network::Mainnet => Self::Mainnet,
             Network::Testnet => Self::Testnet,
             Network::Regtest => Self::Regtest,
+            Network::Signet => Self::Signet,
+            Network::Testnet4 => Self::Testnet4,
         }
     }
 }
@@ -65,6 +75,10 @@ impl From<NetworkInRequest> for Network {
             NetworkInRequest::testnet => Self::Testnet,
             NetworkInRequest::Regtest => Self::Regtest,
             NetworkInRequest::regtest => Self::Regtest,
+            NetworkInRequest::Signet => Self::Signet,
+            NetworkInRequest::signet => Self::Signet,
+            NetworkInRequest::Testnet4 => Self::Testnet4,
+            NetworkInRequest::testnet4 => Self::Testnet4,
         }
     }
 }
@@ -83,6 +97,12 @@ pub enum NetworkInRequest {
     Regtest,
     #[allow(non_camel_case_types)]
     regtest,
+    Signet,
+    #[allow(non_camel_case_types)]
+    signet,
+    Testnet4,
+    #[allow(non_camel_case_types)]
+    testnet4,
 }
 
 impl fmt::Display for NetworkInRequest {
@@ -94,6 +114,10 @@ impl fmt::Display for NetworkInRequest {
             Self::mainnet => write!(f, "mainnet"),
             Self::testnet => write!(f, "testnet"),
             Self::regtest => write!(f, "regtest"),
+            Self::Signet => write!(f, "signet"),
+            Self::signet => write!(f, "signet"),
+            Self::Testnet4 => write!(f, "testnet4"),
+            Self::testnet4 => write!(f, "testnet4"),
         }
     }
 }
@@ -320,12 +344,30 @@ pub enum UtxosFilterInRequest {
     page(Page),
 }
 
+/// The order in which UTXOs are returned.
+#[derive(CandidType, Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub enum UtxosSortOrder {
+    /// Most recent UTXOs first. This is the default order.
+    #[serde(rename = "height_descending")]
+    #[default]
+    HeightDescending,
+
+    /// Oldest UTXOs first.
+    #[serde(rename = "height_ascending")]
+    HeightAscending,
+
+    /// UTXOs with the largest value first.
+    #[serde(rename = "value_descending")]
+    ValueDescending,
+}
+
 /// A request for getting the UTXOs for a given address.
 #[derive(CandidType, Debug, Deserialize, PartialEq, Eq)]
 pub struct GetUtxosRequest {
     pub address: Address,
     pub network: NetworkInRequest,
     pub filter: Option<UtxosFilterInRequest>,
+    pub sort_order: Option<UtxosSortOrder>,
 }
 
 /// The response returned for a request to get the UTXOs of a given address.
@@ -344,6 +386,7 @@ pub enum GetUtxosError {
     MinConfirmationsTooLarge { given: u32, max: u32 },
     UnknownTipBlockHash { tip_block_hash: BlockHash },
     MalformedPage { err: String },
+    SortOrderUnavailable { sort_order: UtxosSortOrder },
 }
 
 /// A request for getting the current fee percentiles.
@@ -375,6 +418,13 @@ impl fmt::Display for GetUtxosError {
             Self::MalformedPage { err } => {
                 write!(f, "The provided page is malformed {}", err)
             }
+            Self::SortOrderUnavailable { sort_order } => {
+                write!(
+                    f,
+                    "The UTXOs cannot be sorted in {:?} order until its index is backfilled.",
+                    sort_order
+                )
+            }
         }
     }
 }
@@ -461,6 +511,21 @@ pub struct SetConfigRequest {
     /// The watchdog canister has the authority to disable the Bitcoin canister's API
     /// if it suspects that there is a problem.
     pub watchdog_canister: Option<Option<Principal>>,
+
+    /// The checkpoints that block headers are validated against.
+    pub checkpoints: Option<Vec<Checkpoint>>,
+
+    /// Whether or not to enable/disable the background audit of the UTXO set.
+    pub utxo_audit: Option<Flag>,
+
+    /// The intervals between rounds of syncing with the bitcoin network.
+    pub sync_intervals: Option<SyncIntervals>,
+
+    /// The limits on the memory used by the canister.
+    pub memory_limits: Option<MemoryLimits>,
+
+    /// The policy for pruning stale forks. Set to `Some(None)` to stop pruning forks.
+    pub fork_pruning: Option<Option<ForkPruning>>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug, Default)]
@@ -484,6 +549,11 @@ pub struct Config {
     /// fetched directly from the replica, and that's what is used in production.
     pub blocks_source: Principal,
 
+    /// The principals from which blocks are retrieved, in order, when the sources before
+    /// them keep rejecting requests.
+    /// If not set, blocks are only retrieved from `blocks_source`.
+    pub fallback_blocks_sources: Option<Vec<Principal>>,
+
     pub syncing: Flag,
 
     pub fees: Fees,
@@ -499,6 +569,35 @@ pub struct Config {
     /// The watchdog canister has the authority to disable the Bitcoin canister's API
     /// if it suspects that there is a problem.
     pub watchdog_canister: Option<Principal>,
+
+    /// The challenge script of a custom signet (BIP325).
+    ///
+    /// If set on signet, blocks are only accepted if the signet solution in their
+    /// coinbase satisfies this challenge. If not set, blocks are only validated by
+    /// their proof of work.
+    pub signet_challenge: Option<Vec<u8>>,
+
+    /// The checkpoints that block headers are validated against.
+    ///
+    /// Headers that fork the chain below a checkpoint are rejected.
+    /// If not set, the default checkpoints of the network are used.
+    pub checkpoints: Option<Vec<Checkpoint>>,
+
+    /// Whether or not to continuously audit the UTXO set in the background.
+    /// If not set, the audit is disabled.
+    pub utxo_audit: Option<Flag>,
+
+    /// The intervals between rounds of syncing with the bitcoin network.
+    /// If not set, the default intervals are used.
+    pub sync_intervals: Option<SyncIntervals>,
+
+    /// The limits on the memory used by the canister.
+    /// If not set, the memory isn't limited.
+    pub memory_limits: Option<MemoryLimits>,
+
+    /// The policy for pruning stale forks from the tree of unstable blocks.
+    /// If not set, forks are kept until the anchor moves past them.
+    pub fork_pruning: Option<ForkPruning>,
 }
 
 impl Default for Config {
@@ -507,15 +606,89 @@ impl Default for Config {
             stability_threshold: 0,
             network: Network::Regtest,
             blocks_source: Principal::management_canister(),
+            fallback_blocks_sources: None,
             syncing: Flag::Enabled,
             fees: Fees::default(),
             api_access: Flag::Enabled,
             disable_api_if_not_fully_synced: Flag::Enabled,
             watchdog_canister: None,
+            signet_challenge: None,
+            checkpoints: None,
+            utxo_audit: None,
+            sync_intervals: None,
+            memory_limits: None,
+            fork_pruning: None,
         }
     }
 }
 
+/// The intervals between rounds of syncing with the bitcoin network, in milliseconds.
+///
+/// A round runs right away while stable blocks are pending ingestion. Otherwise, rounds run
+/// every `catching_up_ms` while new blocks are received, and back off from `idle_min_ms` up
+/// to `idle_max_ms`, doubling after every round that doesn't receive new blocks.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct SyncIntervals {
+    pub catching_up_ms: u64,
+    pub idle_min_ms: u64,
+    pub idle_max_ms: u64,
+}
+
+impl Default for SyncIntervals {
+    fn default() -> Self {
+        Self {
+            catching_up_ms: 0,
+            idle_min_ms: 500,
+            idle_max_ms: 10_000,
+        }
+    }
+}
+
+/// The limits on the memory used by the canister.
+///
+/// Past the soft limit of any memory, the canister stops fetching new blocks, while it keeps
+/// ingesting the blocks it has already fetched. Past the hard limit of any memory, the
+/// canister's API is disabled.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct MemoryLimits {
+    /// The limits of the heap.
+    pub heap: Option<MemoryLimit>,
+
+    /// The limits of the stable memories, by the id of their `MemoryId`.
+    pub stable_memories: Vec<(u8, MemoryLimit)>,
+}
+
+/// The soft and hard limits of a memory, in bytes.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct MemoryLimit {
+    pub soft_limit_bytes: u64,
+    pub hard_limit_bytes: u64,
+}
+
+/// A policy for pruning stale forks from the tree of unstable blocks.
+///
+/// A pruned fork, along with any block that extends it, is no longer considered, so the
+/// thresholds need to be high enough for a reorganization to the fork to be implausible.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct ForkPruning {
+    /// A fork is pruned if the work of its best chain trails that of the main chain by more
+    /// than this many times the work of the anchor block.
+    pub max_work_deficit: u32,
+
+    /// A fork is pruned if more than this many blocks were added to the tree of unstable
+    /// blocks since the fork was last extended.
+    pub max_blocks_without_extension: u32,
+}
+
+/// A block hash that the chain is required to contain at a given height.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Checkpoint {
+    pub height: Height,
+
+    /// The hash of the block, in the same byte order as `tip_block_hash`.
+    pub block_hash: BlockHash,
+}
+
 #[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default)]
 pub struct Fees {
     /// The base fee to charge for all `get_utxos` requests.
@@ -549,6 +722,236 @@ pub struct Fees {
     pub send_transaction_per_byte: u128,
 }
 
+/// Errors when getting the median time past.
+#[derive(CandidType, Debug, Deserialize, PartialEq, Eq, Clone)]
+pub enum GetMedianTimePastError {
+    HeightTooLarge { given: Height, max: Height },
+}
+
+impl fmt::Display for GetMedianTimePastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HeightTooLarge { given, max } => write!(
+                f,
+                "The requested height {} is larger than the height of the tip {}.",
+                given, max
+            ),
+        }
+    }
+}
+
+/// The height of a block in the main chain and its number of confirmations.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct GetBlockHeightResponse {
+    pub height: Height,
+    pub confirmations: u32,
+}
+
+/// Errors when getting the height of a block.
+#[derive(CandidType, Debug, Deserialize, PartialEq, Eq, Clone)]
+pub enum GetBlockHeightError {
+    MalformedBlockHash,
+}
+
+impl fmt::Display for GetBlockHeightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MalformedBlockHash => write!(f, "Malformed block hash."),
+        }
+    }
+}
+
+/// Errors when invalidating a block.
+#[derive(CandidType, Debug, Deserialize, PartialEq, Eq, Clone)]
+pub enum InvalidateBlockError {
+    MalformedBlockHash,
+    /// The block isn't one of the unstable blocks.
+    UnknownBlock,
+    /// The anchor block, i.e. the latest stable block, cannot be invalidated.
+    AnchorBlock,
+}
+
+impl fmt::Display for InvalidateBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MalformedBlockHash => write!(f, "Malformed block hash."),
+            Self::UnknownBlock => write!(f, "The block isn't one of the unstable blocks."),
+            Self::AnchorBlock => write!(f, "The anchor block cannot be invalidated."),
+        }
+    }
+}
+
+/// Errors when reconsidering a block.
+#[derive(CandidType, Debug, Deserialize, PartialEq, Eq, Clone)]
+pub enum ReconsiderBlockError {
+    MalformedBlockHash,
+    /// The block wasn't invalidated.
+    NotInvalidated,
+}
+
+impl fmt::Display for ReconsiderBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MalformedBlockHash => write!(f, "Malformed block hash."),
+            Self::NotInvalidated => write!(f, "The block wasn't invalidated."),
+        }
+    }
+}
+
+/// An action that a controller took on the canister.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub enum AdminAction {
+    #[serde(rename = "invalidate_block")]
+    InvalidateBlock { block_hash: BlockHash },
+    #[serde(rename = "reconsider_block")]
+    ReconsiderBlock { block_hash: BlockHash },
+}
+
+/// An entry of the audit log of the actions that controllers took on the canister.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct AuditLogEntry {
+    /// The time of the action, in seconds since the Unix epoch.
+    pub timestamp: u64,
+
+    /// The controller that took the action.
+    pub caller: Principal,
+
+    pub action: AdminAction,
+
+    /// The error that the action failed with, if any.
+    pub error: Option<String>,
+}
+
+/// The state of a soft fork deployment, as defined in BIP9.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DeploymentState {
+    #[serde(rename = "defined")]
+    Defined,
+    #[serde(rename = "started")]
+    Started,
+    #[serde(rename = "locked_in")]
+    LockedIn,
+    #[serde(rename = "active")]
+    Active,
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+/// A soft fork deployment and its state for the block after the tip of the main chain.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Deployment {
+    pub name: String,
+
+    /// The bit of the block version that signals for the deployment.
+    pub bit: u8,
+
+    pub state: DeploymentState,
+
+    /// The height of the first block that the state applies to.
+    pub since: Height,
+}
+
+/// A manifest of the canister's stable memory, listing the memories that a snapshot of
+/// the stable memory consists of.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct StableMemoryManifest {
+    /// The size of the chunks that the memories are exported in, in bytes.
+    pub chunk_size: u64,
+
+    pub memories: Vec<StableMemoryInfo>,
+}
+
+/// The size of one of the memories in stable memory.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct StableMemoryInfo {
+    pub memory_id: u8,
+
+    /// The size of the memory, in bytes.
+    pub size: u64,
+
+    pub num_chunks: u64,
+}
+
+/// A request for a chunk of one of the memories in stable memory.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct GetStableMemoryChunkRequest {
+    pub memory_id: u8,
+    pub chunk_index: u64,
+}
+
+/// A chunk of one of the memories in stable memory.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct StableMemoryChunk {
+    #[serde(with = "serde_bytes")]
+    pub bytes: Vec<u8>,
+
+    /// The SHA-256 of the bytes.
+    #[serde(with = "serde_bytes")]
+    pub sha256: Vec<u8>,
+}
+
+/// Errors when getting a chunk of stable memory.
+#[derive(CandidType, Debug, Deserialize, PartialEq, Eq, Clone)]
+pub enum GetStableMemoryChunkError {
+    UnknownMemory,
+    ChunkIndexOutOfBounds { given: u64, num_chunks: u64 },
+}
+
+impl fmt::Display for GetStableMemoryChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownMemory => write!(f, "Unknown memory."),
+            Self::ChunkIndexOutOfBounds { given, num_chunks } => write!(
+                f,
+                "The requested chunk index {} is out of bounds. The memory has {} chunks.",
+                given, num_chunks
+            ),
+        }
+    }
+}
+
+/// The results of a round of auditing the UTXO set against its indices.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct UtxoAuditReport {
+    /// The time the round started, in seconds since the Unix epoch.
+    pub started_at: u64,
+
+    /// The time the round completed, in seconds since the Unix epoch.
+    pub completed_at: u64,
+
+    /// The number of UTXOs that were audited.
+    pub num_utxos: u64,
+
+    /// The number of addresses in the address index that were audited.
+    pub num_addresses: u64,
+
+    /// The number of UTXOs that are owned by an address but are missing from the address index.
+    pub utxos_missing_from_address_index: u64,
+
+    /// The number of UTXOs that are owned by an address but are missing from the index of
+    /// addresses by value.
+    pub utxos_missing_from_value_index: u64,
+
+    /// The number of UTXOs that are owned by an address but are missing from the index of
+    /// addresses by ascending height.
+    #[serde(default)]
+    pub utxos_missing_from_height_ascending_index: u64,
+
+    /// The number of entries in the address index that don't match a UTXO.
+    pub stale_address_index_entries: u64,
+
+    /// The number of entries in the index of addresses by value that don't match a UTXO.
+    pub stale_value_index_entries: u64,
+
+    /// The number of entries in the index of addresses by ascending height that don't match
+    /// a UTXO.
+    #[serde(default)]
+    pub stale_height_ascending_index_entries: u64,
+
+    /// The number of addresses whose balance doesn't match the sum of their UTXOs.
+    pub balance_mismatches: u64,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
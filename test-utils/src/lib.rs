@@ -1,8 +1,26 @@
 use bitcoin::{
-    secp256k1::rand::rngs::OsRng, secp256k1::Secp256k1, util::uint::Uint256, Address, Block,
-    BlockHash, BlockHeader, KeyPair, Network, OutPoint, PublicKey, Script, Transaction, TxIn,
-    TxMerkleNode, TxOut, Witness, XOnlyPublicKey,
+    blockdata::script::Builder, secp256k1::rand::rngs::OsRng, secp256k1::Secp256k1,
+    util::uint::Uint256, Address, Block, BlockHash, BlockHeader, KeyPair, Network, OutPoint,
+    PublicKey, Script, Transaction, TxIn, TxMerkleNode, TxOut, Witness, WitnessMerkleNode, Wtxid,
+    XOnlyPublicKey,
 };
+use std::{cell::RefCell, collections::HashMap};
+
+/// The block subsidy of the first blocks of the chain.
+const INITIAL_SUBSIDY: u64 = 50_0000_0000;
+
+/// The number of blocks after which the subsidy is halved on regtest, which halves the
+/// subsidy the most often of all networks.
+const REGTEST_HALVING_INTERVAL: u32 = 150;
+
+/// The prefix of the coinbase output that commits to the witnesses of a block (BIP141).
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+thread_local! {
+    // The heights of the blocks built by the `BlockBuilder`, which are needed to build
+    // coinbases that are valid as per BIP34.
+    static BLOCK_HEIGHTS: RefCell<HashMap<BlockHash, u32>> = RefCell::new(HashMap::new());
+}
 
 /// Generates a random P2PKH address.
 pub fn random_p2pkh_address(network: Network) -> Address {
@@ -55,14 +73,24 @@ impl BlockBuilder {
         self
     }
 
+    /// Builds the block.
+    ///
+    /// If the block doesn't start with a coinbase transaction, a random coinbase that's valid
+    /// at the height of the block, and that commits to the witnesses of the block if there are
+    /// any, is added to it. The height of a block is only known if its predecessor is a genesis
+    /// block or was built by a `BlockBuilder` of the same thread.
     pub fn build(self) -> Block {
-        let txdata = if self.transactions.is_empty() {
-            // Create a random coinbase transaction.
-            vec![TransactionBuilder::coinbase().build()]
-        } else {
-            self.transactions
+        let height = match &self.prev_header {
+            Some(prev_header) => prev_height(prev_header).map(|height| height + 1),
+            None => Some(0),
         };
 
+        let mut txdata = self.transactions;
+        if !txdata.first().map_or(false, Transaction::is_coin_base) {
+            txdata.insert(0, coinbase(height));
+            commit_witnesses(&mut txdata);
+        }
+
         let merkle_root =
             bitcoin::util::hash::bitcoin_merkle_root(txdata.iter().map(|tx| tx.txid().as_hash()))
                 .unwrap();
@@ -73,10 +101,69 @@ impl BlockBuilder {
             None => genesis(merkle_root),
         };
 
+        if let Some(height) = height {
+            BLOCK_HEIGHTS.with(|heights| heights.borrow_mut().insert(header.block_hash(), height));
+        }
+
         Block { header, txdata }
     }
 }
 
+// Returns the height of the block with the given header, if it's known.
+fn prev_height(prev_header: &BlockHeader) -> Option<u32> {
+    if prev_header.prev_blockhash == BlockHash::default() {
+        // A genesis block.
+        return Some(0);
+    }
+
+    BLOCK_HEIGHTS.with(|heights| heights.borrow().get(&prev_header.block_hash()).cloned())
+}
+
+// Returns a random coinbase transaction that claims the block subsidy at the given height.
+// The coinbase starts with the height as specified in BIP34, if the height is known.
+fn coinbase(height: Option<u32>) -> Transaction {
+    let address = random_p2pkh_address(Network::Regtest);
+    match height {
+        Some(height) => TransactionBuilder::coinbase()
+            .with_height(height)
+            .with_output(
+                &address,
+                INITIAL_SUBSIDY >> (height / REGTEST_HALVING_INTERVAL).min(63),
+            )
+            .build(),
+        None => TransactionBuilder::coinbase()
+            .with_output(&address, INITIAL_SUBSIDY)
+            .build(),
+    }
+}
+
+// Adds a witness commitment to the coinbase if any of the transactions has witness data.
+fn commit_witnesses(txdata: &mut [Transaction]) {
+    let has_witness = txdata
+        .iter()
+        .any(|tx| tx.input.iter().any(|input| !input.witness.is_empty()));
+    if !has_witness {
+        return;
+    }
+
+    // The coinbase's wtxid is zero in the witness root, so it doesn't matter that the coinbase
+    // is updated after the witness root is computed.
+    let wtxids = txdata.iter().enumerate().map(|(i, tx)| match i {
+        0 => Wtxid::default().as_hash(),
+        _ => tx.wtxid().as_hash(),
+    });
+    let witness_root =
+        WitnessMerkleNode::from_hash(bitcoin::util::hash::bitcoin_merkle_root(wtxids).unwrap());
+    let witness_reserved_value = [0; 32];
+    let commitment = Block::compute_witness_commitment(&witness_root, &witness_reserved_value);
+
+    txdata[0].input[0].witness = Witness::from_vec(vec![witness_reserved_value.to_vec()]);
+    txdata[0].output.push(TxOut {
+        value: 0,
+        script_pubkey: Script::from([&WITNESS_COMMITMENT_HEADER[..], &commitment[..]].concat()),
+    });
+}
+
 fn genesis(merkle_root: TxMerkleNode) -> BlockHeader {
     let target = Uint256([
         0xffffffffffffffffu64,
@@ -123,7 +210,7 @@ impl TransactionBuilder {
     }
 
     pub fn with_input(mut self, previous_output: OutPoint, witness: Option<Witness>) -> Self {
-        if self.input == vec![coinbase_input()] {
+        if self.is_coinbase() {
             panic!("A call `with_input` should not be possible if `coinbase` was called");
         }
 
@@ -146,11 +233,25 @@ impl TransactionBuilder {
         self
     }
 
+    /// Starts the coinbase with the given block height, as specified in BIP34.
+    pub fn with_height(mut self, height: u32) -> Self {
+        if !self.is_coinbase() {
+            panic!("A call `with_height` is only possible if `coinbase` was called");
+        }
+
+        self.input[0].script_sig = Builder::new().push_int(height as i64).into_script();
+        self
+    }
+
     pub fn with_lock_time(mut self, time: u32) -> Self {
         self.lock_time = time;
         self
     }
 
+    fn is_coinbase(&self) -> bool {
+        self.input.len() == 1 && self.input[0].previous_output == OutPoint::null()
+    }
+
     pub fn build(self) -> Transaction {
         let input = if self.input.is_empty() {
             // Default to coinbase if no inputs provided.
@@ -247,6 +348,19 @@ mod test {
                 .with_input(bitcoin::OutPoint::new(coinbase_tx.txid(), 0), None);
         }
 
+        #[test]
+        fn with_height() {
+            let tx = TransactionBuilder::coinbase().with_height(1).build();
+            assert!(tx.is_coin_base());
+            assert_eq!(tx.input[0].script_sig.as_bytes(), &[0x51]);
+        }
+
+        #[test]
+        #[should_panic(expected = "A call `with_height` is only possible if `coinbase` was called")]
+        fn with_height_panic() {
+            TransactionBuilder::new().with_height(1);
+        }
+
         #[test]
         fn with_output() {
             let address = random_p2pkh_address(Network::Regtest);
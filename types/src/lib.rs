@@ -1,12 +1,10 @@
 //! Types used across crates.
 //! NOTE: These types are _not_ part of the interface.
 
-use bitcoin::{
-    util::uint::Uint256, Block as BitcoinBlock, Network as BitcoinNetwork,
-    OutPoint as BitcoinOutPoint,
-};
+use bitcoin::{util::uint::Uint256, Block as BitcoinBlock, OutPoint as BitcoinOutPoint};
 use candid::CandidType;
 use ic_btc_interface::{Network, Txid as PublicTxid};
+use ic_btc_validation::Network as ValidationNetwork;
 use ic_stable_structures::{BoundedStorable, Storable};
 use serde::{Deserialize, Serialize};
 use std::{borrow::Cow, cell::RefCell, str::FromStr};
@@ -41,6 +39,11 @@ impl Block {
         &self.block.header
     }
 
+    /// Returns the underlying `bitcoin::Block`.
+    pub fn internal_bitcoin_block(&self) -> &BitcoinBlock {
+        &self.block
+    }
+
     pub fn block_hash(&self) -> BlockHash {
         self.block_hash
             .borrow_mut()
@@ -61,6 +64,17 @@ impl Block {
         Self::target_difficulty(network, self.header().target())
     }
 
+    /// Returns the work of the block, i.e. the expected number of hashes needed to mine it.
+    pub fn work(&self) -> Uint256 {
+        // Mocked blocks have a work equal to their mocked difficulty.
+        #[cfg(feature = "mock_difficulty")]
+        if let Some(difficulty) = self.mock_difficulty {
+            return Uint256::from_u64(difficulty).unwrap();
+        }
+
+        self.header().work()
+    }
+
     pub fn consensus_encode(&self, buffer: &mut Vec<u8>) -> Result<usize, std::io::Error> {
         use bitcoin::consensus::Encodable;
         self.block.consensus_encode(buffer)
@@ -70,7 +84,7 @@ impl Block {
     // The definition here corresponds to what is referred as "bdiff" in
     // https://en.bitcoin.it/wiki/Difficulty
     pub fn target_difficulty(network: Network, target: Uint256) -> u64 {
-        (ic_btc_validation::max_target(&into_bitcoin_network(network)) / target).low_u64()
+        (ic_btc_validation::max_target(&into_validation_network(network)) / target).low_u64()
     }
 }
 
@@ -246,6 +260,14 @@ impl From<bitcoin::BlockHash> for BlockHash {
     }
 }
 
+impl From<&BlockHash> for bitcoin::BlockHash {
+    fn from(block_hash: &BlockHash) -> Self {
+        use bitcoin::hashes::Hash;
+
+        Self::from_hash(Hash::from_slice(&block_hash.0).expect("block hash must be valid"))
+    }
+}
+
 impl FromStr for BlockHash {
     type Err = String;
 
@@ -278,11 +300,13 @@ impl std::fmt::Debug for BlockHash {
     }
 }
 
-fn into_bitcoin_network(network: Network) -> BitcoinNetwork {
+fn into_validation_network(network: Network) -> ValidationNetwork {
     match network {
-        Network::Mainnet => BitcoinNetwork::Bitcoin,
-        Network::Testnet => BitcoinNetwork::Testnet,
-        Network::Regtest => BitcoinNetwork::Regtest,
+        Network::Mainnet => ValidationNetwork::Bitcoin,
+        Network::Testnet => ValidationNetwork::Testnet,
+        Network::Regtest => ValidationNetwork::Regtest,
+        Network::Signet => ValidationNetwork::Signet,
+        Network::Testnet4 => ValidationNetwork::Testnet4,
     }
 }
 
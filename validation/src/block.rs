@@ -0,0 +1,550 @@
+//! Structural and contextual validation of the transactions in a block.
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin::{
+    blockdata::script::Builder, hash_types::WitnessCommitment, hashes::Hash,
+    util::hash::bitcoin_merkle_root, Block, OutPoint, Transaction, TxMerkleNode, Txid,
+};
+
+use crate::{
+    constants::{
+        bip34_height, segwit_height, subsidy_halving_interval, INITIAL_SUBSIDY, MAX_BLOCK_WEIGHT,
+        MIN_WITNESS_COMMITMENT_LENGTH, WITNESS_COMMITMENT_HEADER,
+    },
+    BlockHeight, Network,
+};
+
+/// An error thrown when trying to validate a block.
+#[derive(Debug, PartialEq)]
+pub enum ValidateBlockError {
+    /// Used when the block doesn't contain any transactions.
+    NoTransactions,
+    /// Used when the first transaction of the block isn't a coinbase.
+    FirstTransactionIsNotCoinbase,
+    /// Used when a transaction other than the first one is a coinbase.
+    MultipleCoinbases,
+    /// Used when the merkle root in the header doesn't match the transactions.
+    InvalidMerkleRoot,
+    /// Used when the same txid appears more than once in the block.
+    DuplicateTransaction(Txid),
+    /// Used when the weight of the block exceeds the maximum allowed.
+    BlockWeightTooHigh { weight: usize, max_weight: usize },
+    /// Used when the coinbase doesn't start with the height of the block as
+    /// specified in BIP34.
+    InvalidCoinbaseHeight { height: BlockHeight },
+    /// Used when the witness commitment in the coinbase doesn't match the
+    /// witness data of the transactions.
+    InvalidWitnessCommitment,
+    /// Used when transactions have witness data, but the block either doesn't
+    /// have a witness commitment or segwit isn't active yet.
+    UnexpectedWitness,
+    /// Used when an input spends an outpoint that cannot be found.
+    InputNotFound(OutPoint),
+    /// Used when the outputs of a transaction are worth more than its inputs.
+    OutputsExceedInputs(Txid),
+    /// Used when the coinbase claims more than the block subsidy plus the fees.
+    CoinbaseValueTooHigh { value: u128, max_value: u128 },
+}
+
+pub trait TxOutStore {
+    /// Returns the value of the transaction output referenced by the given outpoint,
+    /// or `None` if the outpoint isn't known to the store.
+    fn get_tx_out_value(&self, outpoint: &OutPoint) -> Option<u64>;
+}
+
+/// Validates the transactions of a block that is to be inserted at the given height.
+/// The header of the block is assumed to have been validated already. If a failure
+/// occurs, a [ValidateBlockError](ValidateBlockError) will be returned.
+pub fn validate_block(
+    network: &Network,
+    store: &impl TxOutStore,
+    block: &Block,
+    height: BlockHeight,
+) -> Result<(), ValidateBlockError> {
+    validate_coinbase_position(block)?;
+
+    // Txids are computed once as they're needed by multiple checks.
+    let txids: Vec<Txid> = block.txdata.iter().map(Transaction::txid).collect();
+
+    validate_merkle_root(block, &txids)?;
+    validate_no_duplicates(&txids)?;
+    validate_weight(block)?;
+    validate_coinbase_height(network, block, height)?;
+    validate_witness_commitment(block, height >= segwit_height(network))?;
+    validate_coinbase_value(network, store, block, &txids, height)
+}
+
+/// Checks that the first transaction, and only the first one, is a coinbase.
+fn validate_coinbase_position(block: &Block) -> Result<(), ValidateBlockError> {
+    match block.txdata.first() {
+        None => return Err(ValidateBlockError::NoTransactions),
+        Some(tx) if !tx.is_coin_base() => {
+            return Err(ValidateBlockError::FirstTransactionIsNotCoinbase)
+        }
+        Some(_) => {}
+    }
+
+    if block.txdata.iter().skip(1).any(Transaction::is_coin_base) {
+        return Err(ValidateBlockError::MultipleCoinbases);
+    }
+
+    Ok(())
+}
+
+fn validate_merkle_root(block: &Block, txids: &[Txid]) -> Result<(), ValidateBlockError> {
+    let merkle_root =
+        bitcoin_merkle_root(txids.iter().map(|txid| txid.as_hash())).map(TxMerkleNode::from_hash);
+
+    if merkle_root != Some(block.header.merkle_root) {
+        return Err(ValidateBlockError::InvalidMerkleRoot);
+    }
+
+    Ok(())
+}
+
+/// Checks that no txid appears twice in the block. Besides being invalid on its own,
+/// duplicate transactions can be used to produce a different block with the same
+/// merkle root (CVE-2012-2459).
+fn validate_no_duplicates(txids: &[Txid]) -> Result<(), ValidateBlockError> {
+    let mut seen = BTreeSet::new();
+    for txid in txids {
+        if !seen.insert(txid) {
+            return Err(ValidateBlockError::DuplicateTransaction(*txid));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_weight(block: &Block) -> Result<(), ValidateBlockError> {
+    let weight = block.weight();
+    if weight > MAX_BLOCK_WEIGHT {
+        return Err(ValidateBlockError::BlockWeightTooHigh {
+            weight,
+            max_weight: MAX_BLOCK_WEIGHT,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that the coinbase's script starts with the serialized block height.
+/// See https://github.com/bitcoin/bips/blob/master/bip-0034.mediawiki
+fn validate_coinbase_height(
+    network: &Network,
+    block: &Block,
+    height: BlockHeight,
+) -> Result<(), ValidateBlockError> {
+    if height < bip34_height(network) {
+        return Ok(());
+    }
+
+    let expected_prefix = Builder::new().push_int(height as i64).into_script();
+    let script_sig = block.txdata[0].input[0].script_sig.as_bytes();
+    if !script_sig.starts_with(expected_prefix.as_bytes()) {
+        return Err(ValidateBlockError::InvalidCoinbaseHeight { height });
+    }
+
+    Ok(())
+}
+
+/// Checks the witness commitment as specified in BIP141. The commitment is optional
+/// as long as none of the transactions have witness data.
+fn validate_witness_commitment(
+    block: &Block,
+    segwit_active: bool,
+) -> Result<(), ValidateBlockError> {
+    let coinbase = &block.txdata[0];
+
+    // The commitment is in the last output that starts with the commitment header.
+    // It is ignored before segwit is active.
+    let commitment_output = if segwit_active {
+        coinbase.output.iter().rev().find(|output| {
+            output.script_pubkey.len() >= MIN_WITNESS_COMMITMENT_LENGTH
+                && output.script_pubkey[0..WITNESS_COMMITMENT_HEADER.len()]
+                    == WITNESS_COMMITMENT_HEADER
+        })
+    } else {
+        None
+    };
+
+    if let Some(output) = commitment_output {
+        // The witness reserved value must be the only item in the coinbase's witness.
+        let witness: Vec<&[u8]> = coinbase.input[0].witness.iter().collect();
+        if witness.len() != 1 || witness[0].len() != 32 {
+            return Err(ValidateBlockError::InvalidWitnessCommitment);
+        }
+
+        let witness_root = block
+            .witness_root()
+            .expect("a block with transactions must have a witness root");
+        let commitment = WitnessCommitment::from_slice(
+            &output.script_pubkey[WITNESS_COMMITMENT_HEADER.len()..MIN_WITNESS_COMMITMENT_LENGTH],
+        )
+        .expect("slice must be 32 bytes");
+        if commitment != Block::compute_witness_commitment(&witness_root, witness[0]) {
+            return Err(ValidateBlockError::InvalidWitnessCommitment);
+        }
+
+        return Ok(());
+    }
+
+    let has_witness = block
+        .txdata
+        .iter()
+        .any(|tx| tx.input.iter().any(|input| !input.witness.is_empty()));
+    if has_witness {
+        return Err(ValidateBlockError::UnexpectedWitness);
+    }
+
+    Ok(())
+}
+
+/// Checks that the coinbase doesn't claim more than the block subsidy plus the fees
+/// of the block's transactions.
+fn validate_coinbase_value(
+    network: &Network,
+    store: &impl TxOutStore,
+    block: &Block,
+    txids: &[Txid],
+    height: BlockHeight,
+) -> Result<(), ValidateBlockError> {
+    // The outputs of the block's transactions, which may be spent by later
+    // transactions in the same block.
+    let mut block_tx_outs: BTreeMap<OutPoint, u64> = BTreeMap::new();
+    let mut fees: u128 = 0;
+
+    for (tx, txid) in block.txdata.iter().zip(txids).skip(1) {
+        let mut input_value: u128 = 0;
+        for input in tx.input.iter() {
+            let value = match block_tx_outs.get(&input.previous_output) {
+                Some(value) => *value,
+                None => store
+                    .get_tx_out_value(&input.previous_output)
+                    .ok_or(ValidateBlockError::InputNotFound(input.previous_output))?,
+            };
+            input_value += value as u128;
+        }
+
+        let output_value = sum_outputs(tx);
+        if output_value > input_value {
+            return Err(ValidateBlockError::OutputsExceedInputs(*txid));
+        }
+        fees += input_value - output_value;
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            block_tx_outs.insert(
+                OutPoint {
+                    txid: *txid,
+                    vout: vout as u32,
+                },
+                output.value,
+            );
+        }
+    }
+
+    let max_value = subsidy(network, height) as u128 + fees;
+    let value = sum_outputs(&block.txdata[0]);
+    if value > max_value {
+        return Err(ValidateBlockError::CoinbaseValueTooHigh { value, max_value });
+    }
+
+    Ok(())
+}
+
+fn sum_outputs(tx: &Transaction) -> u128 {
+    tx.output.iter().map(|output| output.value as u128).sum()
+}
+
+/// Returns the block subsidy at the given height.
+fn subsidy(network: &Network, height: BlockHeight) -> u64 {
+    let halvings = height / subsidy_halving_interval(network);
+    if halvings >= 64 {
+        return 0;
+    }
+
+    INITIAL_SUBSIDY >> halvings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::{
+        blockdata::constants::genesis_block, Script, TxIn, TxOut, Witness, WitnessMerkleNode,
+    };
+
+    /// A `TxOutStore` backed by a map from outpoints to values.
+    #[derive(Default)]
+    struct SimpleTxOutStore(BTreeMap<OutPoint, u64>);
+
+    impl TxOutStore for SimpleTxOutStore {
+        fn get_tx_out_value(&self, outpoint: &OutPoint) -> Option<u64> {
+            self.0.get(outpoint).cloned()
+        }
+    }
+
+    fn coinbase(height: BlockHeight, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(height as i64).into_script(),
+                sequence: 0xffffffff,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
+    fn spend(previous_output: OutPoint, value: u64) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: Script::new(),
+                sequence: 0xffffffff,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
+    /// Builds a block with the given transactions and a matching merkle root.
+    fn block(txdata: Vec<Transaction>) -> Block {
+        let mut block = genesis_block(bitcoin::Network::Regtest);
+        block.txdata = txdata;
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
+
+    /// Adds a witness commitment to the coinbase of the block.
+    fn commit_witnesses(mut block: Block) -> Block {
+        block.txdata[0].input[0].witness = Witness::from_vec(vec![vec![0; 32]]);
+        let witness_root: WitnessMerkleNode = block.witness_root().unwrap();
+        let commitment = Block::compute_witness_commitment(&witness_root, &[0; 32]);
+        block.txdata[0].output.push(TxOut {
+            value: 0,
+            script_pubkey: Script::from(
+                [
+                    WITNESS_COMMITMENT_HEADER.to_vec(),
+                    commitment.into_inner().to_vec(),
+                ]
+                .concat(),
+            ),
+        });
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
+
+    fn outpoint(seed: u8) -> OutPoint {
+        OutPoint {
+            txid: Txid::from_slice(&[seed; 32]).unwrap(),
+            vout: 0,
+        }
+    }
+
+    #[test]
+    fn accepts_genesis_blocks() {
+        for network in [
+            bitcoin::Network::Bitcoin,
+            bitcoin::Network::Testnet,
+            bitcoin::Network::Signet,
+            bitcoin::Network::Regtest,
+        ] {
+            assert_eq!(
+                validate_block(
+                    &network.into(),
+                    &SimpleTxOutStore::default(),
+                    &genesis_block(network),
+                    0
+                ),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_block_with_fees() {
+        let store = SimpleTxOutStore(vec![(outpoint(1), 1_000)].into_iter().collect());
+        let tx_1 = spend(outpoint(1), 900);
+        let tx_2 = spend(
+            OutPoint {
+                txid: tx_1.txid(),
+                vout: 0,
+            },
+            850,
+        );
+
+        // The coinbase can claim the subsidy and the fees of both transactions.
+        let block = block(vec![coinbase(1, INITIAL_SUBSIDY + 150), tx_1, tx_2]);
+        assert_eq!(validate_block(&Network::Regtest, &store, &block, 1), Ok(()));
+    }
+
+    #[test]
+    fn rejects_block_without_transactions() {
+        let mut block = genesis_block(bitcoin::Network::Regtest);
+        block.txdata = vec![];
+        assert_eq!(
+            validate_block(&Network::Regtest, &SimpleTxOutStore::default(), &block, 1),
+            Err(ValidateBlockError::NoTransactions)
+        );
+    }
+
+    #[test]
+    fn rejects_misplaced_coinbases() {
+        let store = SimpleTxOutStore(vec![(outpoint(1), 1_000)].into_iter().collect());
+
+        let block_1 = block(vec![
+            spend(outpoint(1), 1_000),
+            coinbase(1, INITIAL_SUBSIDY),
+        ]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &store, &block_1, 1),
+            Err(ValidateBlockError::FirstTransactionIsNotCoinbase)
+        );
+
+        let block_2 = block(vec![coinbase(1, INITIAL_SUBSIDY), coinbase(2, 0)]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &store, &block_2, 1),
+            Err(ValidateBlockError::MultipleCoinbases)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_merkle_root() {
+        let mut block = block(vec![coinbase(1, INITIAL_SUBSIDY)]);
+        block.header.merkle_root = TxMerkleNode::from_slice(&[1; 32]).unwrap();
+        assert_eq!(
+            validate_block(&Network::Regtest, &SimpleTxOutStore::default(), &block, 1),
+            Err(ValidateBlockError::InvalidMerkleRoot)
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_transactions() {
+        let store = SimpleTxOutStore(vec![(outpoint(1), 1_000)].into_iter().collect());
+        let tx = spend(outpoint(1), 1_000);
+        let block = block(vec![coinbase(1, INITIAL_SUBSIDY), tx.clone(), tx.clone()]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &store, &block, 1),
+            Err(ValidateBlockError::DuplicateTransaction(tx.txid()))
+        );
+    }
+
+    #[test]
+    fn rejects_block_above_max_weight() {
+        let mut coinbase = coinbase(1, INITIAL_SUBSIDY);
+        coinbase.output[0].script_pubkey = Script::from(vec![0; MAX_BLOCK_WEIGHT / 4]);
+        let block = block(vec![coinbase]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &SimpleTxOutStore::default(), &block, 1),
+            Err(ValidateBlockError::BlockWeightTooHigh {
+                weight: block.weight(),
+                max_weight: MAX_BLOCK_WEIGHT
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_coinbase_height() {
+        let block = block(vec![coinbase(2, INITIAL_SUBSIDY)]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &SimpleTxOutStore::default(), &block, 3),
+            Err(ValidateBlockError::InvalidCoinbaseHeight { height: 3 })
+        );
+
+        // The height isn't checked before BIP34 is active.
+        assert_eq!(
+            validate_block(&Network::Bitcoin, &SimpleTxOutStore::default(), &block, 3),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn checks_witness_commitment() {
+        let store = SimpleTxOutStore(vec![(outpoint(1), 1_000)].into_iter().collect());
+        let mut tx = spend(outpoint(1), 1_000);
+        tx.input[0].witness = Witness::from_vec(vec![vec![1, 2, 3]]);
+
+        let block_without_commitment = block(vec![coinbase(1, INITIAL_SUBSIDY), tx.clone()]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &store, &block_without_commitment, 1),
+            Err(ValidateBlockError::UnexpectedWitness)
+        );
+
+        let valid_block = commit_witnesses(block_without_commitment);
+        assert_eq!(
+            validate_block(&Network::Regtest, &store, &valid_block, 1),
+            Ok(())
+        );
+
+        // Modifying the witness data invalidates the commitment.
+        let mut invalid_block = valid_block.clone();
+        invalid_block.txdata[1].input[0].witness = Witness::from_vec(vec![vec![4, 5, 6]]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &store, &invalid_block, 1),
+            Err(ValidateBlockError::InvalidWitnessCommitment)
+        );
+
+        // Witness data isn't allowed before segwit is active, even with a commitment.
+        assert_eq!(
+            validate_block(&Network::Testnet, &store, &valid_block, 1),
+            Err(ValidateBlockError::UnexpectedWitness)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_inputs() {
+        let block = block(vec![
+            coinbase(1, INITIAL_SUBSIDY),
+            spend(outpoint(1), 1_000),
+        ]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &SimpleTxOutStore::default(), &block, 1),
+            Err(ValidateBlockError::InputNotFound(outpoint(1)))
+        );
+    }
+
+    #[test]
+    fn rejects_outputs_exceeding_inputs() {
+        let store = SimpleTxOutStore(vec![(outpoint(1), 1_000)].into_iter().collect());
+        let tx = spend(outpoint(1), 1_001);
+        let block = block(vec![coinbase(1, INITIAL_SUBSIDY), tx.clone()]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &store, &block, 1),
+            Err(ValidateBlockError::OutputsExceedInputs(tx.txid()))
+        );
+    }
+
+    #[test]
+    fn rejects_coinbase_above_subsidy_and_fees() {
+        let store = SimpleTxOutStore(vec![(outpoint(1), 1_000)].into_iter().collect());
+        let block = block(vec![
+            coinbase(1, INITIAL_SUBSIDY + 101),
+            spend(outpoint(1), 900),
+        ]);
+        assert_eq!(
+            validate_block(&Network::Regtest, &store, &block, 1),
+            Err(ValidateBlockError::CoinbaseValueTooHigh {
+                value: INITIAL_SUBSIDY as u128 + 101,
+                max_value: INITIAL_SUBSIDY as u128 + 100,
+            })
+        );
+    }
+
+    #[test]
+    fn subsidy_halves() {
+        assert_eq!(subsidy(&Network::Bitcoin, 209_999), INITIAL_SUBSIDY);
+        assert_eq!(subsidy(&Network::Bitcoin, 210_000), INITIAL_SUBSIDY / 2);
+        assert_eq!(subsidy(&Network::Regtest, 150), INITIAL_SUBSIDY / 2);
+        assert_eq!(subsidy(&Network::Bitcoin, 64 * 210_000), 0);
+    }
+}
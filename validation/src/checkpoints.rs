@@ -0,0 +1,126 @@
+use bitcoin::{BlockHash, BlockHeader};
+use std::str::FromStr;
+
+use crate::{header::HeaderStore, BlockHeight, Network, ValidateHeaderError};
+
+/// A block hash that the chain is required to contain at a given height.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub height: BlockHeight,
+    pub hash: BlockHash,
+}
+
+// The checkpoints of mainnet, as they were hard-coded in bitcoind.
+const MAINNET_CHECKPOINTS: &[(BlockHeight, &str)] = &[
+    (
+        11_111,
+        "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d",
+    ),
+    (
+        33_333,
+        "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6",
+    ),
+    (
+        74_000,
+        "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20",
+    ),
+    (
+        105_000,
+        "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97",
+    ),
+    (
+        134_444,
+        "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe",
+    ),
+    (
+        168_000,
+        "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763",
+    ),
+    (
+        193_000,
+        "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317",
+    ),
+    (
+        210_000,
+        "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e",
+    ),
+    (
+        216_116,
+        "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e",
+    ),
+    (
+        225_430,
+        "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932",
+    ),
+    (
+        250_000,
+        "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214",
+    ),
+    (
+        279_000,
+        "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40",
+    ),
+    (
+        295_000,
+        "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983",
+    ),
+];
+
+// The checkpoints of testnet, as they were hard-coded in bitcoind.
+const TESTNET_CHECKPOINTS: &[(BlockHeight, &str)] = &[(
+    546,
+    "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70",
+)];
+
+/// Returns the default checkpoints of the given network, sorted by height.
+pub fn default_checkpoints(network: &Network) -> Vec<Checkpoint> {
+    let checkpoints = match network {
+        Network::Bitcoin => MAINNET_CHECKPOINTS,
+        Network::Testnet => TESTNET_CHECKPOINTS,
+        Network::Testnet4 | Network::Signet | Network::Regtest => &[],
+    };
+
+    checkpoints
+        .iter()
+        .map(|(height, hash)| Checkpoint {
+            height: *height,
+            hash: BlockHash::from_str(hash).expect("checkpoint hash must be valid"),
+        })
+        .collect()
+}
+
+/// Validates that the given header doesn't fork the chain below a checkpoint.
+///
+/// The latest checkpoint at or below the header's height is checked against the
+/// header itself, or against the header's ancestor at the checkpoint's height.
+/// Ancestors that aren't available in the store are assumed to be valid.
+pub(crate) fn is_checkpoint_valid(
+    checkpoints: &[Checkpoint],
+    store: &impl HeaderStore,
+    header: &BlockHeader,
+) -> Result<(), ValidateHeaderError> {
+    let height = store.height() + 1;
+    let checkpoint = match checkpoints
+        .iter()
+        .filter(|checkpoint| checkpoint.height <= height)
+        .max_by_key(|checkpoint| checkpoint.height)
+    {
+        Some(checkpoint) => checkpoint,
+        None => return Ok(()),
+    };
+
+    let hash = if checkpoint.height == height {
+        Some(header.block_hash())
+    } else {
+        store
+            .get_with_height(checkpoint.height)
+            .map(|ancestor| ancestor.block_hash())
+    };
+
+    match hash {
+        Some(hash) if hash != checkpoint.hash => Err(ValidateHeaderError::ForkBelowCheckpoint {
+            checkpoint_height: checkpoint.height,
+        }),
+        _ => Ok(()),
+    }
+}
@@ -1,6 +1,6 @@
-use bitcoin::{util::uint::Uint256, Network};
+use bitcoin::util::uint::Uint256;
 
-use crate::BlockHeight;
+use crate::{BlockHeight, Network};
 
 /// Expected number of blocks for 2 weeks (2_016).
 pub const DIFFICULTY_ADJUSTMENT_INTERVAL: BlockHeight = 6 * 24 * 14;
@@ -8,6 +8,23 @@ pub const DIFFICULTY_ADJUSTMENT_INTERVAL: BlockHeight = 6 * 24 * 14;
 /// Needed to help test check for the 20 minute testnet/regtest rule
 pub const TEN_MINUTES: u32 = 60 * 10;
 
+/// The maximum number of seconds that the first block of a difficulty adjustment
+/// interval can be behind its predecessor when BIP94 is enforced.
+pub const MAX_TIMEWARP: u32 = 600;
+
+/// The maximum weight of a block as specified in BIP141.
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+
+/// The subsidy of the blocks before the first halving (50 BTC).
+pub const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+
+/// The prefix of the witness commitment output's script: OP_RETURN, a push of 36 bytes,
+/// and the witness commitment header.
+pub const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// The minimum length of the witness commitment output's script.
+pub const MIN_WITNESS_COMMITMENT_LENGTH: usize = 38;
+
 /// Bitcoin mainnet maximum target value
 const BITCOIN_MAX_TARGET: Uint256 = Uint256([
     0x0000000000000000,
@@ -24,6 +41,14 @@ const TESTNET_MAX_TARGET: Uint256 = Uint256([
     0x00000000ffff0000,
 ]);
 
+/// Bitcoin testnet4 maximum target value
+const TESTNET4_MAX_TARGET: Uint256 = Uint256([
+    0x0000000000000000,
+    0x0000000000000000,
+    0x0000000000000000,
+    0x00000000ffff0000,
+]);
+
 /// Bitcoin regtest maximum target value
 const REGTEST_MAX_TARGET: Uint256 = Uint256([
     0x0000000000000000,
@@ -45,6 +70,7 @@ pub fn max_target(network: &Network) -> Uint256 {
     match network {
         Network::Bitcoin => BITCOIN_MAX_TARGET,
         Network::Testnet => TESTNET_MAX_TARGET,
+        Network::Testnet4 => TESTNET4_MAX_TARGET,
         Network::Regtest => REGTEST_MAX_TARGET,
         Network::Signet => SIGNET_MAX_TARGET,
     }
@@ -54,21 +80,61 @@ pub fn max_target(network: &Network) -> Uint256 {
 /// readjusted in the network after a fixed time interval.
 pub fn no_pow_retargeting(network: &Network) -> bool {
     match network {
-        Network::Bitcoin | Network::Testnet | Network::Signet => false,
+        Network::Bitcoin | Network::Testnet | Network::Testnet4 | Network::Signet => false,
         Network::Regtest => true,
     }
 }
 
+/// Returns true iff the network enforces the BIP94 rules, i.e. the timewarp fix
+/// and computing the next difficulty from the first block of the adjustment
+/// interval rather than the last one.
+/// See https://github.com/bitcoin/bips/blob/master/bip-0094.mediawiki
+pub fn enforce_bip94(network: &Network) -> bool {
+    match network {
+        Network::Testnet4 => true,
+        Network::Bitcoin | Network::Testnet | Network::Signet | Network::Regtest => false,
+    }
+}
+
 /// Returns the PoW limit bits of the bitcoin network
 pub fn pow_limit_bits(network: &Network) -> u32 {
     match network {
         Network::Bitcoin => 0x1d00ffff,
         Network::Testnet => 0x1d00ffff,
+        Network::Testnet4 => 0x1d00ffff,
         Network::Regtest => 0x207fffff,
         Network::Signet => 0x1e0377ae,
     }
 }
 
+/// Returns the number of blocks after which the block subsidy is halved.
+pub fn subsidy_halving_interval(network: &Network) -> BlockHeight {
+    match network {
+        Network::Bitcoin | Network::Testnet | Network::Testnet4 | Network::Signet => 210_000,
+        Network::Regtest => 150,
+    }
+}
+
+/// Returns the height from which the coinbase must start with the block height
+/// as specified in BIP34.
+pub fn bip34_height(network: &Network) -> BlockHeight {
+    match network {
+        Network::Bitcoin => 227_931,
+        Network::Testnet => 21_111,
+        Network::Testnet4 | Network::Signet | Network::Regtest => 1,
+    }
+}
+
+/// Returns the height from which segwit (BIP141) is enforced.
+pub fn segwit_height(network: &Network) -> BlockHeight {
+    match network {
+        Network::Bitcoin => 481_824,
+        Network::Testnet => 834_624,
+        Network::Testnet4 | Network::Signet => 1,
+        Network::Regtest => 0,
+    }
+}
+
 #[cfg(test)]
 pub mod test {
     /// Mainnet 000000000000000000063108ecc1f03f7fd1481eb20f97307d532a612bc97f04
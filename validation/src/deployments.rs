@@ -0,0 +1,494 @@
+use crate::{header::HeaderStore, BlockHeight, Network};
+
+/// The start time of a deployment that is always active.
+pub const ALWAYS_ACTIVE: i64 = -1;
+
+/// The start time of a deployment that is never active.
+pub const NEVER_ACTIVE: i64 = -2;
+
+/// The timeout of a deployment that never times out.
+pub const NO_TIMEOUT: i64 = i64::MAX;
+
+// The top bits of a block version that signals for BIP9 deployments.
+const VERSION_BITS_TOP_BITS: u32 = 0x2000_0000;
+const VERSION_BITS_TOP_MASK: u32 = 0xE000_0000;
+
+/// A soft fork that is deployed using BIP9 version bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deployment {
+    pub name: &'static str,
+
+    /// The bit of the block version that signals for the deployment.
+    pub bit: u8,
+
+    /// The median time past from which blocks can signal for the deployment,
+    /// or one of `ALWAYS_ACTIVE` and `NEVER_ACTIVE`.
+    pub start_time: i64,
+
+    /// The median time past from which the deployment fails if it isn't locked in.
+    pub timeout: i64,
+
+    /// The number of blocks in a period that need to signal for the deployment to lock in.
+    pub threshold: u32,
+
+    /// The minimum height at which a locked in deployment becomes active.
+    pub min_activation_height: BlockHeight,
+}
+
+/// The state of a deployment, as defined in BIP9.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeploymentState {
+    Defined,
+    Started,
+    LockedIn,
+    Active,
+    Failed,
+}
+
+const MAINNET_DEPLOYMENTS: &[Deployment] = &[
+    Deployment {
+        name: "csv",
+        bit: 0,
+        start_time: 1_462_060_800,
+        timeout: 1_493_596_800,
+        threshold: 1916,
+        min_activation_height: 0,
+    },
+    Deployment {
+        name: "segwit",
+        bit: 1,
+        start_time: 1_479_168_000,
+        timeout: 1_510_704_000,
+        threshold: 1916,
+        min_activation_height: 0,
+    },
+    Deployment {
+        name: "taproot",
+        bit: 2,
+        start_time: 1_619_222_400,
+        timeout: 1_628_640_000,
+        threshold: 1815,
+        min_activation_height: 709_632,
+    },
+    Deployment {
+        name: "testdummy",
+        bit: 28,
+        start_time: 1_199_145_601,
+        timeout: 1_230_767_999,
+        threshold: 1815,
+        min_activation_height: 0,
+    },
+];
+
+const TESTNET_DEPLOYMENTS: &[Deployment] = &[
+    Deployment {
+        name: "csv",
+        bit: 0,
+        start_time: 1_456_790_400,
+        timeout: 1_493_596_800,
+        threshold: 1512,
+        min_activation_height: 0,
+    },
+    Deployment {
+        name: "segwit",
+        bit: 1,
+        start_time: 1_462_060_800,
+        timeout: 1_493_596_800,
+        threshold: 1512,
+        min_activation_height: 0,
+    },
+    Deployment {
+        name: "taproot",
+        bit: 2,
+        start_time: 1_619_222_400,
+        timeout: 1_628_640_000,
+        threshold: 1512,
+        min_activation_height: 0,
+    },
+    Deployment {
+        name: "testdummy",
+        bit: 28,
+        start_time: 1_199_145_601,
+        timeout: 1_230_767_999,
+        threshold: 1512,
+        min_activation_height: 0,
+    },
+];
+
+const TESTNET4_DEPLOYMENTS: &[Deployment] = &[
+    always_active("csv", 0, 1512),
+    always_active("segwit", 1, 1512),
+    always_active("taproot", 2, 1512),
+    Deployment {
+        name: "testdummy",
+        bit: 28,
+        start_time: 1_199_145_601,
+        timeout: 1_230_767_999,
+        threshold: 1512,
+        min_activation_height: 0,
+    },
+];
+
+const SIGNET_DEPLOYMENTS: &[Deployment] = &[
+    always_active("csv", 0, 1815),
+    always_active("segwit", 1, 1815),
+    always_active("taproot", 2, 1815),
+    Deployment {
+        name: "testdummy",
+        bit: 28,
+        start_time: NEVER_ACTIVE,
+        timeout: NO_TIMEOUT,
+        threshold: 1815,
+        min_activation_height: 0,
+    },
+];
+
+const REGTEST_DEPLOYMENTS: &[Deployment] = &[
+    always_active("csv", 0, 108),
+    always_active("segwit", 1, 108),
+    always_active("taproot", 2, 108),
+    Deployment {
+        name: "testdummy",
+        bit: 28,
+        start_time: 0,
+        timeout: NO_TIMEOUT,
+        threshold: 108,
+        min_activation_height: 0,
+    },
+];
+
+const fn always_active(name: &'static str, bit: u8, threshold: u32) -> Deployment {
+    Deployment {
+        name,
+        bit,
+        start_time: ALWAYS_ACTIVE,
+        timeout: NO_TIMEOUT,
+        threshold,
+        min_activation_height: 0,
+    }
+}
+
+/// Returns the BIP9 deployments of the given network.
+pub fn deployments(network: &Network) -> &'static [Deployment] {
+    match network {
+        Network::Bitcoin => MAINNET_DEPLOYMENTS,
+        Network::Testnet => TESTNET_DEPLOYMENTS,
+        Network::Testnet4 => TESTNET4_DEPLOYMENTS,
+        Network::Signet => SIGNET_DEPLOYMENTS,
+        Network::Regtest => REGTEST_DEPLOYMENTS,
+    }
+}
+
+// Returns the number of blocks in a period in which the state of a deployment is the same.
+fn period(network: &Network) -> BlockHeight {
+    match network {
+        Network::Bitcoin | Network::Testnet | Network::Testnet4 | Network::Signet => 2016,
+        Network::Regtest => 144,
+    }
+}
+
+/// Returns the state of the deployment for the block that extends the tip of the store,
+/// along with the height of the first block that this state applies to.
+///
+/// The store must contain all the headers from genesis up to its tip.
+pub fn deployment_state(
+    network: &Network,
+    store: &impl HeaderStore,
+    deployment: &Deployment,
+) -> (DeploymentState, BlockHeight) {
+    match deployment.start_time {
+        ALWAYS_ACTIVE => return (DeploymentState::Active, 0),
+        NEVER_ACTIVE => return (DeploymentState::Failed, 0),
+        _ => {}
+    }
+
+    let period = period(network);
+    let next_height = store.height() + 1;
+
+    // Walk back through the periods until reaching one that is known to be in the
+    // `Defined` state, i.e. a period that starts at genesis or whose previous block
+    // has a median time past before the start time.
+    let mut period_start = next_height - next_height % period;
+    let mut period_starts = vec![];
+    while period_start > 0 && median_time_past(store, period_start - 1) >= deployment.start_time {
+        period_starts.push(period_start);
+        period_start -= period;
+    }
+
+    // Walk forward through the periods, computing the state transitions.
+    let mut state = DeploymentState::Defined;
+    let mut since = 0;
+    for period_start in period_starts.into_iter().rev() {
+        let prev_height = period_start - 1;
+        let next_state = match state {
+            DeploymentState::Defined => {
+                if median_time_past(store, prev_height) >= deployment.start_time {
+                    DeploymentState::Started
+                } else {
+                    DeploymentState::Defined
+                }
+            }
+            DeploymentState::Started => {
+                if count_signaling_blocks(store, deployment, period_start - period, period_start)
+                    >= deployment.threshold
+                {
+                    DeploymentState::LockedIn
+                } else if median_time_past(store, prev_height) >= deployment.timeout {
+                    DeploymentState::Failed
+                } else {
+                    DeploymentState::Started
+                }
+            }
+            DeploymentState::LockedIn => {
+                if period_start >= deployment.min_activation_height {
+                    DeploymentState::Active
+                } else {
+                    DeploymentState::LockedIn
+                }
+            }
+            DeploymentState::Active | DeploymentState::Failed => state,
+        };
+
+        if next_state != state {
+            state = next_state;
+            since = period_start;
+        }
+    }
+
+    (state, since)
+}
+
+// Returns the median time past of the block at the given height, i.e. the median of
+// the timestamps of the block and up to 10 of its predecessors.
+fn median_time_past(store: &impl HeaderStore, height: BlockHeight) -> i64 {
+    let mut times: Vec<_> = (height.saturating_sub(10)..=height)
+        .map(|height| get_header_time(store, height))
+        .collect();
+    times.sort_unstable();
+    times[times.len() / 2] as i64
+}
+
+// Returns the number of blocks in the given range of heights that signal for the deployment.
+fn count_signaling_blocks(
+    store: &impl HeaderStore,
+    deployment: &Deployment,
+    from_height: BlockHeight,
+    to_height: BlockHeight,
+) -> u32 {
+    (from_height..to_height)
+        .filter(|height| {
+            let version = store
+                .get_with_height(*height)
+                .expect("header must exist in the store")
+                .version as u32;
+            version & VERSION_BITS_TOP_MASK == VERSION_BITS_TOP_BITS
+                && version & (1 << deployment.bit) != 0
+        })
+        .count() as u32
+}
+
+fn get_header_time(store: &impl HeaderStore, height: BlockHeight) -> u32 {
+    store
+        .get_with_height(height)
+        .expect("header must exist in the store")
+        .time
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::{BlockHash, BlockHeader};
+
+    const REGTEST_PERIOD: u32 = 144;
+
+    // A store of a single chain of headers, starting at genesis.
+    struct ChainStore {
+        headers: Vec<BlockHeader>,
+    }
+
+    impl ChainStore {
+        // Creates a chain where the versions of the headers (excluding genesis) are
+        // given by `version(height)`.
+        fn new(len: u32, version: impl Fn(u32) -> i32) -> Self {
+            let mut headers = vec![BlockHeader {
+                version: 1,
+                prev_blockhash: Default::default(),
+                merkle_root: Default::default(),
+                time: 1_296_688_602,
+                bits: 0x207fffff,
+                nonce: 0,
+            }];
+            for height in 1..len {
+                let prev = headers[height as usize - 1];
+                headers.push(BlockHeader {
+                    version: version(height),
+                    prev_blockhash: prev.block_hash(),
+                    time: prev.time + 600,
+                    ..prev
+                });
+            }
+            Self { headers }
+        }
+    }
+
+    impl HeaderStore for ChainStore {
+        fn get_with_block_hash(&self, hash: &BlockHash) -> Option<BlockHeader> {
+            self.headers
+                .iter()
+                .find(|header| header.block_hash() == *hash)
+                .cloned()
+        }
+
+        fn get_with_height(&self, height: u32) -> Option<BlockHeader> {
+            self.headers.get(height as usize).cloned()
+        }
+
+        fn height(&self) -> u32 {
+            self.headers.len() as u32 - 1
+        }
+    }
+
+    fn testdummy() -> Deployment {
+        deployments(&Network::Regtest)
+            .iter()
+            .find(|deployment| deployment.name == "testdummy")
+            .cloned()
+            .unwrap()
+    }
+
+    const SIGNALING_VERSION: i32 = 0x2000_0000 | (1 << 28);
+
+    #[test]
+    fn deployment_is_locked_in_and_activated_when_signaled() {
+        let network = Network::Regtest;
+        let deployment = testdummy();
+        let state_at = |len| {
+            let store = ChainStore::new(len, |_| SIGNALING_VERSION);
+            deployment_state(&network, &store, &deployment)
+        };
+
+        // The first period is always defined.
+        assert_eq!(state_at(1), (DeploymentState::Defined, 0));
+        assert_eq!(state_at(REGTEST_PERIOD - 1), (DeploymentState::Defined, 0));
+
+        // The deployment starts in the second period.
+        assert_eq!(
+            state_at(REGTEST_PERIOD),
+            (DeploymentState::Started, REGTEST_PERIOD)
+        );
+
+        // The second period is fully signaling, so the deployment is locked in on the third.
+        assert_eq!(
+            state_at(2 * REGTEST_PERIOD - 1),
+            (DeploymentState::Started, REGTEST_PERIOD)
+        );
+        assert_eq!(
+            state_at(2 * REGTEST_PERIOD),
+            (DeploymentState::LockedIn, 2 * REGTEST_PERIOD)
+        );
+
+        // And active on the fourth.
+        assert_eq!(
+            state_at(3 * REGTEST_PERIOD),
+            (DeploymentState::Active, 3 * REGTEST_PERIOD)
+        );
+        assert_eq!(
+            state_at(5 * REGTEST_PERIOD + 10),
+            (DeploymentState::Active, 3 * REGTEST_PERIOD)
+        );
+    }
+
+    #[test]
+    fn deployment_is_locked_in_only_at_threshold() {
+        let network = Network::Regtest;
+        let deployment = testdummy();
+
+        // Signal with one block less than the threshold in the second period.
+        let version = |threshold: u32| {
+            move |height: u32| {
+                if height < REGTEST_PERIOD + threshold {
+                    SIGNALING_VERSION
+                } else {
+                    4
+                }
+            }
+        };
+
+        let store = ChainStore::new(3 * REGTEST_PERIOD, version(deployment.threshold - 1));
+        assert_eq!(
+            deployment_state(&network, &store, &deployment),
+            (DeploymentState::Started, REGTEST_PERIOD)
+        );
+
+        let store = ChainStore::new(3 * REGTEST_PERIOD, version(deployment.threshold));
+        assert_eq!(
+            deployment_state(&network, &store, &deployment),
+            (DeploymentState::Active, 3 * REGTEST_PERIOD)
+        );
+    }
+
+    #[test]
+    fn deployment_fails_after_timeout() {
+        let network = Network::Regtest;
+        let store = ChainStore::new(4 * REGTEST_PERIOD, |_| 4);
+        let genesis_time = store.headers[0].time as i64;
+
+        let deployment = Deployment {
+            timeout: genesis_time + (REGTEST_PERIOD as i64) * 600,
+            ..testdummy()
+        };
+        assert_eq!(
+            deployment_state(&network, &store, &deployment),
+            (DeploymentState::Failed, 2 * REGTEST_PERIOD)
+        );
+
+        // A deployment whose start time hasn't been reached yet remains defined.
+        let deployment = Deployment {
+            start_time: genesis_time + 10 * (REGTEST_PERIOD as i64) * 600,
+            ..testdummy()
+        };
+        assert_eq!(
+            deployment_state(&network, &store, &deployment),
+            (DeploymentState::Defined, 0)
+        );
+    }
+
+    #[test]
+    fn min_activation_height_delays_activation() {
+        let network = Network::Regtest;
+        let store = ChainStore::new(5 * REGTEST_PERIOD, |_| SIGNALING_VERSION);
+        let deployment = Deployment {
+            min_activation_height: 4 * REGTEST_PERIOD + 1,
+            ..testdummy()
+        };
+
+        assert_eq!(
+            deployment_state(&network, &store, &deployment),
+            (DeploymentState::Active, 5 * REGTEST_PERIOD)
+        );
+    }
+
+    #[test]
+    fn always_and_never_active_deployments() {
+        let store = ChainStore::new(1, |_| 1);
+        for network in [Network::Testnet4, Network::Signet, Network::Regtest] {
+            let taproot = deployments(&network)
+                .iter()
+                .find(|deployment| deployment.name == "taproot")
+                .unwrap();
+            assert_eq!(
+                deployment_state(&network, &store, taproot),
+                (DeploymentState::Active, 0)
+            );
+        }
+
+        let testdummy = deployments(&Network::Signet)
+            .iter()
+            .find(|deployment| deployment.name == "testdummy")
+            .unwrap();
+        assert_eq!(
+            deployment_state(&Network::Signet, &store, testdummy),
+            (DeploymentState::Failed, 0)
+        );
+    }
+}
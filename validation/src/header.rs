@@ -1,10 +1,12 @@
-use bitcoin::{util::uint::Uint256, BlockHash, BlockHeader, Network};
+use bitcoin::{util::uint::Uint256, BlockHash, BlockHeader};
 
 use crate::{
+    checkpoints::{default_checkpoints, is_checkpoint_valid, Checkpoint},
     constants::{
-        max_target, no_pow_retargeting, pow_limit_bits, DIFFICULTY_ADJUSTMENT_INTERVAL, TEN_MINUTES,
+        enforce_bip94, max_target, no_pow_retargeting, pow_limit_bits,
+        DIFFICULTY_ADJUSTMENT_INTERVAL, MAX_TIMEWARP, TEN_MINUTES,
     },
-    BlockHeight,
+    BlockHeight, Network,
 };
 
 /// An error thrown when trying to validate a header.
@@ -19,6 +21,13 @@ pub enum ValidateHeaderError {
         block_time: u64,
         max_allowed_time: u64,
     },
+    /// Used when BIP94 is enforced and the timestamp of the first header of a
+    /// difficulty adjustment interval is more than 10 minutes before the
+    /// timestamp of the previous header.
+    HeaderIsTimewarped {
+        block_time: u64,
+        min_allowed_time: u64,
+    },
     /// Used when the PoW in the header is invalid as per the target mentioned
     /// in the header.
     InvalidPoWForHeaderTarget,
@@ -31,6 +40,9 @@ pub enum ValidateHeaderError {
     /// Used when the predecessor of the input header is not found in the
     /// HeaderStore.
     PrevHeaderNotFound,
+    /// Used when the chain of the header doesn't contain the block of the
+    /// checkpoint at the given height.
+    ForkBelowCheckpoint { checkpoint_height: BlockHeight },
 }
 
 const ONE_HOUR: u64 = 3_600;
@@ -53,13 +65,31 @@ pub trait HeaderStore {
     }
 }
 
-/// Validates a header. If a failure occurs, a
-/// [ValidateHeaderError](ValidateHeaderError) will be returned.
+/// Validates a header against the default checkpoints of the network.
+/// If a failure occurs, a [ValidateHeaderError](ValidateHeaderError) will be returned.
 pub fn validate_header(
     network: &Network,
     store: &impl HeaderStore,
     header: &BlockHeader,
     current_time: u64,
+) -> Result<(), ValidateHeaderError> {
+    validate_header_with_checkpoints(
+        network,
+        store,
+        header,
+        current_time,
+        &default_checkpoints(network),
+    )
+}
+
+/// Validates a header against the given checkpoints. If a failure occurs, a
+/// [ValidateHeaderError](ValidateHeaderError) will be returned.
+pub fn validate_header_with_checkpoints(
+    network: &Network,
+    store: &impl HeaderStore,
+    header: &BlockHeader,
+    current_time: u64,
+    checkpoints: &[Checkpoint],
 ) -> Result<(), ValidateHeaderError> {
     let prev_height = store.height();
     let prev_header = match store.get_with_block_hash(&header.prev_blockhash) {
@@ -69,7 +99,9 @@ pub fn validate_header(
         }
     };
 
+    is_checkpoint_valid(checkpoints, store, header)?;
     is_timestamp_valid(store, header, current_time)?;
+    is_timewarp_valid(network, &prev_header, prev_height, header)?;
 
     let header_target = header.target();
     if header_target > max_target(network) {
@@ -119,23 +151,61 @@ fn is_timestamp_valid(
     current_time: u64,
 ) -> Result<(), ValidateHeaderError> {
     timestamp_is_less_than_2h_in_future(header.time as u64, current_time)?;
-    let mut times = vec![];
-    let mut current_header = *header;
+    let prev_header = store
+        .get_with_block_hash(&header.prev_blockhash)
+        .ok_or(ValidateHeaderError::PrevHeaderNotFound)?;
+    if header.time <= median_time_past(store, &prev_header) {
+        return Err(ValidateHeaderError::HeaderIsOld);
+    }
+
+    Ok(())
+}
+
+/// Returns the median time past of the given header, i.e. the median of the timestamps
+/// of the header and up to 10 of its ancestors.
+pub fn median_time_past(store: &impl HeaderStore, header: &BlockHeader) -> u32 {
+    let mut times = vec![header.time];
     let initial_hash = store.get_initial_hash();
-    for _ in 0..11 {
-        if let Some(prev_header) = store.get_with_block_hash(&current_header.prev_blockhash) {
-            times.push(prev_header.time);
-            if current_header.prev_blockhash == initial_hash {
-                break;
+    if header.block_hash() != initial_hash {
+        let mut current_header = *header;
+        while times.len() < 11 {
+            match store.get_with_block_hash(&current_header.prev_blockhash) {
+                Some(prev_header) => {
+                    times.push(prev_header.time);
+                    if current_header.prev_blockhash == initial_hash {
+                        break;
+                    }
+                    current_header = prev_header;
+                }
+                None => break,
             }
-            current_header = prev_header;
         }
     }
 
     times.sort_unstable();
-    let median = times[times.len() / 2];
-    if header.time <= median {
-        return Err(ValidateHeaderError::HeaderIsOld);
+    times[times.len() / 2]
+}
+
+/// Validates the timewarp fix of BIP94.
+/// "The timestamp of the first block of a difficulty adjustment period must
+/// not be lower than the timestamp of the last block of the previous period
+/// minus 600 seconds."
+fn is_timewarp_valid(
+    network: &Network,
+    prev_header: &BlockHeader,
+    prev_height: BlockHeight,
+    header: &BlockHeader,
+) -> Result<(), ValidateHeaderError> {
+    if !enforce_bip94(network) || (prev_height + 1) % DIFFICULTY_ADJUSTMENT_INTERVAL != 0 {
+        return Ok(());
+    }
+
+    let min_allowed_time = prev_header.time.saturating_sub(MAX_TIMEWARP);
+    if header.time < min_allowed_time {
+        return Err(ValidateHeaderError::HeaderIsTimewarped {
+            block_time: header.time as u64,
+            min_allowed_time: min_allowed_time as u64,
+        });
     }
 
     Ok(())
@@ -151,7 +221,7 @@ fn get_next_target(
     timestamp: u32,
 ) -> Uint256 {
     match network {
-        Network::Testnet | Network::Regtest => {
+        Network::Testnet | Network::Testnet4 | Network::Regtest => {
             if (prev_height + 1) % DIFFICULTY_ADJUSTMENT_INTERVAL != 0 {
                 // This if statements is reached only for Regtest and Testnet networks
                 // Here is the quote from "https://en.bitcoin.it/wiki/Testnet"
@@ -187,7 +257,7 @@ fn get_next_target(
     }
 }
 
-/// This method is only valid when used for testnet and regtest networks.
+/// This method is only valid when used for testnet(4) and regtest networks.
 /// As per "https://en.bitcoin.it/wiki/Testnet",
 /// "If no block has been found in 20 minutes, the difficulty automatically
 /// resets back to the minimum for a single block, after which it
@@ -203,7 +273,7 @@ fn find_next_difficulty_in_chain(
     // This is the maximum difficulty target for the network
     let pow_limit_bits = pow_limit_bits(network);
     match network {
-        Network::Testnet | Network::Regtest => {
+        Network::Testnet | Network::Testnet4 | Network::Regtest => {
             let mut current_header = *prev_header;
             let mut current_height = prev_height;
             let mut current_hash = current_header.block_hash();
@@ -289,7 +359,15 @@ fn compute_next_difficulty(
     // Computing new difficulty target.
     // new difficulty target = old difficult target * (adjusted_interval /
     // 2_weeks);
-    let mut target = prev_header.target();
+    //
+    // With BIP94, the old difficulty target is the one of the first block in the
+    // interval rather than the last, so that a min-difficulty block at the end of
+    // the interval doesn't reset the base of the computation.
+    let mut target = if enforce_bip94(network) {
+        last_adjustment_header.target()
+    } else {
+        prev_header.target()
+    };
     target = target.mul_u32(adjusted_interval);
     target = target / Uint256::from_u64(target_adjustment_interval_time as u64).unwrap();
 
@@ -374,10 +452,10 @@ mod test {
         }
 
         fn get_with_height(&self, height: u32) -> Option<BlockHeader> {
-            let blocks_to_traverse = self.height - height;
+            let blocks_to_traverse = self.height.checked_sub(height)?;
             let mut header = self.headers.get(&self.tip_hash).unwrap().header;
             for _ in 0..blocks_to_traverse {
-                header = self.headers.get(&header.prev_blockhash).unwrap().header;
+                header = self.headers.get(&header.prev_blockhash)?.header;
             }
             Some(header)
         }
@@ -503,6 +581,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_median_time_past() {
+        let header_705600 = deserialize_header(MAINNET_HEADER_705600);
+        let header_705601 = deserialize_header(MAINNET_HEADER_705601);
+        let header_705602 = deserialize_header(MAINNET_HEADER_705602);
+        let mut store = SimpleHeaderStore::new(header_705600, 705_600);
+        store.add(header_705601);
+        store.add(header_705602);
+
+        // The ancestors before the initial header aren't available in the store.
+        assert_eq!(median_time_past(&store, &header_705600), header_705600.time);
+
+        let mut times = vec![header_705600.time, header_705601.time, header_705602.time];
+        times.sort_unstable();
+        assert_eq!(median_time_past(&store, &header_705602), times[1]);
+    }
+
     #[test]
     fn test_is_timestamp_valid() {
         let header_705600 = deserialize_header(MAINNET_HEADER_705600);
@@ -740,4 +835,162 @@ mod test {
             assert_eq!(target, BlockHeader::u256_from_compact_target(expected_pow));
         }
     }
+
+    #[test]
+    fn test_is_checkpoint_valid() {
+        let network = Network::Regtest;
+        // A chain with heights [0, 9].
+        let (store, last_header) = create_chain(&network, pow_limit_bits(&network), 10);
+        let header = next_block_header(last_header, pow_limit_bits(&network));
+        let checkpoint_at = |height| Checkpoint {
+            height,
+            hash: store.get_with_height(height).unwrap().block_hash(),
+        };
+
+        // Checkpoints on the chain of the header are valid.
+        assert_eq!(
+            is_checkpoint_valid(&[checkpoint_at(3), checkpoint_at(7)], &store, &header),
+            Ok(())
+        );
+
+        // A checkpoint at the height of the header must match the header itself.
+        let checkpoint = Checkpoint {
+            height: 10,
+            hash: header.block_hash(),
+        };
+        assert_eq!(is_checkpoint_valid(&[checkpoint], &store, &header), Ok(()));
+
+        let mut fork_header = header;
+        fork_header.time += 1;
+        assert_eq!(
+            is_checkpoint_valid(&[checkpoint], &store, &fork_header),
+            Err(ValidateHeaderError::ForkBelowCheckpoint {
+                checkpoint_height: 10
+            })
+        );
+
+        // A header whose chain doesn't contain the block of the latest checkpoint is rejected.
+        let checkpoint = Checkpoint {
+            height: 7,
+            hash: checkpoint_at(6).hash,
+        };
+        assert_eq!(
+            is_checkpoint_valid(&[checkpoint_at(3), checkpoint], &store, &header),
+            Err(ValidateHeaderError::ForkBelowCheckpoint {
+                checkpoint_height: 7
+            })
+        );
+
+        // Checkpoints above the height of the header are ignored.
+        let checkpoint = Checkpoint {
+            height: 11,
+            hash: checkpoint_at(6).hash,
+        };
+        assert_eq!(is_checkpoint_valid(&[checkpoint], &store, &header), Ok(()));
+    }
+
+    #[test]
+    fn test_default_checkpoints_are_sorted() {
+        for network in [
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Testnet4,
+            Network::Signet,
+            Network::Regtest,
+        ] {
+            let heights: Vec<_> = default_checkpoints(&network)
+                .iter()
+                .map(|checkpoint| checkpoint.height)
+                .collect();
+            let mut sorted_heights = heights.clone();
+            sorted_heights.sort_unstable();
+            sorted_heights.dedup();
+            assert_eq!(heights, sorted_heights);
+        }
+    }
+
+    #[test]
+    fn test_timewarp_testnet4() {
+        let network = Network::Testnet4;
+        // A chain with heights [0, 2015], so the next header is the first one of a
+        // difficulty adjustment interval.
+        let (store, last_header) = create_chain(
+            &network,
+            pow_limit_bits(&network),
+            DIFFICULTY_ADJUSTMENT_INTERVAL,
+        );
+        assert_eq!(store.height(), DIFFICULTY_ADJUSTMENT_INTERVAL - 1);
+
+        let mut header = next_block_header(last_header, pow_limit_bits(&network));
+
+        // The timestamp can be up to `MAX_TIMEWARP` seconds before the previous header.
+        header.time = last_header.time - MAX_TIMEWARP;
+        assert_eq!(
+            is_timewarp_valid(&network, &last_header, store.height(), &header),
+            Ok(())
+        );
+
+        // One more second and the header is rejected.
+        header.time = last_header.time - MAX_TIMEWARP - 1;
+        assert_eq!(
+            validate_header(&network, &store, &header, MOCK_CURRENT_TIME),
+            Err(ValidateHeaderError::HeaderIsTimewarped {
+                block_time: (last_header.time - MAX_TIMEWARP - 1) as u64,
+                min_allowed_time: (last_header.time - MAX_TIMEWARP) as u64,
+            })
+        );
+
+        // The rule only applies at difficulty adjustment boundaries.
+        assert_eq!(
+            is_timewarp_valid(&network, &last_header, store.height() - 1, &header),
+            Ok(())
+        );
+
+        // The rule isn't enforced on networks without BIP94.
+        for network in [Network::Bitcoin, Network::Testnet, Network::Regtest] {
+            assert_eq!(
+                is_timewarp_valid(&network, &last_header, store.height(), &header),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn test_min_difficulty_blocks_do_not_reset_retarget_base_testnet4() {
+        // The first block of the interval has a non-limit PoW, while all the other
+        // blocks in the interval are min-difficulty blocks.
+        let initial_pow = 0x1c0ffff0;
+        let (store, last_header) = create_chain(
+            &Network::Testnet4,
+            initial_pow,
+            DIFFICULTY_ADJUSTMENT_INTERVAL,
+        );
+        assert_eq!(last_header.bits, pow_limit_bits(&Network::Testnet4));
+
+        // The interval took 2015 * 10 minutes, so the target shrinks by 2015 / 2016.
+
+        // Testnet3 computes the next target from the last (min-difficulty) block.
+        assert_eq!(
+            get_next_target(
+                &Network::Testnet,
+                &store,
+                &last_header,
+                store.height(),
+                last_header.time + TEN_MINUTES,
+            ),
+            BlockHeader::u256_from_compact_target(0x1d00ffde)
+        );
+
+        // Testnet4 computes the next target from the first block of the interval.
+        assert_eq!(
+            get_next_target(
+                &Network::Testnet4,
+                &store,
+                &last_header,
+                store.height(),
+                last_header.time + TEN_MINUTES,
+            ),
+            BlockHeader::u256_from_compact_target(0x1c0ffde7)
+        );
+    }
 }
@@ -1,7 +1,25 @@
+mod block;
+mod checkpoints;
 mod constants;
+mod deployments;
 mod header;
+mod network;
+mod signet;
 
+pub use crate::block::{validate_block, TxOutStore, ValidateBlockError};
+pub use crate::checkpoints::{default_checkpoints, Checkpoint};
 pub use crate::constants::max_target;
-pub use crate::header::{validate_header, HeaderStore, ValidateHeaderError};
+pub use crate::deployments::{
+    deployment_state, deployments, Deployment, DeploymentState, ALWAYS_ACTIVE, NEVER_ACTIVE,
+    NO_TIMEOUT,
+};
+pub use crate::header::{
+    median_time_past, validate_header, validate_header_with_checkpoints, HeaderStore,
+    ValidateHeaderError,
+};
+pub use crate::network::Network;
+pub use crate::signet::{
+    validate_signet_block, validate_signet_challenge, ValidateSignetBlockError,
+};
 
 type BlockHeight = u32;
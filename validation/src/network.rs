@@ -0,0 +1,23 @@
+/// The Bitcoin networks supported by the validation rules.
+///
+/// This mirrors `bitcoin::Network`, but also includes networks that aren't known to the
+/// `bitcoin` crate (e.g. testnet4) and have their own consensus rules.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Network {
+    Bitcoin,
+    Testnet,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl From<bitcoin::Network> for Network {
+    fn from(network: bitcoin::Network) -> Self {
+        match network {
+            bitcoin::Network::Bitcoin => Self::Bitcoin,
+            bitcoin::Network::Testnet => Self::Testnet,
+            bitcoin::Network::Signet => Self::Signet,
+            bitcoin::Network::Regtest => Self::Regtest,
+        }
+    }
+}
@@ -0,0 +1,569 @@
+//! Validation of the signet block solution as specified in BIP325.
+//! https://github.com/bitcoin/bips/blob/master/bip-0325.mediawiki
+use bitcoin::{
+    blockdata::{
+        opcodes::{self, all::*},
+        script::{Builder, Instruction},
+    },
+    consensus::{serialize, Decodable},
+    secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, Verification},
+    util::hash::bitcoin_merkle_root,
+    Block, OutPoint, Script, Transaction, TxIn, TxOut, Witness,
+};
+
+use crate::constants::{MIN_WITNESS_COMMITMENT_LENGTH, WITNESS_COMMITMENT_HEADER};
+
+/// The header of the section in the witness commitment that holds the signet solution.
+const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+/// An error thrown when trying to validate the signet solution of a block.
+#[derive(Debug, PartialEq)]
+pub enum ValidateSignetBlockError {
+    /// Used when the block doesn't have a coinbase transaction.
+    MissingCoinbase,
+    /// Used when the coinbase transaction doesn't have a witness commitment output.
+    MissingWitnessCommitment,
+    /// Used when the signet solution in the witness commitment cannot be parsed.
+    MalformedSolution,
+    /// Used when the challenge isn't one of the supported script templates:
+    /// `OP_TRUE`, `<pubkey> OP_CHECKSIG`, and bare `OP_CHECKMULTISIG`.
+    UnsupportedChallenge,
+    /// Used when the signet solution doesn't satisfy the challenge.
+    InvalidSolution,
+}
+
+/// The script templates of challenges that can be verified.
+enum Challenge {
+    /// `OP_TRUE`: any push-only solution is accepted.
+    True,
+    /// `<pubkey> OP_CHECKSIG`
+    CheckSig(PublicKey),
+    /// `OP_m <pubkey_1> ... <pubkey_n> OP_n OP_CHECKMULTISIG`
+    CheckMultiSig {
+        required: usize,
+        public_keys: Vec<PublicKey>,
+    },
+}
+
+/// Validates that the signet solution in the block's coinbase satisfies the given
+/// challenge. If a failure occurs, a
+/// [ValidateSignetBlockError](ValidateSignetBlockError) will be returned.
+pub fn validate_signet_block(
+    challenge: &Script,
+    block: &Block,
+) -> Result<(), ValidateSignetBlockError> {
+    let parsed_challenge = parse_challenge(challenge)?;
+    let to_sign = to_sign_transaction(challenge, block)?;
+
+    let input = &to_sign.input[0];
+    // None of the supported challenges is a witness program.
+    if !input.witness.is_empty() {
+        return Err(ValidateSignetBlockError::InvalidSolution);
+    }
+
+    let pushes = get_pushes(&input.script_sig).ok_or(ValidateSignetBlockError::InvalidSolution)?;
+    let secp = Secp256k1::verification_only();
+    let is_valid = match parsed_challenge {
+        Challenge::True => true,
+        Challenge::CheckSig(public_key) => match pushes.as_slice() {
+            [signature] => verify_signature(&secp, &to_sign, challenge, signature, &public_key),
+            _ => false,
+        },
+        Challenge::CheckMultiSig {
+            required,
+            public_keys,
+        } => match pushes.split_first() {
+            // The dummy element consumed by `OP_CHECKMULTISIG` must be empty (BIP147).
+            Some((dummy, signatures)) if dummy.is_empty() && signatures.len() == required => {
+                verify_multisig(&secp, &to_sign, challenge, signatures, &public_keys)
+            }
+            _ => false,
+        },
+    };
+
+    if !is_valid {
+        return Err(ValidateSignetBlockError::InvalidSolution);
+    }
+
+    Ok(())
+}
+
+/// Validates that the given challenge is one of the supported script templates, i.e. that
+/// the solutions of blocks can be validated against it.
+pub fn validate_signet_challenge(challenge: &Script) -> Result<(), ValidateSignetBlockError> {
+    parse_challenge(challenge).map(|_| ())
+}
+
+fn parse_challenge(challenge: &Script) -> Result<Challenge, ValidateSignetBlockError> {
+    let instructions = challenge
+        .instructions()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ValidateSignetBlockError::UnsupportedChallenge)?;
+
+    let parse_key = |bytes: &[u8]| {
+        PublicKey::from_slice(bytes).map_err(|_| ValidateSignetBlockError::UnsupportedChallenge)
+    };
+
+    match instructions.as_slice() {
+        [Instruction::Op(OP_PUSHNUM_1)] => Ok(Challenge::True),
+        [Instruction::PushBytes(key), Instruction::Op(OP_CHECKSIG)] => {
+            Ok(Challenge::CheckSig(parse_key(key)?))
+        }
+        [Instruction::Op(m), keys @ .., Instruction::Op(n), Instruction::Op(OP_CHECKMULTISIG)] => {
+            let required = pushnum(*m).ok_or(ValidateSignetBlockError::UnsupportedChallenge)?;
+            let total = pushnum(*n).ok_or(ValidateSignetBlockError::UnsupportedChallenge)?;
+            if required == 0 || required > total || total != keys.len() {
+                return Err(ValidateSignetBlockError::UnsupportedChallenge);
+            }
+
+            let public_keys = keys
+                .iter()
+                .map(|key| match key {
+                    Instruction::PushBytes(key) => parse_key(key),
+                    Instruction::Op(_) => Err(ValidateSignetBlockError::UnsupportedChallenge),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(Challenge::CheckMultiSig {
+                required,
+                public_keys,
+            })
+        }
+        _ => Err(ValidateSignetBlockError::UnsupportedChallenge),
+    }
+}
+
+/// Returns the number pushed by an `OP_1` to `OP_16` opcode.
+fn pushnum(opcode: opcodes::All) -> Option<usize> {
+    let code = opcode.into_u8();
+    if (OP_PUSHNUM_1.into_u8()..=OP_PUSHNUM_16.into_u8()).contains(&code) {
+        Some((code - OP_PUSHNUM_1.into_u8() + 1) as usize)
+    } else {
+        None
+    }
+}
+
+/// Returns the data pushed by the script, or `None` if the script isn't push-only.
+fn get_pushes(script: &Script) -> Option<Vec<Vec<u8>>> {
+    script
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(data)) => Some(data.to_vec()),
+            Ok(Instruction::Op(op)) => pushnum(op).map(|n| vec![n as u8]),
+            Err(_) => None,
+        })
+        .collect()
+}
+
+/// Builds the `to_sign` transaction of BIP325, which spends the `to_spend` transaction
+/// committing to the block, using the solution found in the block's coinbase.
+fn to_sign_transaction(
+    challenge: &Script,
+    block: &Block,
+) -> Result<Transaction, ValidateSignetBlockError> {
+    let mut coinbase = block
+        .txdata
+        .first()
+        .ok_or(ValidateSignetBlockError::MissingCoinbase)?
+        .clone();
+
+    // The witness commitment is in the last output that starts with the commitment header.
+    let commitment_output = coinbase
+        .output
+        .iter_mut()
+        .rev()
+        .find(|output| {
+            output.script_pubkey.len() >= MIN_WITNESS_COMMITMENT_LENGTH
+                && output.script_pubkey[0..WITNESS_COMMITMENT_HEADER.len()]
+                    == WITNESS_COMMITMENT_HEADER
+        })
+        .ok_or(ValidateSignetBlockError::MissingWitnessCommitment)?;
+
+    // A block without a solution is allowed, to support `OP_TRUE` as a trivial challenge.
+    let (script_sig, witness) = match fetch_and_clear_solution(&mut commitment_output.script_pubkey)
+    {
+        Some(solution) => {
+            let mut reader = solution.as_slice();
+            let script_sig = Script::consensus_decode(&mut reader)
+                .map_err(|_| ValidateSignetBlockError::MalformedSolution)?;
+            let witness = Witness::consensus_decode(&mut reader)
+                .map_err(|_| ValidateSignetBlockError::MalformedSolution)?;
+            if !reader.is_empty() {
+                return Err(ValidateSignetBlockError::MalformedSolution);
+            }
+            (script_sig, witness)
+        }
+        None => (Script::new(), Witness::default()),
+    };
+
+    // The merkle root of the block where the coinbase doesn't contain the solution.
+    let signet_merkle_root = bitcoin_merkle_root(
+        std::iter::once(coinbase.txid().as_hash())
+            .chain(block.txdata.iter().skip(1).map(|tx| tx.txid().as_hash())),
+    )
+    .expect("the block has at least one transaction");
+
+    let mut block_data = serialize(&block.header.version);
+    block_data.extend(serialize(&block.header.prev_blockhash));
+    block_data.extend(serialize(&signet_merkle_root));
+    block_data.extend(serialize(&block.header.time));
+
+    let to_spend = Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: Builder::new()
+                .push_opcode(OP_PUSHBYTES_0)
+                .push_slice(&block_data)
+                .into_script(),
+            sequence: 0,
+            witness: Witness::default(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: challenge.clone(),
+        }],
+    };
+
+    Ok(Transaction {
+        version: 0,
+        lock_time: 0,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig,
+            sequence: 0,
+            witness,
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    })
+}
+
+/// Returns the signet solution in the witness commitment script, if any, and removes
+/// it from the script, leaving only the signet header in its place.
+fn fetch_and_clear_solution(witness_commitment: &mut Script) -> Option<Vec<u8>> {
+    let mut replacement = vec![];
+    let mut solution = None;
+
+    for instruction in witness_commitment.instructions() {
+        match instruction {
+            Ok(Instruction::PushBytes(data)) => {
+                // The push only counts if it has the header _and_ some data.
+                if solution.is_none()
+                    && data.len() > SIGNET_HEADER.len()
+                    && data[..SIGNET_HEADER.len()] == SIGNET_HEADER
+                {
+                    solution = Some(data[SIGNET_HEADER.len()..].to_vec());
+                    replacement.extend_from_slice(
+                        Builder::new()
+                            .push_slice(&SIGNET_HEADER)
+                            .into_script()
+                            .as_bytes(),
+                    );
+                } else {
+                    replacement.extend_from_slice(
+                        Builder::new().push_slice(data).into_script().as_bytes(),
+                    );
+                }
+            }
+            Ok(Instruction::Op(op)) => replacement.push(op.into_u8()),
+            // Like Bitcoin Core, stop at the first instruction that cannot be parsed.
+            Err(_) => break,
+        }
+    }
+
+    if solution.is_some() {
+        *witness_commitment = Script::from(replacement);
+    }
+
+    solution
+}
+
+/// Verifies an ECDSA signature, with the sighash type appended, over the first input
+/// of the `to_sign` transaction.
+fn verify_signature<C: Verification>(
+    secp: &Secp256k1<C>,
+    to_sign: &Transaction,
+    challenge: &Script,
+    signature: &[u8],
+    public_key: &PublicKey,
+) -> bool {
+    let (sighash_type, der) = match signature.split_last() {
+        Some((sighash_type, der)) => (*sighash_type, der),
+        None => return false,
+    };
+
+    let mut signature = match Signature::from_der(der) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    // High S values are valid in blocks, but libsecp256k1 only verifies low S values.
+    signature.normalize_s();
+
+    let sighash = to_sign.signature_hash(0, challenge, sighash_type as u32);
+    let message = Message::from_slice(&sighash[..]).expect("sighash must be 32 bytes");
+    secp.verify_ecdsa(&message, &signature, public_key).is_ok()
+}
+
+/// Verifies the signatures of an `OP_CHECKMULTISIG`. As in Bitcoin Core, the
+/// signatures must be in the same order as the public keys they correspond to.
+fn verify_multisig<C: Verification>(
+    secp: &Secp256k1<C>,
+    to_sign: &Transaction,
+    challenge: &Script,
+    signatures: &[Vec<u8>],
+    public_keys: &[PublicKey],
+) -> bool {
+    let mut public_keys = public_keys.iter();
+    signatures.iter().all(|signature| {
+        public_keys
+            .any(|public_key| verify_signature(secp, to_sign, challenge, signature, public_key))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::{
+        blockdata::constants::genesis_block, secp256k1::SecretKey, EcdsaSighashType, Network,
+    };
+
+    fn challenge_true() -> Script {
+        Builder::new().push_opcode(OP_PUSHNUM_1).into_script()
+    }
+
+    fn secret_key(seed: u8) -> SecretKey {
+        SecretKey::from_slice(&[seed; 32]).unwrap()
+    }
+
+    fn public_key(seed: u8) -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret_key(seed))
+    }
+
+    fn challenge_multisig(required: i64, seeds: &[u8]) -> Script {
+        let mut builder = Builder::new().push_int(required);
+        for seed in seeds {
+            builder = builder.push_slice(&public_key(*seed).serialize());
+        }
+        builder
+            .push_int(seeds.len() as i64)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    /// Builds a block on top of the signet genesis with a coinbase that contains a
+    /// witness commitment, and optionally a signet solution.
+    fn block_with_solution(solution: Option<Vec<u8>>) -> Block {
+        let genesis = genesis_block(Network::Signet);
+        let mut commitment = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(&[WITNESS_COMMITMENT_HEADER[2..].to_vec(), vec![0; 32]].concat());
+        if let Some(solution) = solution {
+            commitment = commitment.push_slice(&[SIGNET_HEADER.to_vec(), solution].concat());
+        }
+
+        let coinbase = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(1).into_script(),
+                sequence: u32::MAX,
+                witness: Witness::from_vec(vec![vec![0; 32]]),
+            }],
+            output: vec![
+                TxOut {
+                    value: 5_000_000_000,
+                    script_pubkey: Script::new(),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: commitment.into_script(),
+                },
+            ],
+        };
+
+        let mut block = Block {
+            header: genesis.header,
+            txdata: vec![coinbase],
+        };
+        block.header.prev_blockhash = genesis.block_hash();
+        block.header.time += 600;
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
+
+    fn encode_solution(script_sig: &Script, witness: &Witness) -> Vec<u8> {
+        [serialize(script_sig), serialize(witness)].concat()
+    }
+
+    /// Signs the given block with the given keys and returns a block containing the solution.
+    fn sign_block(challenge: &Script, seeds: &[u8], dummy: bool) -> Block {
+        // The sighash doesn't depend on the solution, so it can be computed from a block
+        // where the solution is already cleared, i.e. only the signet header is left.
+        let to_sign = to_sign_transaction(challenge, &block_with_solution(Some(vec![]))).unwrap();
+        let sighash = to_sign.signature_hash(0, challenge, EcdsaSighashType::All as u32);
+        let message = Message::from_slice(&sighash[..]).unwrap();
+
+        let secp = Secp256k1::new();
+        let mut builder = Builder::new();
+        if dummy {
+            builder = builder.push_opcode(OP_PUSHBYTES_0);
+        }
+        for seed in seeds {
+            let mut signature = secp
+                .sign_ecdsa(&message, &secret_key(*seed))
+                .serialize_der()
+                .to_vec();
+            signature.push(EcdsaSighashType::All as u8);
+            builder = builder.push_slice(&signature);
+        }
+
+        block_with_solution(Some(encode_solution(
+            &builder.into_script(),
+            &Witness::default(),
+        )))
+    }
+
+    #[test]
+    fn op_true_challenge_accepts_blocks_without_solution() {
+        let block = block_with_solution(None);
+        assert_eq!(validate_signet_block(&challenge_true(), &block), Ok(()));
+    }
+
+    #[test]
+    fn block_without_witness_commitment_is_rejected() {
+        let mut block = block_with_solution(None);
+        block.txdata[0].output.pop();
+        assert_eq!(
+            validate_signet_block(&challenge_true(), &block),
+            Err(ValidateSignetBlockError::MissingWitnessCommitment)
+        );
+
+        block.txdata.clear();
+        assert_eq!(
+            validate_signet_block(&challenge_true(), &block),
+            Err(ValidateSignetBlockError::MissingCoinbase)
+        );
+    }
+
+    #[test]
+    fn multisig_challenge() {
+        // A 1-of-2 multisig, like the challenge of the default signet.
+        let challenge = challenge_multisig(1, &[1, 2]);
+
+        for seed in [1, 2] {
+            let block = sign_block(&challenge, &[seed], true);
+            assert_eq!(validate_signet_block(&challenge, &block), Ok(()));
+        }
+
+        // Signed by a key that isn't part of the challenge.
+        let block = sign_block(&challenge, &[3], true);
+        assert_eq!(
+            validate_signet_block(&challenge, &block),
+            Err(ValidateSignetBlockError::InvalidSolution)
+        );
+
+        // Missing the dummy element.
+        let block = sign_block(&challenge, &[1], false);
+        assert_eq!(
+            validate_signet_block(&challenge, &block),
+            Err(ValidateSignetBlockError::InvalidSolution)
+        );
+
+        // No solution at all.
+        assert_eq!(
+            validate_signet_block(&challenge, &block_with_solution(None)),
+            Err(ValidateSignetBlockError::InvalidSolution)
+        );
+    }
+
+    #[test]
+    fn multisig_challenge_requires_signatures_in_order() {
+        let challenge = challenge_multisig(2, &[1, 2, 3]);
+
+        let block = sign_block(&challenge, &[1, 3], true);
+        assert_eq!(validate_signet_block(&challenge, &block), Ok(()));
+
+        let block = sign_block(&challenge, &[3, 1], true);
+        assert_eq!(
+            validate_signet_block(&challenge, &block),
+            Err(ValidateSignetBlockError::InvalidSolution)
+        );
+
+        // Not enough signatures.
+        let block = sign_block(&challenge, &[1], true);
+        assert_eq!(
+            validate_signet_block(&challenge, &block),
+            Err(ValidateSignetBlockError::InvalidSolution)
+        );
+    }
+
+    #[test]
+    fn checksig_challenge() {
+        let challenge = Builder::new()
+            .push_slice(&public_key(1).serialize())
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+
+        let block = sign_block(&challenge, &[1], false);
+        assert_eq!(validate_signet_block(&challenge, &block), Ok(()));
+
+        let block = sign_block(&challenge, &[2], false);
+        assert_eq!(
+            validate_signet_block(&challenge, &block),
+            Err(ValidateSignetBlockError::InvalidSolution)
+        );
+    }
+
+    #[test]
+    fn signature_commits_to_the_block() {
+        let challenge = challenge_multisig(1, &[1]);
+        let mut block = sign_block(&challenge, &[1], true);
+        assert_eq!(validate_signet_block(&challenge, &block), Ok(()));
+
+        // Changing the header invalidates the signature.
+        block.header.time += 1;
+        assert_eq!(
+            validate_signet_block(&challenge, &block),
+            Err(ValidateSignetBlockError::InvalidSolution)
+        );
+
+        // Changing the nonce doesn't, as it isn't committed to.
+        block.header.time -= 1;
+        block.header.nonce += 1;
+        assert_eq!(validate_signet_block(&challenge, &block), Ok(()));
+    }
+
+    #[test]
+    fn malformed_solution_is_rejected() {
+        let mut solution = encode_solution(&Script::new(), &Witness::default());
+        solution.push(0);
+        let block = block_with_solution(Some(solution));
+        assert_eq!(
+            validate_signet_block(&challenge_true(), &block),
+            Err(ValidateSignetBlockError::MalformedSolution)
+        );
+    }
+
+    #[test]
+    fn unsupported_challenge_is_rejected() {
+        // A P2WPKH challenge.
+        let challenge = Builder::new()
+            .push_opcode(OP_PUSHBYTES_0)
+            .push_slice(&[0; 20])
+            .into_script();
+        assert_eq!(
+            validate_signet_block(&challenge, &block_with_solution(None)),
+            Err(ValidateSignetBlockError::UnsupportedChallenge)
+        );
+        assert_eq!(
+            validate_signet_challenge(&challenge),
+            Err(ValidateSignetBlockError::UnsupportedChallenge)
+        );
+        assert_eq!(validate_signet_challenge(&challenge_true()), Ok(()));
+    }
+}
@@ -42,6 +42,9 @@ pub enum BitcoinBlockApi {
 
     #[serde(rename = "chain_api_btc_com_mainnet")]
     ChainApiBtcComMainnet,
+
+    #[serde(rename = "mempool_space_signet")]
+    MempoolSpaceSignet,
 }
 
 impl std::fmt::Display for BitcoinBlockApi {
@@ -63,6 +66,7 @@ impl BitcoinBlockApi {
         match bitcoin_network {
             BitcoinNetwork::Mainnet => Self::providers_mainnet(),
             BitcoinNetwork::Testnet => Self::providers_testnet(),
+            BitcoinNetwork::Signet => Self::providers_signet(),
         }
     }
 
@@ -71,6 +75,7 @@ impl BitcoinBlockApi {
         match bitcoin_network {
             BitcoinNetwork::Mainnet => Self::explorers_mainnet(),
             BitcoinNetwork::Testnet => Self::explorers_testnet(),
+            BitcoinNetwork::Signet => Self::explorers_signet(),
         }
     }
 
@@ -92,6 +97,15 @@ impl BitcoinBlockApi {
         providers
     }
 
+    /// Returns the list of all signet API providers.
+    fn providers_signet() -> Vec<Self> {
+        let mut providers = Self::explorers_signet();
+        // Add the Bitcoin canister, since it's not an explorer.
+        providers.push(BitcoinBlockApi::BitcoinCanister);
+
+        providers
+    }
+
     /// Returns the list of mainnet explorers only.
     fn explorers_mainnet() -> Vec<Self> {
         let mut explorers = vec![
@@ -124,6 +138,16 @@ impl BitcoinBlockApi {
         explorers
     }
 
+    /// Returns the list of signet explorers only.
+    fn explorers_signet() -> Vec<Self> {
+        let mut explorers = vec![BitcoinBlockApi::MempoolSpaceSignet];
+        // Remove the explorers that are not configured.
+        let configured: HashSet<_> = crate::storage::get_config().explorers.into_iter().collect();
+        explorers.retain(|x| configured.contains(x));
+
+        explorers
+    }
+
     /// Fetches the block data from the API.
     pub async fn fetch_data(&self) -> serde_json::Value {
         match self {
@@ -197,6 +221,22 @@ impl BitcoinBlockApi {
             BitcoinBlockApi::ChainApiBtcComMainnet => {
                 http_request(endpoint_chain_api_btc_com_block_mainnet()).await
             }
+            BitcoinBlockApi::MempoolSpaceSignet => {
+                let futures = vec![
+                    http_request(endpoint_mempool_space_height_signet()),
+                    http_request(endpoint_mempool_space_hash_signet()),
+                ];
+                let results = futures::future::join_all(futures).await;
+                match (results[0]["height"].as_u64(), results[1]["hash"].as_str()) {
+                    (Some(height), Some(hash)) => {
+                        json!({
+                            "height": height,
+                            "hash": hash,
+                        })
+                    }
+                    _ => json!({}),
+                }
+            }
         }
     }
 }
@@ -421,12 +461,30 @@ mod test {
         .await;
     }
 
+    #[tokio::test]
+    async fn test_mempool_space_signet() {
+        test_utils::mock_signet_outcalls();
+        run_test(
+            BitcoinBlockApi::MempoolSpaceSignet,
+            vec![
+                (endpoint_mempool_space_hash_signet(), 1),
+                (endpoint_mempool_space_height_signet(), 1),
+            ],
+            json!({
+                "height": 150001,
+                "hash": "000000000000000000eee111111111111111111111111111111111111111111",
+            }),
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn test_http_request_failed_with_404() {
         test_utils::mock_all_outcalls_404();
         let all_providers = BitcoinBlockApi::providers_mainnet()
             .into_iter()
             .chain(BitcoinBlockApi::providers_testnet().into_iter())
+            .chain(BitcoinBlockApi::providers_signet().into_iter())
             .collect::<Vec<_>>();
         for provider in all_providers {
             let response = provider.fetch_data().await;
@@ -489,6 +547,7 @@ mod test {
                 BitcoinBlockApi::ChainApiBtcComMainnet,
                 "chain_api_btc_com_mainnet",
             ),
+            (BitcoinBlockApi::MempoolSpaceSignet, "mempool_space_signet"),
         ]
         .iter()
         .cloned()
@@ -496,6 +555,7 @@ mod test {
         let all_providers = BitcoinBlockApi::providers_mainnet()
             .into_iter()
             .chain(BitcoinBlockApi::providers_testnet().into_iter())
+            .chain(BitcoinBlockApi::providers_signet().into_iter())
             .collect::<Vec<_>>();
         for provider in all_providers {
             assert_eq!(provider.to_string(), expected[&provider].to_string());
@@ -22,6 +22,10 @@ const MAINNET_BITCOIN_CANISTER_PRINCIPAL: &str = "ghsi2-tqaaa-aaaan-aaaca-cai";
 /// Testnet bitcoin canister principal.
 const TESTNET_BITCOIN_CANISTER_PRINCIPAL: &str = "g4xu7-jiaaa-aaaan-aaaaq-cai";
 
+/// The minimum number of explorers to compare against on signet,
+/// where only a single explorer is available.
+const SIGNET_MIN_EXPLORERS: u64 = 1;
+
 /// The number of seconds to wait before the first data fetch.
 const DELAY_BEFORE_FIRST_FETCH_SEC: u64 = 1;
 
@@ -36,6 +40,9 @@ pub enum BitcoinNetwork {
 
     #[serde(rename = "testnet")]
     Testnet,
+
+    #[serde(rename = "signet")]
+    Signet,
 }
 
 /// Watchdog canister configuration.
@@ -72,6 +79,7 @@ impl Config {
         match BITCOIN_NETWORK {
             BitcoinNetwork::Mainnet => Self::mainnet(),
             BitcoinNetwork::Testnet => Self::testnet(),
+            BitcoinNetwork::Signet => Self::signet(),
         }
     }
 
@@ -117,6 +125,23 @@ impl Config {
         }
     }
 
+    /// Creates a new configuration for the signet.
+    ///
+    /// There is no well-known Bitcoin canister for signet, so its principal
+    /// must be provided when initializing the watchdog.
+    pub fn signet() -> Self {
+        Self {
+            bitcoin_network: BitcoinNetwork::Signet,
+            blocks_behind_threshold: BLOCKS_BEHIND_THRESHOLD,
+            blocks_ahead_threshold: BLOCKS_AHEAD_THRESHOLD,
+            min_explorers: SIGNET_MIN_EXPLORERS,
+            bitcoin_canister_principal: Principal::anonymous(),
+            delay_before_first_fetch_sec: DELAY_BEFORE_FIRST_FETCH_SEC,
+            interval_between_fetches_sec: INTERVAL_BETWEEN_FETCHES_SEC,
+            explorers: vec![BitcoinBlockApi::MempoolSpaceSignet],
+        }
+    }
+
     /// Returns the number of blocks behind threshold as a negative number.
     pub fn get_blocks_behind_threshold(&self) -> i64 {
         -(self.blocks_behind_threshold as i64)
@@ -189,4 +214,12 @@ mod test {
             TESTNET_BITCOIN_CANISTER_ENDPOINT
         );
     }
+
+    #[test]
+    fn test_config_signet() {
+        let config = Config::signet();
+        assert_eq!(config.bitcoin_network, BitcoinNetwork::Signet);
+        assert_eq!(config.min_explorers, SIGNET_MIN_EXPLORERS);
+        assert_eq!(config.explorers, vec![BitcoinBlockApi::MempoolSpaceSignet]);
+    }
 }
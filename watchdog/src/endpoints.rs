@@ -6,7 +6,8 @@ use crate::{
     transform_api_blockcypher_com_block, transform_bitcoin_canister,
     transform_blockchain_info_hash, transform_blockchain_info_height,
     transform_blockstream_info_hash, transform_blockstream_info_height,
-    transform_chain_api_btc_com_block,
+    transform_chain_api_btc_com_block, transform_mempool_space_hash,
+    transform_mempool_space_height,
 };
 use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
 use regex::Regex;
@@ -27,6 +28,7 @@ fn endpoint_api_bitaps_com_block(bitcoin_network: BitcoinNetwork) -> HttpRequest
     let url = match bitcoin_network {
         BitcoinNetwork::Mainnet => "https://api.bitaps.com/btc/v1/blockchain/block/last",
         BitcoinNetwork::Testnet => "https://api.bitaps.com/btc/testnet/v1/blockchain/block/last",
+        BitcoinNetwork::Signet => unreachable!("api.bitaps.com does not serve signet data"),
     };
     HttpRequestConfig::new(
         url,
@@ -61,6 +63,7 @@ fn endpoint_api_blockchair_com_block(bitcoin_network: BitcoinNetwork) -> HttpReq
     let url = match bitcoin_network {
         BitcoinNetwork::Mainnet => "https://api.blockchair.com/bitcoin/stats",
         BitcoinNetwork::Testnet => "https://api.blockchair.com/bitcoin/testnet/stats",
+        BitcoinNetwork::Signet => unreachable!("api.blockchair.com does not serve signet data"),
     };
     HttpRequestConfig::new(
         url,
@@ -95,6 +98,7 @@ fn endpoint_api_blockcypher_com_block(bitcoin_network: BitcoinNetwork) -> HttpRe
     let url = match bitcoin_network {
         BitcoinNetwork::Mainnet => "https://api.blockcypher.com/v1/btc/main",
         BitcoinNetwork::Testnet => "https://api.blockcypher.com/v1/btc/test3",
+        BitcoinNetwork::Signet => unreachable!("api.blockcypher.com does not serve signet data"),
     };
     HttpRequestConfig::new(
         url,
@@ -218,6 +222,7 @@ fn endpoint_blockstream_info_hash(bitcoin_network: BitcoinNetwork) -> HttpReques
     let url = match bitcoin_network {
         BitcoinNetwork::Mainnet => "https://blockstream.info/api/blocks/tip/hash",
         BitcoinNetwork::Testnet => "https://blockstream.info/testnet/api/blocks/tip/hash",
+        BitcoinNetwork::Signet => unreachable!("blockstream.info does not serve signet data"),
     };
     HttpRequestConfig::new(
         url,
@@ -251,6 +256,7 @@ fn endpoint_blockstream_info_height(bitcoin_network: BitcoinNetwork) -> HttpRequ
     let url = match bitcoin_network {
         BitcoinNetwork::Mainnet => "https://blockstream.info/api/blocks/tip/height",
         BitcoinNetwork::Testnet => "https://blockstream.info/testnet/api/blocks/tip/height",
+        BitcoinNetwork::Signet => unreachable!("blockstream.info does not serve signet data"),
     };
     HttpRequestConfig::new(
         url,
@@ -294,6 +300,48 @@ pub fn endpoint_chain_api_btc_com_block_mainnet() -> HttpRequestConfig {
     )
 }
 
+/// Creates a config for fetching signet hash data from mempool.space.
+pub fn endpoint_mempool_space_hash_signet() -> HttpRequestConfig {
+    HttpRequestConfig::new(
+        "https://mempool.space/signet/api/blocks/tip/hash",
+        Some(TransformFnWrapper {
+            name: "transform_mempool_space_hash",
+            func: transform_mempool_space_hash,
+        }),
+        |raw| {
+            apply_to_body(raw, |text| {
+                json!({
+                    "hash": text,
+                })
+                .to_string()
+            })
+        },
+    )
+}
+
+/// Creates a config for fetching signet height data from mempool.space.
+pub fn endpoint_mempool_space_height_signet() -> HttpRequestConfig {
+    HttpRequestConfig::new(
+        "https://mempool.space/signet/api/blocks/tip/height",
+        Some(TransformFnWrapper {
+            name: "transform_mempool_space_height",
+            func: transform_mempool_space_height,
+        }),
+        |raw| {
+            apply_to_body(raw, |text| {
+                text.parse::<u64>()
+                    .map(|height| {
+                        json!({
+                            "height": height,
+                        })
+                        .to_string()
+                    })
+                    .unwrap_or_default()
+            })
+        },
+    )
+}
+
 /// Applies the given transformation function to the body of the response.
 fn apply_to_body(raw: TransformArgs, f: impl FnOnce(String) -> String) -> HttpResponse {
     let mut response = HttpResponse {
@@ -523,10 +571,37 @@ mod test {
         .await;
     }
 
+    #[tokio::test]
+    async fn test_mempool_space_hash_signet() {
+        run_http_request_test(
+            endpoint_mempool_space_hash_signet(),
+            "https://mempool.space/signet/api/blocks/tip/hash",
+            test_utils::MEMPOOL_SPACE_HASH_SIGNET_RESPONSE,
+            json!({
+                "hash": "000000000000000000eee111111111111111111111111111111111111111111",
+            }),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_mempool_space_height_signet() {
+        run_http_request_test(
+            endpoint_mempool_space_height_signet(),
+            "https://mempool.space/signet/api/blocks/tip/height",
+            test_utils::MEMPOOL_SPACE_HEIGHT_SIGNET_RESPONSE,
+            json!({
+                "height": 150001,
+            }),
+        )
+        .await;
+    }
+
     #[test]
     fn test_transform_function_names() {
         test_utils::mock_mainnet_outcalls();
         test_utils::mock_testnet_outcalls();
+        test_utils::mock_signet_outcalls();
 
         let names = ic_http::mock::registered_transform_function_names();
         let names = names.iter().map(|s| s.as_str()).collect::<Vec<_>>();
@@ -542,7 +617,9 @@ mod test {
                 "transform_blockchain_info_height",
                 "transform_blockstream_info_hash",
                 "transform_blockstream_info_height",
-                "transform_chain_api_btc_com_block"
+                "transform_chain_api_btc_com_block",
+                "transform_mempool_space_hash",
+                "transform_mempool_space_height"
             ]
         );
     }
@@ -165,3 +165,13 @@ fn transform_blockstream_info_height(raw: TransformArgs) -> HttpResponse {
 fn transform_chain_api_btc_com_block(raw: TransformArgs) -> HttpResponse {
     endpoint_chain_api_btc_com_block_mainnet().transform(raw)
 }
+
+#[query]
+fn transform_mempool_space_hash(raw: TransformArgs) -> HttpResponse {
+    endpoint_mempool_space_hash_signet().transform(raw)
+}
+
+#[query]
+fn transform_mempool_space_height(raw: TransformArgs) -> HttpResponse {
+    endpoint_mempool_space_height_signet().transform(raw)
+}
@@ -39,13 +39,15 @@ pub fn get_metrics() -> CandidHttpResponse {
 /// Encodes the metrics in the Prometheus format.
 fn encode_metrics(w: &mut MetricsEncoder<Vec<u8>>) -> std::io::Result<()> {
     let config = crate::storage::get_config();
-    let (mainnet, testnet) = match config.bitcoin_network {
-        BitcoinNetwork::Mainnet => (1.0, 0.0),
-        BitcoinNetwork::Testnet => (0.0, 1.0),
+    let (mainnet, testnet, signet) = match config.bitcoin_network {
+        BitcoinNetwork::Mainnet => (1.0, 0.0, 0.0),
+        BitcoinNetwork::Testnet => (0.0, 1.0, 0.0),
+        BitcoinNetwork::Signet => (0.0, 0.0, 1.0),
     };
     w.gauge_vec("bitcoin_network", "Bitcoin network.")?
         .value(&[("network", "mainnet")], mainnet)?
-        .value(&[("network", "testnet")], testnet)?;
+        .value(&[("network", "testnet")], testnet)?
+        .value(&[("network", "signet")], signet)?;
     w.encode_gauge(
         "blocks_behind_threshold",
         config.get_blocks_behind_threshold() as f64,
@@ -88,6 +88,28 @@ pub fn mock_testnet_outcalls() {
     }
 }
 
+/// Mocks all the signet outcalls to be successful.
+pub fn mock_signet_outcalls() {
+    let mocks = [
+        (
+            endpoint_mempool_space_hash_signet(),
+            MEMPOOL_SPACE_HASH_SIGNET_RESPONSE,
+        ),
+        (
+            endpoint_mempool_space_height_signet(),
+            MEMPOOL_SPACE_HEIGHT_SIGNET_RESPONSE,
+        ),
+    ];
+    for (config, response_body) in mocks {
+        let request = config.request();
+        let mock_response = ic_http::create_response()
+            .status(200)
+            .body(response_body)
+            .build();
+        ic_http::mock::mock(request, mock_response);
+    }
+}
+
 /// Mocks all the outcalls to fail with status code 404.
 pub fn mock_all_outcalls_404() {
     let mocks = [
@@ -103,6 +125,8 @@ pub fn mock_all_outcalls_404() {
         endpoint_blockstream_info_height_mainnet(),
         endpoint_blockstream_info_height_testnet(),
         endpoint_chain_api_btc_com_block_mainnet(),
+        endpoint_mempool_space_hash_signet(),
+        endpoint_mempool_space_height_signet(),
     ];
     for config in mocks {
         let request = config.request();
@@ -126,6 +150,8 @@ pub fn mock_all_outcalls_abusing_api() {
         endpoint_blockstream_info_height_mainnet(),
         endpoint_blockstream_info_height_testnet(),
         endpoint_chain_api_btc_com_block_mainnet(),
+        endpoint_mempool_space_hash_signet(),
+        endpoint_mempool_space_height_signet(),
     ];
     for config in mocks {
         let request = config.request();
@@ -372,6 +398,13 @@ pub const BLOCKSTREAM_INFO_HASH_TESTNET_RESPONSE: &str =
 // https://blockstream.info/testnet/api/blocks/tip/height
 pub const BLOCKSTREAM_INFO_HEIGHT_TESTNET_RESPONSE: &str = r#"2000004"#;
 
+// https://mempool.space/signet/api/blocks/tip/hash
+pub const MEMPOOL_SPACE_HASH_SIGNET_RESPONSE: &str =
+    r#"000000000000000000eee111111111111111111111111111111111111111111"#;
+
+// https://mempool.space/signet/api/blocks/tip/height
+pub const MEMPOOL_SPACE_HEIGHT_SIGNET_RESPONSE: &str = r#"150001"#;
+
 // https://chain.api.btc.com/v3/block/latest
 pub const CHAIN_API_BTC_COM_MAINNET_RESPONSE: &str = r#"{
     "data": {